@@ -0,0 +1,12 @@
+-- Sign-in nonces issued by GET /auth/nonce
+-- Rows are deleted when consumed by register/login, so a nonce can be used once
+
+CREATE TABLE auth_nonces (
+    nonce VARCHAR(255) PRIMARY KEY,
+    wallet_address VARCHAR(42) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_auth_nonces_wallet_address ON auth_nonces(wallet_address);
+CREATE INDEX idx_auth_nonces_expires_at ON auth_nonces(expires_at);
@@ -0,0 +1,9 @@
+-- Failed wallet sign-in attempts used for account lockout
+-- failed_attempts resets on a successful login or once a lockout is applied
+
+CREATE TABLE login_attempts (
+    wallet_address VARCHAR(42) PRIMARY KEY,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,4 @@
+-- Optional per-key IP allowlist as CIDR strings (e.g. 203.0.113.0/24)
+-- NULL means the key may be used from any address
+
+ALTER TABLE api_keys ADD COLUMN allowed_ips TEXT[];
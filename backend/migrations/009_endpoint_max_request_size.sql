@@ -0,0 +1,4 @@
+-- Per-endpoint cap on proxied request bodies, in bytes
+-- NULL falls back to the platform-wide MAX_REQUEST_BODY_BYTES default
+
+ALTER TABLE api_endpoints ADD COLUMN max_request_size BIGINT CHECK (max_request_size > 0);
@@ -0,0 +1,4 @@
+-- Whether upstream 4xx responses are billed to the consumer
+-- 5xx responses and upstream failures are never billed
+
+ALTER TABLE api_endpoints ADD COLUMN bill_client_errors BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,18 @@
+-- Active upstream health checks
+-- Each probe of an endpoint's upstream_url is recorded; after enough consecutive
+-- failures the endpoint is flagged as degraded until a probe succeeds again
+
+ALTER TABLE api_endpoints ADD COLUMN is_degraded BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE api_endpoints ADD COLUMN consecutive_health_failures INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE endpoint_health (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    healthy BOOLEAN NOT NULL,
+    status_code INTEGER,
+    latency_ms INTEGER NOT NULL,
+    error_message TEXT,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_endpoint_health_endpoint_checked ON endpoint_health(endpoint_id, checked_at DESC);
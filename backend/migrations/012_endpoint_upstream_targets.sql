@@ -0,0 +1,7 @@
+-- Weighted upstream targets for load balancing and failover
+-- An empty list means the endpoint is served by upstream_url alone
+
+ALTER TABLE api_endpoints ADD COLUMN upstream_targets JSONB NOT NULL DEFAULT '[]';
+
+-- Which upstream target served each proxied request
+ALTER TABLE request_logs ADD COLUMN upstream_target TEXT;
@@ -0,0 +1,4 @@
+-- Optional rewrite of the incoming path before it is appended to the upstream URL
+-- NULL forwards the path unchanged
+
+ALTER TABLE api_endpoints ADD COLUMN path_rewrite JSONB;
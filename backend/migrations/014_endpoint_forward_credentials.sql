@@ -0,0 +1,4 @@
+-- Whether the consumer's AugustCredits credentials are passed through to the upstream
+-- By default the Authorization and X-API-Key headers and the api_key query parameter are stripped
+
+ALTER TABLE api_endpoints ADD COLUMN forward_credentials BOOLEAN NOT NULL DEFAULT false;
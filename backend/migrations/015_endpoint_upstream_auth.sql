@@ -0,0 +1,4 @@
+-- Credentials the gateway attaches to upstream requests on the owner's behalf
+-- Stored AES-256-GCM encrypted with UPSTREAM_CREDENTIALS_KEY; NULL sends none
+
+ALTER TABLE api_endpoints ADD COLUMN upstream_auth_encrypted TEXT;
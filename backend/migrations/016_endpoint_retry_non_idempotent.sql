@@ -0,0 +1,5 @@
+-- Whether failed POST/PUT/PATCH/DELETE requests are retried automatically
+-- Off by default so upstream side effects are not repeated; consumers can still
+-- opt in per request with an Idempotency-Key header
+
+ALTER TABLE api_endpoints ADD COLUMN retry_non_idempotent BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+-- WebSocket proxying for streaming upstream APIs
+-- Endpoints expose their upstream socket at upstream_ws_url and choose how
+-- streaming sessions are billed; plain HTTP endpoints keep per-request pricing
+
+CREATE TYPE pricing_model AS ENUM ('per_request', 'per_connection_minute', 'per_message');
+
+ALTER TABLE api_endpoints ADD COLUMN upstream_ws_url TEXT;
+ALTER TABLE api_endpoints ADD COLUMN pricing_model pricing_model NOT NULL DEFAULT 'per_request';
@@ -0,0 +1,7 @@
+-- Streaming passthrough for SSE and chunked upstream responses
+-- Streamed responses can be billed by the kilobyte, and each log entry records
+-- how long its stream stayed open
+
+ALTER TYPE pricing_model ADD VALUE 'per_kilobyte';
+
+ALTER TABLE request_logs ADD COLUMN stream_duration_ms INTEGER;
@@ -0,0 +1,13 @@
+-- Byte-based pricing for data-heavy endpoints
+-- per_kilobyte charges price_per_kilobyte for each started kilobyte of response;
+-- per_request_plus_bytes adds that on top of price_per_request
+
+ALTER TYPE pricing_model ADD VALUE 'per_request_plus_bytes';
+
+ALTER TABLE api_endpoints ADD COLUMN price_per_kilobyte TEXT;
+
+-- per_kilobyte endpoints were previously charged price_per_request per kilobyte
+UPDATE api_endpoints SET price_per_kilobyte = price_per_request WHERE pricing_model = 'per_kilobyte';
+
+-- Request and kilobyte components of each logged cost
+ALTER TABLE request_logs ADD COLUMN cost_breakdown JSONB;
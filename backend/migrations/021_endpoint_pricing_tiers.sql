@@ -0,0 +1,5 @@
+-- Volume discount tiers per endpoint
+-- Each tier prices a consumer's billable units up to and including its up_to within
+-- a billing period; units past the last bounded tier are charged the list price
+
+ALTER TABLE api_endpoints ADD COLUMN pricing_tiers JSONB NOT NULL DEFAULT '[]';
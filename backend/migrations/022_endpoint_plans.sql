@@ -0,0 +1,39 @@
+-- Monthly subscription plans for endpoints
+-- A subscriber pays monthly_price up front for each period, which covers
+-- included_requests billable units; units past that are charged overage_price.
+-- Cancelling stops renewal but keeps the plan's pricing until current_period_end.
+
+CREATE TYPE subscription_status AS ENUM ('active', 'cancelled');
+
+CREATE TABLE endpoint_plans (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    monthly_price TEXT NOT NULL,
+    included_requests BIGINT NOT NULL,
+    overage_price TEXT NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT true, -- retired plans accept no new subscribers and are not renewed
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (endpoint_id, name)
+);
+
+CREATE TABLE endpoint_subscriptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    plan_id UUID NOT NULL REFERENCES endpoint_plans(id),
+    status subscription_status NOT NULL DEFAULT 'active',
+    current_period_start TIMESTAMPTZ NOT NULL,
+    current_period_end TIMESTAMPTZ NOT NULL,
+    requests_used BIGINT NOT NULL DEFAULT 0,
+    cancel_at_period_end BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- At most one live subscription per consumer and endpoint
+CREATE UNIQUE INDEX idx_endpoint_subscriptions_active ON endpoint_subscriptions(user_id, endpoint_id)
+    WHERE status = 'active';
+CREATE INDEX idx_endpoint_subscriptions_period_end ON endpoint_subscriptions(current_period_end)
+    WHERE status = 'active';
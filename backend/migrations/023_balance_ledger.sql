@@ -0,0 +1,35 @@
+-- Off-chain balance ledger for prepaid usage
+-- Proxied calls place a hold on the consumer's balance before they are forwarded.
+-- The hold is replaced by the actual charge once the call is priced, or released
+-- if the call fails. Available funds are the balance minus every open hold.
+
+CREATE TYPE ledger_entry_type AS ENUM ('deposit', 'charge');
+
+CREATE TABLE user_balances (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    balance NUMERIC NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE balance_holds (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    amount NUMERIC NOT NULL CHECK (amount > 0),
+    request_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_balance_holds_user_id ON balance_holds(user_id);
+CREATE INDEX idx_balance_holds_created_at ON balance_holds(created_at);
+
+CREATE TABLE balance_ledger (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    entry_type ledger_entry_type NOT NULL,
+    amount NUMERIC NOT NULL, -- signed; charges are negative
+    balance_after NUMERIC NOT NULL,
+    reference VARCHAR(255), -- request ID or transaction hash
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_balance_ledger_user_id ON balance_ledger(user_id, created_at);
@@ -0,0 +1,9 @@
+-- Sandbox mode per endpoint
+-- Requests sent with X-AugustCredits-Sandbox: true get the endpoint's canned
+-- sandbox_response, or are forwarded to sandbox_upstream_url when no response is
+-- configured. Sandbox requests are never billed and are marked in the request log.
+
+ALTER TABLE api_endpoints ADD COLUMN sandbox_response JSONB;
+ALTER TABLE api_endpoints ADD COLUMN sandbox_upstream_url TEXT;
+
+ALTER TABLE request_logs ADD COLUMN is_sandbox BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+-- Tags and categories for the public endpoint listing
+-- Tags are stored lowercased; search matches them along with the name and description
+
+ALTER TABLE api_endpoints ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE api_endpoints ADD COLUMN category VARCHAR(100);
+
+CREATE INDEX idx_api_endpoints_tags ON api_endpoints USING GIN (tags);
+CREATE INDEX idx_api_endpoints_category ON api_endpoints(category);
@@ -0,0 +1,8 @@
+-- Soft deletion of endpoints
+-- Deleted endpoints keep their row so usage records and request logs still reference
+-- it. Only live endpoints need unique names, so a deleted name can be registered again.
+
+ALTER TABLE api_endpoints ADD COLUMN deleted_at TIMESTAMPTZ;
+
+ALTER TABLE api_endpoints DROP CONSTRAINT api_endpoints_name_key;
+CREATE UNIQUE INDEX idx_api_endpoints_live_name ON api_endpoints(name) WHERE deleted_at IS NULL;
@@ -0,0 +1,27 @@
+-- SLA tracking with refunds for calls that breached it
+-- A billed call slower than sla_max_latency_ms, or failing with a 5xx when
+-- sla_error_refund is set, is flagged against its usage record until the
+-- billing cycle credits the consumer back.
+
+ALTER TABLE api_endpoints
+    ADD COLUMN sla_max_latency_ms INTEGER,
+    ADD COLUMN sla_error_refund BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TYPE ledger_entry_type ADD VALUE 'refund';
+
+CREATE TYPE sla_breach AS ENUM ('latency', 'server_error');
+
+CREATE TABLE sla_refunds (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    usage_record_id UUID NOT NULL REFERENCES usage_records(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    request_id VARCHAR(255) NOT NULL UNIQUE,
+    amount NUMERIC NOT NULL,
+    breach sla_breach NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    refunded_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_sla_refunds_pending ON sla_refunds(created_at) WHERE refunded_at IS NULL;
+CREATE INDEX idx_sla_refunds_endpoint ON sla_refunds(endpoint_id, created_at);
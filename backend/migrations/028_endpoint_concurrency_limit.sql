@@ -0,0 +1,4 @@
+-- Per-endpoint cap on the calls a consumer may have in flight at once
+-- NULL leaves concurrency unlimited; the window rate limit still applies.
+
+ALTER TABLE api_endpoints ADD COLUMN max_concurrent_requests INTEGER;
@@ -0,0 +1,14 @@
+-- OpenAPI documents describing the API behind an endpoint
+-- The document is kept as uploaded and served raw; its summary is worked out once on
+-- upload for the endpoint details.
+
+CREATE TYPE spec_format AS ENUM ('json', 'yaml');
+
+CREATE TABLE endpoint_specs (
+    endpoint_id UUID PRIMARY KEY REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    format spec_format NOT NULL,
+    content TEXT NOT NULL,
+    summary JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
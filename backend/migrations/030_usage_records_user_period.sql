@@ -0,0 +1,6 @@
+-- Monthly request counts are checked on every proxied call for users with a monthly
+-- limit; this index lets the count be read from the current period's rows alone
+-- instead of every usage record the user has ever had.
+
+CREATE INDEX idx_usage_records_user_period
+    ON usage_records(user_id, billing_period) INCLUDE (request_count);
@@ -0,0 +1,10 @@
+-- Per-endpoint choice of rate limiting algorithm
+-- Token buckets refill at rate_limit per rate_limit_window and hold up to
+-- rate_limit_burst tokens, so short bursts pass while the sustained rate stays capped.
+-- A NULL burst uses the platform default.
+
+CREATE TYPE rate_limit_algorithm AS ENUM ('sliding_window', 'token_bucket');
+
+ALTER TABLE api_endpoints
+    ADD COLUMN rate_limit_algorithm rate_limit_algorithm NOT NULL DEFAULT 'sliding_window',
+    ADD COLUMN rate_limit_burst INTEGER;
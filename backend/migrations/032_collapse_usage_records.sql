@@ -0,0 +1,58 @@
+-- Usage is kept as one row per user, endpoint and billing period, with each call
+-- added to the period's counts. Databases created before the uniqueness constraint
+-- may hold a row per call; fold those into the period's oldest row, moving any SLA
+-- refunds across, and make sure the constraint is in place.
+
+CREATE TEMP TABLE usage_record_merges ON COMMIT DROP AS
+SELECT id, keep_id FROM (
+    SELECT id, first_value(id) OVER (
+        PARTITION BY user_id, endpoint_id, billing_period
+        ORDER BY timestamp, id
+    ) AS keep_id
+    FROM usage_records
+) ranked
+WHERE id <> keep_id;
+
+UPDATE usage_records ur SET
+    request_count = merged.request_count,
+    total_cost = merged.total_cost,
+    timestamp = merged.timestamp,
+    status = merged.status
+FROM (
+    SELECT COALESCE(m.keep_id, u.id) AS id,
+           SUM(u.request_count) AS request_count,
+           SUM(u.total_cost::NUMERIC)::TEXT AS total_cost,
+           MAX(u.timestamp) AS timestamp,
+           -- Usage that was never billed must stay billable
+           CASE WHEN bool_or(u.status = 'pending') THEN 'pending'::usage_status
+                ELSE MIN(u.status) END AS status
+    FROM usage_records u
+    LEFT JOIN usage_record_merges m ON m.id = u.id
+    WHERE COALESCE(m.keep_id, u.id) IN (SELECT keep_id FROM usage_record_merges)
+    GROUP BY COALESCE(m.keep_id, u.id)
+) merged
+WHERE ur.id = merged.id;
+
+UPDATE sla_refunds r SET usage_record_id = m.keep_id
+FROM usage_record_merges m
+WHERE r.usage_record_id = m.id;
+
+DELETE FROM usage_records WHERE id IN (SELECT id FROM usage_record_merges);
+
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_constraint
+        WHERE conrelid = 'usage_records'::regclass AND contype = 'u'
+          AND conkey = ARRAY(
+              SELECT attnum FROM pg_attribute
+              WHERE attrelid = 'usage_records'::regclass
+                AND attname IN ('user_id', 'endpoint_id', 'billing_period')
+              ORDER BY attnum
+          )::SMALLINT[]
+    ) THEN
+        ALTER TABLE usage_records
+            ADD CONSTRAINT usage_records_user_id_endpoint_id_billing_period_key
+            UNIQUE (user_id, endpoint_id, billing_period);
+    END IF;
+END $$;
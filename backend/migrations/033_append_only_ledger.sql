@@ -0,0 +1,16 @@
+-- The balance ledger is the audit trail behind user_balances: every deposit, charge
+-- and refund appends an entry and none is ever rewritten. Entries may only go when
+-- their user is deleted, which cascades to the ledger after the user row is gone.
+
+CREATE OR REPLACE FUNCTION reject_ledger_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND NOT EXISTS (SELECT 1 FROM users WHERE id = OLD.user_id) THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'balance_ledger is append-only';
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER balance_ledger_append_only BEFORE UPDATE OR DELETE ON balance_ledger
+    FOR EACH ROW EXECUTE FUNCTION reject_ledger_changes();
@@ -0,0 +1,12 @@
+-- Withdrawals pay out a user's prepaid balance on-chain. The balance is debited up
+-- front and the payout tracked as a payment transaction; a payout that fails on-chain
+-- is credited back with a reversal entry.
+
+ALTER TYPE ledger_entry_type ADD VALUE 'withdrawal';
+ALTER TYPE ledger_entry_type ADD VALUE 'reversal';
+
+ALTER TABLE payment_transactions ADD COLUMN destination_address VARCHAR(42);
+
+-- Daily withdrawal caps sum a user's withdrawals since midnight
+CREATE INDEX idx_payment_transactions_user_type_created
+    ON payment_transactions(user_id, transaction_type, created_at);
@@ -0,0 +1,16 @@
+-- Month-to-date request totals per user, kept alongside usage_records so the
+-- monthly limit check reads one row instead of summing every endpoint's usage.
+-- The billing run rebuilds any total that drifts from usage_records.
+
+CREATE TABLE user_monthly_usage (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    billing_period VARCHAR(7) NOT NULL,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, billing_period)
+);
+
+INSERT INTO user_monthly_usage (user_id, billing_period, request_count)
+SELECT user_id, billing_period, SUM(request_count)::BIGINT
+FROM usage_records
+GROUP BY user_id, billing_period;
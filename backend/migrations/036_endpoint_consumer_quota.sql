@@ -0,0 +1,15 @@
+-- Per-endpoint cap on the billable units any one consumer may use per month,
+-- independent of the consumer's own monthly_limit. NULL leaves consumers uncapped.
+
+ALTER TABLE api_endpoints ADD COLUMN consumer_monthly_quota BIGINT;
+
+-- Owner-granted exceptions to an endpoint's quota for individual consumers.
+-- A NULL monthly_quota lifts the cap for that consumer entirely.
+CREATE TABLE endpoint_consumer_overrides (
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    monthly_quota BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (endpoint_id, user_id)
+);
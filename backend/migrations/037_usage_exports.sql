@@ -0,0 +1,21 @@
+-- Usage exports too large to stream in a single response are written to disk in
+-- the background and downloaded by id once ready.
+
+CREATE TYPE export_format AS ENUM ('csv', 'jsonl');
+CREATE TYPE export_status AS ENUM ('pending', 'ready', 'failed');
+
+CREATE TABLE usage_exports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    requested_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE, -- NULL exports every user's requests
+    format export_format NOT NULL,
+    start_time TIMESTAMPTZ,
+    end_time TIMESTAMPTZ NOT NULL,
+    status export_status NOT NULL DEFAULT 'pending',
+    row_count BIGINT,
+    error_message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_usage_exports_requested_by ON usage_exports(requested_by, created_at);
@@ -0,0 +1,18 @@
+-- Usage attributed to the named API key that made each call
+-- Request logs record the key of every proxied call. Billed usage is also totalled
+-- per key, endpoint and month beside usage_records, which stay one row per user,
+-- endpoint and month since tiered pricing and billing are worked out from them.
+
+ALTER TABLE request_logs ADD COLUMN api_key_id UUID REFERENCES api_keys(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_request_logs_api_key ON request_logs(api_key_id, timestamp) WHERE api_key_id IS NOT NULL;
+
+CREATE TABLE api_key_usage (
+    api_key_id UUID NOT NULL REFERENCES api_keys(id) ON DELETE CASCADE,
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    billing_period VARCHAR(7) NOT NULL, -- YYYY-MM format
+    request_count BIGINT NOT NULL DEFAULT 0,
+    total_cost TEXT NOT NULL DEFAULT '0',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (api_key_id, endpoint_id, billing_period)
+);
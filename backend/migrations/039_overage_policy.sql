@@ -0,0 +1,15 @@
+-- What happens once a user passes their monthly request limit: calls are blocked,
+-- allowed at a multiple of their price, or allowed for a number of grace requests.
+-- Usage past the limit is totalled per user and month so it can be itemised.
+
+ALTER TABLE users ADD COLUMN overage_policy JSONB NOT NULL DEFAULT '{"mode": "block"}';
+
+CREATE TABLE user_overage_usage (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    billing_period VARCHAR(7) NOT NULL, -- YYYY-MM format
+    request_count BIGINT NOT NULL DEFAULT 0,
+    total_cost TEXT NOT NULL DEFAULT '0',
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), -- first call admitted past the limit
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, billing_period)
+);
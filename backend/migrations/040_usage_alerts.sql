@@ -0,0 +1,22 @@
+-- Alerts raised when a user's trailing-hour request rate to an endpoint spikes far
+-- above its usual hourly rate. At most one alert per user and endpoint is active at
+-- a time; it is resolved automatically once the rate falls back under the threshold.
+-- The usual rate comes from per-user, per-endpoint rows in daily_stats (both ids set).
+
+CREATE TYPE alert_status AS ENUM ('active', 'resolved');
+
+CREATE TABLE usage_alerts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    status alert_status NOT NULL DEFAULT 'active',
+    hourly_requests BIGINT NOT NULL, -- trailing-hour requests when the alert was raised
+    peak_hourly_requests BIGINT NOT NULL, -- highest trailing-hour requests seen while active
+    baseline_hourly DOUBLE PRECISION NOT NULL, -- usual hourly requests the spike was measured against
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), -- last check that still found the spike
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX idx_usage_alerts_active ON usage_alerts(user_id, endpoint_id) WHERE status = 'active';
+CREATE INDEX idx_usage_alerts_user_created ON usage_alerts(user_id, created_at DESC);
@@ -0,0 +1,112 @@
+-- daily_stats holds one row per day for each endpoint (user_id NULL), each user
+-- (endpoint_id NULL) and each user and endpoint pair. Postgres treats NULLs as
+-- distinct, so UNIQUE(date, endpoint_id, user_id) never matched the endpoint or user
+-- rows and re-running the aggregation duplicated them; the unique index now compares
+-- missing ids as the nil UUID instead.
+
+DELETE FROM daily_stats a
+USING daily_stats b
+WHERE a.date = b.date
+    AND a.endpoint_id IS NOT DISTINCT FROM b.endpoint_id
+    AND a.user_id IS NOT DISTINCT FROM b.user_id
+    AND (a.created_at, a.id) < (b.created_at, b.id);
+
+ALTER TABLE daily_stats DROP CONSTRAINT IF EXISTS daily_stats_date_endpoint_id_user_id_key;
+
+CREATE UNIQUE INDEX idx_daily_stats_scope ON daily_stats (
+    date,
+    COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'::UUID),
+    COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::UUID)
+);
+
+ALTER TABLE daily_stats ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Aggregates one UTC day of non-sandbox requests, the same traffic the live stats
+-- queries count, replacing any rows already cached for that day
+CREATE OR REPLACE FUNCTION aggregate_daily_stats(target_date DATE DEFAULT (NOW() AT TIME ZONE 'UTC')::DATE - 1)
+RETURNS INTEGER AS $$
+DECLARE
+    day_start TIMESTAMPTZ := target_date::TIMESTAMP AT TIME ZONE 'UTC';
+    day_end TIMESTAMPTZ := (target_date + 1)::TIMESTAMP AT TIME ZONE 'UTC';
+    affected INTEGER;
+    inserted_count INTEGER := 0;
+BEGIN
+    -- By endpoint
+    INSERT INTO daily_stats (date, endpoint_id, total_requests, total_cost, unique_users, avg_response_time, error_rate)
+    SELECT
+        target_date,
+        rl.endpoint_id,
+        COUNT(*),
+        COALESCE(SUM(rl.cost::NUMERIC), 0)::TEXT,
+        COUNT(DISTINCT rl.user_id),
+        COALESCE(AVG(rl.response_time_ms), 0),
+        AVG(CASE WHEN rl.status_code >= 400 THEN 1.0 ELSE 0.0 END)
+    FROM request_logs rl
+    WHERE rl.timestamp >= day_start AND rl.timestamp < day_end AND NOT rl.is_sandbox
+    GROUP BY rl.endpoint_id
+    ON CONFLICT (date, COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'::UUID),
+                 COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::UUID)) DO UPDATE SET
+        total_requests = EXCLUDED.total_requests,
+        total_cost = EXCLUDED.total_cost,
+        unique_users = EXCLUDED.unique_users,
+        avg_response_time = EXCLUDED.avg_response_time,
+        error_rate = EXCLUDED.error_rate,
+        updated_at = NOW();
+
+    GET DIAGNOSTICS affected = ROW_COUNT;
+    inserted_count := inserted_count + affected;
+
+    -- By user
+    INSERT INTO daily_stats (date, user_id, total_requests, total_cost, unique_users, avg_response_time, error_rate)
+    SELECT
+        target_date,
+        rl.user_id,
+        COUNT(*),
+        COALESCE(SUM(rl.cost::NUMERIC), 0)::TEXT,
+        1, -- Always 1 for user-specific stats
+        COALESCE(AVG(rl.response_time_ms), 0),
+        AVG(CASE WHEN rl.status_code >= 400 THEN 1.0 ELSE 0.0 END)
+    FROM request_logs rl
+    WHERE rl.timestamp >= day_start AND rl.timestamp < day_end AND NOT rl.is_sandbox
+    GROUP BY rl.user_id
+    ON CONFLICT (date, COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'::UUID),
+                 COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::UUID)) DO UPDATE SET
+        total_requests = EXCLUDED.total_requests,
+        total_cost = EXCLUDED.total_cost,
+        unique_users = EXCLUDED.unique_users,
+        avg_response_time = EXCLUDED.avg_response_time,
+        error_rate = EXCLUDED.error_rate,
+        updated_at = NOW();
+
+    GET DIAGNOSTICS affected = ROW_COUNT;
+    inserted_count := inserted_count + affected;
+
+    -- By user and endpoint
+    INSERT INTO daily_stats (date, endpoint_id, user_id, total_requests, total_cost, unique_users, avg_response_time, error_rate)
+    SELECT
+        target_date,
+        rl.endpoint_id,
+        rl.user_id,
+        COUNT(*),
+        COALESCE(SUM(rl.cost::NUMERIC), 0)::TEXT,
+        1,
+        COALESCE(AVG(rl.response_time_ms), 0),
+        AVG(CASE WHEN rl.status_code >= 400 THEN 1.0 ELSE 0.0 END)
+    FROM request_logs rl
+    WHERE rl.timestamp >= day_start AND rl.timestamp < day_end AND NOT rl.is_sandbox
+    GROUP BY rl.endpoint_id, rl.user_id
+    ON CONFLICT (date, COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'::UUID),
+                 COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::UUID)) DO UPDATE SET
+        total_requests = EXCLUDED.total_requests,
+        total_cost = EXCLUDED.total_cost,
+        unique_users = EXCLUDED.unique_users,
+        avg_response_time = EXCLUDED.avg_response_time,
+        error_rate = EXCLUDED.error_rate,
+        updated_at = NOW();
+
+    GET DIAGNOSTICS affected = ROW_COUNT;
+    inserted_count := inserted_count + affected;
+
+    RETURN inserted_count;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,6 @@
+-- Request logs are browsed newest first in (timestamp, id) order, a page at a time
+-- from a cursor; these indexes let each page seek straight to its cursor, across
+-- all users for admins and within one user for everyone else.
+
+CREATE INDEX idx_request_logs_timestamp_id ON request_logs(timestamp, id);
+CREATE INDEX idx_request_logs_user_timestamp_id ON request_logs(user_id, timestamp, id);
@@ -0,0 +1,16 @@
+-- Soft deletion of user accounts
+-- Deleted users keep their row so billing history and aggregates still reference it.
+-- Their request logs are anonymized once the retention window has passed: the user
+-- linkage and client fingerprints are cleared, leaving endpoint and cost data intact.
+-- Only live users need unique wallets, so a deleted wallet can register again.
+
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN logs_anonymized_at TIMESTAMPTZ;
+
+ALTER TABLE users DROP CONSTRAINT users_wallet_address_key;
+CREATE UNIQUE INDEX idx_users_live_wallet_address ON users(wallet_address) WHERE deleted_at IS NULL;
+
+CREATE INDEX idx_users_pending_anonymization ON users(deleted_at)
+    WHERE deleted_at IS NOT NULL AND logs_anonymized_at IS NULL;
+
+ALTER TABLE request_logs ALTER COLUMN user_id DROP NOT NULL;
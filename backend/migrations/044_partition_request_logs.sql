@@ -0,0 +1,73 @@
+-- Monthly range partitioning of request_logs
+-- Old logs are removed by dropping whole partitions instead of deleting rows, which
+-- would bloat the table and hold locks on the hot logging path. Partitions are named
+-- request_logs_YYYY_MM and cover one UTC month; the gateway creates upcoming ones
+-- ahead of time and drops those past the retention window. Rows outside every
+-- monthly partition land in request_logs_default and are moved into their month's
+-- partition when it is created.
+
+ALTER TABLE request_logs RENAME TO request_logs_legacy;
+ALTER INDEX request_logs_pkey RENAME TO request_logs_legacy_pkey;
+
+CREATE TABLE request_logs (
+    LIKE request_logs_legacy INCLUDING DEFAULTS,
+    PRIMARY KEY (id, timestamp)
+) PARTITION BY RANGE (timestamp);
+
+CREATE TABLE request_logs_default PARTITION OF request_logs DEFAULT;
+
+-- Creates the partition for the UTC month containing `month`, returning false if it
+-- already exists
+CREATE OR REPLACE FUNCTION create_request_log_partition(month DATE)
+RETURNS BOOLEAN AS $$
+DECLARE
+    range_start TIMESTAMPTZ := date_trunc('month', month::TIMESTAMP) AT TIME ZONE 'UTC';
+    range_end TIMESTAMPTZ := (date_trunc('month', month::TIMESTAMP) + INTERVAL '1 month') AT TIME ZONE 'UTC';
+    partition_name TEXT := 'request_logs_' || to_char(month, 'YYYY_MM');
+BEGIN
+    IF to_regclass(partition_name) IS NOT NULL THEN
+        RETURN false;
+    END IF;
+
+    EXECUTE format('CREATE TABLE %I (LIKE request_logs INCLUDING DEFAULTS)', partition_name);
+    EXECUTE format(
+        'WITH moved AS (DELETE FROM request_logs_default WHERE timestamp >= %L AND timestamp < %L RETURNING *)
+         INSERT INTO %I SELECT * FROM moved',
+        range_start, range_end, partition_name
+    );
+    EXECUTE format(
+        'ALTER TABLE request_logs ATTACH PARTITION %I FOR VALUES FROM (%L) TO (%L)',
+        partition_name, range_start, range_end
+    );
+    RETURN true;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Partitions for every month with logs, through the next two months
+DO $$
+DECLARE
+    month DATE := date_trunc('month', COALESCE(
+        (SELECT MIN(timestamp) FROM request_logs_legacy) AT TIME ZONE 'UTC',
+        NOW() AT TIME ZONE 'UTC'
+    ))::DATE;
+BEGIN
+    WHILE month <= (date_trunc('month', NOW() AT TIME ZONE 'UTC') + INTERVAL '2 months')::DATE LOOP
+        PERFORM create_request_log_partition(month);
+        month := (month + INTERVAL '1 month')::DATE;
+    END LOOP;
+END;
+$$;
+
+INSERT INTO request_logs SELECT * FROM request_logs_legacy;
+DROP TABLE request_logs_legacy;
+
+ALTER TABLE request_logs ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE request_logs ADD FOREIGN KEY (endpoint_id) REFERENCES api_endpoints(id) ON DELETE CASCADE;
+ALTER TABLE request_logs ADD FOREIGN KEY (api_key_id) REFERENCES api_keys(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_request_logs_timestamp_id ON request_logs(timestamp, id);
+CREATE INDEX idx_request_logs_user_timestamp_id ON request_logs(user_id, timestamp, id);
+CREATE INDEX idx_request_logs_endpoint_timestamp ON request_logs(endpoint_id, timestamp);
+CREATE INDEX idx_request_logs_user_endpoint_timestamp ON request_logs(user_id, endpoint_id, timestamp);
+CREATE INDEX idx_request_logs_status_code ON request_logs(status_code);
+CREATE INDEX idx_request_logs_api_key ON request_logs(api_key_id, timestamp) WHERE api_key_id IS NOT NULL;
@@ -0,0 +1,64 @@
+-- Double-entry balance ledger
+-- Every change to a user's balance is posted as a ledger transaction: the user's entry
+-- plus an opposite entry on the platform account the funds moved from or to, so the
+-- entries of a transaction always sum to zero. Deposits and withdrawals move funds
+-- against the treasury, charges and refunds against revenue, and administrator
+-- corrections against adjustments. Platform entries carry no running balance, which
+-- would serialize every charge on one row; a platform account's balance is the sum of
+-- its entries.
+
+ALTER TYPE ledger_entry_type ADD VALUE 'adjustment';
+
+CREATE TYPE platform_account AS ENUM ('treasury', 'revenue', 'adjustments');
+
+ALTER TABLE balance_ledger
+    ALTER COLUMN user_id DROP NOT NULL,
+    ALTER COLUMN balance_after DROP NOT NULL,
+    ADD COLUMN account platform_account,
+    ADD COLUMN transaction_id UUID;
+
+-- Entries so far stood alone: each becomes its own transaction, balanced against the
+-- platform account it would have been posted to
+ALTER TABLE balance_ledger DISABLE TRIGGER balance_ledger_append_only;
+UPDATE balance_ledger SET transaction_id = id;
+ALTER TABLE balance_ledger ENABLE TRIGGER balance_ledger_append_only;
+
+INSERT INTO balance_ledger (transaction_id, account, entry_type, amount, reference, created_at)
+SELECT transaction_id,
+       CASE WHEN entry_type IN ('charge', 'refund') THEN 'revenue' ELSE 'treasury' END::platform_account,
+       entry_type, -amount, reference, created_at
+FROM balance_ledger;
+
+ALTER TABLE balance_ledger
+    ALTER COLUMN transaction_id SET NOT NULL,
+    ADD CONSTRAINT balance_ledger_one_account CHECK ((user_id IS NULL) <> (account IS NULL)),
+    ADD CONSTRAINT balance_ledger_user_balance CHECK (user_id IS NULL OR balance_after IS NOT NULL);
+
+CREATE INDEX idx_balance_ledger_transaction_id ON balance_ledger(transaction_id);
+
+CREATE OR REPLACE FUNCTION check_ledger_transaction_balanced()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF (SELECT SUM(amount) FROM balance_ledger WHERE transaction_id = NEW.transaction_id) <> 0 THEN
+        RAISE EXCEPTION 'ledger transaction % does not balance', NEW.transaction_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
+
+-- Checked at commit, once every entry of the transaction has been posted
+CREATE CONSTRAINT TRIGGER balance_ledger_balanced AFTER INSERT ON balance_ledger
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION check_ledger_transaction_balanced();
+
+-- Platform entries are never removed; a deleted user's entries still go with them,
+-- leaving the platform side as the record of what moved
+CREATE OR REPLACE FUNCTION reject_ledger_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND OLD.user_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = OLD.user_id) THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'balance_ledger is append-only';
+END;
+$$ language 'plpgsql';
@@ -0,0 +1,6 @@
+-- Where audited actions came from, as a SHA-256 hash of the client address
+-- NULL for entries written before it was recorded and for system actions
+
+ALTER TABLE audit_log ADD COLUMN ip_address_hash VARCHAR(64);
+
+CREATE INDEX idx_audit_log_actor_created_at ON audit_log(actor_id, created_at);
@@ -0,0 +1,8 @@
+-- Billing records move from pending to processing while their usage is submitted
+-- on-chain, then to completed or failed. updated_at tells a record still being billed
+-- from one left processing by a billing run that was interrupted.
+
+ALTER TABLE billing_records ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE billing_records SET updated_at = COALESCE(processed_at, created_at);
+
+CREATE INDEX idx_billing_records_status_updated_at ON billing_records(status, updated_at);
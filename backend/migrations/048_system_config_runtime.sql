@@ -0,0 +1,18 @@
+-- Runtime settings are changed by admins through the API and read by the gateway
+-- without a redeploy. Seeded defaults have no updater, and a setting outlives the
+-- admin who last changed it.
+
+ALTER TABLE system_config ALTER COLUMN updated_by DROP NOT NULL;
+ALTER TABLE system_config DROP CONSTRAINT system_config_updated_by_fkey;
+ALTER TABLE system_config ADD CONSTRAINT system_config_updated_by_fkey
+    FOREIGN KEY (updated_by) REFERENCES users(id) ON DELETE SET NULL;
+UPDATE system_config SET updated_by = NULL
+WHERE updated_by = '00000000-0000-0000-0000-000000000000';
+
+UPDATE system_config
+SET description = 'Requests allowed per rate limit window on endpoints without their own limit'
+WHERE key = 'default_rate_limit';
+
+INSERT INTO system_config (key, value, description) VALUES
+('maintenance_mode', 'false', 'Reject proxied requests with 503 while true')
+ON CONFLICT (key) DO NOTHING;
@@ -0,0 +1,10 @@
+-- Gateways without Redis persist each user-endpoint pair's current rate limit window
+-- so a restart does not hand every consumer a fresh one. One row per window; rows
+-- are deleted once window_end has passed.
+
+ALTER TABLE rate_limit_entries ADD COLUMN window_end TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE rate_limit_entries ALTER COLUMN window_end DROP DEFAULT;
+
+DROP INDEX idx_rate_limit_entries_user_endpoint;
+CREATE UNIQUE INDEX idx_rate_limit_entries_window ON rate_limit_entries(user_id, endpoint_id, window_start);
+CREATE INDEX idx_rate_limit_entries_window_end ON rate_limit_entries(window_end);
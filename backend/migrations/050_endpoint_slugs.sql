@@ -0,0 +1,13 @@
+-- Public endpoint slugs
+-- Endpoint names only need to be unique among an owner's endpoints. Proxied calls are
+-- routed by a slug made from the owner and the name, unique across all owners.
+-- Existing endpoints keep their name as their slug so consumers' URLs keep working.
+
+ALTER TABLE api_endpoints ADD COLUMN slug VARCHAR(255);
+UPDATE api_endpoints SET slug = name;
+ALTER TABLE api_endpoints ALTER COLUMN slug SET NOT NULL;
+
+DROP INDEX idx_api_endpoints_live_name;
+CREATE UNIQUE INDEX idx_api_endpoints_live_owner_name ON api_endpoints(owner_id, name) WHERE deleted_at IS NULL;
+CREATE UNIQUE INDEX idx_api_endpoints_live_slug ON api_endpoints(slug) WHERE deleted_at IS NULL;
+CREATE INDEX idx_api_endpoints_slug ON api_endpoints(slug);
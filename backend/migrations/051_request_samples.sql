@@ -0,0 +1,27 @@
+-- Request samples
+-- Endpoints can opt into keeping a redacted copy of some of their calls for debugging.
+-- Samples are short-lived: the retention job deletes them after a few days.
+
+ALTER TABLE api_endpoints
+    ADD COLUMN capture_samples BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN sample_rate DOUBLE PRECISION NOT NULL DEFAULT 1.0, -- fraction of calls sampled
+    ADD COLUMN sample_max_body_bytes INTEGER NOT NULL DEFAULT 4096; -- bytes of each body kept
+
+CREATE TABLE request_samples (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    endpoint_id UUID NOT NULL REFERENCES api_endpoints(id) ON DELETE CASCADE,
+    request_id VARCHAR(255) NOT NULL, -- matches request_logs.request_id
+    method VARCHAR(10) NOT NULL,
+    path TEXT NOT NULL, -- credentials in the query redacted
+    request_headers JSONB NOT NULL DEFAULT '{}', -- sensitive values redacted
+    request_body TEXT NOT NULL,
+    request_truncated BOOLEAN NOT NULL DEFAULT false,
+    status_code INTEGER NOT NULL,
+    response_headers JSONB NOT NULL DEFAULT '{}',
+    response_body TEXT NOT NULL,
+    response_truncated BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_request_samples_request ON request_samples(endpoint_id, request_id);
+CREATE INDEX idx_request_samples_created_at ON request_samples(created_at);
@@ -0,0 +1,22 @@
+-- Contract event processing
+-- Events of the billing and payments contracts are applied once their blocks are
+-- confirmed. Each event is recorded in the same transaction that applies it, so an
+-- event is never applied twice, and the last block processed is kept so a restart
+-- resumes where it stopped.
+
+CREATE TABLE chain_event_cursors (
+    name VARCHAR(64) PRIMARY KEY,
+    last_block BIGINT NOT NULL, -- last block whose events have all been applied
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE chain_events (
+    transaction_hash VARCHAR(66) NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    event_name VARCHAR(64) NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (transaction_hash, log_index)
+);
+
+CREATE INDEX idx_chain_events_block_number ON chain_events(block_number);
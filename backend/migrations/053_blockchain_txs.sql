@@ -0,0 +1,30 @@
+-- Persistent blockchain transaction queue
+-- Operations such as billing submissions are written here before their transaction is
+-- sent, so a restart never loses one. The background worker sends pending entries,
+-- records the hash before waiting for confirmation, and resends failed entries with
+-- backoff until they run out of attempts.
+
+CREATE TYPE blockchain_tx_status AS ENUM ('pending', 'submitted', 'confirmed', 'failed', 'abandoned');
+
+CREATE TABLE blockchain_txs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    operation VARCHAR(50) NOT NULL,
+    payload JSONB NOT NULL,
+    status blockchain_tx_status NOT NULL DEFAULT 'pending',
+    tx_hash VARCHAR(66), -- transaction of the latest attempt
+    block_number BIGINT,
+    gas_used TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_blockchain_txs_due ON blockchain_txs(next_attempt_at) WHERE status IN ('pending', 'failed');
+CREATE INDEX idx_blockchain_txs_submitted ON blockchain_txs(updated_at) WHERE status = 'submitted';
+
+-- Usage queued for billing stays pending until the entry carrying it is confirmed
+ALTER TABLE usage_records ADD COLUMN blockchain_tx_id UUID REFERENCES blockchain_txs(id);
+
+CREATE INDEX idx_usage_records_blockchain_tx_id ON usage_records(blockchain_tx_id) WHERE blockchain_tx_id IS NOT NULL;
@@ -0,0 +1,28 @@
+-- On-chain balance reconciliation
+-- Each user's ledger balance is compared with the billing contract's balance for their
+-- wallet, less the usage charged in the ledger but not yet billed on-chain. Drifts
+-- within the configured threshold are corrected with an adjustment entry referencing
+-- the report; larger ones are flagged for an administrator to review.
+
+CREATE TYPE reconciliation_status AS ENUM ('corrected', 'flagged', 'resolved');
+
+CREATE TABLE reconciliation_reports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address VARCHAR(42) NOT NULL,
+    block_number BIGINT NOT NULL, -- block the contract balance was read at
+    chain_balance NUMERIC NOT NULL,
+    pending_charges NUMERIC NOT NULL,
+    ledger_balance NUMERIC NOT NULL,
+    drift NUMERIC NOT NULL, -- chain balance less pending charges, less the ledger balance
+    status reconciliation_status NOT NULL,
+    resolved_by UUID REFERENCES users(id),
+    resolution_note TEXT,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- A user has at most one flagged drift awaiting review, refreshed by later runs
+CREATE UNIQUE INDEX idx_reconciliation_reports_flagged ON reconciliation_reports(user_id) WHERE status = 'flagged';
+CREATE INDEX idx_reconciliation_reports_status_created ON reconciliation_reports(status, created_at DESC);
@@ -0,0 +1,11 @@
+-- Multi-chain deployments
+-- Payments are accepted on every configured chain while usage is billed on the first.
+-- Billed usage, billing records and deposits record the chain their transaction was
+-- on, and contract events the chain they were emitted on. Rows from before are left
+-- without a chain, which was then the only one configured.
+
+ALTER TABLE usage_records ADD COLUMN chain_id BIGINT;
+ALTER TABLE billing_records ADD COLUMN chain_id BIGINT;
+ALTER TABLE payment_transactions ADD COLUMN chain_id BIGINT;
+ALTER TABLE chain_events ADD COLUMN chain_id BIGINT;
+
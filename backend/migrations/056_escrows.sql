@@ -0,0 +1,30 @@
+-- Escrows opened through the API
+-- An escrow is funded from its creator's ledger balance: the amount is debited when the
+-- escrow is recorded, before the payments contract is called, and credited back if the
+-- contract call fails. The contract's id for the escrow is kept once its creation is
+-- confirmed, so it can be released and looked up on-chain.
+
+ALTER TYPE ledger_entry_type ADD VALUE 'escrow';
+
+CREATE TYPE escrow_status AS ENUM ('pending', 'active', 'releasing', 'released', 'failed');
+
+CREATE TABLE escrows (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    recipient_address VARCHAR(42) NOT NULL,
+    amount NUMERIC NOT NULL CHECK (amount > 0),
+    release_delay_secs BIGINT NOT NULL CHECK (release_delay_secs >= 0),
+    description TEXT NOT NULL,
+    status escrow_status NOT NULL DEFAULT 'pending',
+    chain_id BIGINT NOT NULL,
+    escrow_id NUMERIC(78, 0), -- the payments contract's id, once creation is confirmed
+    transaction_hash VARCHAR(66),
+    release_transaction_hash VARCHAR(66),
+    error_message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    released_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_escrows_user_created ON escrows(user_id, created_at DESC);
+CREATE UNIQUE INDEX idx_escrows_chain_escrow ON escrows(chain_id, escrow_id) WHERE escrow_id IS NOT NULL;
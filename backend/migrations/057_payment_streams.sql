@@ -0,0 +1,33 @@
+-- Payment streams opened through the API
+-- A stream pays its recipient continuously over its duration. It is funded from the
+-- payer's ledger balance: the total is debited when the stream is recorded, before the
+-- payments contract is called, and credited back if the contract call fails. Streams
+-- are mirrored here so they can be listed without reading the contract; what a
+-- recipient can claim is read from the contract when a single stream is looked up.
+
+ALTER TYPE ledger_entry_type ADD VALUE 'stream';
+
+CREATE TYPE payment_stream_status AS ENUM ('pending', 'active', 'failed');
+
+CREATE TABLE payment_streams (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    payer_id UUID NOT NULL REFERENCES users(id),
+    recipient_address VARCHAR(42) NOT NULL,
+    total_amount NUMERIC NOT NULL CHECK (total_amount > 0),
+    claimed_amount NUMERIC NOT NULL DEFAULT 0,
+    duration_secs BIGINT NOT NULL CHECK (duration_secs > 0),
+    description TEXT NOT NULL,
+    status payment_stream_status NOT NULL DEFAULT 'pending',
+    chain_id BIGINT NOT NULL,
+    stream_id NUMERIC(78, 0), -- the payments contract's id, once creation is confirmed
+    transaction_hash VARCHAR(66),
+    error_message TEXT,
+    started_at TIMESTAMPTZ,
+    last_claimed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_payment_streams_payer_created ON payment_streams(payer_id, created_at DESC);
+CREATE INDEX idx_payment_streams_recipient_created ON payment_streams(LOWER(recipient_address), created_at DESC);
+CREATE UNIQUE INDEX idx_payment_streams_chain_stream ON payment_streams(chain_id, stream_id) WHERE stream_id IS NOT NULL;
@@ -0,0 +1,10 @@
+-- Wallet addresses stored in EIP-55 checksummed form
+-- New accounts store their wallet checksummed, while earlier ones keep the lowercase
+-- form they were registered with, so wallets are matched case-insensitively and kept
+-- unique regardless of case.
+
+DROP INDEX idx_users_live_wallet_address;
+CREATE UNIQUE INDEX idx_users_live_wallet_address ON users(LOWER(wallet_address)) WHERE deleted_at IS NULL;
+
+DROP INDEX idx_users_wallet_address;
+CREATE INDEX idx_users_wallet_address ON users(LOWER(wallet_address));
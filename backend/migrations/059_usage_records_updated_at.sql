@@ -0,0 +1,9 @@
+-- Usage record modification times
+-- Usage records track when they last changed as they move through billing. Existing
+-- records start from when they were recorded.
+
+ALTER TABLE usage_records ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE usage_records SET updated_at = timestamp;
+
+CREATE TRIGGER update_usage_records_updated_at BEFORE UPDATE ON usage_records
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,6 @@
+-- One log entry per recorded call
+-- A call whose write is retried after its first attempt did commit must not be logged
+-- or billed twice. Retries carry the time the call was first recorded, which also
+-- picks its partition, so the pair identifies the entry.
+
+CREATE UNIQUE INDEX idx_request_logs_request ON request_logs(request_id, timestamp);
@@ -0,0 +1,8 @@
+-- Deposits reported by their payer
+-- A user may report a deposit transfer before its event is applied; the report stays
+-- pending until then, when it is confirmed with the amount actually credited. Each
+-- user reports a transfer once.
+
+CREATE UNIQUE INDEX idx_payment_transactions_reported_deposit
+    ON payment_transactions(user_id, chain_id, transaction_hash)
+    WHERE transaction_type = 'deposit' AND status = 'pending';
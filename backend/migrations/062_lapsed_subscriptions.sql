@@ -0,0 +1,5 @@
+-- Lapsed subscriptions
+-- A subscription whose renewal the consumer's balance cannot cover lapses at the end
+-- of its period instead of being charged.
+
+ALTER TYPE subscription_status ADD VALUE 'lapsed';
@@ -0,0 +1,59 @@
+//! Ethereum address validation for AugustCredits
+//!
+//! Addresses are 0x-prefixed 20-byte hex. Mixed-case addresses must carry a valid
+//! EIP-55 checksum, so a mistyped character is refused instead of registering a wallet
+//! nobody controls or paying out to one; all-lowercase and all-uppercase addresses
+//! carry no checksum and are accepted as they are. Addresses are stored checksummed.
+
+use crate::error::{AppError, AppResult};
+use ethers::{types::Address, utils::to_checksum};
+
+/// Parses an address, describing what is wrong with it otherwise
+pub fn parse(value: &str) -> Result<Address, String> {
+    let hex = value.strip_prefix("0x")
+        .ok_or_else(|| format!("{} must start with 0x", value))?;
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} must be 40 hex digits after the 0x", value));
+    }
+    let address: Address = value.parse()
+        .map_err(|_| format!("{} is not an address", value))?;
+
+    let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && checksummed(address) != value {
+        return Err(format!("{} has an invalid EIP-55 checksum", value));
+    }
+    Ok(address)
+}
+
+/// Parses the address given as a request's `field`
+pub fn parse_field(field: &str, value: &str) -> AppResult<Address> {
+    parse(value).map_err(|reason| AppError::Validation(format!("Invalid {}: {}", field, reason)))
+}
+
+/// The address in EIP-55 checksummed form, as it is stored
+pub fn checksummed(address: Address) -> String {
+    to_checksum(&address, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that checksums are verified on mixed-case addresses only
+    #[test]
+    fn test_parse_address() {
+        let address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(checksummed(parse(address).unwrap()), address);
+        assert!(parse(&address.to_lowercase()).is_ok());
+        assert!(parse(&format!("0x{}", address[2..].to_uppercase())).is_ok());
+
+        // One character's case flipped breaks the checksum
+        assert!(parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").unwrap_err().contains("checksum"));
+        assert!(parse("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+        assert!(parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").is_err());
+        assert!(parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeZ").is_err());
+
+        let error = parse_field("destination_address", "0x123").unwrap_err();
+        assert!(matches!(error, AppError::Validation(msg) if msg.starts_with("Invalid destination_address")));
+    }
+}
@@ -0,0 +1,240 @@
+//! Usage spike alerts for AugustCredits
+//!
+//! Background task that compares each user's trailing-hour request rate to every
+//! endpoint against their usual hourly rate from `daily_stats`, and raises an alert
+//! when it spikes past the configured multiple, as a leaked API key or a runaway
+//! client would. Alerts are resolved automatically once the rate falls back, and a
+//! cool-down keeps a flapping rate from raising a new alert on every check.
+
+use crate::{
+    config::{AlertConfig, Config},
+    database::Database,
+    models::{EndpointRequestRate, UsageAlert},
+};
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Webhook event sent when a usage spike alert is raised
+pub const SPIKE_DETECTED_EVENT: &str = "usage.spike_detected";
+/// Webhook event sent when a usage spike alert resolves
+pub const SPIKE_RESOLVED_EVENT: &str = "usage.spike_resolved";
+
+/// Alerts raised and resolved by one check
+#[derive(Debug, Default)]
+pub struct AlertRun {
+    pub raised: Vec<UsageAlert>,
+    pub resolved: Vec<UsageAlert>,
+}
+
+/// Watches request rates and maintains usage spike alerts
+#[derive(Clone)]
+pub struct UsageAlerter {
+    database: Arc<Database>,
+    config: AlertConfig,
+    webhooks: bool,
+}
+
+impl UsageAlerter {
+    /// Creates an alerter, queueing webhook events when webhooks are enabled
+    pub fn new(config: &Config, database: Arc<Database>) -> Self {
+        Self {
+            database,
+            config: config.alerts.clone(),
+            webhooks: config.is_feature_enabled("webhooks"),
+        }
+    }
+
+    /// Runs checks on the configured interval until the process exits
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check_all(Utc::now()).await {
+                    error!("Usage alert check failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Raises alerts for spiking request rates and resolves those that have calmed down
+    pub async fn check_all(&self, now: DateTime<Utc>) -> Result<AlertRun> {
+        let today = now.date_naive();
+        let baseline_start = today - ChronoDuration::days(self.config.baseline_days as i64);
+        self.database.backfill_daily_stats(baseline_start, today).await?;
+
+        let rates = self.database
+            .get_endpoint_request_rates(now - ChronoDuration::hours(1), today, self.config.baseline_days)
+            .await?;
+        let mut spiking: HashMap<(Uuid, Uuid), EndpointRequestRate> = rates
+            .into_iter()
+            .filter(|rate| is_spike(rate, &self.config))
+            .map(|rate| ((rate.user_id, rate.endpoint_id), rate))
+            .collect();
+
+        let mut run = AlertRun::default();
+        for alert in self.database.list_active_usage_alerts().await? {
+            match spiking.remove(&(alert.user_id, alert.endpoint_id)) {
+                Some(rate) => self.database.touch_usage_alert(alert.id, rate.hourly_requests, now).await?,
+                None => {
+                    if let Some(resolved) = self.database.resolve_usage_alert(alert.id, now).await? {
+                        info!("Usage spike on endpoint {} for user {} resolved", resolved.endpoint_name, resolved.user_id);
+                        self.notify(SPIKE_RESOLVED_EVENT, &resolved).await;
+                        run.resolved.push(resolved);
+                    }
+                }
+            }
+        }
+
+        let cooldown_start = now - ChronoDuration::seconds(self.config.cooldown_secs as i64);
+        for rate in spiking.values() {
+            if let Some(alert) = self.database.raise_usage_alert(rate, now, cooldown_start).await? {
+                warn!(
+                    "Usage spike on endpoint {} for user {}: {} requests in the last hour, usually {:.1}",
+                    alert.endpoint_name, alert.user_id, alert.hourly_requests, alert.baseline_hourly
+                );
+                self.notify(SPIKE_DETECTED_EVENT, &alert).await;
+                run.raised.push(alert);
+            }
+        }
+
+        Ok(run)
+    }
+
+    async fn notify(&self, event_type: &str, alert: &UsageAlert) {
+        if !self.webhooks {
+            return;
+        }
+        let payload = match serde_json::to_value(alert) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to encode usage alert {}: {}", alert.id, e);
+                return;
+            }
+        };
+        if let Err(e) = self.database.enqueue_webhook_event(alert.user_id, event_type, &payload).await {
+            error!("Failed to queue usage alert webhook for user {}: {}", alert.user_id, e);
+        }
+    }
+}
+
+/// Whether a trailing-hour rate is far enough above its baseline to alert on
+fn is_spike(rate: &EndpointRequestRate, config: &AlertConfig) -> bool {
+    rate.hourly_requests >= config.min_requests
+        && rate.hourly_requests as f64 > rate.baseline_hourly * config.spike_multiplier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::models::{AlertQuery, AlertStatus, CreateEndpointRequest, CreateRequestLogRequest};
+
+    fn rate(hourly_requests: i64, baseline_hourly: f64) -> EndpointRequestRate {
+        EndpointRequestRate {
+            user_id: Uuid::new_v4(),
+            endpoint_id: Uuid::new_v4(),
+            hourly_requests,
+            baseline_hourly,
+        }
+    }
+
+    /// Tests that a spike must beat both the baseline multiple and the request floor
+    #[test]
+    fn test_is_spike() {
+        let config = AlertConfig {
+            enabled: true,
+            interval_secs: 300,
+            spike_multiplier: 5.0,
+            min_requests: 100,
+            baseline_days: 7,
+            cooldown_secs: 3600,
+        };
+
+        assert!(is_spike(&rate(600, 100.0), &config));
+        assert!(!is_spike(&rate(500, 100.0), &config));
+        // New traffic with no history only alerts past the floor
+        assert!(is_spike(&rate(100, 0.0), &config));
+        assert!(!is_spike(&rate(99, 0.0), &config));
+        assert!(!is_spike(&rate(99, 1.0), &config));
+    }
+
+    /// Tests that a spike raises one alert, that the cool-down suppresses repeats and
+    /// that the alert resolves once the rate falls back
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_spike_alert_lifecycle() {
+        let mut config = test_support::config();
+        config.alerts.min_requests = 3;
+        config.alerts.spike_multiplier = 2.0;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let alerter = UsageAlerter::new(&config, database.clone());
+
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("alerts-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "1000".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        for _ in 0..3 {
+            database.create_request_log(CreateRequestLogRequest {
+                user_id: user.id,
+                endpoint_id: endpoint.id,
+                request_id: Uuid::new_v4().to_string(),
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                status_code: 200,
+                response_time_ms: 10,
+                request_size: None,
+                response_size: None,
+                ip_address_hash: "test".to_string(),
+                user_agent_hash: None,
+                cost: "1000".parse().unwrap(),
+                error_message: None,
+                upstream_target: None,
+                stream_duration_ms: None,
+                cost_breakdown: None,
+                is_sandbox: false,
+                api_key_id: None,
+            }).await.unwrap();
+        }
+
+        let now = Utc::now();
+        let run = alerter.check_all(now).await.unwrap();
+        let alert = run.raised.iter().find(|alert| alert.endpoint_id == endpoint.id).unwrap();
+        assert_eq!(alert.status, AlertStatus::Active);
+        assert_eq!(alert.hourly_requests, 3);
+
+        // Still spiking: the active alert is kept, not raised again
+        let run = alerter.check_all(now + ChronoDuration::minutes(5)).await.unwrap();
+        assert!(run.raised.iter().all(|raised| raised.endpoint_id != endpoint.id));
+
+        // An hour later the requests have aged out and the alert resolves
+        let later = now + ChronoDuration::minutes(61);
+        let run = alerter.check_all(later).await.unwrap();
+        assert!(run.resolved.iter().any(|resolved| resolved.id == alert.id));
+
+        let alerts = database.list_user_alerts(user.id, AlertQuery::default()).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].status, AlertStatus::Resolved);
+        assert!(alerts[0].resolved_at.is_some());
+
+        // Within the cool-down a fresh spike does not raise a second alert
+        let rate = EndpointRequestRate {
+            user_id: user.id,
+            endpoint_id: endpoint.id,
+            hourly_requests: 10,
+            baseline_hourly: 0.0,
+        };
+        let cooldown = ChronoDuration::seconds(config.alerts.cooldown_secs as i64);
+        assert!(database.raise_usage_alert(&rate, later, later - cooldown).await.unwrap().is_none());
+        assert!(database.raise_usage_alert(&rate, later, now + ChronoDuration::seconds(1)).await.unwrap().is_some());
+    }
+}
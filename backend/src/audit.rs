@@ -0,0 +1,146 @@
+//! Audit trail for AugustCredits
+//!
+//! Records who performed administrative and security-sensitive actions, on what, and
+//! from where. Entries are written once the action has succeeded, and a failed write
+//! is logged rather than returned, so auditing can never fail the action itself.
+//! Client addresses are resolved through trusted proxies like the IP allowlist and
+//! only their SHA-256 hash is stored.
+
+use crate::{
+    database::Database,
+    error::{AppError, AppResult},
+    models::{AuditActor, AuditLogEntry, AuditLogQuery},
+    AppState,
+};
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tracing::error;
+use uuid::Uuid;
+
+/// Writes and browses the audit log
+#[derive(Clone)]
+pub struct AuditService {
+    database: Arc<Database>,
+}
+
+impl AuditService {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    /// Appends an entry to the audit log, logging any failure instead of returning it
+    pub async fn record(
+        &self,
+        actor: &AuditActor,
+        action: &str,
+        target_type: &str,
+        target_id: Option<Uuid>,
+        metadata: serde_json::Value,
+    ) {
+        if let Err(e) = self.database.create_audit_log_entry(actor, action, target_type, target_id, metadata).await {
+            error!("Failed to record {} on {} {:?} in audit log: {}", action, target_type, target_id, e);
+        }
+    }
+
+    /// Lists entries newest first, filtered by actor, action and time range
+    pub async fn list(&self, query: AuditLogQuery) -> AppResult<Vec<AuditLogEntry>> {
+        if let (Some(start), Some(end)) = (query.start, query.end) {
+            if start >= end {
+                return Err(AppError::Validation("start must be before end".to_string()));
+            }
+        }
+        Ok(self.database.list_audit_log(&query).await?)
+    }
+}
+
+/// Hashed address of the client a request came from, if it is known
+pub struct RequestOrigin {
+    ip_address_hash: Option<String>,
+}
+
+impl RequestOrigin {
+    /// The given user acting from this origin
+    pub fn actor(&self, id: Uuid) -> AuditActor {
+        AuditActor {
+            id: Some(id),
+            ip_address_hash: self.ip_address_hash.clone(),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for RequestOrigin
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+        let ip_address_hash = peer
+            .map(|peer| app_state.auth.client_ip(peer, &parts.headers))
+            .map(hash_ip_address);
+        Ok(RequestOrigin { ip_address_hash })
+    }
+}
+
+fn hash_ip_address(ip: IpAddr) -> String {
+    hex::encode(Sha256::digest(ip.to_string().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use chrono::{Duration, Utc};
+
+    /// Tests that entries record their origin and can be filtered by actor, action
+    /// and time range
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_audit_log_filters() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let audit = AuditService::new(database.clone());
+
+        let admin = database.create_user(test_support::user_request()).await.unwrap();
+        let origin = RequestOrigin { ip_address_hash: Some(hash_ip_address("203.0.113.7".parse().unwrap())) };
+        let actor = origin.actor(admin.id);
+        let target = Uuid::new_v4();
+
+        let before = Utc::now();
+        audit.record(&actor, "admin.deactivate_user", "user", Some(target), serde_json::json!({ "reason": "abuse" })).await;
+        audit.record(&actor, "admin.delete_user", "user", Some(target), serde_json::json!({ "reason": "abuse" })).await;
+
+        let query = |action: Option<&str>| AuditLogQuery {
+            actor_id: Some(admin.id),
+            action: action.map(str::to_string),
+            start: Some(before),
+            ..Default::default()
+        };
+        let entries = audit.list(query(None)).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "admin.delete_user");
+        assert_eq!(entries[0].ip_address_hash, actor.ip_address_hash);
+        assert_eq!(entries[0].metadata["reason"], "abuse");
+
+        let entries = audit.list(query(Some("admin.deactivate_user"))).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].target_id, Some(target));
+
+        let later = AuditLogQuery { start: Some(Utc::now() + Duration::minutes(1)), ..query(None) };
+        assert!(audit.list(later).await.unwrap().is_empty());
+        let inverted = AuditLogQuery { end: Some(before - Duration::minutes(1)), ..query(None) };
+        assert!(matches!(audit.list(inverted).await, Err(AppError::Validation(_))));
+    }
+}
@@ -6,25 +6,190 @@
 
 use anyhow::{Context, Result};
 use ethers::{
-    contract::Contract,
-    core::types::*,
+    abi::RawLog,
+    contract::{Contract, EthEvent, EthLogDecode, Multicall},
+    core::types::{transaction::eip2718::TypedTransaction, *},
     middleware::SignerMiddleware,
     providers::{Http, Middleware, Provider},
     signers::{LocalWallet, Signer},
-    utils::parse_ether,
 };
+use futures::{stream::BoxStream, StreamExt};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tokio::time::sleep;
-use tracing::{debug, error, info};
+use tokio::{sync::Mutex, time::sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-use crate::config::{Config, BlockchainConfig};
+use crate::config::{BlockchainConfig, ChainConfig, Config};
 
 type SignerProvider = SignerMiddleware<Provider<Http>, LocalWallet>;
 
+/// Billing contract methods the client calls, checked against its ABI at startup
+const BILLING_METHODS: [&str; 11] = [
+    "registerUser",
+    "depositBalance",
+    "withdrawBalance",
+    "registerApiEndpoint",
+    "recordUsage",
+    "batchBilling",
+    "getUserBalance",
+    "getUserUsage",
+    "getEndpointPrice",
+    "estimateCost",
+    "canAffordUsage",
+];
+
+/// Metering contract methods the client calls
+const METERING_METHODS: [&str; 5] = [
+    "setRateLimit",
+    "checkRateLimit",
+    "logRequest",
+    "getEndpointStats",
+    "getUserStats",
+];
+
+/// Payments contract methods the client calls
+const PAYMENTS_METHODS: [&str; 6] = [
+    "createEscrow",
+    "releaseEscrow",
+    "createPaymentStream",
+    "claimFromStream",
+    "getEscrowDetails",
+    "getClaimableAmount",
+];
+
+/// Most blocks whose logs are fetched in one request, as many nodes cap the range
+const MAX_LOG_BLOCK_RANGE: u64 = 2000;
+
+/// Most balance reads aggregated into one multicall, keeping each `eth_call` small
+const MULTICALL_BATCH_SIZE: usize = 100;
+
+/// First wait between polls for a transaction receipt
+const MIN_CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Longest wait between polls for a transaction receipt
+const MAX_CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Recent blocks whose tips the EIP-1559 priority fee is based on
+const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Percentile of each block's tips sampled from the fee history
+const PRIORITY_FEE_PERCENTILE: f64 = 50.0;
+
+/// Longest wait for the node to report fees before cached ones are used instead
+const FEE_REFRESH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tip offered when no recent block had a transaction paying one
+const DEFAULT_PRIORITY_FEE_GWEI: u64 = 1;
+
+/// Selector of `isValidSignature(bytes32,bytes)`, which is also the EIP-1271 success value
+const EIP1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// Fees per gas of an EIP-1559 transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eip1559Fees {
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// Fees of the settlement chain as last read from its node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeData {
+    /// Legacy gas price suggested by the node
+    pub gas_price: U256,
+    /// Base fee of the next block, on chains with EIP-1559
+    pub base_fee_per_gas: Option<U256>,
+    /// Fees transactions are offered, on chains with EIP-1559
+    pub eip1559: Option<Eip1559Fees>,
+    pub refreshed_at: DateTime<Utc>,
+}
+
+impl FeeData {
+    /// Price a transaction is expected to pay per gas: the base fee plus tip on chains
+    /// with EIP-1559, otherwise `legacy_gas_price`, which is what legacy transactions
+    /// are sent at
+    fn expected_gas_price(&self, legacy_gas_price: U256) -> U256 {
+        match (self.base_fee_per_gas, self.eip1559) {
+            (Some(base_fee), Some(fees)) => base_fee + fees.max_priority_fee_per_gas,
+            _ => legacy_gas_price,
+        }
+    }
+}
+
+/// Whether fee data read at `refreshed_at` may still be used at `now`
+fn is_fresh(refreshed_at: DateTime<Utc>, now: DateTime<Utc>, cache_secs: u64) -> bool {
+    now.signed_duration_since(refreshed_at) < chrono::Duration::seconds(cache_secs as i64)
+}
+
+/// Wait before the next receipt poll, growing by half each time up to the maximum
+fn next_poll_interval(interval: Duration) -> Duration {
+    (interval + interval / 2).min(MAX_CONFIRMATION_POLL_INTERVAL)
+}
+
+fn gwei_to_wei(gwei: u64) -> U256 {
+    U256::from(gwei) * U256::exp10(9)
+}
+
+/// Multiplies a fee, to a thousandth of the multiplier
+fn scale_fee(fee: U256, multiplier: f64) -> U256 {
+    fee * U256::from((multiplier * 1000.0).round() as u64) / 1000
+}
+
+/// EIP-1559 fees for the next block: the median recent tip scaled by
+/// `priority_fee_multiplier`, and a max fee of the next base fee scaled by
+/// `max_fee_multiplier` plus that tip
+///
+/// Returns `None` when the chain reports no base fee, as chains without EIP-1559 do.
+fn eip1559_fees(history: &FeeHistory, max_fee_multiplier: f64, priority_fee_multiplier: f64) -> Option<Eip1559Fees> {
+    // The last entry is the base fee of the block after the newest one sampled
+    let base_fee = *history.base_fee_per_gas.last()?;
+    if base_fee.is_zero() {
+        return None;
+    }
+    
+    // Empty blocks report zero tips, which say nothing about what gets included
+    let mut tips: Vec<U256> = history.reward.iter()
+        .filter_map(|rewards| rewards.first().copied())
+        .filter(|tip| !tip.is_zero())
+        .collect();
+    tips.sort();
+    let tip = tips.get(tips.len() / 2).copied().unwrap_or_else(|| gwei_to_wei(DEFAULT_PRIORITY_FEE_GWEI));
+    
+    let max_priority_fee_per_gas = scale_fee(tip, priority_fee_multiplier);
+    Some(Eip1559Fees {
+        max_fee_per_gas: scale_fee(base_fee, max_fee_multiplier) + max_priority_fee_per_gas,
+        max_priority_fee_per_gas,
+    })
+}
+
+/// Parses a JSON contract ABI, checking that it declares every one of `methods`
+fn parse_abi(abi_json: &str, methods: &[&str]) -> Result<ethers::abi::Abi> {
+    let abi: ethers::abi::Abi = serde_json::from_str(abi_json)
+        .context("Failed to parse contract ABI")?;
+    
+    let missing: Vec<&str> = methods.iter()
+        .copied()
+        .filter(|method| abi.function(method).is_err())
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!("Contract ABI is missing methods: {}", missing.join(", "));
+    }
+    Ok(abi)
+}
+
+/// ABI-encodes an EIP-1271 `isValidSignature(bytes32,bytes)` call
+fn encode_is_valid_signature_call(hash: H256, signature: Bytes) -> Bytes {
+    let args = ethers::abi::encode(&[
+        ethers::abi::Token::FixedBytes(hash.as_bytes().to_vec()),
+        ethers::abi::Token::Bytes(signature.to_vec()),
+    ]);
+    [EIP1271_MAGIC_VALUE.as_slice(), &args].concat().into()
+}
+
 /// Result of a blockchain transaction with detailed status information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionResult {
@@ -44,6 +209,40 @@ pub enum TransactionStatus {
     Reverted(String),
 }
 
+/// A contract call queued to be sent by the transaction queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum TxOperation {
+    /// Bills many users' usage in one transaction, one entry per user and endpoint
+    BatchBilling {
+        users: Vec<Address>,
+        endpoints: Vec<String>,
+        request_counts: Vec<U256>,
+    },
+    /// Bills one user's usage of one endpoint
+    RecordUsage {
+        user: Address,
+        endpoint: String,
+        request_count: U256,
+    },
+    /// Pays out a withdrawal already debited from the user's balance
+    Payout {
+        withdrawal_id: uuid::Uuid,
+        destination: Address,
+        amount: U256,
+    },
+}
+
+impl TxOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxOperation::BatchBilling { .. } => "batch_billing",
+            TxOperation::RecordUsage { .. } => "record_usage",
+            TxOperation::Payout { .. } => "payout",
+        }
+    }
+}
+
 /// On-chain user account with balance and usage tracking
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserAccount {
@@ -86,92 +285,248 @@ pub struct EscrowDeposit {
     pub is_disputed: bool,
 }
 
-/// Main blockchain client for smart contract interactions
-pub struct BlockchainClient {
+/// Node connection and contracts of one configured chain
+struct ChainClient {
+    chain_id: u64,
+    provider: Arc<Provider<Http>>,
+    billing_contract: Contract<Provider<Http>>,
+    metering_contract: Contract<Provider<Http>>,
+    payments_contract: Contract<Provider<Http>>,
+    /// The contracts connected through the backend's wallet, unless the client is
+    /// read-only
+    writer: Option<ChainWriter>,
+    /// ERC-20 token whose transfers to the payments contract are deposits, when
+    /// balances are kept in one
+    token: Option<Address>,
+    confirmation_blocks: u64,
+}
+
+/// A chain's contracts connected through the backend's wallet, to send transactions to
+struct ChainWriter {
     provider: Arc<SignerProvider>,
-    config: BlockchainConfig,
     billing_contract: Contract<SignerProvider>,
     metering_contract: Contract<SignerProvider>,
     payments_contract: Contract<SignerProvider>,
-    chain_id: u64,
+}
+
+/// Returned by every method that sends a transaction when no private key is configured
+#[derive(Debug, thiserror::Error)]
+#[error("Blockchain client is in read-only mode: no private key is configured")]
+pub struct ReadOnlyMode;
+
+/// Whether the client can send transactions, as reported by health checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockchainMode {
+    ReadWrite,
+    /// No private key is configured, so contracts are read but no transactions sent
+    ReadOnly,
+}
+
+/// Main blockchain client for smart contract interactions
+///
+/// Holds a connection to every configured chain. Transactions and contract reads go
+/// to the settlement chain, the first configured, while deposits are followed and
+/// balances read on each chain by its id. Without a private key the client is
+/// read-only: reads work as usual and every transaction fails with [`ReadOnlyMode`].
+pub struct BlockchainClient {
+    /// Configured chains, the settlement chain first
+    chains: Vec<ChainClient>,
+    config: BlockchainConfig,
+    /// Cancelled on shutdown, ending waits for confirmation
+    shutdown: CancellationToken,
+    /// Settlement chain fees last read, reused for `gas_price_cache_secs`
+    fee_cache: Mutex<Option<FeeData>>,
 }
 
 impl BlockchainClient {
-    /// Creates a new blockchain client with contract connections
+    /// Creates a new blockchain client with contract connections, read-only when no
+    /// private key is configured
+    ///
+    /// Fails if any chain's RPC node reports a different chain id than configured,
+    /// as transactions signed for the configured chain would be rejected by it.
     pub async fn new(config: &Config) -> Result<Self> {
-        let provider = Provider::<Http>::try_from(&config.blockchain.rpc_url)
-            .context("Failed to create HTTP provider")?;
-        
-        let wallet: LocalWallet = config.blockchain.private_key
-            .parse()
-            .context("Invalid private key")?;
-        
-        let wallet = wallet.with_chain_id(config.blockchain.chain_id);
-        let provider = Arc::new(SignerMiddleware::new(provider, wallet));
-        
-        // Load contract ABIs and create contract instances
-        // TODO: Uncomment when ABI files are available
-        // let billing_contract = Self::load_contract(
-        //     &provider,
-        //     &config.blockchain.billing_contract_address,
-        //     include_str!("../contracts/abi/AugustCreditsBilling.json"),
-        // ).await?;
-        // 
-        // let metering_contract = Self::load_contract(
-        //     &provider,
-        //     &config.blockchain.metering_contract_address,
-        //     include_str!("../contracts/abi/AugustCreditsMetering.json"),
-        // ).await?;
-        // 
-        // let payments_contract = Self::load_contract(
-        //     &provider,
-        //     &config.blockchain.payments_contract_address,
-        //     include_str!("../contracts/abi/AugustCreditsPayments.json"),
-        // ).await?;
-
-        // Placeholder contracts
-        let abi: ethers::abi::Abi = serde_json::from_str("[]").unwrap();
-        let billing_contract = Contract::new(Address::zero(), abi.clone(), provider.clone());
-        let metering_contract = Contract::new(Address::zero(), abi.clone(), provider.clone());
-        let payments_contract = Contract::new(Address::zero(), abi, provider.clone());
+        let client = Self::build(config)?;
+        client.verify_chain_ids().await?;
+        Ok(client)
+    }
+    
+    /// Creates a client without asking the nodes which chain they are on, for tests
+    /// that never reach them
+    #[cfg(test)]
+    pub fn new_lazy(config: &Config) -> Result<Self> {
+        Self::build(config)
+    }
+    
+    /// Connects to every configured chain's node and loads its contracts
+    fn build(config: &Config) -> Result<Self> {
+        let wallet: Option<LocalWallet> = config.blockchain.private_key.as_deref()
+            .map(|key| key.parse().context("Invalid private key"))
+            .transpose()?;
+        if wallet.is_none() {
+            warn!("No private key configured, the blockchain client is read-only");
+        }
+        
+        let chains = config.blockchain.chains.iter()
+            .map(|chain| Self::connect(&config.blockchain, chain, wallet.clone())
+                .with_context(|| format!("Failed to connect to chain {}", chain.chain_id)))
+            .collect::<Result<_>>()?;
         
         Ok(Self {
-            provider,
+            chains,
             config: config.blockchain.clone(),
+            shutdown: CancellationToken::new(),
+            fee_cache: Mutex::new(None),
+        })
+    }
+    
+    /// Connects to a chain's node and loads its contracts
+    fn connect(config: &BlockchainConfig, chain: &ChainConfig, wallet: Option<LocalWallet>) -> Result<ChainClient> {
+        let provider = Arc::new(Provider::<Http>::try_from(&chain.rpc_url)
+            .context("Failed to create HTTP provider")?);
+        
+        let billing_contract = Self::load_contract(
+            &provider,
+            &chain.billing_contract_address,
+            &config.billing_abi_path,
+            &BILLING_METHODS,
+        ).context("Failed to load billing contract")?;
+        
+        let metering_contract = Self::load_contract(
+            &provider,
+            &chain.metering_contract_address,
+            &config.metering_abi_path,
+            &METERING_METHODS,
+        ).context("Failed to load metering contract")?;
+        
+        let payments_contract = Self::load_contract(
+            &provider,
+            &chain.payments_contract_address,
+            &config.payments_abi_path,
+            &PAYMENTS_METHODS,
+        ).context("Failed to load payments contract")?;
+        
+        let token = chain.token_address.as_deref()
+            .map(|address| address.parse::<Address>())
+            .transpose()
+            .context("Invalid token contract address")?;
+        
+        let writer = wallet.map(|wallet| {
+            let signer = Arc::new(SignerMiddleware::new((*provider).clone(), wallet.with_chain_id(chain.chain_id)));
+            ChainWriter {
+                billing_contract: billing_contract.connect(signer.clone()),
+                metering_contract: metering_contract.connect(signer.clone()),
+                payments_contract: payments_contract.connect(signer.clone()),
+                provider: signer,
+            }
+        });
+        
+        Ok(ChainClient {
+            chain_id: chain.chain_id,
+            provider,
             billing_contract,
             metering_contract,
             payments_contract,
-            chain_id: config.blockchain.chain_id,
+            writer,
+            token,
+            confirmation_blocks: chain.confirmation_blocks,
         })
     }
     
-    /// Loads a smart contract instance from address and ABI
-    async fn load_contract(
-        provider: &Arc<SignerProvider>,
+    /// Checks that each chain's RPC node is on the chain it is configured as
+    async fn verify_chain_ids(&self) -> Result<()> {
+        for chain in &self.chains {
+            let reported = chain.provider.get_chainid().await
+                .with_context(|| format!("Failed to read the chain id of chain {}'s RPC node", chain.chain_id))?;
+            check_chain_id(chain.chain_id, reported)?;
+            info!("Verified RPC node is on chain {}", chain.chain_id);
+        }
+        Ok(())
+    }
+    
+    /// Whether transactions can be sent, or the client is read-only
+    pub fn mode(&self) -> BlockchainMode {
+        match self.settlement().writer {
+            Some(_) => BlockchainMode::ReadWrite,
+            None => BlockchainMode::ReadOnly,
+        }
+    }
+    
+    /// Address of the wallet the backend signs transactions with
+    pub fn wallet_address(&self) -> Result<Address> {
+        Ok(self.settlement().writer()?.provider.address())
+    }
+    
+    /// The chain transactions are sent to and contract reads go to
+    fn settlement(&self) -> &ChainClient {
+        &self.chains[0]
+    }
+    
+    /// The configured chain with `chain_id`
+    fn chain(&self, chain_id: u64) -> Result<&ChainClient> {
+        self.chains.iter()
+            .find(|chain| chain.chain_id == chain_id)
+            .with_context(|| format!("Chain {} is not configured", chain_id))
+    }
+    
+    /// Stops waiting for confirmations once `shutdown` is cancelled
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+    
+    /// Loads a smart contract instance from its address and the ABI file at `abi_path`,
+    /// failing if the ABI lacks any of the `methods` the client calls
+    fn load_contract(
+        provider: &Arc<Provider<Http>>,
         address: &str,
-        abi_json: &str,
-    ) -> Result<Contract<SignerProvider>> {
+        abi_path: &str,
+        methods: &[&str],
+    ) -> Result<Contract<Provider<Http>>> {
         let address: Address = address.parse().context("Invalid contract address")?;
-        let abi: ethers::abi::Abi = serde_json::from_str(abi_json)
-            .context("Failed to parse contract ABI")?;
+        let abi_json = std::fs::read_to_string(abi_path)
+            .with_context(|| format!("Failed to read contract ABI {}", abi_path))?;
+        let abi = parse_abi(&abi_json, methods)
+            .with_context(|| format!("Invalid contract ABI {}", abi_path))?;
         
         Ok(Contract::new(address, abi, provider.clone()))
     }
     
-    /// Verifies blockchain connectivity and contract availability
-    pub async fn health_check(&self) -> Result<()> {
-        let block_number = self.provider.get_block_number().await
-            .context("Failed to get latest block number")?;
-        
-        debug!("Latest block number: {}", block_number);
-        Ok(())
+    /// Checks connectivity to each configured chain's node, and that it is still on
+    /// the configured chain
+    pub async fn health_check(&self) -> Vec<ChainHealth> {
+        let checks = self.chains.iter().map(|chain| async move {
+            let unhealthy = ChainHealth { chain_id: chain.chain_id, verified_chain_id: None, healthy: false, latest_block: None };
+            match futures::try_join!(chain.provider.get_block_number(), chain.provider.get_chainid()) {
+                Ok((block_number, reported)) => match check_chain_id(chain.chain_id, reported) {
+                    Ok(chain_id) => {
+                        debug!("Latest block number on chain {}: {}", chain.chain_id, block_number);
+                        ChainHealth {
+                            verified_chain_id: Some(chain_id),
+                            healthy: true,
+                            latest_block: Some(block_number.as_u64()),
+                            ..unhealthy
+                        }
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        unhealthy
+                    }
+                },
+                Err(e) => {
+                    warn!("Chain {} is unreachable: {}", chain.chain_id, e);
+                    unhealthy
+                }
+            }
+        });
+        futures::future::join_all(checks).await
     }
     
     // Billing Contract Methods
     
     /// Registers a new user on the blockchain with their API key
     pub async fn register_user(&self, user_address: Address, api_key: String) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("registerUser", api_key)?
             .from(user_address);
         
@@ -180,7 +535,7 @@ impl BlockchainClient {
     
     /// Deposits funds to a user's on-chain balance
     pub async fn deposit_balance(&self, user_address: Address, amount: U256) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("depositBalance", amount)?
             .from(user_address);
         
@@ -189,7 +544,7 @@ impl BlockchainClient {
     
     /// Withdraws funds from a user's on-chain balance
     pub async fn withdraw_balance(&self, user_address: Address, amount: U256) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("withdrawBalance", amount)?
             .from(user_address);
         
@@ -203,7 +558,7 @@ impl BlockchainClient {
         name: String,
         price_per_request: U256,
     ) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("registerApiEndpoint", (name, price_per_request))?
             .from(owner_address);
         
@@ -217,7 +572,7 @@ impl BlockchainClient {
         endpoint: String,
         request_count: U256,
     ) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("recordUsage", (api_key, endpoint, request_count))?;
         
         self.execute_transaction(call).await
@@ -230,7 +585,7 @@ impl BlockchainClient {
         endpoints: Vec<String>,
         request_counts: Vec<U256>,
     ) -> Result<TransactionResult> {
-        let call = self.billing_contract
+        let call = self.settlement().writer()?.billing_contract
             .method::<_, H256>("batchBilling", (users, endpoints, request_counts))?;
         
         self.execute_transaction(call).await
@@ -238,7 +593,7 @@ impl BlockchainClient {
     
     /// Retrieves a user's current on-chain balance
     pub async fn get_user_balance(&self, user_address: Address) -> Result<U256> {
-        let balance: U256 = self.billing_contract
+        let balance: U256 = self.settlement().billing_contract
             .method("getUserBalance", user_address)?
             .call()
             .await
@@ -247,9 +602,29 @@ impl BlockchainClient {
         Ok(balance)
     }
     
+    /// Retrieves the on-chain balances of `users` on a chain as of `block`, in order, reading
+    /// them through Multicall3 in batches of [`MULTICALL_BATCH_SIZE`]
+    pub async fn get_user_balances(&self, chain_id: u64, users: &[Address], block: u64) -> Result<Vec<U256>> {
+        let chain = self.chain(chain_id)?;
+        let mut balances = Vec::with_capacity(users.len());
+        for batch in users.chunks(MULTICALL_BATCH_SIZE) {
+            let mut multicall = Multicall::new_with_chain_id(chain.provider.clone(), None, Some(chain_id))
+                .context("Multicall is not available on this chain")?
+                .block(block);
+            for user in batch {
+                multicall.add_call(chain.billing_contract.method::<_, U256>("getUserBalance", *user)?, false);
+            }
+            let results: Vec<U256> = multicall.call_array().await
+                .with_context(|| format!("Failed to get {} user balances at block {}", batch.len(), block))?;
+            balances.extend(results);
+        }
+        
+        Ok(balances)
+    }
+    
     /// Gets total usage for a user on a specific endpoint
     pub async fn get_user_usage(&self, user_address: Address, endpoint: String) -> Result<U256> {
-        let usage: U256 = self.billing_contract
+        let usage: U256 = self.settlement().billing_contract
             .method("getUserUsage", (user_address, endpoint))?
             .call()
             .await
@@ -260,7 +635,7 @@ impl BlockchainClient {
     
     /// Retrieves the current price per request for an endpoint
     pub async fn get_endpoint_price(&self, endpoint: String) -> Result<U256> {
-        let price: U256 = self.billing_contract
+        let price: U256 = self.settlement().billing_contract
             .method("getEndpointPrice", endpoint)?
             .call()
             .await
@@ -271,7 +646,7 @@ impl BlockchainClient {
     
     /// Calculates the total cost for a given number of requests
     pub async fn estimate_cost(&self, endpoint: String, request_count: U256) -> Result<U256> {
-        let cost: U256 = self.billing_contract
+        let cost: U256 = self.settlement().billing_contract
             .method("estimateCost", (endpoint, request_count))?
             .call()
             .await
@@ -287,7 +662,7 @@ impl BlockchainClient {
         endpoint: String,
         request_count: U256,
     ) -> Result<bool> {
-        let can_afford: bool = self.billing_contract
+        let can_afford: bool = self.settlement().billing_contract
             .method("canAffordUsage", (user_address, endpoint, request_count))?
             .call()
             .await
@@ -305,7 +680,7 @@ impl BlockchainClient {
         requests_per_period: U256,
         period_duration: U256,
     ) -> Result<TransactionResult> {
-        let call = self.metering_contract
+        let call = self.settlement().writer()?.metering_contract
             .method::<_, H256>("setRateLimit", (endpoint, requests_per_period, period_duration))?;
         
         self.execute_transaction(call).await
@@ -317,7 +692,7 @@ impl BlockchainClient {
         user_address: Address,
         endpoint: String,
     ) -> Result<(bool, U256, U256)> {
-        let result: (bool, U256, U256) = self.metering_contract
+        let result: (bool, U256, U256) = self.settlement().metering_contract
             .method("checkRateLimit", (user_address, endpoint))?
             .call()
             .await
@@ -336,7 +711,7 @@ impl BlockchainClient {
         status_code: u16,
         ip_hash: [u8; 32],
     ) -> Result<TransactionResult> {
-        let call = self.metering_contract
+        let call = self.settlement().writer()?.metering_contract
             .method::<_, H256>(
                 "logRequest",
                 (user_address, endpoint, request_id, response_time, status_code, ip_hash),
@@ -347,7 +722,7 @@ impl BlockchainClient {
     
     /// Retrieves comprehensive statistics for an API endpoint
     pub async fn get_endpoint_stats(&self, endpoint: String) -> Result<(U256, U256, U256, U256)> {
-        let stats: (U256, U256, U256, U256) = self.metering_contract
+        let stats: (U256, U256, U256, U256) = self.settlement().metering_contract
             .method("getEndpointStats", endpoint)?
             .call()
             .await
@@ -358,7 +733,7 @@ impl BlockchainClient {
     
     /// Gets usage statistics for a specific user
     pub async fn get_user_stats(&self, user_address: Address) -> Result<(U256, U256, U256)> {
-        let stats: (U256, U256, U256) = self.metering_contract
+        let stats: (U256, U256, U256) = self.settlement().metering_contract
             .method("getUserStats", user_address)?
             .call()
             .await
@@ -378,7 +753,7 @@ impl BlockchainClient {
         release_delay: U256,
         description: String,
     ) -> Result<TransactionResult> {
-        let call = self.payments_contract
+        let call = self.settlement().writer()?.payments_contract
             .method::<_, H256>("createEscrow", (recipient, amount, release_delay, description))?
             .from(user_address);
         
@@ -387,7 +762,7 @@ impl BlockchainClient {
     
     /// Releases funds from an escrow deposit
     pub async fn release_escrow(&self, user_address: Address, escrow_id: U256) -> Result<TransactionResult> {
-        let call = self.payments_contract
+        let call = self.settlement().writer()?.payments_contract
             .method::<_, H256>("releaseEscrow", escrow_id)?
             .from(user_address);
         
@@ -403,7 +778,7 @@ impl BlockchainClient {
         duration: U256,
         description: String,
     ) -> Result<TransactionResult> {
-        let call = self.payments_contract
+        let call = self.settlement().writer()?.payments_contract
             .method::<_, H256>("createPaymentStream", (recipient, total_amount, duration, description))?
             .from(user_address);
         
@@ -412,16 +787,51 @@ impl BlockchainClient {
     
     /// Claims available funds from a payment stream
     pub async fn claim_from_stream(&self, user_address: Address, stream_id: U256) -> Result<TransactionResult> {
-        let call = self.payments_contract
+        let call = self.settlement().writer()?.payments_contract
             .method::<_, H256>("claimFromStream", stream_id)?
             .from(user_address);
         
         self.execute_transaction(call).await
     }
     
+    /// Id of the escrow a confirmed `createEscrow` transaction opened, or `None` if
+    /// its receipt holds no `EscrowCreated` event
+    pub async fn get_created_escrow_id(&self, tx_hash: H256) -> Result<Option<U256>> {
+        let created = self.payments_receipt_event::<EscrowCreated>(tx_hash).await?;
+        Ok(created.map(|created| created.escrow_id))
+    }
+    
+    /// Id of the stream a confirmed `createPaymentStream` transaction opened, or `None`
+    /// if its receipt holds no `PaymentStreamCreated` event
+    pub async fn get_created_stream_id(&self, tx_hash: H256) -> Result<Option<U256>> {
+        let created = self.payments_receipt_event::<PaymentStreamCreated>(tx_hash).await?;
+        Ok(created.map(|created| created.stream_id))
+    }
+    
+    /// Amount a confirmed `claimFromStream` transaction paid out, or `None` if its
+    /// receipt holds no `StreamClaimed` event
+    pub async fn get_claimed_stream_amount(&self, tx_hash: H256) -> Result<Option<U256>> {
+        let claimed = self.payments_receipt_event::<StreamClaimed>(tx_hash).await?;
+        Ok(claimed.map(|claimed| claimed.amount))
+    }
+    
+    /// First event of type `E` the payments contract emitted in a transaction
+    async fn payments_receipt_event<E: EthEvent>(&self, tx_hash: H256) -> Result<Option<E>> {
+        let chain = self.settlement();
+        let receipt = chain.provider.get_transaction_receipt(tx_hash).await
+            .context("Failed to get transaction receipt")?;
+        let payments = chain.payments_contract.address();
+        
+        let event = receipt.into_iter()
+            .flat_map(|receipt| receipt.logs)
+            .filter(|log| log.address == payments && log.topics.first() == Some(&E::signature()))
+            .find_map(|log| <E as EthEvent>::decode_log(&RawLog::from(log)).ok());
+        Ok(event)
+    }
+    
     /// Retrieves detailed information about an escrow deposit
     pub async fn get_escrow_details(&self, escrow_id: U256) -> Result<(Address, Address, U256, U256, bool, bool)> {
-        let details: (Address, Address, U256, U256, bool, bool) = self.payments_contract
+        let details: (Address, Address, U256, U256, bool, bool) = self.settlement().payments_contract
             .method("getEscrowDetails", escrow_id)?
             .call()
             .await
@@ -432,7 +842,7 @@ impl BlockchainClient {
     
     /// Calculates the currently claimable amount from a payment stream
     pub async fn get_claimable_amount(&self, stream_id: U256) -> Result<U256> {
-        let amount: U256 = self.payments_contract
+        let amount: U256 = self.settlement().payments_contract
             .method("getClaimableAmount", stream_id)?
             .call()
             .await
@@ -441,6 +851,34 @@ impl BlockchainClient {
         Ok(amount)
     }
     
+    // Contract Wallet Signatures
+    
+    /// Checks a signature against an EIP-1271 smart-contract wallet
+    ///
+    /// Returns false if `wallet` has no code or the contract does not return the
+    /// EIP-1271 magic value for `hash` and `signature`.
+    pub async fn verify_contract_signature(&self, wallet: Address, hash: H256, signature: Bytes) -> Result<bool> {
+        let code = self.settlement().provider.get_code(wallet, None).await
+            .context("Failed to get wallet code")?;
+        if code.is_empty() {
+            return Ok(false);
+        }
+        
+        let call: TypedTransaction = TransactionRequest::new()
+            .to(wallet)
+            .data(encode_is_valid_signature_call(hash, signature))
+            .into();
+        
+        // Contracts that reject the signature may revert instead of returning a non-magic value
+        match self.settlement().provider.call(&call, None).await {
+            Ok(output) => Ok(output.len() >= 4 && output[..4] == EIP1271_MAGIC_VALUE),
+            Err(e) => {
+                debug!("isValidSignature call to {:?} failed: {}", wallet, e);
+                Ok(false)
+            }
+        }
+    }
+    
     // Transaction execution and monitoring
     
     /// Executes a contract transaction with retry logic and gas optimization
@@ -448,9 +886,7 @@ impl BlockchainClient {
         &self,
         call: ethers::contract::builders::ContractCall<SignerProvider, D>,
     ) -> Result<TransactionResult> {
-        let call = call
-            .gas(self.config.gas_limit)
-            .gas_price(parse_ether(self.config.gas_price_gwei)?);
+        let call = self.with_fees(call.gas(self.config.gas_limit)).await;
         
         // Execute transaction with retry logic
         for attempt in 1..=self.config.retry_attempts {
@@ -484,73 +920,236 @@ impl BlockchainClient {
         unreachable!()
     }
     
-    /// Waits for transaction confirmation with timeout handling
-    async fn wait_for_confirmation(&self, tx_hash: H256) -> Result<TransactionResult> {
-        let mut confirmations = 0;
-        let required_confirmations = self.config.confirmation_blocks;
+    /// Prices a call with EIP-1559 fees based on recent blocks, or at the configured
+    /// legacy gas price on chains without a base fee
+    async fn with_fees<D: ethers::abi::Detokenize>(
+        &self,
+        mut call: ethers::contract::builders::ContractCall<SignerProvider, D>,
+    ) -> ethers::contract::builders::ContractCall<SignerProvider, D> {
+        let fees = match self.fee_data().await {
+            Ok(fees) => fees.eip1559,
+            Err(e) => {
+                debug!("Fee data unavailable, using legacy gas pricing: {:#}", e);
+                None
+            }
+        };
+        
+        match (fees, &mut call.tx) {
+            (Some(fees), TypedTransaction::Eip1559(tx)) => {
+                tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+                call
+            }
+            _ => call.legacy().gas_price(gwei_to_wei(self.config.gas_price_gwei)),
+        }
+    }
+    
+    /// Contract call of a queued operation
+    fn operation_call(&self, operation: &TxOperation) -> Result<ethers::contract::builders::ContractCall<SignerProvider, H256>> {
+        let call = match operation {
+            TxOperation::BatchBilling { users, endpoints, request_counts } => self.settlement().writer()?.billing_contract
+                .method::<_, H256>("batchBilling", (users.clone(), endpoints.clone(), request_counts.clone()))?,
+            // Accounts are identified by wallet so API keys never end up on chain
+            TxOperation::RecordUsage { user, endpoint, request_count } => self.settlement().writer()?.billing_contract
+                .method::<_, H256>("recordUsage", (format!("{:?}", user), endpoint.clone(), *request_count))?,
+            TxOperation::Payout { destination, amount, .. } => self.settlement().writer()?.billing_contract
+                .method::<_, H256>("withdrawBalance", *amount)?
+                .from(*destination),
+        };
+        Ok(call)
+    }
+    
+    /// Sends a queued operation's transaction without waiting for it to be mined
+    pub async fn send_operation(&self, operation: &TxOperation) -> Result<H256> {
+        let call = self.with_fees(self.operation_call(operation)?.gas(self.config.gas_limit)).await;
+        
+        let pending_tx = call.send().await.context("Failed to send transaction")?;
+        info!("Transaction sent: {:?}", pending_tx.tx_hash());
+        Ok(pending_tx.tx_hash())
+    }
+    
+    /// Whether the node no longer knows a sent transaction, so it will never be mined
+    pub async fn is_dropped(&self, tx_hash: H256) -> Result<bool> {
+        let transaction = self.settlement().provider.get_transaction(tx_hash).await
+            .context("Failed to get transaction")?;
+        Ok(transaction.is_none())
+    }
+    
+    /// Waits for transaction confirmation
+    ///
+    /// The transaction is reported pending, with the confirmations seen so far, once
+    /// `confirmation_timeout_secs` pass, once `confirmation_timeout_blocks` are mined
+    /// without it, or on shutdown. Polling slows down the longer the wait.
+    pub async fn wait_for_confirmation(&self, tx_hash: H256) -> Result<TransactionResult> {
+        let required_confirmations = self.settlement().confirmation_blocks;
+        let deadline = Instant::now() + Duration::from_secs(self.config.confirmation_timeout_secs);
+        let start_block = self.settlement().provider.get_block_number().await?.as_u64();
+        let mut poll_interval = MIN_CONFIRMATION_POLL_INTERVAL;
+        let mut result = TransactionResult {
+            hash: tx_hash,
+            block_number: None,
+            gas_used: None,
+            status: TransactionStatus::Pending,
+            confirmations: 0,
+        };
         
         loop {
-            match self.provider.get_transaction_receipt(tx_hash).await? {
-                Some(receipt) => {
-                    if let Some(status) = receipt.status {
-                        if status == U64::from(0) {
-                            return Ok(TransactionResult {
-                                hash: tx_hash,
-                                block_number: receipt.block_number.map(|n| n.as_u64()),
-                                gas_used: receipt.gas_used,
-                                status: TransactionStatus::Failed,
-                                confirmations,
-                            });
-                        }
-                    }
-                    
-                    if let Some(block_number) = receipt.block_number {
-                        let latest_block = self.provider.get_block_number().await?;
-                        confirmations = latest_block.saturating_sub(block_number).as_u64();
-                        
-                        if confirmations >= required_confirmations {
-                            return Ok(TransactionResult {
-                                hash: tx_hash,
-                                block_number: Some(block_number.as_u64()),
-                                gas_used: receipt.gas_used,
-                                status: TransactionStatus::Confirmed,
-                                confirmations,
-                            });
-                        }
-                    }
+            let latest_block = self.settlement().provider.get_block_number().await?.as_u64();
+            if let Some(receipt) = self.settlement().provider.get_transaction_receipt(tx_hash).await? {
+                result.block_number = receipt.block_number.map(|n| n.as_u64());
+                result.gas_used = receipt.gas_used;
+                
+                // A reverted transaction stays reverted however deep its block gets
+                if receipt.status == Some(U64::zero()) {
+                    let block = result.block_number.map_or_else(|| "unknown".to_string(), |n| n.to_string());
+                    result.status = TransactionStatus::Reverted(format!("execution reverted in block {}", block));
+                    return Ok(result);
                 }
-                None => {
-                    // Transaction not yet mined
+                
+                if let Some(mined_in) = result.block_number {
+                    result.confirmations = latest_block.saturating_sub(mined_in);
+                    if result.confirmations >= required_confirmations {
+                        result.status = TransactionStatus::Confirmed;
+                        return Ok(result);
+                    }
                 }
             }
             
-            sleep(Duration::from_secs(2)).await;
+            let unmined_too_long = result.block_number.is_none()
+                && self.config.confirmation_timeout_blocks
+                    .is_some_and(|blocks| latest_block.saturating_sub(start_block) >= blocks);
+            if unmined_too_long || Instant::now() >= deadline {
+                debug!("Stopped waiting for {:?} after {} confirmations", tx_hash, result.confirmations);
+                return Ok(result);
+            }
+            
+            tokio::select! {
+                _ = sleep(poll_interval) => {}
+                _ = self.shutdown.cancelled() => return Ok(result),
+            }
+            poll_interval = next_poll_interval(poll_interval);
         }
     }
     
     // Event listening
     
-    // TODO: Implement proper event handling with specific event types
-    // This requires defining specific event structs that implement EthEvent
-    // pub async fn listen_to_events<F>(&self, mut _event_handler: F) -> Result<()>
-    // where
-    //     F: FnMut(ContractEvent) + Send + 'static,
-    // {
-    //     // Placeholder implementation - event listening disabled until proper event types are defined
-    //     warn!("Event listening is currently disabled - requires specific event type definitions");
-    //     Ok(())
-    // }
+    /// Latest block of a chain at least its `confirmation_blocks` deep, so a reorg
+    /// cannot undo it
+    pub async fn confirmed_block(&self, chain_id: u64) -> Result<u64> {
+        self.chain(chain_id)?.confirmed_block().await
+    }
+    
+    /// Streams a chain's billing and payments contracts' events from `from_block` on,
+    /// one batch per range of confirmed blocks, polling every `poll_interval` once
+    /// caught up
+    ///
+    /// Errors are yielded after waiting a poll interval and the range is fetched again,
+    /// so the stream only ends when dropped.
+    pub fn subscribe_events(self: Arc<Self>, chain_id: u64, from_block: u64, poll_interval: Duration) -> BoxStream<'static, Result<EventBatch>> {
+        futures::stream::unfold((self, from_block), move |(client, next)| async move {
+            loop {
+                let fetched = match client.chain(chain_id) {
+                    Ok(chain) => chain.fetch_events(next).await,
+                    Err(e) => Err(e),
+                };
+                match fetched {
+                    Ok(Some(batch)) => {
+                        let next = batch.to_block + 1;
+                        return Some((Ok(batch), (client, next)));
+                    }
+                    Ok(None) => sleep(poll_interval).await,
+                    Err(e) => {
+                        sleep(poll_interval).await;
+                        return Some((Err(e), (client, next)));
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
     
     // Utility methods
     
-    /// Returns the blockchain network chain ID
-    pub fn get_chain_id(&self) -> u64 {
-        self.chain_id
+    /// Ids of the configured chains, the settlement chain first
+    pub fn chain_ids(&self) -> Vec<u64> {
+        self.chains.iter().map(|chain| chain.chain_id).collect()
     }
     
     /// Retrieves current network gas price
     pub async fn get_gas_price(&self) -> Result<U256> {
-        self.provider.get_gas_price().await.context("Failed to get gas price")
+        Ok(self.fee_data().await?.gas_price)
+    }
+    
+    /// Fees of the settlement chain, read from its node at most once every
+    /// `gas_price_cache_secs`
+    ///
+    /// EIP-1559 fees are left out when the chain has no base fee or the node cannot
+    /// report its fee history. The cache is not locked while the node is read, and when
+    /// it fails or takes longer than [`FEE_REFRESH_TIMEOUT`] the stale fees are returned.
+    pub async fn fee_data(&self) -> Result<FeeData> {
+        let cached = self.fee_cache.lock().await.clone();
+        if let Some(fees) = cached.as_ref().filter(|fees| is_fresh(fees.refreshed_at, Utc::now(), self.config.gas_price_cache_secs)) {
+            return Ok(fees.clone());
+        }
+        
+        let refreshed = tokio::time::timeout(FEE_REFRESH_TIMEOUT, self.read_fee_data())
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out reading fees")));
+        match (refreshed, cached) {
+            (Ok(fees), _) => {
+                *self.fee_cache.lock().await = Some(fees.clone());
+                Ok(fees)
+            }
+            (Err(e), Some(stale)) => {
+                warn!("Using fees read at {} as they could not be refreshed: {:#}", stale.refreshed_at, e);
+                Ok(stale)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+    
+    /// Reads the settlement chain's current fees from its node
+    async fn read_fee_data(&self) -> Result<FeeData> {
+        let provider = &self.settlement().provider;
+        let (gas_price, history) = futures::join!(
+            provider.get_gas_price(),
+            provider.fee_history(FEE_HISTORY_BLOCKS, BlockNumber::Latest, &[PRIORITY_FEE_PERCENTILE]),
+        );
+        let gas_price = gas_price.context("Failed to get gas price")?;
+        let history = history
+            .map_err(|e| debug!("Fee history unavailable: {}", e))
+            .ok();
+        
+        let fees = FeeData {
+            gas_price,
+            base_fee_per_gas: history.as_ref()
+                .and_then(|history| history.base_fee_per_gas.last().copied())
+                .filter(|base_fee| !base_fee.is_zero()),
+            eip1559: history.as_ref()
+                .and_then(|history| eip1559_fees(history, self.config.max_fee_multiplier, self.config.priority_fee_multiplier)),
+            refreshed_at: Utc::now(),
+        };
+        Ok(fees)
+    }
+    
+    /// Current fees of the settlement chain, with what a billing batch using its whole
+    /// gas budget would cost at them
+    pub async fn gas_report(&self) -> Result<GasReport> {
+        let fees = self.fee_data().await?;
+        let batch_gas = self.config.billing_batch_gas_budget;
+        let batch_cost = fees.expected_gas_price(gwei_to_wei(self.config.gas_price_gwei)) * U256::from(batch_gas);
+        
+        Ok(GasReport {
+            chain_id: self.settlement().chain_id,
+            gas_price: fees.gas_price.to_string(),
+            base_fee_per_gas: fees.base_fee_per_gas.map(|fee| fee.to_string()),
+            max_fee_per_gas: fees.eip1559.map(|fees| fees.max_fee_per_gas.to_string()),
+            max_priority_fee_per_gas: fees.eip1559.map(|fees| fees.max_priority_fee_per_gas.to_string()),
+            refreshed_at: fees.refreshed_at,
+            cache_age_secs: Utc::now().signed_duration_since(fees.refreshed_at).num_seconds().max(0),
+            batch_gas,
+            batch_cost: batch_cost.to_string(),
+        })
     }
     
     /// Estimates gas cost for a contract call
@@ -562,33 +1161,709 @@ impl BlockchainClient {
     }
 }
 
-/// Smart contract events for real-time monitoring
-#[derive(Debug, Clone)]
+impl ChainClient {
+    /// The contracts to send transactions to, unless the client is read-only
+    fn writer(&self) -> Result<&ChainWriter> {
+        self.writer.as_ref().ok_or_else(|| ReadOnlyMode.into())
+    }
+    
+    /// Latest block at least `confirmation_blocks` deep, so a reorg cannot undo it
+    async fn confirmed_block(&self) -> Result<u64> {
+        let head = self.provider.get_block_number().await
+            .context("Failed to get latest block number")?;
+        Ok(head.as_u64().saturating_sub(self.confirmation_blocks))
+    }
+    
+    /// Fetches the events of the confirmed blocks from `from_block` on, or `None` when
+    /// there are none yet
+    async fn fetch_events(&self, from_block: u64) -> Result<Option<EventBatch>> {
+        let confirmed = self.confirmed_block().await?;
+        if from_block > confirmed {
+            return Ok(None);
+        }
+        let to_block = confirmed.min(from_block + MAX_LOG_BLOCK_RANGE - 1);
+        
+        let billing = self.billing_contract.address();
+        let payments = self.payments_contract.address();
+        let filter = Filter::new()
+            .address(vec![billing, payments])
+            .from_block(from_block)
+            .to_block(to_block);
+        let mut logs = self.provider.get_logs(&filter).await
+            .with_context(|| format!("Failed to get contract logs of blocks {}-{}", from_block, to_block))?;
+        
+        // Only the token's transfers into the payments contract, not all of its traffic
+        if let Some(token) = self.token {
+            let deposits = Filter::new()
+                .address(token)
+                .topic0(TokenTransfer::signature())
+                .topic2(H256::from(payments))
+                .from_block(from_block)
+                .to_block(to_block);
+            logs.extend(self.provider.get_logs(&deposits).await
+                .with_context(|| format!("Failed to get token deposits of blocks {}-{}", from_block, to_block))?);
+            logs.sort_by_key(|log| (log.block_number, log.log_index));
+        }
+        
+        let events = logs.iter()
+            .filter(|log| log.removed != Some(true))
+            .filter_map(|log| chain_event(log, billing, payments, self.token))
+            .collect();
+        Ok(Some(EventBatch { events, to_block }))
+    }
+}
+
+/// Whether a configured chain's node could be reached, as reported by health checks
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainHealth {
+    pub chain_id: u64,
+    /// Chain id the node reported, when it matches the configured one
+    pub verified_chain_id: Option<u64>,
+    pub healthy: bool,
+    pub latest_block: Option<u64>,
+}
+
+/// Settlement chain fees for operators timing billing runs; amounts are in wei
+#[derive(Debug, Clone, Serialize)]
+pub struct GasReport {
+    pub chain_id: u64,
+    pub gas_price: String,
+    pub base_fee_per_gas: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
+    /// When the fees were read from the node
+    pub refreshed_at: DateTime<Utc>,
+    pub cache_age_secs: i64,
+    /// Gas of a billing batch using its whole budget, the most batches are split to
+    pub batch_gas: u64,
+    /// Expected cost of such a batch at the current fees
+    pub batch_cost: String,
+}
+
+/// Checks the chain id an RPC node reported against the configured `chain_id`
+fn check_chain_id(chain_id: u64, reported: U256) -> Result<u64> {
+    if reported != U256::from(chain_id) {
+        anyhow::bail!(
+            "Chain id mismatch: chain {} is configured, but its RPC node reports chain {}; \
+             check the chain id and RPC URL configuration",
+            chain_id, reported
+        );
+    }
+    Ok(chain_id)
+}
+
+/// Opens and releases escrows on the payments contract from the backend's wallet,
+/// which funds them out of users' ledger balances
+#[async_trait::async_trait]
+pub trait EscrowContract: Send + Sync {
+    /// Opens an escrow of `amount` wei for `recipient`, returning once the transaction
+    /// settles along with the id the contract gave the escrow, when it was confirmed
+    async fn open_escrow(
+        &self,
+        recipient: Address,
+        amount: U256,
+        release_delay: U256,
+        description: String,
+    ) -> Result<(TransactionResult, Option<U256>)>;
+    
+    /// Releases an escrow to its recipient, returning once the transaction settles
+    async fn release(&self, escrow_id: U256) -> Result<TransactionResult>;
+    
+    /// Reads an escrow's state from the contract
+    async fn escrow_details(&self, escrow_id: U256) -> Result<EscrowDeposit>;
+}
+
+#[async_trait::async_trait]
+impl EscrowContract for BlockchainClient {
+    async fn open_escrow(
+        &self,
+        recipient: Address,
+        amount: U256,
+        release_delay: U256,
+        description: String,
+    ) -> Result<(TransactionResult, Option<U256>)> {
+        let result = self.create_escrow(self.wallet_address()?, recipient, amount, release_delay, description).await?;
+        let escrow_id = match result.status {
+            TransactionStatus::Confirmed => self.get_created_escrow_id(result.hash).await?,
+            _ => None,
+        };
+        Ok((result, escrow_id))
+    }
+    
+    async fn release(&self, escrow_id: U256) -> Result<TransactionResult> {
+        self.release_escrow(self.wallet_address()?, escrow_id).await
+    }
+    
+    async fn escrow_details(&self, escrow_id: U256) -> Result<EscrowDeposit> {
+        let (user, recipient, amount, release_time, is_released, is_disputed) = self.get_escrow_details(escrow_id).await?;
+        Ok(EscrowDeposit { id: escrow_id, user, recipient, amount, release_time, is_released, is_disputed })
+    }
+}
+
+/// Opens payment streams on the payments contract from the backend's wallet, which
+/// funds them out of users' ledger balances, and claims from them
+#[async_trait::async_trait]
+pub trait PaymentStreamContract: Send + Sync {
+    /// Opens a stream paying `total_amount` wei to `recipient` over `duration` seconds,
+    /// returning once the transaction settles along with the id the contract gave the
+    /// stream, when it was confirmed
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        total_amount: U256,
+        duration: U256,
+        description: String,
+    ) -> Result<(TransactionResult, Option<U256>)>;
+    
+    /// Claims what has accrued on a stream for its recipient, returning once the
+    /// transaction settles along with the amount paid out, when it was confirmed
+    async fn claim(&self, stream_id: U256) -> Result<(TransactionResult, Option<U256>)>;
+    
+    /// Amount the stream's recipient can claim now
+    async fn claimable(&self, stream_id: U256) -> Result<U256>;
+}
+
+#[async_trait::async_trait]
+impl PaymentStreamContract for BlockchainClient {
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        total_amount: U256,
+        duration: U256,
+        description: String,
+    ) -> Result<(TransactionResult, Option<U256>)> {
+        let result = self.create_payment_stream(self.wallet_address()?, recipient, total_amount, duration, description).await?;
+        let stream_id = match result.status {
+            TransactionStatus::Confirmed => self.get_created_stream_id(result.hash).await?,
+            _ => None,
+        };
+        Ok((result, stream_id))
+    }
+    
+    async fn claim(&self, stream_id: U256) -> Result<(TransactionResult, Option<U256>)> {
+        let result = self.claim_from_stream(self.wallet_address()?, stream_id).await?;
+        let amount = match result.status {
+            TransactionStatus::Confirmed => self.get_claimed_stream_amount(result.hash).await?,
+            _ => None,
+        };
+        Ok((result, amount))
+    }
+    
+    async fn claimable(&self, stream_id: U256) -> Result<U256> {
+        self.get_claimable_amount(stream_id).await
+    }
+}
+
+/// Reads users' balances on each chain's billing contract, for reconciling them with
+/// the ledger
+#[async_trait::async_trait]
+pub trait BalanceReader: Send + Sync {
+    /// Balances of `users` on a chain as of `block`, in the same order
+    async fn balances_at(&self, chain_id: u64, users: &[Address], block: u64) -> Result<Vec<U256>>;
+
+    /// Latest block of a chain deep enough that a reorg cannot undo it
+    async fn confirmed_block(&self, chain_id: u64) -> Result<u64>;
+}
+
+#[async_trait::async_trait]
+impl BalanceReader for BlockchainClient {
+    async fn balances_at(&self, chain_id: u64, users: &[Address], block: u64) -> Result<Vec<U256>> {
+        self.get_user_balances(chain_id, users, block).await
+    }
+
+    async fn confirmed_block(&self, chain_id: u64) -> Result<u64> {
+        BlockchainClient::confirmed_block(self, chain_id).await
+    }
+}
+
+/// Sends the transaction queue's operations and follows them until they settle
+#[async_trait::async_trait]
+pub trait TxSubmitter: Send + Sync {
+    /// Sends an operation's transaction, returning its hash once the node accepts it
+    async fn send(&self, operation: &TxOperation) -> Result<H256>;
+
+    /// Waits for a sent transaction to settle, reporting it pending if the wait times out
+    async fn confirm(&self, tx_hash: H256) -> Result<TransactionResult>;
+
+    /// Whether the node no longer knows a sent transaction, so it will never be mined
+    async fn is_dropped(&self, tx_hash: H256) -> Result<bool>;
+}
+
+#[async_trait::async_trait]
+impl TxSubmitter for BlockchainClient {
+    async fn send(&self, operation: &TxOperation) -> Result<H256> {
+        self.send_operation(operation).await
+    }
+
+    async fn confirm(&self, tx_hash: H256) -> Result<TransactionResult> {
+        self.wait_for_confirmation(tx_hash).await
+    }
+
+    async fn is_dropped(&self, tx_hash: H256) -> Result<bool> {
+        BlockchainClient::is_dropped(self, tx_hash).await
+    }
+}
+
+/// Estimates the gas of operations before they are queued, so batches can be sized to fit
+#[async_trait::async_trait]
+pub trait GasEstimator: Send + Sync {
+    /// Gas the operation's transaction would use if sent now
+    async fn estimate_operation_gas(&self, operation: &TxOperation) -> Result<U256>;
+}
+
+#[async_trait::async_trait]
+impl GasEstimator for BlockchainClient {
+    async fn estimate_operation_gas(&self, operation: &TxOperation) -> Result<U256> {
+        self.estimate_gas(&self.operation_call(operation)?).await
+    }
+}
+
+/// Funds deposited to a user's balance on the payments contract
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct BalanceDeposited {
+    #[ethevent(indexed)]
+    pub user: Address,
+    pub amount: U256,
+    pub timestamp: U256,
+}
+
+/// ERC-20 tokens moved between wallets; a transfer to the payments contract is a deposit
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+#[ethevent(name = "Transfer")]
+pub struct TokenTransfer {
+    #[ethevent(indexed)]
+    pub from: Address,
+    #[ethevent(indexed)]
+    pub to: Address,
+    pub value: U256,
+}
+
+/// Usage billed on the billing contract by `recordUsage`
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct UsageRecorded {
+    #[ethevent(indexed)]
+    pub user: Address,
+    pub endpoint: String,
+    pub request_count: U256,
+    pub cost: U256,
+}
+
+/// One entry of a `batchBilling` call settled on the billing contract
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct BillingProcessed {
+    #[ethevent(indexed)]
+    pub user: Address,
+    pub endpoint: String,
+    pub request_count: U256,
+    pub cost: U256,
+}
+
+/// Escrow opened on the payments contract
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct EscrowCreated {
+    #[ethevent(indexed)]
+    pub escrow_id: U256,
+    #[ethevent(indexed)]
+    pub user: Address,
+    pub recipient: Address,
+    pub amount: U256,
+    pub release_time: U256,
+}
+
+/// Payment stream opened on the payments contract
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct PaymentStreamCreated {
+    #[ethevent(indexed)]
+    pub stream_id: U256,
+    #[ethevent(indexed)]
+    pub payer: Address,
+    #[ethevent(indexed)]
+    pub recipient: Address,
+    pub total_amount: U256,
+    pub start_time: U256,
+    pub end_time: U256,
+}
+
+/// Accrued funds claimed from a payment stream
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct StreamClaimed {
+    #[ethevent(indexed)]
+    pub stream_id: U256,
+    #[ethevent(indexed)]
+    pub recipient: Address,
+    pub amount: U256,
+}
+
+/// Escrowed funds released to a recipient's balance on the payments contract
+#[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+pub struct EscrowReleased {
+    #[ethevent(indexed)]
+    pub escrow_id: U256,
+    #[ethevent(indexed)]
+    pub user: Address,
+    pub recipient: Address,
+    pub amount: U256,
+}
+
+/// Contract events the backend acts on
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContractEvent {
-    Billing(ethers::core::types::Log),
-    Metering(ethers::core::types::Log),
-    Payments(ethers::core::types::Log),
+    BalanceDeposited(BalanceDeposited),
+    TokenDeposited(TokenTransfer),
+    UsageRecorded(UsageRecorded),
+    BillingProcessed(BillingProcessed),
+    EscrowReleased(EscrowReleased),
+}
+
+impl ContractEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContractEvent::BalanceDeposited(_) => "BalanceDeposited",
+            ContractEvent::TokenDeposited(_) => "Transfer",
+            ContractEvent::UsageRecorded(_) => "UsageRecorded",
+            ContractEvent::BillingProcessed(_) => "BillingProcessed",
+            ContractEvent::EscrowReleased(_) => "EscrowReleased",
+        }
+    }
+}
+
+/// A contract event and the log it was emitted in
+#[derive(Debug, Clone)]
+pub struct ChainEvent {
+    pub event: ContractEvent,
+    pub block_number: u64,
+    pub transaction_hash: H256,
+    pub log_index: u64,
+}
+
+/// Events of a range of confirmed blocks, in chain order
+#[derive(Debug, Clone)]
+pub struct EventBatch {
+    pub events: Vec<ChainEvent>,
+    /// Last block of the range; the next batch starts after it
+    pub to_block: u64,
+}
+
+/// Decodes a log into the event it carries, if it is one the backend acts on and was
+/// emitted by the contract expected to emit it
+///
+/// When balances are kept in `token`, deposits are its transfers to the payments
+/// contract, and the contract's own deposit events are ignored so a deposit made
+/// through it is not credited twice.
+fn chain_event(log: &Log, billing: Address, payments: Address, token: Option<Address>) -> Option<ChainEvent> {
+    fn decode<E: EthLogDecode>(raw: &RawLog) -> Option<E> {
+        E::decode_log(raw).ok()
+    }
+    
+    let raw = RawLog::from(log.clone());
+    let topic = *log.topics.first()?;
+    let event = if token == Some(log.address) && topic == TokenTransfer::signature() {
+        let transfer: TokenTransfer = decode(&raw)?;
+        if transfer.to != payments {
+            return None;
+        }
+        ContractEvent::TokenDeposited(transfer)
+    } else if token.is_none() && log.address == payments && topic == BalanceDeposited::signature() {
+        ContractEvent::BalanceDeposited(decode(&raw)?)
+    } else if log.address == payments && topic == EscrowReleased::signature() {
+        ContractEvent::EscrowReleased(decode(&raw)?)
+    } else if log.address == billing && topic == UsageRecorded::signature() {
+        ContractEvent::UsageRecorded(decode(&raw)?)
+    } else if log.address == billing && topic == BillingProcessed::signature() {
+        ContractEvent::BillingProcessed(decode(&raw)?)
+    } else {
+        return None;
+    };
+    
+    Some(ChainEvent {
+        event,
+        block_number: log.block_number?.as_u64(),
+        transaction_hash: log.transaction_hash?,
+        log_index: log.log_index?.as_u64(),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
+    use crate::test_support;
+    
+    
+    /// Tests that the client loads the bundled ABIs and can encode the calls it makes
+    /// against them, without needing a node
+    #[tokio::test]
+    async fn test_contract_abis_loaded() {
+        let client = BlockchainClient::new_lazy(&test_support::config()).unwrap();
+        let chain = client.settlement();
+        
+        for (contract, methods) in [
+            (&chain.billing_contract, BILLING_METHODS.as_slice()),
+            (&chain.metering_contract, METERING_METHODS.as_slice()),
+            (&chain.payments_contract, PAYMENTS_METHODS.as_slice()),
+        ] {
+            assert_ne!(contract.address(), Address::zero());
+            for method in methods {
+                assert!(contract.abi().function(method).is_ok(), "{} missing", method);
+            }
+        }
+        
+        let endpoint = "weather".to_string();
+        assert!(chain.billing_contract
+            .method::<_, H256>("recordUsage", ("0xabc".to_string(), endpoint.clone(), U256::from(3)))
+            .is_ok());
+        assert!(chain.metering_contract
+            .method::<_, H256>("logRequest", (Address::zero(), endpoint, [0u8; 32], U256::from(120), 200u16, [0u8; 32]))
+            .is_ok());
+        assert!(chain.payments_contract
+            .method::<_, (Address, Address, U256, U256, bool, bool)>("getEscrowDetails", U256::one())
+            .is_ok());
+        
+        // Escrow and stream ids and claimed amounts are read from the events the ABI declares
+        for (event, signature) in [
+            ("EscrowCreated", EscrowCreated::signature()),
+            ("PaymentStreamCreated", PaymentStreamCreated::signature()),
+            ("StreamClaimed", StreamClaimed::signature()),
+        ] {
+            assert_eq!(chain.payments_contract.abi().event(event).unwrap().signature(), signature, "{} differs", event);
+        }
+    }
+    
+    /// Tests that logs decode into typed events only when emitted by the contract
+    /// expected to emit them
+    /// Tests that without a private key the client reports itself read-only and
+    /// refuses to send transactions before reaching a node
+    #[tokio::test]
+    async fn test_read_only_mode() {
+        let mut config = test_support::config();
+        assert_eq!(BlockchainClient::new_lazy(&config).unwrap().mode(), BlockchainMode::ReadWrite);
+        
+        config.blockchain.private_key = None;
+        let client = BlockchainClient::new_lazy(&config).unwrap();
+        assert_eq!(client.mode(), BlockchainMode::ReadOnly);
+        assert!(client.wallet_address().unwrap_err().is::<ReadOnlyMode>());
+        
+        let operation = TxOperation::RecordUsage { user: Address::zero(), endpoint: "weather".to_string(), request_count: U256::one() };
+        assert!(client.send_operation(&operation).await.unwrap_err().is::<ReadOnlyMode>());
+        assert!(client.withdraw_balance(Address::zero(), U256::one()).await.unwrap_err().is::<ReadOnlyMode>());
+    }
+    
+    #[test]
+    fn test_chain_event_decoding() {
+        use ethers::abi::Token;
+        
+        let (billing, payments) = (Address::repeat_byte(0xb1), Address::repeat_byte(0xa7));
+        let user = Address::repeat_byte(0x11);
+        let log = |address, topics: Vec<H256>, data: Vec<Token>| Log {
+            address,
+            topics,
+            data: ethers::abi::encode(&data).into(),
+            block_number: Some(U64::from(10)),
+            transaction_hash: Some(H256::repeat_byte(0xaa)),
+            log_index: Some(U256::from(2)),
+            ..Default::default()
+        };
+        
+        let deposit = log(
+            payments,
+            vec![BalanceDeposited::signature(), H256::from(user)],
+            vec![Token::Uint(U256::from(500)), Token::Uint(U256::from(1_700_000_000u64))],
+        );
+        let event = chain_event(&deposit, billing, payments, None).unwrap();
+        assert_eq!(event.event, ContractEvent::BalanceDeposited(BalanceDeposited {
+            user,
+            amount: U256::from(500),
+            timestamp: U256::from(1_700_000_000u64),
+        }));
+        assert_eq!((event.block_number, event.log_index), (10, 2));
+        assert_eq!(event.transaction_hash, H256::repeat_byte(0xaa));
+        
+        // Only the payments contract credits balances
+        assert!(chain_event(&Log { address: billing, ..deposit.clone() }, billing, payments, None).is_none());
+        
+        let usage = log(
+            billing,
+            vec![UsageRecorded::signature(), H256::from(user)],
+            vec![Token::String("weather".to_string()), Token::Uint(U256::from(3)), Token::Uint(U256::from(30))],
+        );
+        assert_eq!(chain_event(&usage, billing, payments, None).unwrap().event, ContractEvent::UsageRecorded(UsageRecorded {
+            user,
+            endpoint: "weather".to_string(),
+            request_count: U256::from(3),
+            cost: U256::from(30),
+        }));
+        
+        let unknown = log(billing, vec![H256::repeat_byte(0x01)], vec![]);
+        assert!(chain_event(&unknown, billing, payments, None).is_none());
+        
+        // With a token, its transfers into the payments contract are the deposits
+        let token = Address::repeat_byte(0x70);
+        let transfer = |to| log(
+            token,
+            vec![TokenTransfer::signature(), H256::from(user), H256::from(to)],
+            vec![Token::Uint(U256::from(1_500_000))],
+        );
+        assert_eq!(chain_event(&transfer(payments), billing, payments, Some(token)).unwrap().event, ContractEvent::TokenDeposited(TokenTransfer {
+            from: user,
+            to: payments,
+            value: U256::from(1_500_000),
+        }));
+        assert!(chain_event(&transfer(Address::repeat_byte(0x22)), billing, payments, Some(token)).is_none());
+        assert!(chain_event(&transfer(payments), billing, payments, None).is_none());
+        assert!(chain_event(&deposit, billing, payments, Some(token)).is_none());
+    }
+    
+    #[test]
+    fn test_next_poll_interval() {
+        let mut interval = MIN_CONFIRMATION_POLL_INTERVAL;
+        let mut waits = Vec::new();
+        for _ in 0..8 {
+            waits.push(interval.as_secs());
+            interval = next_poll_interval(interval);
+        }
+        assert_eq!(waits, vec![2, 3, 4, 6, 10, 15, 22, 30]);
+        assert_eq!(next_poll_interval(MAX_CONFIRMATION_POLL_INTERVAL), MAX_CONFIRMATION_POLL_INTERVAL);
+    }
+    
+    #[test]
+    fn test_fee_calculation() {
+        assert_eq!(gwei_to_wei(20), U256::from(20_000_000_000u64));
+        assert_eq!(scale_fee(U256::from(1000), 1.5), U256::from(1500));
+        assert_eq!(scale_fee(U256::from(1000), 1.0), U256::from(1000));
+        
+        let gwei = |amount: u64| gwei_to_wei(amount);
+        let history = |base_fees: Vec<U256>, tips: Vec<U256>| FeeHistory {
+            base_fee_per_gas: base_fees,
+            gas_used_ratio: vec![0.5; tips.len()],
+            oldest_block: U256::from(100),
+            reward: tips.into_iter().map(|tip| vec![tip]).collect(),
+        };
+        
+        // Median of the non-empty blocks' tips, on top of twice the next base fee
+        let fees = eip1559_fees(
+            &history(vec![gwei(10), gwei(12), gwei(30)], vec![gwei(3), U256::zero(), gwei(1), gwei(2)]),
+            2.0,
+            1.0,
+        ).unwrap();
+        assert_eq!(fees, Eip1559Fees { max_fee_per_gas: gwei(62), max_priority_fee_per_gas: gwei(2) });
+        
+        let fees = eip1559_fees(&history(vec![gwei(30)], vec![gwei(2)]), 1.0, 1.5).unwrap();
+        assert_eq!(fees, Eip1559Fees { max_fee_per_gas: gwei(33), max_priority_fee_per_gas: gwei(3) });
+        
+        // Only empty blocks sampled
+        let fees = eip1559_fees(&history(vec![gwei(30)], vec![U256::zero()]), 2.0, 1.0).unwrap();
+        assert_eq!(fees.max_priority_fee_per_gas, gwei(DEFAULT_PRIORITY_FEE_GWEI));
+        
+        // Chains without EIP-1559 report no base fee
+        assert!(eip1559_fees(&history(vec![], vec![]), 2.0, 1.0).is_none());
+        assert!(eip1559_fees(&history(vec![U256::zero()], vec![gwei(1)]), 2.0, 1.0).is_none());
+    }
+    
+    /// Tests that cached fees are reused until they age out, and that the gas report
+    /// prices a billing batch at the base fee plus tip, or the legacy gas price
+    #[tokio::test]
+    async fn test_gas_report_from_cache() {
+        let now = Utc::now();
+        assert!(is_fresh(now - chrono::Duration::seconds(14), now, 15));
+        assert!(!is_fresh(now - chrono::Duration::seconds(15), now, 15));
+        assert!(!is_fresh(now, now, 0));
+        
+        let mut config = test_support::config();
+        config.blockchain.gas_price_cache_secs = 3600;
+        config.blockchain.billing_batch_gas_budget = 1_000_000;
+        config.blockchain.gas_price_gwei = 20;
+        let client = BlockchainClient::new_lazy(&config).unwrap();
+        let fees = FeeData {
+            gas_price: gwei_to_wei(35),
+            base_fee_per_gas: Some(gwei_to_wei(30)),
+            eip1559: Some(Eip1559Fees { max_fee_per_gas: gwei_to_wei(62), max_priority_fee_per_gas: gwei_to_wei(2) }),
+            refreshed_at: now - chrono::Duration::seconds(10),
+        };
+        *client.fee_cache.lock().await = Some(fees.clone());
+        
+        // Served from the cache, without reaching the node
+        assert_eq!(client.get_gas_price().await.unwrap(), gwei_to_wei(35));
+        let report = client.gas_report().await.unwrap();
+        assert_eq!(report.chain_id, 1);
+        assert_eq!(report.max_fee_per_gas.as_deref(), Some("62000000000"));
+        assert!(report.cache_age_secs >= 10);
+        assert_eq!(report.batch_gas, 1_000_000);
+        assert_eq!(report.batch_cost, "32000000000000000");
+        
+        let legacy = FeeData { base_fee_per_gas: None, eip1559: None, ..fees };
+        assert_eq!(legacy.expected_gas_price(gwei_to_wei(20)), gwei_to_wei(20));
+    }
+    
+    /// Tests that fees which cannot be refreshed fall back to the stale cache, if any
+    #[tokio::test]
+    async fn test_fee_data_falls_back_to_stale_cache() {
+        let mut config = test_support::config();
+        config.blockchain.gas_price_cache_secs = 15;
+        config.blockchain.chains[0].rpc_url = "http://127.0.0.1:1".to_string();
+        let client = BlockchainClient::new_lazy(&config).unwrap();
+        assert!(client.fee_data().await.is_err());
+        
+        let stale = FeeData {
+            gas_price: gwei_to_wei(35),
+            base_fee_per_gas: None,
+            eip1559: None,
+            refreshed_at: Utc::now() - chrono::Duration::minutes(5),
+        };
+        *client.fee_cache.lock().await = Some(stale.clone());
+        let fees = client.fee_data().await.unwrap();
+        assert_eq!(fees.gas_price, gwei_to_wei(35));
+        assert_eq!(fees.refreshed_at, stale.refreshed_at);
+    }
+    
+    #[test]
+    fn test_parse_abi_requires_methods() {
+        let abi = r#"[{"type":"function","name":"recordUsage","inputs":[],"outputs":[],"stateMutability":"nonpayable"}]"#;
+        assert!(parse_abi(abi, &["recordUsage"]).is_ok());
+        
+        let error = parse_abi(abi, &["recordUsage", "batchBilling", "getUserBalance"]).unwrap_err();
+        assert_eq!(error.to_string(), "Contract ABI is missing methods: batchBilling, getUserBalance");
+        assert!(parse_abi("[]", &["recordUsage"]).is_err());
+        assert!(parse_abi("not json", &[]).is_err());
+    }
+    
+    /// Tests that a node on another chain than configured is refused, naming both
+    #[test]
+    fn test_check_chain_id() {
+        assert_eq!(check_chain_id(1, U256::one()).unwrap(), 1);
+        
+        let error = check_chain_id(1, U256::from(11155111)).unwrap_err().to_string();
+        assert!(error.contains("chain 1 is configured"), "{}", error);
+        assert!(error.contains("reports chain 11155111"), "{}", error);
+    }
     
     #[tokio::test]
     #[ignore] // Requires actual blockchain connection
     async fn test_blockchain_client_creation() {
-        let config = Config::load().unwrap();
+        let config = test_support::config();
         let client = BlockchainClient::new(&config).await;
         assert!(client.is_ok());
     }
     
+    #[test]
+    fn test_is_valid_signature_encoding() {
+        let hash = H256::repeat_byte(0xab);
+        let calldata = encode_is_valid_signature_call(hash, Bytes::from(vec![0x01, 0x02, 0x03]));
+        
+        // selector, bytes32 hash, offset, length, padded signature
+        assert_eq!(calldata.len(), 4 + 32 * 4);
+        assert_eq!(calldata[..4], EIP1271_MAGIC_VALUE);
+        assert_eq!(&calldata[4..36], hash.as_bytes());
+        assert_eq!(U256::from_big_endian(&calldata[36..68]), U256::from(64));
+        assert_eq!(U256::from_big_endian(&calldata[68..100]), U256::from(3));
+        assert_eq!(calldata[100..103], [0x01, 0x02, 0x03]);
+    }
+    
     #[tokio::test]
     #[ignore] // Requires actual blockchain connection
     async fn test_health_check() {
-        let config = Config::load().unwrap();
+        let config = test_support::config();
         let client = BlockchainClient::new(&config).await.unwrap();
-        let result = client.health_check().await;
-        assert!(result.is_ok());
+        let chains = client.health_check().await;
+        assert_eq!(chains.len(), config.blockchain.chains.len());
+        assert!(chains.iter().all(|chain| chain.healthy && chain.verified_chain_id == Some(chain.chain_id)));
     }
 }
\ No newline at end of file
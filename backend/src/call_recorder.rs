@@ -0,0 +1,243 @@
+//! Recording of proxied calls
+//!
+//! A call is logged and its usage added to the consumer's aggregates in one
+//! transaction, so a crash can never leave it billed but unlogged or logged but
+//! unbilled. Writes that fail are retried in order from a small in-memory queue, and
+//! are idempotent, since a write reported as failed may still have committed; those
+//! still failing after [`RETRY_ATTEMPTS`] are dead-lettered to the log with the
+//! entry they would have written, so they can be replayed by hand.
+
+use crate::{
+    database::Database,
+    error::AppError,
+    metering::MeteringService,
+    models::{ApiEndpoint, CreateRequestLogRequest, RequestLog, UsageCharge},
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// Most failed writes waiting for a retry; further failures are dead-lettered at once
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Attempts a queued write gets before it is dead-lettered
+pub const RETRY_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry of a write, doubled after each further failure
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Log target of writes given up on
+const DEAD_LETTER_TARGET: &str = "augustcredits::dead_letter";
+
+/// A finished call to log, billed when it carries a charge
+#[derive(Clone)]
+pub struct RecordedCall {
+    pub log: CreateRequestLogRequest,
+    pub charge: Option<UsageCharge>,
+    /// Hold to settle and endpoint whose SLA the call is checked against, for proxied calls
+    pub settlement: Option<(ApiEndpoint, Option<Uuid>)>,
+    /// When the call was first recorded, kept across retries so a retried write lands in
+    /// the same billing period and is recognised if an earlier attempt did commit
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl RecordedCall {
+    /// A finished call, recorded as of now
+    pub fn new(
+        log: CreateRequestLogRequest,
+        charge: Option<UsageCharge>,
+        settlement: Option<(ApiEndpoint, Option<Uuid>)>,
+    ) -> Self {
+        Self { log, charge, settlement, recorded_at: Utc::now() }
+    }
+
+    /// A call that is only logged, such as a sandbox or rejected one
+    pub fn unbilled(log: CreateRequestLogRequest) -> Self {
+        Self::new(log, None, None)
+    }
+}
+
+/// Sizes of a call logged before its response was streamed
+#[derive(Clone)]
+pub struct CompletedCall {
+    pub user_id: Uuid,
+    pub request_id: String,
+    pub started_at: DateTime<Utc>,
+    pub request_size: Option<i64>,
+    pub response_size: Option<i64>,
+    pub stream_duration_ms: Option<i32>,
+}
+
+#[derive(Clone)]
+enum CallWrite {
+    Record(Box<RecordedCall>),
+    Complete(CompletedCall),
+}
+
+impl CallWrite {
+    fn request_id(&self) -> &str {
+        match self {
+            CallWrite::Record(call) => &call.log.request_id,
+            CallWrite::Complete(call) => &call.request_id,
+        }
+    }
+}
+
+/// Writes finished calls, retrying the ones that fail in the background
+#[derive(Clone)]
+pub struct CallRecorder {
+    database: Arc<Database>,
+    metering: Arc<MeteringService>,
+    queue: mpsc::Sender<CallWrite>,
+    /// Receiving end of the queue until the first failure starts the retry task
+    pending: Arc<Mutex<Option<mpsc::Receiver<CallWrite>>>>,
+}
+
+impl CallRecorder {
+    pub fn new(database: Arc<Database>, metering: Arc<MeteringService>) -> Self {
+        let (queue, pending) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            database,
+            metering,
+            queue,
+            pending: Arc::new(Mutex::new(Some(pending))),
+        }
+    }
+
+    /// Logs a call and records its usage, then settles its hold; returns the entry
+    /// written, or `None` when the write failed and was queued for retry
+    pub async fn record(&self, call: RecordedCall) -> Option<RequestLog> {
+        match self.record_once(&call).await {
+            Ok(log) => Some(log),
+            Err(e) => {
+                warn!("Failed to record request {}, queued for retry: {:#}", call.log.request_id, e);
+                self.enqueue(CallWrite::Record(Box::new(call)));
+                None
+            }
+        }
+    }
+
+    /// Fills in the sizes of a call logged before its response was streamed
+    pub async fn complete(&self, call: CompletedCall) {
+        if let Err(e) = self.complete_once(&call).await {
+            warn!("Failed to complete log of request {}, queued for retry: {:#}", call.request_id, e);
+            self.enqueue(CallWrite::Complete(call));
+        }
+    }
+
+    async fn record_once(&self, call: &RecordedCall) -> Result<RequestLog> {
+        let log = match self.database.record_proxied_request(&call.log, call.charge.as_ref(), call.recorded_at).await {
+            Ok(log) => log,
+            // Retrying cannot fix a price that cannot be worked out, so the call is
+            // logged unbilled as it would be without the charge
+            Err(e) if e.downcast_ref::<AppError>().is_some() => {
+                error!("Failed to record usage for request {}: {:#}", call.log.request_id, e);
+                let log = self.database.record_proxied_request(&call.log, None, call.recorded_at).await?;
+                if let Some((_, hold)) = &call.settlement {
+                    self.metering.release_funds(*hold).await;
+                }
+                return Ok(log);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some((endpoint, hold)) = &call.settlement {
+            let user_id = call.log.user_id;
+            let request_id = &log.request_id;
+            self.metering.settle_funds(user_id, *hold, log.cost, request_id).await;
+            self.metering
+                .flag_sla_breach(user_id, endpoint, log.status_code, log.response_time_ms, log.cost, request_id)
+                .await;
+        }
+        Ok(log)
+    }
+
+    async fn complete_once(&self, call: &CompletedCall) -> Result<()> {
+        let found = self.database
+            .complete_request_log(
+                call.user_id,
+                &call.request_id,
+                call.started_at,
+                call.request_size,
+                call.response_size,
+                call.stream_duration_ms,
+            )
+            .await?;
+        if !found {
+            bail!("request has not been logged yet");
+        }
+        Ok(())
+    }
+
+    fn enqueue(&self, write: CallWrite) {
+        if let Some(pending) = self.pending.lock().unwrap().take() {
+            tokio::spawn(self.clone().retry(pending));
+        }
+        if let Err(e) = self.queue.try_send(write) {
+            let write = match e {
+                mpsc::error::TrySendError::Full(write) | mpsc::error::TrySendError::Closed(write) => write,
+            };
+            let recorder = self.clone();
+            tokio::spawn(async move { recorder.dead_letter(write, "retry queue is full".to_string()).await });
+        }
+    }
+
+    /// Retries queued writes one at a time, so a call's sizes are never written before
+    /// the call itself
+    async fn retry(self, mut pending: mpsc::Receiver<CallWrite>) {
+        while let Some(write) = pending.recv().await {
+            let mut backoff = RETRY_BACKOFF;
+            let mut attempt = 1;
+            loop {
+                tokio::time::sleep(backoff).await;
+                let result = match &write {
+                    CallWrite::Record(call) => self.record_once(call).await.map(|_| ()),
+                    CallWrite::Complete(call) => self.complete_once(call).await,
+                };
+                match result {
+                    Ok(()) => break,
+                    Err(e) if attempt >= RETRY_ATTEMPTS => {
+                        self.dead_letter(write, format!("{:#}", e)).await;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Retry {} of request {} failed: {:#}", attempt, write.request_id(), e);
+                        attempt += 1;
+                        backoff *= 2;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives up on a write, logging what it would have written and releasing the hold
+    /// of a call that was never billed
+    async fn dead_letter(&self, write: CallWrite, reason: String) {
+        match write {
+            CallWrite::Record(call) => {
+                let entry = serde_json::to_string(&call.log).unwrap_or_default();
+                error!(
+                    target: DEAD_LETTER_TARGET,
+                    "Dropped request {} ({}), billed: {}: {}",
+                    call.log.request_id, reason, call.charge.is_some(), entry
+                );
+                if let Some((_, hold)) = call.settlement {
+                    self.metering.release_funds(hold).await;
+                }
+            }
+            CallWrite::Complete(call) => {
+                error!(
+                    target: DEAD_LETTER_TARGET,
+                    "Dropped sizes of request {} ({}): {:?} bytes in, {:?} bytes out, streamed {:?}ms",
+                    call.request_id, reason, call.request_size, call.response_size, call.stream_duration_ms
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Contract event processing for AugustCredits
+//!
+//! Background task, one per configured chain, that follows the billing and payments
+//! contracts' events once their blocks are confirmed. Deposits, made in the configured ERC-20 token by transferring
+//! it to the payments contract, and escrow releases credit the ledger balance of the
+//! account registered with the paying or receiving wallet, and usage billed on-chain marks the
+//! matching pending usage record billed, so usage whose billing transaction was never
+//! confirmed to the backend is not billed again. Deposits and billed usage record the
+//! chain they were made on. Each batch of blocks is applied in one
+//! database transaction together with the position reached, so a restart resumes where
+//! it stopped and never applies an event twice.
+
+use crate::{
+    blockchain::{BlockchainClient, ChainEvent, ContractEvent, EventBatch},
+    config::{BlockchainConfig, Config},
+    database::Database,
+    models::{BillingTransaction, CostAmount},
+};
+use anyhow::Result;
+use ethers::types::{Address, U256};
+use futures::StreamExt;
+use sqlx::{Postgres, Transaction};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Cursor the position in a chain's contract events is saved under
+///
+/// The settlement chain keeps the name used before other chains could be configured,
+/// so an existing deployment resumes where it stopped.
+pub fn cursor(config: &BlockchainConfig, chain_id: u64) -> String {
+    if chain_id == config.settlement_chain().chain_id {
+        "contracts".to_string()
+    } else {
+        format!("contracts:{}", chain_id)
+    }
+}
+
+/// What applying one batch of events changed
+#[derive(Debug, Default)]
+pub struct EventRun {
+    pub credited: u64,
+    pub usage_billed: u64,
+    /// Events already applied by an earlier run
+    pub skipped: u64,
+}
+
+/// Applies a chain's confirmed contract events to balances and usage
+#[derive(Clone)]
+pub struct ChainEventJob {
+    database: Arc<Database>,
+    blockchain: Arc<BlockchainClient>,
+    chain_id: u64,
+    cursor: String,
+    poll_interval: Duration,
+    start_block: Option<u64>,
+    amount_decimals: u32,
+}
+
+impl ChainEventJob {
+    pub fn new(config: &Config, database: Arc<Database>, blockchain: Arc<BlockchainClient>, chain_id: u64) -> Self {
+        Self {
+            database,
+            blockchain,
+            chain_id,
+            cursor: cursor(&config.blockchain, chain_id),
+            poll_interval: Duration::from_secs(config.blockchain.events_poll_interval_secs),
+            start_block: config.blockchain.chain(chain_id).and_then(|chain| chain.events_start_block),
+            amount_decimals: config.blockchain.amount_decimals(),
+        }
+    }
+
+    /// Follows the contracts' events in the background, starting over from the saved
+    /// position whenever a batch fails to apply
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.follow().await {
+                    error!("Contract event processing on chain {} failed: {:#}", self.chain_id, e);
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        })
+    }
+
+    async fn follow(&self) -> Result<()> {
+        let from_block = match self.database.get_chain_event_cursor(&self.cursor).await? {
+            Some(last_block) => last_block + 1,
+            None => match self.start_block {
+                Some(block) => block,
+                None => self.blockchain.confirmed_block(self.chain_id).await?,
+            },
+        };
+        info!("Processing contract events on chain {} from block {}", self.chain_id, from_block);
+
+        let mut batches = self.blockchain.clone().subscribe_events(self.chain_id, from_block, self.poll_interval);
+        while let Some(batch) = batches.next().await {
+            match batch {
+                Ok(batch) => {
+                    let run = self.apply(&batch).await?;
+                    if run.credited > 0 || run.usage_billed > 0 {
+                        info!(
+                            "Applied contract events on chain {} up to block {}: {} credit(s), {} usage record(s) billed",
+                            self.chain_id, batch.to_block, run.credited, run.usage_billed
+                        );
+                    }
+                }
+                Err(e) => warn!("Failed to fetch contract events on chain {}: {:#}", self.chain_id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a batch of events and saves the position reached in one transaction
+    pub async fn apply(&self, batch: &EventBatch) -> Result<EventRun> {
+        let mut run = EventRun::default();
+        let mut tx = self.database.begin_transaction().await?;
+
+        for event in &batch.events {
+            let transaction_hash = format!("{:?}", event.transaction_hash);
+            let recorded = self.database
+                .record_chain_event_in(&mut tx, self.chain_id, &transaction_hash, event.log_index, event.block_number, event.event.name())
+                .await?;
+            if !recorded {
+                run.skipped += 1;
+                continue;
+            }
+
+            match &event.event {
+                ContractEvent::BalanceDeposited(deposit) => {
+                    if self.credit(&mut tx, event, deposit.user, deposit.amount, true).await? {
+                        run.credited += 1;
+                    }
+                }
+                ContractEvent::TokenDeposited(transfer) => {
+                    if self.credit(&mut tx, event, transfer.from, transfer.value, true).await? {
+                        run.credited += 1;
+                    }
+                }
+                ContractEvent::EscrowReleased(release) => {
+                    if self.credit(&mut tx, event, release.recipient, release.amount, false).await? {
+                        run.credited += 1;
+                    }
+                }
+                ContractEvent::UsageRecorded(usage) => {
+                    if self.bill_usage(&mut tx, event, usage.user, &usage.endpoint, usage.request_count).await? {
+                        run.usage_billed += 1;
+                    }
+                }
+                ContractEvent::BillingProcessed(usage) => {
+                    if self.bill_usage(&mut tx, event, usage.user, &usage.endpoint, usage.request_count).await? {
+                        run.usage_billed += 1;
+                    }
+                }
+            }
+        }
+
+        self.database.set_chain_event_cursor_in(&mut tx, &self.cursor, batch.to_block).await?;
+        tx.commit().await?;
+        Ok(run)
+    }
+
+    /// Credits funds that reached a wallet's balance on-chain to its account, recording
+    /// them as a deposit when the wallet paid them in
+    async fn credit(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        event: &ChainEvent,
+        wallet: Address,
+        amount: U256,
+        deposit: bool,
+    ) -> Result<bool> {
+        let wallet = format!("{:?}", wallet);
+        let Some(user_id) = self.database.get_user_id_by_wallet_in(tx, &wallet).await? else {
+            warn!(
+                "{} of {} in {:?} is for unregistered wallet {}",
+                event.event.name(), amount, event.transaction_hash, wallet
+            );
+            return Ok(false);
+        };
+        if amount.is_zero() {
+            return Ok(false);
+        }
+        let Some(amount) = CostAmount::from_base_units(amount, self.amount_decimals) else {
+            warn!("{} of {} in {:?} is too large to credit", event.event.name(), amount, event.transaction_hash);
+            return Ok(false);
+        };
+
+        let reference = format!("{:?}", event.transaction_hash);
+        let amount = amount.to_string();
+        self.database.credit_balance_in(tx, user_id, &amount, Some(&reference)).await?;
+        if deposit {
+            self.database
+                .record_deposit_in(tx, user_id, &amount, self.chain_id, &reference, event.block_number)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    /// Marks usage billed on-chain as billed
+    async fn bill_usage(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        event: &ChainEvent,
+        wallet: Address,
+        endpoint: &str,
+        request_count: U256,
+    ) -> Result<bool> {
+        let Some(request_count) = i64::try_from(request_count).ok() else {
+            warn!("{} in {:?} has an impossible request count", event.event.name(), event.transaction_hash);
+            return Ok(false);
+        };
+
+        let transaction = BillingTransaction {
+            hash: format!("{:?}", event.transaction_hash),
+            gas_used: None,
+            block_number: Some(event.block_number as i64),
+            chain_id: self.chain_id as i64,
+        };
+        self.database
+            .bill_recorded_usage_in(tx, &format!("{:?}", wallet), endpoint, request_count, &transaction)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::{
+        blockchain::{BalanceDeposited, UsageRecorded},
+        models::{CreateEndpointRequest, CreateUserRequest, UsageStatus},
+    };
+    use ethers::types::H256;
+    use uuid::Uuid;
+
+    /// Tests that deposits are credited and on-chain billing marks usage billed exactly
+    /// once, even when a batch is applied again, and that the position is saved
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_apply_contract_events() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let blockchain = Arc::new(BlockchainClient::new_lazy(&config).unwrap());
+        let chain_id = config.blockchain.settlement_chain().chain_id;
+        let job = ChainEventJob::new(&config, database.clone(), blockchain, chain_id);
+
+        let wallet = Address::from_low_u64_be(Uuid::new_v4().as_u128() as u64);
+        let user = database.create_user(CreateUserRequest {
+            wallet_address: format!("{:?}", wallet),
+            email: None,
+            username: None,
+            tier: None,
+        }).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, serde_json::from_value::<CreateEndpointRequest>(serde_json::json!({
+            "name": format!("events-{}", Uuid::new_v4().simple()),
+            "upstream_url": "https://api.example.com",
+            "price_per_request": "10",
+        })).unwrap()).await.unwrap();
+        let record = database.create_usage_record(user.id, endpoint.id, 3, "30", "2024-01").await.unwrap();
+
+        let event = |log_index, event| ChainEvent {
+            event,
+            block_number: 100,
+            transaction_hash: H256::from_low_u64_be(Uuid::new_v4().as_u128() as u64),
+            log_index,
+        };
+        let batch = EventBatch {
+            events: vec![
+                event(0, ContractEvent::BalanceDeposited(BalanceDeposited {
+                    user: wallet,
+                    amount: U256::from(1000),
+                    timestamp: U256::zero(),
+                })),
+                event(1, ContractEvent::BalanceDeposited(BalanceDeposited {
+                    user: Address::from_low_u64_be(Uuid::new_v4().as_u128() as u64),
+                    amount: U256::from(1000),
+                    timestamp: U256::zero(),
+                })),
+                event(2, ContractEvent::UsageRecorded(UsageRecorded {
+                    user: wallet,
+                    endpoint: endpoint.name.clone(),
+                    request_count: U256::from(3),
+                    cost: U256::from(30),
+                })),
+            ],
+            to_block: 100,
+        };
+
+        let run = job.apply(&batch).await.unwrap();
+        assert_eq!((run.credited, run.usage_billed, run.skipped), (1, 1, 0));
+        let run = job.apply(&batch).await.unwrap();
+        assert_eq!((run.credited, run.usage_billed, run.skipped), (0, 0, 3));
+
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "1000");
+        let billed = database.get_usage_record(user.id, endpoint.id, "2024-01").await.unwrap().unwrap();
+        assert_eq!(billed.id, record.id);
+        assert!(matches!(billed.status, UsageStatus::Billed));
+        assert_eq!(billed.transaction_hash.as_deref(), Some(format!("{:?}", batch.events[2].transaction_hash).as_str()));
+        assert_eq!(billed.chain_id, Some(chain_id as i64));
+        assert_eq!(database.get_chain_event_cursor("contracts").await.unwrap(), Some(100));
+    }
+}
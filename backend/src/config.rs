@@ -9,33 +9,163 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::env;
 
+use crate::models::CostAmount;
+
 /// Complete application configuration loaded from environment variables
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub server_address: String,
     pub database_url: String,
+    /// Read replica that analytics queries run on instead of the primary
+    pub database_read_url: Option<String>,
+    pub database_pool: DatabasePoolConfig,
     pub redis_url: String,
     pub blockchain: BlockchainConfig,
     pub auth: AuthConfig,
     pub rate_limiting: RateLimitingConfig,
     pub monitoring: MonitoringConfig,
+    pub gateway: GatewayConfig,
+    pub health_check: HealthCheckConfig,
+    pub alerts: AlertConfig,
+    pub daily_stats: DailyStatsConfig,
+    pub retention: RetentionConfig,
+    pub reconciliation: ReconciliationConfig,
+    pub billing: BillingConfig,
+    pub webhooks: WebhookConfig,
+    pub exports: ExportConfig,
     pub features: FeatureFlags,
 }
 
+/// Database connection pool configuration parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabasePoolConfig {
+    pub max_connections: u32,
+    /// Connections kept open even when idle
+    pub min_connections: u32,
+    pub acquire_timeout_seconds: u64,
+    /// Idle connections above the minimum are closed after this long; 0 keeps them
+    pub idle_timeout_seconds: u64,
+    /// Connections are replaced after this long; 0 keeps them indefinitely
+    pub max_lifetime_seconds: u64,
+}
+
 /// Blockchain network configuration for smart contract interactions
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockchainConfig {
+    /// Chains payments are accepted on; usage is billed, and withdrawals paid out, on
+    /// the first
+    pub chains: Vec<ChainConfig>,
+    /// Decimal places of the token, which ledger amounts are allowed down to
+    pub token_decimals: u32,
+    /// JSON ABI files of the billing, metering and payments contracts
+    pub billing_abi_path: String,
+    pub metering_abi_path: String,
+    pub payments_abi_path: String,
+    /// Key of the wallet transactions are sent from; unset to run read-only, reading
+    /// the contracts but sending no transactions
+    pub private_key: Option<String>,
+    pub gas_limit: u64,
+    /// Gas price of legacy transactions, on chains without EIP-1559
+    pub gas_price_gwei: u64,
+    /// EIP-1559 max fee per gas as a multiple of the next block's base fee, on top of
+    /// the priority fee
+    pub max_fee_multiplier: f64,
+    /// Multiple of the median recent priority fee offered as the EIP-1559 tip
+    pub priority_fee_multiplier: f64,
+    /// How long fee data read from the node is reused before it is read again; 0 to
+    /// read it for every transaction
+    pub gas_price_cache_secs: u64,
+    /// How long a sent transaction is waited on before it is treated as still pending
+    pub confirmation_timeout_secs: u64,
+    /// Blocks mined without a sent transaction before it is treated as still pending;
+    /// unset to only wait `confirmation_timeout_secs`
+    pub confirmation_timeout_blocks: Option<u64>,
+    pub retry_attempts: u32,
+    pub retry_delay_ms: u64,
+    /// Most a user may withdraw per UTC day, in ledger units; unset for no cap
+    pub withdrawal_daily_limit: Option<CostAmount>,
+    /// Apply deposits and billing confirmations from contract events
+    pub events_enabled: bool,
+    pub events_poll_interval_secs: u64,
+    /// Send queued transactions, such as billing submissions, from the background worker
+    pub tx_queue_enabled: bool,
+    pub tx_queue_poll_interval_secs: u64,
+    /// Sends of a queued transaction before it is given up on
+    pub tx_max_attempts: u32,
+    /// Delay before a failed queued transaction is first resent, doubling with each attempt
+    pub tx_retry_backoff_secs: u64,
+    /// Most gas one batch billing transaction is estimated to use; larger batches are
+    /// split. At most `gas_limit`
+    pub billing_batch_gas_budget: u64,
+}
+
+/// Node and contracts of one chain the platform is deployed on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
     pub rpc_url: String,
     pub chain_id: u64,
     pub billing_contract_address: String,
     pub metering_contract_address: String,
     pub payments_contract_address: String,
-    pub private_key: String,
-    pub gas_limit: u64,
-    pub gas_price_gwei: u64,
+    /// ERC-20 token balances and prices are kept in, such as USDC; unset to keep them
+    /// in the native coin's wei. Deposits are token transfers to the payments contract
+    pub token_address: Option<String>,
     pub confirmation_blocks: u64,
-    pub retry_attempts: u32,
-    pub retry_delay_ms: u64,
+    /// First block whose events are applied when none have been yet; the latest
+    /// confirmed block when unset
+    pub events_start_block: Option<u64>,
+}
+
+impl ChainConfig {
+    /// Loads a chain from the environment variables named with `suffix`, such as
+    /// `BLOCKCHAIN_RPC_URL_42161` for the suffix `_42161`
+    fn from_env(chain_id: u64, suffix: &str) -> Result<Self> {
+        let var = |name: &str| env::var(format!("{}{}", name, suffix));
+        let required = |name: &str| var(name)
+            .with_context(|| format!("{}{} environment variable is required", name, suffix));
+        let invalid = |name: &str| format!("Invalid {}{}", name, suffix);
+
+        Ok(Self {
+            rpc_url: required("BLOCKCHAIN_RPC_URL")?,
+            chain_id,
+            billing_contract_address: required("BILLING_CONTRACT_ADDRESS")?,
+            metering_contract_address: required("METERING_CONTRACT_ADDRESS")?,
+            payments_contract_address: required("PAYMENTS_CONTRACT_ADDRESS")?,
+            token_address: var("BLOCKCHAIN_TOKEN_ADDRESS").ok()
+                .filter(|address| !address.is_empty()),
+            confirmation_blocks: var("BLOCKCHAIN_CONFIRMATION_BLOCKS")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .with_context(|| invalid("BLOCKCHAIN_CONFIRMATION_BLOCKS"))?,
+            events_start_block: var("BLOCKCHAIN_EVENTS_START_BLOCK").ok()
+                .filter(|block| !block.is_empty())
+                .map(|block| block.parse())
+                .transpose()
+                .with_context(|| invalid("BLOCKCHAIN_EVENTS_START_BLOCK"))?,
+        })
+    }
+}
+
+impl BlockchainConfig {
+    /// Chain usage is billed and withdrawals are paid out on
+    pub fn settlement_chain(&self) -> &ChainConfig {
+        &self.chains[0]
+    }
+
+    /// The configured chain with `chain_id`, if payments are accepted on it
+    pub fn chain(&self, chain_id: u64) -> Option<&ChainConfig> {
+        self.chains.iter().find(|chain| chain.chain_id == chain_id)
+    }
+
+    /// Decimal places ledger amounts are kept to: the token's, or none when they are
+    /// kept in wei
+    pub fn amount_decimals(&self) -> u32 {
+        if self.settlement_chain().token_address.is_some() {
+            self.token_decimals
+        } else {
+            0
+        }
+    }
 }
 
 /// Authentication and security settings for user management
@@ -49,6 +179,14 @@ pub struct AuthConfig {
     pub require_email_verification: bool,
     pub max_login_attempts: u32,
     pub lockout_duration_minutes: u64,
+    /// Domain that Sign-In with Ethereum messages must be bound to
+    pub siwe_domain: String,
+    /// URI presented in Sign-In with Ethereum messages
+    pub siwe_uri: String,
+    /// Proxy addresses (CIDR) whose X-Forwarded-For header is trusted
+    pub trusted_proxies: Vec<String>,
+    /// How often expired nonces, refresh tokens and token revocations are deleted
+    pub cleanup_interval_secs: u64,
 }
 
 /// Rate limiting configuration to prevent API abuse
@@ -60,6 +198,16 @@ pub struct RateLimitingConfig {
     pub enable_ip_rate_limiting: bool,
     pub enable_user_rate_limiting: bool,
     pub redis_key_prefix: String,
+    /// Keep per-endpoint rate limit windows in Redis so replicas share them
+    pub use_redis: bool,
+    /// Count against local windows while Redis is unreachable instead of refusing requests
+    pub redis_local_fallback: bool,
+    /// Without Redis, write local windows through to the database so they survive restarts
+    pub persist_windows: bool,
+    /// How often rate limit state held in memory is swept of expired windows
+    pub cleanup_interval_secs: u64,
+    /// Most user-endpoint pairs held in memory before the least recently used are evicted
+    pub max_local_entries: usize,
 }
 
 /// Observability and monitoring configuration for system health
@@ -73,6 +221,121 @@ pub struct MonitoringConfig {
     pub prometheus_namespace: String,
 }
 
+/// Proxy behaviour for requests forwarded to upstream endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    /// Largest request body held in memory so it can be replayed on retry;
+    /// bigger bodies are streamed upstream and sent only once
+    pub replay_buffer_bytes: usize,
+    /// Request body cap for endpoints that do not set their own max_request_size
+    pub max_request_body_bytes: u64,
+    /// Hex-encoded 256-bit key encrypting stored upstream credentials;
+    /// endpoints cannot store credentials while it is unset
+    pub upstream_credentials_key: Option<String>,
+    /// How long responses to requests sent with an Idempotency-Key are kept for replay
+    pub idempotency_ttl_secs: u64,
+    /// Relay CORS preflights on /proxy routes to the upstream instead of answering them locally
+    pub forward_cors_preflight: bool,
+    /// How long a streamed (SSE or chunked) response may go without sending data
+    /// before it is cut off; streams have no overall timeout
+    pub stream_idle_timeout_secs: u64,
+    /// Request events buffered per user for live usage streams; clients falling
+    /// further behind miss the oldest
+    pub usage_stream_buffer: usize,
+    /// How often live usage streams send an aggregate usage snapshot
+    pub usage_snapshot_interval_secs: u64,
+}
+
+/// Active probing of endpoint upstreams
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Consecutive failed probes before an endpoint is marked degraded
+    pub failure_threshold: u32,
+    /// Share of the normal price charged while an endpoint is degraded (0-100)
+    pub degraded_price_percent: u32,
+    /// Deactivate degraded endpoints instead of only discounting them
+    pub auto_deactivate: bool,
+}
+
+/// Usage spike detection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// How many times its usual hourly rate a user must call an endpoint in the
+    /// trailing hour to raise an alert
+    pub spike_multiplier: f64,
+    /// Fewest trailing-hour requests that can raise an alert, however low the baseline
+    pub min_requests: i64,
+    /// Days of daily stats the usual hourly rate is averaged over
+    pub baseline_days: u32,
+    /// How long after an alert no new alert is raised for the same user and endpoint
+    pub cooldown_secs: u64,
+}
+
+/// Nightly aggregation of request logs into daily stats
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyStatsConfig {
+    pub enabled: bool,
+    /// How many past days are checked for missing stats on each run
+    pub backfill_days: u32,
+}
+
+/// Periodic comparison of users' ledger balances with their billing contract balances
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Largest drift, in ledger units, corrected in the ledger without review; unset to
+    /// flag every drift for review
+    pub auto_correct_threshold: Option<CostAmount>,
+}
+
+/// Scheduled billing runs, which queue closed months' usage to be billed on-chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillingConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+/// Delivery of queued webhook events to users' webhook endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub poll_interval_secs: u64,
+    /// How long an endpoint has to answer a delivery
+    pub timeout_secs: u64,
+    /// Delay before a failed delivery is first retried, doubling with each attempt
+    pub retry_backoff_secs: u64,
+}
+
+/// Request log retention: monthly partitions and anonymization of deleted users' logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    /// How long a deleted user's request logs stay linked to the account
+    pub deleted_user_log_days: u32,
+    /// How long request logs are kept; 0 keeps them indefinitely
+    pub request_log_days: u32,
+    /// How many months of request log partitions, starting with the current one, exist ahead of time
+    pub partition_months_ahead: u32,
+    /// How long captured request samples are kept
+    pub request_sample_days: u32,
+    pub interval_secs: u64,
+}
+
+/// Usage exports of raw request logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportConfig {
+    /// Exports with more rows than this are written in the background and downloaded
+    /// by id instead of streamed in the response
+    pub async_row_threshold: u64,
+    /// Directory background exports are written to
+    pub directory: String,
+}
+
 /// Feature flags for enabling experimental or optional functionality
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeatureFlags {
@@ -82,6 +345,8 @@ pub struct FeatureFlags {
     pub enable_analytics: bool,
     pub enable_webhooks: bool,
     pub enable_batch_billing: bool,
+    /// Accept EIP-1271 signatures from smart-contract wallets at sign-in
+    pub enable_contract_wallets: bool,
 }
 
 impl Config {
@@ -101,29 +366,73 @@ impl Config {
             database_url: env::var("DATABASE_URL")
                 .context("DATABASE_URL environment variable is required")?,
             
+            database_read_url: env::var("DATABASE_READ_URL").ok().filter(|url| !url.is_empty()),
+            
+            database_pool: DatabasePoolConfig {
+                max_connections: env::var("DATABASE_POOL_MAX_CONNECTIONS")
+                    .unwrap_or_else(|_| "20".to_string())
+                    .parse()
+                    .context("Invalid DATABASE_POOL_MAX_CONNECTIONS")?,
+                
+                min_connections: env::var("DATABASE_POOL_MIN_CONNECTIONS")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .context("Invalid DATABASE_POOL_MIN_CONNECTIONS")?,
+                
+                acquire_timeout_seconds: env::var("DATABASE_POOL_ACQUIRE_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid DATABASE_POOL_ACQUIRE_TIMEOUT_SECS")?,
+                
+                idle_timeout_seconds: env::var("DATABASE_POOL_IDLE_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "600".to_string())
+                    .parse()
+                    .context("Invalid DATABASE_POOL_IDLE_TIMEOUT_SECS")?,
+                
+                max_lifetime_seconds: env::var("DATABASE_POOL_MAX_LIFETIME_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid DATABASE_POOL_MAX_LIFETIME_SECS")?,
+            },
+            
             redis_url: env::var("REDIS_URL")
                 .unwrap_or_else(|_| "redis://localhost:6379".to_string()),
             
             blockchain: BlockchainConfig {
-                rpc_url: env::var("BLOCKCHAIN_RPC_URL")
-                    .context("BLOCKCHAIN_RPC_URL environment variable is required")?,
+                chains: {
+                    let settlement_chain_id = env::var("BLOCKCHAIN_CHAIN_ID")
+                        .unwrap_or_else(|_| "1".to_string())
+                        .parse()
+                        .context("Invalid BLOCKCHAIN_CHAIN_ID")?;
+                    let mut chains = vec![ChainConfig::from_env(settlement_chain_id, "")?];
+                    // Further chains are configured by the same variables, suffixed with their id
+                    for chain_id in env::var("BLOCKCHAIN_ADDITIONAL_CHAIN_IDS").unwrap_or_default().split(',') {
+                        let chain_id = chain_id.trim();
+                        if chain_id.is_empty() {
+                            continue;
+                        }
+                        let chain_id = chain_id.parse()
+                            .with_context(|| format!("Invalid BLOCKCHAIN_ADDITIONAL_CHAIN_IDS entry {}", chain_id))?;
+                        chains.push(ChainConfig::from_env(chain_id, &format!("_{}", chain_id))?);
+                    }
+                    chains
+                },
                 
-                chain_id: env::var("BLOCKCHAIN_CHAIN_ID")
-                    .unwrap_or_else(|_| "1".to_string())
+                token_decimals: env::var("BLOCKCHAIN_TOKEN_DECIMALS")
+                    .unwrap_or_else(|_| "6".to_string())
                     .parse()
-                    .context("Invalid BLOCKCHAIN_CHAIN_ID")?,
+                    .context("Invalid BLOCKCHAIN_TOKEN_DECIMALS")?,
                 
-                billing_contract_address: env::var("BILLING_CONTRACT_ADDRESS")
-                    .context("BILLING_CONTRACT_ADDRESS environment variable is required")?,
+                billing_abi_path: env::var("BILLING_CONTRACT_ABI_PATH")
+                    .unwrap_or_else(|_| "contracts/abi/AugustCreditsBilling.json".to_string()),
                 
-                metering_contract_address: env::var("METERING_CONTRACT_ADDRESS")
-                    .context("METERING_CONTRACT_ADDRESS environment variable is required")?,
+                metering_abi_path: env::var("METERING_CONTRACT_ABI_PATH")
+                    .unwrap_or_else(|_| "contracts/abi/AugustCreditsMetering.json".to_string()),
                 
-                payments_contract_address: env::var("PAYMENTS_CONTRACT_ADDRESS")
-                    .context("PAYMENTS_CONTRACT_ADDRESS environment variable is required")?,
+                payments_abi_path: env::var("PAYMENTS_CONTRACT_ABI_PATH")
+                    .unwrap_or_else(|_| "contracts/abi/AugustCreditsPayments.json".to_string()),
                 
-                private_key: env::var("BLOCKCHAIN_PRIVATE_KEY")
-                    .context("BLOCKCHAIN_PRIVATE_KEY environment variable is required")?,
+                private_key: env::var("BLOCKCHAIN_PRIVATE_KEY").ok().filter(|key| !key.is_empty()),
                 
                 gas_limit: env::var("BLOCKCHAIN_GAS_LIMIT")
                     .unwrap_or_else(|_| "3000000".to_string())
@@ -135,10 +444,31 @@ impl Config {
                     .parse()
                     .context("Invalid BLOCKCHAIN_GAS_PRICE_GWEI")?,
                 
-                confirmation_blocks: env::var("BLOCKCHAIN_CONFIRMATION_BLOCKS")
-                    .unwrap_or_else(|_| "3".to_string())
+                max_fee_multiplier: env::var("BLOCKCHAIN_MAX_FEE_MULTIPLIER")
+                    .unwrap_or_else(|_| "2.0".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_MAX_FEE_MULTIPLIER")?,
+                
+                priority_fee_multiplier: env::var("BLOCKCHAIN_PRIORITY_FEE_MULTIPLIER")
+                    .unwrap_or_else(|_| "1.0".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_PRIORITY_FEE_MULTIPLIER")?,
+                
+                gas_price_cache_secs: env::var("BLOCKCHAIN_GAS_PRICE_CACHE_SECS")
+                    .unwrap_or_else(|_| "15".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_GAS_PRICE_CACHE_SECS")?,
+                
+                confirmation_timeout_secs: env::var("BLOCKCHAIN_CONFIRMATION_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "600".to_string())
                     .parse()
-                    .context("Invalid BLOCKCHAIN_CONFIRMATION_BLOCKS")?,
+                    .context("Invalid BLOCKCHAIN_CONFIRMATION_TIMEOUT_SECS")?,
+                
+                confirmation_timeout_blocks: env::var("BLOCKCHAIN_CONFIRMATION_TIMEOUT_BLOCKS").ok()
+                    .filter(|blocks| !blocks.is_empty())
+                    .map(|blocks| blocks.parse())
+                    .transpose()
+                    .context("Invalid BLOCKCHAIN_CONFIRMATION_TIMEOUT_BLOCKS")?,
                 
                 retry_attempts: env::var("BLOCKCHAIN_RETRY_ATTEMPTS")
                     .unwrap_or_else(|_| "3".to_string())
@@ -149,6 +479,47 @@ impl Config {
                     .unwrap_or_else(|_| "1000".to_string())
                     .parse()
                     .context("Invalid BLOCKCHAIN_RETRY_DELAY_MS")?,
+                
+                withdrawal_daily_limit: env::var("WITHDRAWAL_DAILY_LIMIT").ok()
+                    .filter(|limit| !limit.is_empty())
+                    .map(|limit| limit.parse())
+                    .transpose()
+                    .context("Invalid WITHDRAWAL_DAILY_LIMIT")?,
+                
+                events_enabled: env::var("BLOCKCHAIN_EVENTS_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_EVENTS_ENABLED")?,
+                
+                events_poll_interval_secs: env::var("BLOCKCHAIN_EVENTS_POLL_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "15".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_EVENTS_POLL_INTERVAL_SECS")?,
+                
+                tx_queue_enabled: env::var("BLOCKCHAIN_TX_QUEUE_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_TX_QUEUE_ENABLED")?,
+                
+                tx_queue_poll_interval_secs: env::var("BLOCKCHAIN_TX_QUEUE_POLL_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_TX_QUEUE_POLL_INTERVAL_SECS")?,
+                
+                tx_max_attempts: env::var("BLOCKCHAIN_TX_MAX_ATTEMPTS")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_TX_MAX_ATTEMPTS")?,
+                
+                tx_retry_backoff_secs: env::var("BLOCKCHAIN_TX_RETRY_BACKOFF_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_TX_RETRY_BACKOFF_SECS")?,
+                
+                billing_batch_gas_budget: env::var("BLOCKCHAIN_BILLING_BATCH_GAS_BUDGET")
+                    .unwrap_or_else(|_| "2500000".to_string())
+                    .parse()
+                    .context("Invalid BLOCKCHAIN_BILLING_BATCH_GAS_BUDGET")?,
             },
             
             auth: AuthConfig {
@@ -189,6 +560,24 @@ impl Config {
                     .unwrap_or_else(|_| "15".to_string())
                     .parse()
                     .context("Invalid LOCKOUT_DURATION_MINUTES")?,
+                
+                siwe_domain: env::var("SIWE_DOMAIN")
+                    .unwrap_or_else(|_| "localhost:8080".to_string()),
+                
+                siwe_uri: env::var("SIWE_URI")
+                    .unwrap_or_else(|_| "http://localhost:8080".to_string()),
+                
+                trusted_proxies: env::var("TRUSTED_PROXIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|proxy| proxy.trim().to_string())
+                    .filter(|proxy| !proxy.is_empty())
+                    .collect(),
+                
+                cleanup_interval_secs: env::var("AUTH_CLEANUP_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid AUTH_CLEANUP_INTERVAL_SECS")?,
             },
             
             rate_limiting: RateLimitingConfig {
@@ -219,6 +608,31 @@ impl Config {
                 
                 redis_key_prefix: env::var("REDIS_KEY_PREFIX")
                     .unwrap_or_else(|_| "august_credits".to_string()),
+                
+                use_redis: env::var("RATE_LIMIT_USE_REDIS")
+                    .unwrap_or_else(|_| "false".to_string())
+                    .parse()
+                    .context("Invalid RATE_LIMIT_USE_REDIS")?,
+                
+                redis_local_fallback: env::var("RATE_LIMIT_REDIS_LOCAL_FALLBACK")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid RATE_LIMIT_REDIS_LOCAL_FALLBACK")?,
+                
+                persist_windows: env::var("RATE_LIMIT_PERSIST_WINDOWS")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid RATE_LIMIT_PERSIST_WINDOWS")?,
+                
+                cleanup_interval_secs: env::var("RATE_LIMIT_CLEANUP_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .context("Invalid RATE_LIMIT_CLEANUP_INTERVAL_SECS")?,
+                
+                max_local_entries: env::var("RATE_LIMIT_MAX_LOCAL_ENTRIES")
+                    .unwrap_or_else(|_| "100000".to_string())
+                    .parse()
+                    .context("Invalid RATE_LIMIT_MAX_LOCAL_ENTRIES")?,
             },
             
             monitoring: MonitoringConfig {
@@ -246,6 +660,211 @@ impl Config {
                     .unwrap_or_else(|_| "august_credits".to_string()),
             },
             
+            gateway: GatewayConfig {
+                replay_buffer_bytes: env::var("PROXY_REPLAY_BUFFER_BYTES")
+                    .unwrap_or_else(|_| "1048576".to_string())
+                    .parse()
+                    .context("Invalid PROXY_REPLAY_BUFFER_BYTES")?,
+                
+                max_request_body_bytes: env::var("MAX_REQUEST_BODY_BYTES")
+                    .unwrap_or_else(|_| "10485760".to_string())
+                    .parse()
+                    .context("Invalid MAX_REQUEST_BODY_BYTES")?,
+                
+                upstream_credentials_key: env::var("UPSTREAM_CREDENTIALS_KEY").ok()
+                    .filter(|key| !key.is_empty()),
+                
+                idempotency_ttl_secs: env::var("IDEMPOTENCY_KEY_TTL_SECS")
+                    .unwrap_or_else(|_| "86400".to_string())
+                    .parse()
+                    .context("Invalid IDEMPOTENCY_KEY_TTL_SECS")?,
+                
+                forward_cors_preflight: env::var("FORWARD_CORS_PREFLIGHT")
+                    .unwrap_or_else(|_| "false".to_string())
+                    .parse()
+                    .context("Invalid FORWARD_CORS_PREFLIGHT")?,
+                
+                stream_idle_timeout_secs: env::var("STREAM_IDLE_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .context("Invalid STREAM_IDLE_TIMEOUT_SECS")?,
+                
+                usage_stream_buffer: env::var("USAGE_STREAM_BUFFER")
+                    .unwrap_or_else(|_| "256".to_string())
+                    .parse()
+                    .context("Invalid USAGE_STREAM_BUFFER")?,
+                
+                usage_snapshot_interval_secs: env::var("USAGE_SNAPSHOT_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid USAGE_SNAPSHOT_INTERVAL_SECS")?,
+            },
+            
+            health_check: HealthCheckConfig {
+                enabled: env::var("HEALTH_CHECK_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_ENABLED")?,
+                
+                interval_secs: env::var("HEALTH_CHECK_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_INTERVAL_SECS")?,
+                
+                timeout_secs: env::var("HEALTH_CHECK_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_TIMEOUT_SECS")?,
+                
+                failure_threshold: env::var("HEALTH_CHECK_FAILURE_THRESHOLD")
+                    .unwrap_or_else(|_| "3".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_FAILURE_THRESHOLD")?,
+                
+                degraded_price_percent: env::var("HEALTH_CHECK_DEGRADED_PRICE_PERCENT")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_DEGRADED_PRICE_PERCENT")?,
+                
+                auto_deactivate: env::var("HEALTH_CHECK_AUTO_DEACTIVATE")
+                    .unwrap_or_else(|_| "false".to_string())
+                    .parse()
+                    .context("Invalid HEALTH_CHECK_AUTO_DEACTIVATE")?,
+            },
+            
+            alerts: AlertConfig {
+                enabled: env::var("USAGE_ALERTS_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_ENABLED")?,
+                
+                interval_secs: env::var("USAGE_ALERTS_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_INTERVAL_SECS")?,
+                
+                spike_multiplier: env::var("USAGE_ALERTS_SPIKE_MULTIPLIER")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_SPIKE_MULTIPLIER")?,
+                
+                min_requests: env::var("USAGE_ALERTS_MIN_REQUESTS")
+                    .unwrap_or_else(|_| "100".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_MIN_REQUESTS")?,
+                
+                baseline_days: env::var("USAGE_ALERTS_BASELINE_DAYS")
+                    .unwrap_or_else(|_| "7".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_BASELINE_DAYS")?,
+                
+                cooldown_secs: env::var("USAGE_ALERTS_COOLDOWN_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid USAGE_ALERTS_COOLDOWN_SECS")?,
+            },
+            
+            daily_stats: DailyStatsConfig {
+                enabled: env::var("DAILY_STATS_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid DAILY_STATS_ENABLED")?,
+                
+                backfill_days: env::var("DAILY_STATS_BACKFILL_DAYS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid DAILY_STATS_BACKFILL_DAYS")?,
+            },
+            
+            retention: RetentionConfig {
+                enabled: env::var("RETENTION_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_ENABLED")?,
+                
+                deleted_user_log_days: env::var("RETENTION_DELETED_USER_LOG_DAYS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_DELETED_USER_LOG_DAYS")?,
+                
+                request_log_days: env::var("RETENTION_REQUEST_LOG_DAYS")
+                    .unwrap_or_else(|_| "90".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_REQUEST_LOG_DAYS")?,
+                
+                partition_months_ahead: env::var("RETENTION_PARTITION_MONTHS_AHEAD")
+                    .unwrap_or_else(|_| "3".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_PARTITION_MONTHS_AHEAD")?,
+                
+                request_sample_days: env::var("RETENTION_REQUEST_SAMPLE_DAYS")
+                    .unwrap_or_else(|_| "7".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_REQUEST_SAMPLE_DAYS")?,
+                
+                interval_secs: env::var("RETENTION_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid RETENTION_INTERVAL_SECS")?,
+            },
+            
+            reconciliation: ReconciliationConfig {
+                enabled: env::var("RECONCILIATION_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid RECONCILIATION_ENABLED")?,
+                
+                interval_secs: env::var("RECONCILIATION_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid RECONCILIATION_INTERVAL_SECS")?,
+                
+                auto_correct_threshold: env::var("RECONCILIATION_AUTO_CORRECT_THRESHOLD").ok()
+                    .filter(|threshold| !threshold.is_empty())
+                    .map(|threshold| threshold.parse())
+                    .transpose()
+                    .context("Invalid RECONCILIATION_AUTO_CORRECT_THRESHOLD")?,
+            },
+            
+            billing: BillingConfig {
+                enabled: env::var("BILLING_ENABLED")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid BILLING_ENABLED")?,
+                
+                interval_secs: env::var("BILLING_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .context("Invalid BILLING_INTERVAL_SECS")?,
+            },
+            
+            webhooks: WebhookConfig {
+                poll_interval_secs: env::var("WEBHOOK_POLL_INTERVAL_SECS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .context("Invalid WEBHOOK_POLL_INTERVAL_SECS")?,
+                
+                timeout_secs: env::var("WEBHOOK_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .context("Invalid WEBHOOK_TIMEOUT_SECS")?,
+                
+                retry_backoff_secs: env::var("WEBHOOK_RETRY_BACKOFF_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .context("Invalid WEBHOOK_RETRY_BACKOFF_SECS")?,
+            },
+            
+            exports: ExportConfig {
+                async_row_threshold: env::var("USAGE_EXPORT_ASYNC_ROW_THRESHOLD")
+                    .unwrap_or_else(|_| "100000".to_string())
+                    .parse()
+                    .context("Invalid USAGE_EXPORT_ASYNC_ROW_THRESHOLD")?,
+                
+                directory: env::var("USAGE_EXPORT_DIR")
+                    .unwrap_or_else(|_| "exports".to_string()),
+            },
+            
             features: FeatureFlags {
                 enable_escrow: env::var("ENABLE_ESCROW")
                     .unwrap_or_else(|_| "true".to_string())
@@ -276,6 +895,11 @@ impl Config {
                     .unwrap_or_else(|_| "true".to_string())
                     .parse()
                     .context("Invalid ENABLE_BATCH_BILLING")?,
+                
+                enable_contract_wallets: env::var("ENABLE_CONTRACT_WALLETS")
+                    .unwrap_or_else(|_| "true".to_string())
+                    .parse()
+                    .context("Invalid ENABLE_CONTRACT_WALLETS")?,
             },
         };
 
@@ -297,25 +921,107 @@ impl Config {
             anyhow::bail!("Database URL must be a valid PostgreSQL connection string");
         }
         
+        if let Some(read_url) = &self.database_read_url {
+            if !read_url.starts_with("postgres://") && !read_url.starts_with("postgresql://") {
+                anyhow::bail!("Database read URL must be a valid PostgreSQL connection string");
+            }
+        }
+
+        if self.database_pool.max_connections == 0 {
+            anyhow::bail!("Database pool must allow at least 1 connection");
+        }
+        
+        if self.database_pool.min_connections > self.database_pool.max_connections {
+            anyhow::bail!("Database pool minimum connections cannot exceed the maximum");
+        }
+        
+        if self.database_pool.acquire_timeout_seconds == 0 {
+            anyhow::bail!("Database pool acquire timeout must be at least 1 second");
+        }
+        
         // Validate blockchain configuration
-        if self.blockchain.rpc_url.is_empty() {
-            anyhow::bail!("Blockchain RPC URL cannot be empty");
+        for (i, chain) in self.blockchain.chains.iter().enumerate() {
+            if chain.rpc_url.is_empty() {
+                anyhow::bail!("Blockchain RPC URL of chain {} cannot be empty", chain.chain_id);
+            }
+            
+            if self.blockchain.chains[..i].iter().any(|other| other.chain_id == chain.chain_id) {
+                anyhow::bail!("Chain {} is configured more than once", chain.chain_id);
+            }
+            
+            for (contract, address) in [
+                ("billing", Some(&chain.billing_contract_address)),
+                ("metering", Some(&chain.metering_contract_address)),
+                ("payments", Some(&chain.payments_contract_address)),
+                ("token", chain.token_address.as_ref()),
+            ] {
+                if let Some(Err(reason)) = address.map(|address| crate::address::parse(address)) {
+                    anyhow::bail!("Invalid {} contract address on chain {}: {}", contract, chain.chain_id, reason);
+                }
+            }
+            
+            // One token per deployment, so balances paid in on any chain are alike
+            if chain.token_address.is_some() != self.blockchain.settlement_chain().token_address.is_some() {
+                anyhow::bail!("The token must be configured on every chain or on none");
+            }
         }
         
-        if self.blockchain.billing_contract_address.len() != 42 || !self.blockchain.billing_contract_address.starts_with("0x") {
-            anyhow::bail!("Invalid billing contract address format");
+        // Ledger amounts are decimals of at most 28 places
+        if self.blockchain.token_decimals > 28 {
+            anyhow::bail!("Token decimals must be at most 28");
         }
         
-        if self.blockchain.metering_contract_address.len() != 42 || !self.blockchain.metering_contract_address.starts_with("0x") {
-            anyhow::bail!("Invalid metering contract address format");
+        for (contract, path) in [
+            ("billing", &self.blockchain.billing_abi_path),
+            ("metering", &self.blockchain.metering_abi_path),
+            ("payments", &self.blockchain.payments_abi_path),
+        ] {
+            if !std::path::Path::new(path).is_file() {
+                anyhow::bail!("The {} contract ABI file {} does not exist", contract, path);
+            }
         }
         
-        if self.blockchain.payments_contract_address.len() != 42 || !self.blockchain.payments_contract_address.starts_with("0x") {
-            anyhow::bail!("Invalid payments contract address format");
+        if let Some(private_key) = &self.blockchain.private_key {
+            if private_key.len() != 64 && private_key.len() != 66 {
+                anyhow::bail!("Invalid private key format");
+            }
         }
         
-        if self.blockchain.private_key.len() != 64 && self.blockchain.private_key.len() != 66 {
-            anyhow::bail!("Invalid private key format");
+        if !(self.blockchain.max_fee_multiplier >= 1.0 && self.blockchain.max_fee_multiplier <= 10.0) {
+            anyhow::bail!("Max fee multiplier must be between 1 and 10");
+        }
+        
+        if !(self.blockchain.priority_fee_multiplier > 0.0 && self.blockchain.priority_fee_multiplier <= 10.0) {
+            anyhow::bail!("Priority fee multiplier must be greater than 0 and at most 10");
+        }
+        
+        if self.blockchain.events_poll_interval_secs == 0 {
+            anyhow::bail!("Contract event poll interval must be at least 1 second");
+        }
+        
+        if self.blockchain.confirmation_timeout_secs == 0 || self.blockchain.confirmation_timeout_blocks == Some(0) {
+            anyhow::bail!("Confirmation timeout must be at least 1 second and 1 block");
+        }
+        
+        if self.blockchain.tx_queue_poll_interval_secs == 0 || self.blockchain.tx_retry_backoff_secs == 0 {
+            anyhow::bail!("Transaction queue poll interval and retry backoff must be at least 1 second");
+        }
+        
+        if self.blockchain.tx_max_attempts == 0 {
+            anyhow::bail!("Queued transactions must be allowed at least 1 attempt");
+        }
+        
+        if self.blockchain.billing_batch_gas_budget == 0 || self.blockchain.billing_batch_gas_budget > self.blockchain.gas_limit {
+            anyhow::bail!("Billing batch gas budget must be between 1 and the gas limit");
+        }
+        
+        if let Some(limit) = self.blockchain.withdrawal_daily_limit {
+            if !limit.fits_decimals(self.blockchain.amount_decimals()) || limit.is_zero() || limit.is_sign_negative() {
+                anyhow::bail!(
+                    "Withdrawal daily limit must be positive, with at most {} decimal places",
+                    self.blockchain.amount_decimals()
+                );
+            }
         }
         
         // Validate auth configuration
@@ -323,6 +1029,120 @@ impl Config {
             anyhow::bail!("JWT secret must be at least 32 characters long");
         }
         
+        if self.auth.jwt_expiry_hours == 0 || self.auth.jwt_expiry_hours > 168 {
+            anyhow::bail!("JWT expiry must be between 1 and 168 hours");
+        }
+        
+        if self.auth.refresh_token_expiry_days == 0 || self.auth.refresh_token_expiry_days > 365 {
+            anyhow::bail!("Refresh token expiry must be between 1 and 365 days");
+        }
+        
+        if self.auth.siwe_domain.is_empty() || self.auth.siwe_domain.contains(char::is_whitespace) {
+            anyhow::bail!("SIWE domain must be a non-empty host without whitespace");
+        }
+        
+        if let Some(invalid) = self.auth.trusted_proxies.iter().find(|proxy| proxy.parse::<crate::auth::Cidr>().is_err()) {
+            anyhow::bail!("Invalid trusted proxy address: {}", invalid);
+        }
+        
+        if self.auth.cleanup_interval_secs == 0 {
+            anyhow::bail!("Auth cleanup interval must be at least 1 second");
+        }
+        
+        if self.rate_limiting.use_redis && redis::Client::open(self.redis_url.as_str()).is_err() {
+            anyhow::bail!("Redis URL must be a valid redis:// or rediss:// URL");
+        }
+        
+        if self.gateway.max_request_body_bytes == 0 {
+            anyhow::bail!("Max request body size must be at least 1 byte");
+        }
+        
+        if self.gateway.idempotency_ttl_secs == 0 {
+            anyhow::bail!("Idempotency key TTL must be at least 1 second");
+        }
+        
+        if self.gateway.usage_stream_buffer == 0 || self.gateway.usage_snapshot_interval_secs == 0 {
+            anyhow::bail!("Usage stream buffer and snapshot interval must be at least 1");
+        }
+        
+        if let Some(key) = &self.gateway.upstream_credentials_key {
+            if hex::decode(key).map(|bytes| bytes.len()) != Ok(32) {
+                anyhow::bail!("Upstream credentials key must be 64 hex characters (32 bytes)");
+            }
+        }
+        
+        if self.health_check.interval_secs == 0 || self.health_check.timeout_secs == 0 {
+            anyhow::bail!("Health check interval and timeout must be at least 1 second");
+        }
+        
+        if self.health_check.failure_threshold == 0 {
+            anyhow::bail!("Health check failure threshold must be at least 1");
+        }
+        
+        if self.health_check.degraded_price_percent > 100 {
+            anyhow::bail!("Degraded price percent must be between 0 and 100");
+        }
+        
+        if self.alerts.interval_secs == 0 || self.alerts.baseline_days == 0 {
+            anyhow::bail!("Usage alert interval and baseline days must be at least 1");
+        }
+        
+        if !self.alerts.spike_multiplier.is_finite() || self.alerts.spike_multiplier <= 1.0 {
+            anyhow::bail!("Usage alert spike multiplier must be greater than 1");
+        }
+        
+        if self.alerts.min_requests < 1 {
+            anyhow::bail!("Usage alert minimum requests must be at least 1");
+        }
+        
+        if self.daily_stats.backfill_days == 0 {
+            anyhow::bail!("Daily stats backfill days must be at least 1");
+        }
+        
+        if self.retention.interval_secs == 0 {
+            anyhow::bail!("Retention interval must be at least 1 second");
+        }
+        
+        if self.retention.partition_months_ahead < 2 {
+            anyhow::bail!("Request log partitions must be created at least 2 months ahead");
+        }
+        
+        if self.retention.request_sample_days == 0 {
+            anyhow::bail!("Request samples must be kept for at least 1 day");
+        }
+        
+        if self.reconciliation.interval_secs == 0 {
+            anyhow::bail!("Reconciliation interval must be at least 1 second");
+        }
+        
+        if self.billing.interval_secs == 0 {
+            anyhow::bail!("Billing interval must be at least 1 second");
+        }
+        
+        if self.webhooks.poll_interval_secs == 0
+            || self.webhooks.timeout_secs == 0
+            || self.webhooks.retry_backoff_secs == 0
+        {
+            anyhow::bail!("Webhook poll interval, timeout and retry backoff must be at least 1 second");
+        }
+        
+        if let Some(threshold) = self.reconciliation.auto_correct_threshold {
+            if !threshold.fits_decimals(self.blockchain.amount_decimals()) || threshold.is_zero() || threshold.is_sign_negative() {
+                anyhow::bail!(
+                    "Reconciliation auto-correct threshold must be positive, with at most {} decimal places",
+                    self.blockchain.amount_decimals()
+                );
+            }
+        }
+        
+        if self.exports.directory.is_empty() {
+            anyhow::bail!("Usage export directory cannot be empty");
+        }
+        
+        if self.auth.max_login_attempts == 0 {
+            anyhow::bail!("Max login attempts must be at least 1");
+        }
+        
         if self.auth.bcrypt_cost < 4 || self.auth.bcrypt_cost > 31 {
             anyhow::bail!("Bcrypt cost must be between 4 and 31");
         }
@@ -340,6 +1160,14 @@ impl Config {
             anyhow::bail!("Default burst size must be greater than 0");
         }
         
+        if self.rate_limiting.cleanup_interval_secs == 0 {
+            anyhow::bail!("Rate limit cleanup interval must be greater than 0");
+        }
+        
+        if self.rate_limiting.max_local_entries == 0 {
+            anyhow::bail!("Rate limit cache size must be greater than 0");
+        }
+        
         // Validate monitoring
         if self.monitoring.metrics_port == 0 {
             anyhow::bail!("Metrics port must be greater than 0");
@@ -351,19 +1179,7 @@ impl Config {
     /// Get the full blockchain RPC URL with authentication if needed
     /// Returns the configured blockchain RPC endpoint URL
     pub fn get_blockchain_rpc_url(&self) -> String {
-        self.blockchain.rpc_url.clone()
-    }
-    
-    /// Get the database connection pool configuration
-    /// Creates database connection pool configuration from settings
-    pub fn get_database_pool_config(&self) -> DatabasePoolConfig {
-        DatabasePoolConfig {
-            max_connections: 20,
-            min_connections: 5,
-            acquire_timeout_seconds: 30,
-            idle_timeout_seconds: 600,
-            max_lifetime_seconds: 3600,
-        }
+        self.blockchain.settlement_chain().rpc_url.clone()
     }
     
     /// Check if a feature is enabled
@@ -376,21 +1192,12 @@ impl Config {
             "analytics" => self.features.enable_analytics,
             "webhooks" => self.features.enable_webhooks,
             "batch_billing" => self.features.enable_batch_billing,
+            "contract_wallets" => self.features.enable_contract_wallets,
             _ => false,
         }
     }
 }
 
-/// Database connection pool configuration parameters
-#[derive(Debug, Clone)]
-pub struct DatabasePoolConfig {
-    pub max_connections: u32,
-    pub min_connections: u32,
-    pub acquire_timeout_seconds: u64,
-    pub idle_timeout_seconds: u64,
-    pub max_lifetime_seconds: u64,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -410,6 +1217,109 @@ mod tests {
         
         let config = Config::load();
         assert!(config.is_ok());
+        
+        let mut config = config.unwrap();
+        config.auth.jwt_expiry_hours = 0;
+        assert!(config.validate().is_err());
+        
+        config.auth.jwt_expiry_hours = 24;
+        config.auth.refresh_token_expiry_days = 10_000;
+        assert!(config.validate().is_err());
+        
+        config.auth.refresh_token_expiry_days = 30;
+        config.health_check.degraded_price_percent = 101;
+        assert!(config.validate().is_err());
+        
+        config.health_check.degraded_price_percent = 0;
+        config.alerts.spike_multiplier = 1.0;
+        assert!(config.validate().is_err());
+        
+        config.alerts.spike_multiplier = 5.0;
+        config.database_pool.min_connections = config.database_pool.max_connections + 1;
+        assert!(config.validate().is_err());
+        
+        config.database_pool.min_connections = 0;
+        config.database_read_url = Some("mysql://replica/test".to_string());
+        assert!(config.validate().is_err());
+        
+        config.database_read_url = None;
+        config.gateway.upstream_credentials_key = Some("abcd".to_string());
+        assert!(config.validate().is_err());
+        config.gateway.upstream_credentials_key = Some("ab".repeat(32));
+        assert!(config.validate().is_ok());
+        
+        // No private key runs read-only, but a malformed one is refused
+        config.blockchain.private_key = None;
+        assert!(config.validate().is_ok());
+        config.blockchain.private_key = Some("0x1234".to_string());
+        assert!(config.validate().is_err());
+        config.blockchain.private_key = Some("12".repeat(32));
+        assert!(config.validate().is_ok());
+        
+        config.redis_url = "localhost:6379".to_string();
+        assert!(config.validate().is_ok());
+        config.rate_limiting.use_redis = true;
+        assert!(config.validate().is_err());
+        config.redis_url = "redis://localhost:6379".to_string();
+        assert!(config.validate().is_ok());
+        
+        config.blockchain.metering_abi_path = "contracts/abi/Missing.json".to_string();
+        assert!(config.validate().is_err());
+        
+        config.blockchain.metering_abi_path = "contracts/abi/AugustCreditsMetering.json".to_string();
+        config.blockchain.max_fee_multiplier = 0.5;
+        assert!(config.validate().is_err());
+        config.blockchain.max_fee_multiplier = 2.0;
+        config.blockchain.priority_fee_multiplier = 0.0;
+        assert!(config.validate().is_err());
+        config.blockchain.priority_fee_multiplier = 1.5;
+        assert!(config.validate().is_ok());
+        
+        config.blockchain.tx_max_attempts = 0;
+        assert!(config.validate().is_err());
+        config.blockchain.tx_max_attempts = 5;
+        config.blockchain.tx_retry_backoff_secs = 0;
+        assert!(config.validate().is_err());
+        config.blockchain.tx_retry_backoff_secs = 30;
+        assert!(config.validate().is_ok());
+        
+        config.blockchain.billing_batch_gas_budget = config.blockchain.gas_limit + 1;
+        assert!(config.validate().is_err());
+        config.blockchain.billing_batch_gas_budget = 0;
+        assert!(config.validate().is_err());
+        config.blockchain.billing_batch_gas_budget = config.blockchain.gas_limit;
+        assert!(config.validate().is_ok());
+        
+        config.reconciliation.auto_correct_threshold = Some("0.5".parse().unwrap());
+        assert!(config.validate().is_err());
+        config.reconciliation.auto_correct_threshold = Some("1000".parse().unwrap());
+        assert!(config.validate().is_ok());
+        
+        // Token deployments keep amounts in token units, down to the token's decimals
+        config.blockchain.chains[0].token_address = Some("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".to_string());
+        config.reconciliation.auto_correct_threshold = Some("0.5".parse().unwrap());
+        assert!(config.validate().is_ok());
+        config.reconciliation.auto_correct_threshold = Some("0.0000005".parse().unwrap());
+        assert!(config.validate().is_err());
+        config.reconciliation.auto_correct_threshold = None;
+        config.blockchain.chains[0].token_address = Some("USDC".to_string());
+        assert!(config.validate().is_err());
+        config.blockchain.chains[0].token_address = None;
+        assert!(config.validate().is_ok());
+        
+        // Further chains are checked like the first, and must each be distinct
+        let mut l2 = config.blockchain.chains[0].clone();
+        l2.chain_id = 42161;
+        config.blockchain.chains.push(l2);
+        assert!(config.validate().is_ok());
+        config.blockchain.chains[1].payments_contract_address = "0x123".to_string();
+        assert!(config.validate().is_err());
+        config.blockchain.chains[1].payments_contract_address = config.blockchain.chains[0].payments_contract_address.clone();
+        config.blockchain.chains[1].token_address = Some("0xaf88d065e77c8cC2239327C5EDb3A432268e5831".to_string());
+        assert!(config.validate().is_err());
+        config.blockchain.chains[1].token_address = None;
+        config.blockchain.chains[1].chain_id = config.blockchain.chains[0].chain_id;
+        assert!(config.validate().is_err());
     }
     
     /// Tests feature flag checking functionality
@@ -0,0 +1,97 @@
+//! Daily stats aggregation for AugustCredits
+//!
+//! Background task that rolls each finished UTC day of request logs up into
+//! `daily_stats`, so endpoint stats and admin analytics read one row per day instead
+//! of scanning `request_logs`. It runs at startup and shortly after every midnight,
+//! and aggregates any recent day that is still missing, so a gateway that was down
+//! overnight catches up on its next run.
+
+use crate::{
+    config::{Config, DailyStatsConfig},
+    database::Database,
+};
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// How long after midnight a day is aggregated, so requests still in flight at
+/// midnight have been logged
+const SETTLE_DELAY: ChronoDuration = ChronoDuration::minutes(10);
+
+/// Materializes daily stats for finished days
+#[derive(Clone)]
+pub struct DailyStatsJob {
+    database: Arc<Database>,
+    config: DailyStatsConfig,
+}
+
+impl DailyStatsJob {
+    /// Creates a job that checks the configured number of past days on every run
+    pub fn new(config: &Config, database: Arc<Database>) -> Self {
+        Self {
+            database,
+            config: config.daily_stats.clone(),
+        }
+    }
+
+    /// Aggregates missing days now and then every night until the process exits
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.run(settled_today(Utc::now())).await {
+                    error!("Daily stats aggregation failed: {}", e);
+                }
+                let wait = next_run_after(Utc::now()) - Utc::now();
+                tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+            }
+        })
+    }
+
+    /// Aggregates each of the configured number of days before `today` that has no
+    /// stats yet, returning the days aggregated
+    pub async fn run(&self, today: NaiveDate) -> Result<Vec<NaiveDate>> {
+        let start = today - ChronoDuration::days(self.config.backfill_days as i64);
+        let aggregated = self.database.backfill_daily_stats(start, today).await?;
+        if !aggregated.is_empty() {
+            info!("Aggregated daily stats for {} day(s)", aggregated.len());
+        }
+        Ok(aggregated)
+    }
+}
+
+/// The first day not yet finished and settled at `now`
+fn settled_today(now: DateTime<Utc>) -> NaiveDate {
+    (now - SETTLE_DELAY).date_naive()
+}
+
+/// The next time after `now` at which a day becomes settled
+fn next_run_after(now: DateTime<Utc>) -> DateTime<Utc> {
+    let next_day = settled_today(now).succ_opt().unwrap_or(NaiveDate::MAX);
+    next_day.and_hms_opt(0, 0, 0).unwrap().and_utc() + SETTLE_DELAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Tests that a day is only aggregated once it has settled, and that runs are
+    /// scheduled for the moment the next day does
+    #[test]
+    fn test_schedule() {
+        let evening = Utc.with_ymd_and_hms(2024, 3, 31, 22, 15, 0).unwrap();
+        assert_eq!(settled_today(evening), NaiveDate::from_ymd_opt(2024, 3, 31).unwrap());
+        assert_eq!(next_run_after(evening), Utc.with_ymd_and_hms(2024, 4, 1, 0, 10, 0).unwrap());
+
+        // Just after midnight the previous day is still settling
+        let settling = Utc.with_ymd_and_hms(2024, 4, 1, 0, 5, 0).unwrap();
+        assert_eq!(settled_today(settling), NaiveDate::from_ymd_opt(2024, 3, 31).unwrap());
+        assert_eq!(next_run_after(settling), Utc.with_ymd_and_hms(2024, 4, 1, 0, 10, 0).unwrap());
+
+        let settled = Utc.with_ymd_and_hms(2024, 4, 1, 0, 10, 0).unwrap();
+        assert_eq!(settled_today(settled), NaiveDate::from_ymd_opt(2024, 4, 1).unwrap());
+        assert_eq!(next_run_after(settled), Utc.with_ymd_and_hms(2024, 4, 2, 0, 10, 0).unwrap());
+    }
+}
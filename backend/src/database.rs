@@ -5,36 +5,72 @@
 //! for users, API endpoints, usage tracking, and billing records.
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use futures::{stream::BoxStream, StreamExt};
 
 use sqlx::{
     postgres::{PgPool, PgPoolOptions},
+    types::Json,
     Row, Transaction, Postgres,
 };
 use std::time::Duration;
 use tracing::info;
 use uuid::Uuid;
 
-use crate::models::*;
+use crate::{
+    config::DatabasePoolConfig,
+    models::*,
+    samples::{DEFAULT_SAMPLE_BODY_BYTES, DEFAULT_SAMPLE_RATE},
+};
+
+/// Rows deleted per statement when old request logs are removed without partitions
+const LOG_CLEANUP_BATCH_SIZE: i64 = 10_000;
+
+/// Columns the admin user list can be sorted by through `PaginationParams::sort_by`
+pub const USER_SORT_COLUMNS: [&str; 6] = ["created_at", "last_login", "wallet_address", "email", "username", "tier"];
 
 /// Main database service with connection pooling
 pub struct Database {
     pool: PgPool,
+    /// Optional replica pool that heavy read-only analytics queries run on
+    read_pool: Option<PgPool>,
 }
 
 impl Database {
-    /// Creates a new database connection with optimized pool settings
-    pub async fn new(database_url: &str, max_connections: u32) -> Result<Self> {
-        let pool = PgPoolOptions::new()
-            .max_connections(max_connections)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(database_url)
+    /// Creates a new database connection with the configured pool settings
+    pub async fn new(database_url: &str, pool_config: &DatabasePoolConfig) -> Result<Self> {
+        let pool = Self::connect_pool(database_url, pool_config)
             .await
             .context("Failed to connect to database")?;
 
-        info!("Connected to database with {} max connections", max_connections);
+        info!(
+            "Connected to database with {}-{} pooled connections",
+            pool_config.min_connections, pool_config.max_connections
+        );
         
-        Ok(Self { pool })
+        Ok(Self { pool, read_pool: None })
+    }
+
+    /// Sends analytics queries to a read replica instead of the primary
+    pub async fn with_read_replica(mut self, read_url: &str, pool_config: &DatabasePoolConfig) -> Result<Self> {
+        let read_pool = Self::connect_pool(read_url, pool_config)
+            .await
+            .context("Failed to connect to read replica")?;
+
+        info!("Connected to read replica for analytics queries");
+        self.read_pool = Some(read_pool);
+        Ok(self)
+    }
+
+    async fn connect_pool(url: &str, pool_config: &DatabasePoolConfig) -> std::result::Result<PgPool, sqlx::Error> {
+        PgPoolOptions::new()
+            .max_connections(pool_config.max_connections)
+            .min_connections(pool_config.min_connections)
+            .acquire_timeout(Duration::from_secs(pool_config.acquire_timeout_seconds))
+            .idle_timeout((pool_config.idle_timeout_seconds > 0).then(|| Duration::from_secs(pool_config.idle_timeout_seconds)))
+            .max_lifetime((pool_config.max_lifetime_seconds > 0).then(|| Duration::from_secs(pool_config.max_lifetime_seconds)))
+            .connect(url)
+            .await
     }
     
     /// Runs pending database migrations
@@ -48,12 +84,18 @@ impl Database {
         Ok(())
     }
     
-    /// Verifies database connectivity
+    /// Verifies connectivity to the primary and, when configured, the read replica
     pub async fn health_check(&self) -> Result<()> {
         sqlx::query_scalar::<_, i32>("SELECT 1")
             .fetch_one(&self.pool)
             .await
             .context("Database health check failed")?;
+        if let Some(read_pool) = &self.read_pool {
+            sqlx::query_scalar::<_, i32>("SELECT 1")
+                .fetch_one(read_pool)
+                .await
+                .context("Read replica health check failed")?;
+        }
         Ok(())
     }
     
@@ -62,11 +104,26 @@ impl Database {
         &self.pool
     }
 
+    /// Returns the pool read-only analytics queries run on: the read replica when one
+    /// is configured, the primary otherwise
+    pub fn reader(&self) -> &PgPool {
+        self.read_pool.as_ref().unwrap_or(&self.pool)
+    }
+
+    /// Returns the number of open connections and how many of them are idle
+    pub fn pool_status(&self) -> (u32, usize) {
+        (self.pool.size(), self.pool.num_idle())
+    }
+
+    /// Returns [`Self::pool_status`] for the read replica, if one is configured
+    pub fn read_pool_status(&self) -> Option<(u32, usize)> {
+        self.read_pool.as_ref().map(|pool| (pool.size(), pool.num_idle()))
+    }
+
     /// Creates a test database instance with minimal connections
     #[cfg(test)]
     pub async fn new_test() -> Result<Self> {
-        use crate::config::Config;
-        let config = Config::load().context("Failed to load test config")?;
+        let config = crate::test_support::config();
         
         let pool = PgPoolOptions::new()
             .max_connections(5)
@@ -75,7 +132,19 @@ impl Database {
             .await
             .context("Failed to connect to test database")?;
             
-        Ok(Self { pool })
+        Ok(Self { pool, read_pool: None })
+    }
+    
+    /// Creates a database handle that connects on first use, for tests that
+    /// never reach the database
+    #[cfg(test)]
+    pub fn new_lazy(database_url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(database_url)
+            .context("Failed to create lazy database pool")?;
+            
+        Ok(Self { pool, read_pool: None })
     }
     
     /// User account management operations
@@ -90,7 +159,7 @@ impl Database {
             INSERT INTO users (wallet_address, api_key, email, username, tier, created_at, updated_at)
             VALUES ($1, $2, $3, $4, $5, $6, $7)
             RETURNING id, wallet_address, api_key, email, username, is_active, created_at, updated_at, 
-                      last_login, tier, monthly_limit, rate_limit_override
+                      last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
             "#
         )
         .bind(&request.wallet_address)
@@ -113,8 +182,8 @@ impl Database {
         let user = sqlx::query_as::<_, User>(
             r#"
             SELECT id, wallet_address, api_key, email, username, is_active, created_at, updated_at,
-                   last_login, tier, monthly_limit, rate_limit_override
-            FROM users WHERE id = $1
+                   last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
+            FROM users WHERE id = $1 AND deleted_at IS NULL
             "#
         )
         .bind(user_id)
@@ -130,8 +199,8 @@ impl Database {
         let user = sqlx::query_as::<_, User>(
             r#"
             SELECT id, wallet_address, api_key, email, username, is_active, created_at, updated_at,
-                   last_login, tier, monthly_limit, rate_limit_override
-            FROM users WHERE api_key = $1 AND is_active = true
+                   last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
+            FROM users WHERE api_key = $1 AND is_active = true AND deleted_at IS NULL
             "#
         )
         .bind(api_key)
@@ -147,8 +216,8 @@ impl Database {
         let user = sqlx::query_as::<_, User>(
             r#"
             SELECT id, wallet_address, api_key, email, username, is_active, created_at, updated_at,
-                   last_login, tier, monthly_limit, rate_limit_override
-            FROM users WHERE wallet_address = $1
+                   last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
+            FROM users WHERE LOWER(wallet_address) = LOWER($1) AND deleted_at IS NULL
             "#
         )
         .bind(wallet_address)
@@ -159,6 +228,40 @@ impl Database {
         Ok(user)
     }
     
+    /// Wallet addresses of users after `after` that may have been stored before wallets
+    /// were checksummed, in id order
+    ///
+    /// Only addresses without mixed case can lack a checksum, so the rest are left out.
+    pub async fn list_unchecksummed_wallets(&self, after: Uuid, limit: i64) -> Result<Vec<(Uuid, String)>> {
+        let wallets = sqlx::query_as::<_, (Uuid, String)>(
+            r#"
+            SELECT id, wallet_address FROM users
+            WHERE id > $1 AND (wallet_address !~ '[A-F]' OR wallet_address !~ '[a-f]')
+            ORDER BY id
+            LIMIT $2
+            "#
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list wallet addresses")?;
+
+        Ok(wallets)
+    }
+
+    /// Replaces a user's stored wallet address with another form of the same address
+    pub async fn set_wallet_address(&self, user_id: Uuid, wallet_address: &str) -> Result<()> {
+        sqlx::query("UPDATE users SET wallet_address = $2 WHERE id = $1 AND LOWER(wallet_address) = LOWER($2)")
+            .bind(user_id)
+            .bind(wallet_address)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update wallet address")?;
+
+        Ok(())
+    }
+
     /// Updates user profile information
     pub async fn update_user(&self, user_id: Uuid, request: UpdateUserRequest) -> Result<User> {
         let now = Utc::now();
@@ -167,15 +270,18 @@ impl Database {
             r#"
             UPDATE users SET
                 email = COALESCE($2, email),
+                -- A changed address has to be verified again
+                email_verified = CASE WHEN $2 IS NULL OR $2 = email THEN email_verified ELSE false END,
                 username = COALESCE($3, username),
                 is_active = COALESCE($4, is_active),
                 tier = COALESCE($5, tier),
                 monthly_limit = COALESCE($6, monthly_limit),
                 rate_limit_override = COALESCE($7, rate_limit_override),
+                overage_policy = COALESCE($9, overage_policy),
                 updated_at = $8
-            WHERE id = $1
+            WHERE id = $1 AND deleted_at IS NULL
             RETURNING id, wallet_address, api_key, email, username, is_active, created_at, updated_at,
-                      last_login, tier, monthly_limit, rate_limit_override
+                      last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
             "#
         )
         .bind(user_id)
@@ -186,6 +292,7 @@ impl Database {
         .bind(request.monthly_limit)
         .bind(request.rate_limit_override)
         .bind(now)
+        .bind(request.overage_policy.map(Json))
         .fetch_one(&self.pool)
         .await
         .context("Failed to update user")?;
@@ -193,24 +300,63 @@ impl Database {
         Ok(user)
     }
     
-    /// Lists all users with pagination support
-    pub async fn list_users(&self, pagination: crate::models::Pagination) -> Result<Vec<User>> {
-        let users = sqlx::query_as::<_, User>(
+    /// Lists live users matching the admin filters a page at a time, newest first
+    /// unless `params` sorts by one of [`USER_SORT_COLUMNS`]
+    pub async fn list_users(&self, query: &UserListQuery, params: PaginationParams) -> Result<PaginatedResponse<User>> {
+        let limit = params.limit.unwrap_or(50);
+        let page = params.page.unwrap_or(1).max(1);
+        let offset = (page as i64 - 1) * limit as i64;
+        let column = params.sort_by.as_deref()
+            .and_then(|sort_by| USER_SORT_COLUMNS.into_iter().find(|column| *column == sort_by))
+            .unwrap_or("created_at");
+        let direction = match params.sort_order.unwrap_or_default() {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        let pattern = query.q.as_deref().map(|q| format!("%{}%", escape_like(q)));
+
+        let filter = r#"
+            deleted_at IS NULL
+            AND ($1::TEXT IS NULL OR wallet_address ILIKE $1 OR email ILIKE $1 OR username ILIKE $1)
+            AND ($2::user_tier IS NULL OR tier = $2)
+            AND ($3::BOOLEAN IS NULL OR is_active = $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+            AND ($5::TIMESTAMPTZ IS NULL OR created_at < $5)
+        "#;
+
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM users WHERE {}", filter))
+            .bind(&pattern)
+            .bind(&query.tier)
+            .bind(query.is_active)
+            .bind(query.created_after)
+            .bind(query.created_before)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count users")?;
+
+        let users = sqlx::query_as::<_, User>(&format!(
             r#"
             SELECT id, wallet_address, api_key, email, username, is_active, created_at, updated_at,
-                   last_login, tier, monthly_limit, rate_limit_override
+                   last_login, tier, monthly_limit, rate_limit_override, email_verified, overage_policy
             FROM users
-            ORDER BY created_at DESC
-            LIMIT $1 OFFSET $2
-            "#
-        )
-        .bind(pagination.limit.unwrap_or(100))
-        .bind(pagination.offset.unwrap_or(0))
+            WHERE {}
+            ORDER BY {} {} NULLS LAST, id
+            LIMIT $6 OFFSET $7
+            "#,
+            filter, column, direction
+        ))
+        .bind(&pattern)
+        .bind(&query.tier)
+        .bind(query.is_active)
+        .bind(query.created_after)
+        .bind(query.created_before)
+        .bind(limit as i64)
+        .bind(offset)
         .fetch_all(&self.pool)
         .await
         .context("Failed to list users")?;
         
-        Ok(users)
+        Ok(PaginatedResponse::new(users, total, page, limit))
     }
 
     /// Updates the last login timestamp for a user
@@ -229,669 +375,7073 @@ impl Database {
         Ok(())
     }
     
-    // === API Endpoint Management ===
+    // === Sign-in Nonces ===
     
-    /// Registers a new monetizable API endpoint
-    pub async fn create_endpoint(&self, owner_id: Uuid, request: CreateEndpointRequest) -> Result<ApiEndpoint> {
-        let now = Utc::now();
-        let allowed_methods = request.allowed_methods.unwrap_or_else(|| vec!["GET".to_string()]);
-        
-        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+    /// Stores a nonce issued to a wallet for signature challenges
+    pub async fn create_auth_nonce(&self, nonce: &str, wallet_address: &str, expires_at: DateTime<Utc>) -> Result<AuthNonce> {
+        let record = sqlx::query_as::<_, AuthNonce>(
             r#"
-            INSERT INTO api_endpoints (name, description, owner_id, upstream_url, price_per_request,
-                                     rate_limit, rate_limit_window, requires_auth, allowed_methods,
-                                     request_timeout, retry_attempts, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
-            RETURNING id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                      created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                      allowed_methods, request_timeout, retry_attempts
+            INSERT INTO auth_nonces (nonce, wallet_address, expires_at)
+            VALUES ($1, $2, $3)
+            RETURNING nonce, wallet_address, expires_at, created_at
             "#
         )
-        .bind(&request.name)
-        .bind(&request.description)
-        .bind(owner_id)
-        .bind(&request.upstream_url)
-        .bind(&request.price_per_request)
-        .bind(request.rate_limit)
-        .bind(request.rate_limit_window)
-        .bind(request.requires_auth.unwrap_or(true))
-        .bind(&allowed_methods)
-        .bind(request.request_timeout)
-        .bind(request.retry_attempts)
-        .bind(now)
-        .bind(now)
+        .bind(nonce)
+        .bind(wallet_address)
+        .bind(expires_at)
         .fetch_one(&self.pool)
         .await
-        .context("Failed to create API endpoint")?;
+        .context("Failed to create auth nonce")?;
         
-        info!("Created API endpoint: {} (ID: {})", endpoint.name, endpoint.id);
-        Ok(endpoint)
+        Ok(record)
     }
     
-    /// Retrieves endpoint details by ID
-    pub async fn get_endpoint_by_id(&self, endpoint_id: Uuid) -> Result<Option<ApiEndpoint>> {
-        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+    /// Deletes a nonce and returns it, so each nonce can only be consumed once
+    pub async fn consume_auth_nonce(&self, nonce: &str) -> Result<Option<AuthNonce>> {
+        let record = sqlx::query_as::<_, AuthNonce>(
             r#"
-            SELECT id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                   allowed_methods, request_timeout, retry_attempts
-            FROM api_endpoints WHERE id = $1
+            DELETE FROM auth_nonces WHERE nonce = $1
+            RETURNING nonce, wallet_address, expires_at, created_at
             "#
         )
-        .bind(endpoint_id)
+        .bind(nonce)
         .fetch_optional(&self.pool)
         .await
-        .context("Failed to get endpoint by ID")?;
+        .context("Failed to consume auth nonce")?;
         
-        Ok(endpoint)
+        Ok(record)
     }
     
-    /// Finds endpoint by its unique name
-    pub async fn get_endpoint_by_name(&self, name: &str) -> Result<Option<ApiEndpoint>> {
-        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+    // === Email Verification ===
+    
+    /// Stores a verification token for a user's email, replacing any unused ones
+    pub async fn create_verification_token(
+        &self,
+        user_id: Uuid,
+        token_hash: &str,
+        email: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<VerificationToken> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        sqlx::query("DELETE FROM verification_tokens WHERE user_id = $1 AND used_at IS NULL")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to remove previous verification tokens")?;
+        
+        let token = sqlx::query_as::<_, VerificationToken>(
             r#"
-            SELECT id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                   allowed_methods, request_timeout, retry_attempts
-            FROM api_endpoints WHERE name = $1 AND is_active = true
+            INSERT INTO verification_tokens (user_id, token_hash, email, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, token_hash, email, expires_at, used_at, created_at
             "#
         )
-        .bind(name)
-        .fetch_optional(&self.pool)
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(email)
+        .bind(expires_at)
+        .fetch_one(&mut *tx)
         .await
-        .context("Failed to get endpoint by name")?;
+        .context("Failed to create verification token")?;
         
-        Ok(endpoint)
+        tx.commit().await.context("Failed to commit verification token")?;
+        Ok(token)
     }
     
-    /// Updates endpoint configuration and pricing
-    pub async fn update_endpoint(&self, endpoint_id: Uuid, request: UpdateEndpointRequest) -> Result<ApiEndpoint> {
-        let now = Utc::now();
+    /// Consumes an unexpired verification token and marks the user's email as verified
+    ///
+    /// Returns the verified user's ID, or `None` if the token is unknown, used,
+    /// expired, or was issued for an address the user has since changed.
+    pub async fn verify_email_token(&self, token_hash: &str) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
         
-        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+        let token = sqlx::query_as::<_, VerificationToken>(
             r#"
-            UPDATE api_endpoints SET
-                description = COALESCE($2, description),
-                upstream_url = COALESCE($3, upstream_url),
-                price_per_request = COALESCE($4, price_per_request),
-                is_active = COALESCE($5, is_active),
-                rate_limit = COALESCE($6, rate_limit),
-                rate_limit_window = COALESCE($7, rate_limit_window),
-                requires_auth = COALESCE($8, requires_auth),
-                allowed_methods = COALESCE($9, allowed_methods),
-                request_timeout = COALESCE($10, request_timeout),
-                retry_attempts = COALESCE($11, retry_attempts),
-                updated_at = $12
-            WHERE id = $1
-            RETURNING id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                      created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                      allowed_methods, request_timeout, retry_attempts
+            UPDATE verification_tokens SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING id, user_id, token_hash, email, expires_at, used_at, created_at
             "#
         )
-        .bind(endpoint_id)
-        .bind(request.description)
-        .bind(request.upstream_url)
-        .bind(request.price_per_request)
-        .bind(request.is_active)
-        .bind(request.rate_limit)
-        .bind(request.rate_limit_window)
-        .bind(request.requires_auth)
-        .bind(request.allowed_methods)
-        .bind(request.request_timeout)
-        .bind(request.retry_attempts)
-        .bind(now)
-        .fetch_one(&self.pool)
+        .bind(token_hash)
+        .fetch_optional(&mut *tx)
         .await
-        .context("Failed to update endpoint")?;
-        
-        Ok(endpoint)
-    }
-    
-    /// Lists endpoints with optional owner filtering and pagination
-    pub async fn list_endpoints(&self, owner_id: Option<Uuid>, params: PaginationParams) -> Result<PaginatedResponse<ApiEndpoint>> {
-        let limit = params.limit.unwrap_or(50) as i64;
-        let page = params.page.unwrap_or(1) as i64;
-        let offset = (page - 1) * limit;
+        .context("Failed to consume verification token")?;
         
-        let (total_query, endpoints_query) = if let Some(owner_id) = owner_id {
-            (
-                sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints WHERE owner_id = $1")
-                    .bind(owner_id),
-                sqlx::query_as::<_, ApiEndpoint>(
-                    r#"
-                    SELECT id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                           created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                           allowed_methods, request_timeout, retry_attempts
-                    FROM api_endpoints 
-                    WHERE owner_id = $1
-                    ORDER BY created_at DESC
-                    LIMIT $2 OFFSET $3
-                    "#
-                )
-                .bind(owner_id)
-                .bind(limit)
-                .bind(offset)
-            )
-        } else {
-            (
-                sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints"),
-                sqlx::query_as::<_, ApiEndpoint>(
-                    r#"
-                    SELECT id, name, description, owner_id, upstream_url, price_per_request, is_active,
-                           created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
-                           allowed_methods, request_timeout, retry_attempts
-                    FROM api_endpoints 
-                    ORDER BY created_at DESC
-                    LIMIT $1 OFFSET $2
-                    "#
-                )
-                .bind(limit)
-                .bind(offset)
-            )
+        let Some(token) = token else {
+            return Ok(None);
         };
         
-        let total: i64 = total_query
-            .fetch_one(&self.pool)
-            .await
-            .context("Failed to get total endpoint count")?;
-            
-        let endpoints = endpoints_query
-            .fetch_all(&self.pool)
-            .await
-            .context("Failed to list endpoints")?;
+        let result = sqlx::query(
+            "UPDATE users SET email_verified = true, updated_at = NOW() WHERE id = $1 AND email = $2"
+        )
+        .bind(token.user_id)
+        .bind(&token.email)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark email as verified")?;
         
-        Ok(PaginatedResponse {
-            data: endpoints,
-            total,
-            page: page as u32,
-            limit: limit as u32,
-            total_pages: ((total + limit - 1) / limit) as u32,
-        })
+        if result.rows_affected() != 1 {
+            return Ok(None);
+        }
+        
+        tx.commit().await.context("Failed to commit email verification")?;
+        Ok(Some(token.user_id))
     }
     
-    // === Request Logging ===
+    // === Login Attempts ===
     
-    /// Logs API request details for debugging and analytics
-    pub async fn create_request_log(&self, request: CreateRequestLogRequest) -> Result<RequestLog> {
-        let now = Utc::now();
+    /// Returns when the wallet's lockout ends, if it is currently locked out
+    pub async fn get_login_lockout(&self, wallet_address: &str) -> Result<Option<DateTime<Utc>>> {
+        let locked_until: Option<DateTime<Utc>> = sqlx::query_scalar(
+            "SELECT locked_until FROM login_attempts WHERE wallet_address = $1 AND locked_until > NOW()"
+        )
+        .bind(wallet_address)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get login lockout")?
+        .flatten();
         
-        let log = sqlx::query_as::<_, RequestLog>(
+        Ok(locked_until)
+    }
+    
+    /// Records a failed sign-in and locks the wallet once `max_attempts` is reached
+    ///
+    /// Returns the end of the lockout if the wallet is now locked out.
+    pub async fn record_failed_login(&self, wallet_address: &str, max_attempts: u32, lockout: chrono::Duration) -> Result<Option<DateTime<Utc>>> {
+        let locked_until: Option<DateTime<Utc>> = sqlx::query_scalar(
             r#"
-            INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code,
-                                    response_time_ms, request_size, response_size, ip_address_hash,
-                                    user_agent_hash, timestamp, cost, error_message)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
-            RETURNING id, user_id, endpoint_id, request_id, method, path, status_code,
-                      response_time_ms, request_size, response_size, ip_address_hash,
-                      user_agent_hash, timestamp, cost, error_message
+            INSERT INTO login_attempts (wallet_address, failed_attempts, locked_until, updated_at)
+            VALUES ($1, CASE WHEN $2 <= 1 THEN 0 ELSE 1 END, CASE WHEN $2 <= 1 THEN NOW() + $3 END, NOW())
+            ON CONFLICT (wallet_address) DO UPDATE SET
+                failed_attempts = CASE
+                    WHEN login_attempts.failed_attempts + 1 >= $2 THEN 0
+                    ELSE login_attempts.failed_attempts + 1
+                END,
+                locked_until = CASE
+                    WHEN login_attempts.failed_attempts + 1 >= $2 THEN NOW() + $3
+                    ELSE login_attempts.locked_until
+                END,
+                updated_at = NOW()
+            RETURNING CASE WHEN locked_until > NOW() THEN locked_until END
             "#
         )
-        .bind(request.user_id)
-        .bind(request.endpoint_id)
-        .bind(&request.request_id)
-        .bind(&request.method)
-        .bind(&request.path)
-        .bind(request.status_code)
-        .bind(request.response_time_ms)
-        .bind(request.request_size)
-        .bind(request.response_size)
-        .bind(&request.ip_address_hash)
-        .bind(&request.user_agent_hash)
-        .bind(now)
-        .bind(&request.cost)
-        .bind(&request.error_message)
+        .bind(wallet_address)
+        .bind(max_attempts as i32)
+        .bind(lockout)
         .fetch_one(&self.pool)
         .await
-        .context("Failed to create request log")?;
+        .context("Failed to record failed login")?;
         
-        Ok(log)
+        Ok(locked_until)
     }
     
-    /// Records API usage for billing purposes
-    pub async fn create_usage_record(&self, user_id: Uuid, endpoint_id: Uuid, request_count: i64, total_cost: &str, billing_period: &str) -> Result<UsageRecord> {
-        let now = Utc::now();
+    /// Clears failed sign-in attempts after a successful login
+    pub async fn reset_login_attempts(&self, wallet_address: &str) -> Result<()> {
+        sqlx::query("DELETE FROM login_attempts WHERE wallet_address = $1")
+            .bind(wallet_address)
+            .execute(&self.pool)
+            .await
+            .context("Failed to reset login attempts")?;
         
-        let record = sqlx::query_as::<_, UsageRecord>(
+        Ok(())
+    }
+    
+    // === Refresh Tokens ===
+    
+    /// Stores a hashed refresh token belonging to a token family
+    pub async fn create_refresh_token(&self, user_id: Uuid, token_hash: &str, family_id: Uuid, expires_at: DateTime<Utc>) -> Result<RefreshToken> {
+        let token = sqlx::query_as::<_, RefreshToken>(
             r#"
-            INSERT INTO usage_records (user_id, endpoint_id, request_count, total_cost, billing_period,
-                                     status, timestamp)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING id, user_id, endpoint_id, request_count, total_cost, billing_period,
-                      status, transaction_hash, gas_used, block_number, timestamp
+            INSERT INTO refresh_tokens (user_id, token_hash, family_id, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, token_hash, family_id, expires_at, revoked, created_at
             "#
         )
         .bind(user_id)
-        .bind(endpoint_id)
-        .bind(request_count)
-        .bind(total_cost)
-        .bind(billing_period)
-        .bind(UsageStatus::Pending)
-        .bind(now)
+        .bind(token_hash)
+        .bind(family_id)
+        .bind(expires_at)
         .fetch_one(&self.pool)
         .await
-        .context("Failed to create usage record")?;
+        .context("Failed to create refresh token")?;
         
-        Ok(record)
+        Ok(token)
     }
     
-    /// Retrieves usage history for a specific user within date range
-    pub async fn get_user_usage(&self, user_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<Vec<UsageRecord>> {
-        let records = sqlx::query_as::<_, UsageRecord>(
+    /// Looks up a refresh token by its hash
+    pub async fn get_refresh_token_by_hash(&self, token_hash: &str) -> Result<Option<RefreshToken>> {
+        let token = sqlx::query_as::<_, RefreshToken>(
             r#"
-            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
-                   status, transaction_hash, gas_used, block_number, created_at, updated_at
-            FROM usage_records 
-            WHERE user_id = $1 AND created_at BETWEEN $2 AND $3
-            ORDER BY created_at DESC
+            SELECT id, user_id, token_hash, family_id, expires_at, revoked, created_at
+            FROM refresh_tokens WHERE token_hash = $1
             "#
         )
-        .bind(user_id)
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_all(&self.pool)
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
         .await
-        .context("Failed to get user usage")?;
+        .context("Failed to get refresh token")?;
         
-        Ok(records)
+        Ok(token)
     }
-
-    // === Analytics ===
     
-    /// Calculates total API requests across all endpoints
-    pub async fn get_total_requests(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
-        let count = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM request_logs WHERE created_at BETWEEN $1 AND $2"
+    /// Revokes a single refresh token, returning false if it was already revoked
+    pub async fn revoke_refresh_token(&self, token_id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = true WHERE id = $1 AND revoked = false"
         )
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_one(&self.pool)
+        .bind(token_id)
+        .execute(&self.pool)
         .await
-        .context("Failed to get total requests")?;
+        .context("Failed to revoke refresh token")?;
         
-        Ok(count)
+        Ok(result.rows_affected() == 1)
     }
-
-    /// Calculates total platform revenue from API usage
-    pub async fn get_total_revenue(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<String> {
-        let revenue = sqlx::query_scalar(
-            "SELECT COALESCE(SUM(total_cost::numeric), 0) FROM usage_records WHERE created_at BETWEEN $1 AND $2"
+    
+    /// Revokes every refresh token issued from the same login
+    pub async fn revoke_refresh_token_family(&self, family_id: Uuid) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = true WHERE family_id = $1 AND revoked = false"
         )
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_one(&self.pool)
+        .bind(family_id)
+        .execute(&self.pool)
         .await
-        .context("Failed to get total revenue")?;
+        .context("Failed to revoke refresh token family")?;
         
-        Ok(revenue)
+        Ok(result.rows_affected())
     }
-
-    /// Counts new user registrations in date range
-    pub async fn get_new_users(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
-        let count = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM users WHERE created_at BETWEEN $1 AND $2"
+    
+    // === Access Token Revocation ===
+    
+    /// Records an access token as revoked until it would have expired anyway
+    pub async fn revoke_access_token(&self, jti: &str, user_id: Uuid, expires_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO revoked_tokens (jti, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING
+            "#
         )
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_one(&self.pool)
+        .bind(jti)
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(&self.pool)
         .await
-        .context("Failed to get new users count")?;
+        .context("Failed to revoke access token")?;
         
-        Ok(count)
+        Ok(())
     }
-
-    /// Counts users who made API calls in date range
-    pub async fn get_active_users(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
-        let count = sqlx::query_scalar(
-            "SELECT COUNT(DISTINCT user_id) FROM request_logs WHERE created_at BETWEEN $1 AND $2"
+    
+    /// Revokes every access and refresh token issued to a user up to now
+    pub async fn revoke_all_user_tokens(&self, user_id: Uuid) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        Self::revoke_user_tokens_in(&mut tx, user_id).await?;
+        tx.commit().await.context("Failed to commit token revocation")?;
+        Ok(())
+    }
+    
+    /// Deactivates a user and revokes their outstanding tokens, returning false if no such user exists
+    pub async fn deactivate_user(&self, user_id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let result = sqlx::query(
+            "UPDATE users SET is_active = false, updated_at = NOW() WHERE id = $1"
         )
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_one(&self.pool)
+        .bind(user_id)
+        .execute(&mut *tx)
         .await
-        .context("Failed to get active users count")?;
+        .context("Failed to deactivate user")?;
         
-        Ok(count)
+        if result.rows_affected() != 1 {
+            return Ok(false);
+        }
+        
+        Self::revoke_user_tokens_in(&mut tx, user_id).await?;
+        
+        tx.commit().await.context("Failed to commit user deactivation")?;
+        Ok(true)
     }
-
-    /// Finds users with unpaid usage records for billing
-    pub async fn get_users_with_outstanding_usage(&self) -> Result<Vec<User>> {
-        let users = sqlx::query_as::<_, User>(
+    
+    /// Soft-deletes a user, returning false if no such live user exists
+    ///
+    /// The account is deactivated, its API keys and tokens revoked and its endpoints
+    /// taken offline. Subscriptions it holds or sells stop renewing. Request logs stay
+    /// linked until [`Self::anonymize_deleted_user_logs`] runs past the retention window.
+    pub async fn delete_user(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let deleted = sqlx::query(
             r#"
-            SELECT DISTINCT u.id, u.wallet_address, u.api_key, u.email, u.username, u.is_active, 
-                           u.created_at, u.updated_at, u.last_login, u.tier, u.monthly_limit, u.rate_limit_override
-            FROM users u
-            INNER JOIN usage_records ur ON u.id = ur.user_id
-            WHERE ur.status = 'pending'
+            UPDATE users SET deleted_at = $2, is_active = false, updated_at = $2
+            WHERE id = $1 AND deleted_at IS NULL
             "#
         )
-        .fetch_all(&self.pool)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
         .await
-        .context("Failed to get users with outstanding usage")?;
+        .context("Failed to delete user")?
+        .rows_affected() > 0;
         
-        Ok(users)
-    }
-    
-    /// Retrieves usage statistics for a specific endpoint
-    pub async fn get_endpoint_usage(&self, endpoint_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<Vec<UsageRecord>> {
-        let records = sqlx::query_as::<_, UsageRecord>(
+        if !deleted {
+            return Ok(false);
+        }
+        
+        sqlx::query("UPDATE api_keys SET is_active = false WHERE user_id = $1 AND is_active = true")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to revoke API keys of deleted user")?;
+        
+        Self::revoke_user_tokens_in(&mut tx, user_id).await?;
+        
+        sqlx::query(
             r#"
-            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
-                   status, transaction_hash, gas_used, block_number, created_at, updated_at
-            FROM usage_records 
-            WHERE endpoint_id = $1 AND created_at BETWEEN $2 AND $3
-            ORDER BY created_at DESC
+            UPDATE api_endpoints SET is_active = false, updated_at = $2
+            WHERE owner_id = $1 AND deleted_at IS NULL
             "#
         )
-        .bind(endpoint_id)
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_all(&self.pool)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
         .await
-        .context("Failed to get endpoint usage")?;
+        .context("Failed to deactivate endpoints of deleted user")?;
         
-        Ok(records)
-    }
-    
-    /// Gets usage records ready for blockchain billing
-    pub async fn get_pending_billing(&self, limit: i64) -> Result<Vec<UsageRecord>> {
-        let records = sqlx::query_as::<_, UsageRecord>(
+        sqlx::query(
             r#"
-            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
-                   status, transaction_hash, gas_used, block_number, created_at, updated_at
-            FROM usage_records 
-            WHERE status = 'pending'
-            ORDER BY created_at ASC
-            LIMIT $1
+            UPDATE endpoint_subscriptions SET cancel_at_period_end = true, updated_at = $2
+            WHERE status = 'active'
+              AND (user_id = $1 OR endpoint_id IN (SELECT id FROM api_endpoints WHERE owner_id = $1))
             "#
         )
-        .bind(limit)
-        .fetch_all(&self.pool)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
         .await
-        .context("Failed to get pending billing records")?;
+        .context("Failed to cancel subscriptions of deleted user")?;
         
-        Ok(records)
+        tx.commit().await.context("Failed to commit user deletion")?;
+        Ok(true)
     }
     
-    /// Updates usage record status after blockchain transaction
-    pub async fn update_usage_status(&self, record_id: Uuid, status: UsageStatus, transaction_hash: Option<&str>, gas_used: Option<&str>, block_number: Option<i64>) -> Result<()> {
-        let now = Utc::now();
+    /// Anonymizes the request logs of users deleted before `deleted_before`, clearing
+    /// the user linkage and client fingerprints; returns the number of users processed
+    pub async fn anonymize_deleted_user_logs(&self, deleted_before: DateTime<Utc>) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let user_ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM users
+            WHERE deleted_at < $1 AND logs_anonymized_at IS NULL
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .bind(deleted_before)
+        .fetch_all(&mut *tx)
+        .await
+        .context("Failed to get users pending anonymization")?;
+        
+        if user_ids.is_empty() {
+            return Ok(0);
+        }
         
         sqlx::query(
             r#"
-            UPDATE usage_records SET
-                status = $2,
-                transaction_hash = $3,
-                gas_used = $4,
-                block_number = $5,
-                updated_at = $6
-            WHERE id = $1
+            UPDATE request_logs SET user_id = NULL, api_key_id = NULL, ip_address_hash = '', user_agent_hash = NULL
+            WHERE user_id = ANY($1)
             "#
         )
-        .bind(record_id)
-        .bind(status)
-        .bind(transaction_hash)
-        .bind(gas_used)
-        .bind(block_number)
-        .bind(now)
-        .execute(&self.pool)
+        .bind(&user_ids)
+        .execute(&mut *tx)
         .await
-        .context("Failed to update usage status")?;
+        .context("Failed to anonymize request logs")?;
         
-        Ok(())
+        sqlx::query("UPDATE users SET logs_anonymized_at = NOW() WHERE id = ANY($1)")
+            .bind(&user_ids)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to mark users anonymized")?;
+        
+        tx.commit().await.context("Failed to commit request log anonymization")?;
+        Ok(user_ids.len() as u64)
     }
     
-    // === Rate Limiting ===
-    
-    /// Checks current rate limit status for user-endpoint combination
-    pub async fn check_rate_limit(&self, user_id: Uuid, endpoint_id: Uuid, window_duration: Duration) -> Result<(i32, i32)> {
-        let window_start = Utc::now() - chrono::Duration::from_std(window_duration)
-            .context("Invalid window duration")?;
-        
-        let current_count: i64 = sqlx::query_scalar(
+    async fn revoke_user_tokens_in(tx: &mut Transaction<'_, Postgres>, user_id: Uuid) -> Result<()> {
+        sqlx::query(
             r#"
-            SELECT COUNT(*) FROM request_logs 
-            WHERE user_id = $1 AND endpoint_id = $2 AND created_at >= $3
+            INSERT INTO user_token_revocations (user_id, revoked_before)
+            VALUES ($1, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET revoked_before = EXCLUDED.revoked_before
             "#
         )
         .bind(user_id)
-        .bind(endpoint_id)
-        .bind(window_start)
-        .fetch_one(&self.pool)
+        .execute(&mut **tx)
         .await
-        .context("Failed to check current request count")?;
+        .context("Failed to revoke user access tokens")?;
         
-        // Get rate limit from endpoint or user override
-        let rate_limit: Option<i32> = sqlx::query_scalar(
-            r#"
-            SELECT COALESCE(u.rate_limit_override, e.rate_limit) 
-            FROM users u, api_endpoints e 
-            WHERE u.id = $1 AND e.id = $2
-            "#
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked = true WHERE user_id = $1 AND revoked = false"
         )
         .bind(user_id)
-        .bind(endpoint_id)
-        .fetch_optional(&self.pool)
+        .execute(&mut **tx)
         .await
-        .context("Failed to get rate limit")?;
+        .context("Failed to revoke user refresh tokens")?;
         
-        let limit = rate_limit.unwrap_or(100); // Default rate limit
-        Ok((current_count as i32, limit))
+        Ok(())
     }
     
-    // === Daily Statistics ===
-    
-    /// Retrieves cached daily statistics
-    pub async fn get_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<Option<DailyStats>> {
-        let stats = sqlx::query_as::<_, DailyStats>(
+    /// Returns true if the access token was revoked individually or by a user-wide revocation
+    pub async fn is_access_token_revoked(&self, jti: &str, user_id: Uuid, issued_at: DateTime<Utc>) -> Result<bool> {
+        let revoked: bool = sqlx::query_scalar(
             r#"
-            SELECT date, endpoint_id, user_id, request_count, total_revenue, unique_users,
-                   avg_response_time, error_rate, created_at, updated_at
-            FROM daily_stats 
-            WHERE date = $1 AND endpoint_id = $2 AND user_id = $3
+            SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+                OR EXISTS (
+                    SELECT 1 FROM user_token_revocations
+                    WHERE user_id = $2 AND revoked_before >= $3
+                )
             "#
         )
-        .bind(date)
-        .bind(endpoint_id)
+        .bind(jti)
         .bind(user_id)
-        .fetch_optional(&self.pool)
+        .bind(issued_at)
+        .fetch_one(&self.pool)
         .await
-        .context("Failed to get daily stats")?;
+        .context("Failed to check access token revocation")?;
         
-        Ok(stats)
+        Ok(revoked)
     }
     
-    /// Generates and caches daily statistics
-    pub async fn create_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<DailyStats> {
-        let now = Utc::now();
-        let (request_count, total_revenue, unique_users, avg_response_time, error_rate) = 
-            self.calculate_daily_stats(date, endpoint_id, user_id).await?;
+    // === Auth Cleanup ===
+    
+    /// Deletes sign-in nonces, refresh tokens and access token revocations that expired
+    /// before `now`; none of them can be used or checked once expired
+    pub async fn cleanup_expired_auth_rows(&self, now: DateTime<Utc>) -> Result<ExpiredAuthRows> {
+        let nonces = sqlx::query("DELETE FROM auth_nonces WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .context("Failed to cleanup expired nonces")?
+            .rows_affected();
         
-        let stats = sqlx::query_as::<_, DailyStats>(
+        let refresh_tokens = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .context("Failed to cleanup expired refresh tokens")?
+            .rows_affected();
+        
+        let revoked_tokens = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .context("Failed to cleanup expired token revocations")?
+            .rows_affected();
+        
+        Ok(ExpiredAuthRows { nonces, refresh_tokens, revoked_tokens })
+    }
+    
+    // === API Keys ===
+    
+    /// Stores a hashed named API key for a user
+    pub async fn create_api_key(&self, user_id: Uuid, key_hash: &str, request: &CreateApiKeyRequest) -> Result<ApiKey> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
             r#"
-            INSERT INTO daily_stats (date, endpoint_id, user_id, request_count, total_revenue,
-                                   unique_users, avg_response_time, error_rate, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-            ON CONFLICT (date, endpoint_id, user_id) DO UPDATE SET
-                request_count = EXCLUDED.request_count,
-                total_revenue = EXCLUDED.total_revenue,
-                unique_users = EXCLUDED.unique_users,
-                avg_response_time = EXCLUDED.avg_response_time,
-                error_rate = EXCLUDED.error_rate,
-                updated_at = EXCLUDED.updated_at
-            RETURNING date, endpoint_id, user_id, request_count, total_revenue, unique_users,
-                      avg_response_time, error_rate, created_at, updated_at
+            INSERT INTO api_keys (user_id, key_hash, name, permissions, expires_at, rate_limit_override, allowed_ips)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, user_id, key_hash, name, permissions, is_active, expires_at, last_used,
+                      created_at, usage_count, rate_limit_override, allowed_ips
             "#
         )
-        .bind(date)
-        .bind(endpoint_id)
         .bind(user_id)
-        .bind(request_count)
-        .bind(&total_revenue)
-        .bind(unique_users)
-        .bind(avg_response_time)
-        .bind(error_rate)
-        .bind(now)
-        .bind(now)
+        .bind(key_hash)
+        .bind(request.name.trim())
+        .bind(request.permissions.as_deref().unwrap_or_default())
+        .bind(request.expires_at)
+        .bind(request.rate_limit_override)
+        .bind(request.allowed_ips.as_deref())
         .fetch_one(&self.pool)
         .await
-        .context("Failed to create daily stats")?;
+        .context("Failed to create API key")?;
         
-        Ok(stats)
+        Ok(api_key)
     }
     
-    /// Calculates daily metrics from raw usage data
-    async fn calculate_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<(i64, String, i32, f64, f64)> {
-        let start_of_day = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
-        let end_of_day = date.and_hms_opt(23, 59, 59).unwrap().and_utc();
-        
-        let row = sqlx::query(
+    /// Lists all API keys belonging to a user, newest first
+    pub async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        let api_keys = sqlx::query_as::<_, ApiKey>(
             r#"
-            SELECT
-                COUNT(*) as total_requests,
-                COALESCE(SUM(cost::numeric), 0)::text as total_cost,
-                COUNT(DISTINCT user_id) as unique_users,
-                COALESCE(AVG(response_time_ms), 0) as avg_response_time,
-                COALESCE(AVG(CASE WHEN status_code >= 400 THEN 1.0 ELSE 0.0 END), 0) as error_rate
-            FROM request_logs
-            WHERE timestamp BETWEEN $1 AND $2
-                AND ($3::uuid IS NULL OR endpoint_id = $3)
-                AND ($4::uuid IS NULL OR user_id = $4)
+            SELECT id, user_id, key_hash, name, permissions, is_active, expires_at, last_used,
+                   created_at, usage_count, rate_limit_override, allowed_ips
+            FROM api_keys WHERE user_id = $1
+            ORDER BY created_at DESC
             "#
         )
-        .bind(start_of_day)
-        .bind(end_of_day)
-        .bind(endpoint_id)
         .bind(user_id)
-        .fetch_one(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .context("Failed to calculate daily stats")?;
+        .context("Failed to list API keys")?;
         
-        Ok((
-            row.get::<i64, _>("total_requests"),
-            row.get::<String, _>("total_cost"),
-            row.get::<i64, _>("unique_users") as i32,
-            row.get::<Option<f64>, _>("avg_response_time").unwrap_or(0.0),
-            row.get::<Option<f64>, _>("error_rate").unwrap_or(0.0),
-        ))
+        Ok(api_keys)
     }
     
-    // === Transaction Management ===
-    
-    /// Starts a database transaction for atomic operations
-    pub async fn begin_transaction(&self) -> Result<Transaction<'_, Postgres>> {
-        self.pool.begin().await.context("Failed to begin transaction")
+    /// Gets one of a user's API keys, active or revoked
+    pub async fn get_api_key(&self, key_id: Uuid, user_id: Uuid) -> Result<Option<ApiKey>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            SELECT id, user_id, key_hash, name, permissions, is_active, expires_at, last_used,
+                   created_at, usage_count, rate_limit_override, allowed_ips
+            FROM api_keys WHERE id = $1 AND user_id = $2
+            "#
+        )
+        .bind(key_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get API key")?;
+        
+        Ok(api_key)
     }
     
-    // === Maintenance ===
-    
-    /// Removes old request logs to manage database size
-    pub async fn cleanup_old_logs(&self, days: i32) -> Result<u64> {
-        let cutoff_date = Utc::now() - chrono::Duration::days(days as i64);
+    /// Looks up an active API key by its hash
+    pub async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            SELECT id, user_id, key_hash, name, permissions, is_active, expires_at, last_used,
+                   created_at, usage_count, rate_limit_override, allowed_ips
+            FROM api_keys WHERE key_hash = $1 AND is_active = true
+            "#
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get API key")?;
         
+        Ok(api_key)
+    }
+    
+    /// Deactivates a user's API key, returning false if no active key matched
+    pub async fn revoke_api_key(&self, key_id: Uuid, user_id: Uuid) -> Result<bool> {
         let result = sqlx::query(
-            "DELETE FROM request_logs WHERE timestamp < $1"
+            "UPDATE api_keys SET is_active = false WHERE id = $1 AND user_id = $2 AND is_active = true"
         )
-        .bind(cutoff_date)
+        .bind(key_id)
+        .bind(user_id)
         .execute(&self.pool)
         .await
-        .context("Failed to cleanup old logs")?;
+        .context("Failed to revoke API key")?;
         
-        info!("Cleaned up {} old request logs", result.rows_affected());
-        Ok(result.rows_affected())
+        Ok(result.rows_affected() == 1)
     }
     
-    /// Optimizes database performance with vacuum and analyze
-    pub async fn vacuum_analyze(&self) -> Result<()> {
-        // Note: VACUUM and ANALYZE cannot be run in a transaction
-        sqlx::query("VACUUM ANALYZE")
-            .execute(&self.pool)
-            .await
-            .context("Failed to vacuum analyze database")?;
+    /// Records that an API key authenticated a request; its calls are counted as they
+    /// are logged by [`Self::create_request_log`]
+    pub async fn touch_api_key(&self, key_id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE api_keys SET last_used = NOW() WHERE id = $1"
+        )
+        .bind(key_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update API key usage")?;
         
-        info!("Database vacuum analyze completed");
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Config;
+    
+    /// Swaps a user's primary API key if it still matches `current_api_key`
+    ///
+    /// When `grace_key` is given, the old key's hash is stored as a named key that
+    /// keeps authenticating until the given expiry. Returns false if the key was
+    /// rotated concurrently.
+    pub async fn rotate_user_api_key(
+        &self,
+        user_id: Uuid,
+        current_api_key: &str,
+        new_api_key: &str,
+        grace_key: Option<(&str, DateTime<Utc>)>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let result = sqlx::query(
+            "UPDATE users SET api_key = $3 WHERE id = $1 AND api_key = $2"
+        )
+        .bind(user_id)
+        .bind(current_api_key)
+        .bind(new_api_key)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to rotate API key")?;
+        
+        if result.rows_affected() != 1 {
+            return Ok(false);
+        }
+        
+        if let Some((old_key_hash, expires_at)) = grace_key {
+            sqlx::query(
+                r#"
+                INSERT INTO api_keys (user_id, key_hash, name, expires_at)
+                VALUES ($1, $2, 'Rotated primary key', $3)
+                "#
+            )
+            .bind(user_id)
+            .bind(old_key_hash)
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to store rotated API key")?;
+        }
+        
+        tx.commit().await.context("Failed to commit API key rotation")?;
+        Ok(true)
+    }
+    
+    /// Replaces a user's primary API key and deactivates all of their named keys
+    ///
+    /// Returns the number of named keys revoked, or `None` if no such user exists.
+    pub async fn regenerate_user_api_key(&self, user_id: Uuid, new_api_key: &str) -> Result<Option<u64>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let result = sqlx::query(
+            "UPDATE users SET api_key = $2, updated_at = NOW() WHERE id = $1"
+        )
+        .bind(user_id)
+        .bind(new_api_key)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to regenerate API key")?;
+        
+        if result.rows_affected() != 1 {
+            return Ok(None);
+        }
+        
+        let revoked = sqlx::query(
+            "UPDATE api_keys SET is_active = false WHERE user_id = $1 AND is_active = true"
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to revoke named API keys")?;
+        
+        tx.commit().await.context("Failed to commit API key regeneration")?;
+        Ok(Some(revoked.rows_affected()))
+    }
+    
+    // === Audit Log ===
+    
+    /// Appends an entry to the audit trail
+    pub async fn create_audit_log_entry(
+        &self,
+        actor: &AuditActor,
+        action: &str,
+        target_type: &str,
+        target_id: Option<Uuid>,
+        metadata: serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log (actor_id, action, target_type, target_id, metadata, ip_address_hash)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#
+        )
+        .bind(actor.id)
+        .bind(action)
+        .bind(target_type)
+        .bind(target_id)
+        .bind(metadata)
+        .bind(&actor.ip_address_hash)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write audit log entry")?;
+        
+        Ok(())
+    }
+    
+    /// Lists audit log entries, newest first, filtered by actor, action and time range
+    pub async fn list_audit_log(&self, query: &AuditLogQuery) -> Result<Vec<AuditLogEntry>> {
+        let entries = sqlx::query_as::<_, AuditLogEntry>(
+            r#"
+            SELECT id, actor_id, action, target_type, target_id, metadata, ip_address_hash, created_at
+            FROM audit_log
+            WHERE ($1::UUID IS NULL OR actor_id = $1)
+                AND ($2::TEXT IS NULL OR action = $2)
+                AND ($3::TIMESTAMPTZ IS NULL OR created_at >= $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR created_at < $4)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $5 OFFSET $6
+            "#
+        )
+        .bind(query.actor_id)
+        .bind(&query.action)
+        .bind(query.start)
+        .bind(query.end)
+        .bind(query.limit.unwrap_or(50).clamp(1, 500))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list audit log")?;
+        
+        Ok(entries)
+    }
+    
+    // === System Configuration ===
+    
+    /// Lists every runtime setting, ordered by key
+    pub async fn list_system_config(&self) -> Result<Vec<SystemConfig>> {
+        let settings = sqlx::query_as::<_, SystemConfig>(
+            "SELECT key, value, description, updated_at, updated_by FROM system_config ORDER BY key"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list system config")?;
+        
+        Ok(settings)
+    }
+    
+    /// Gets a runtime setting by key
+    pub async fn get_system_config(&self, key: &str) -> Result<Option<SystemConfig>> {
+        let setting = sqlx::query_as::<_, SystemConfig>(
+            "SELECT key, value, description, updated_at, updated_by FROM system_config WHERE key = $1"
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get system config")?;
+        
+        Ok(setting)
+    }
+    
+    /// Creates or changes a runtime setting, keeping its description unless a new one is given
+    pub async fn set_system_config(
+        &self,
+        key: &str,
+        value: &str,
+        description: Option<&str>,
+        updated_by: Uuid,
+    ) -> Result<SystemConfig> {
+        let setting = sqlx::query_as::<_, SystemConfig>(
+            r#"
+            INSERT INTO system_config (key, value, description, updated_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (key) DO UPDATE SET
+                value = EXCLUDED.value,
+                description = COALESCE(EXCLUDED.description, system_config.description),
+                updated_by = EXCLUDED.updated_by
+            RETURNING key, value, description, updated_at, updated_by
+            "#
+        )
+        .bind(key)
+        .bind(value)
+        .bind(description)
+        .bind(updated_by)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to set system config")?;
+        
+        Ok(setting)
+    }
+    
+    // === API Endpoint Management ===
+    
+    /// Registers a new monetizable API endpoint, routed by a slug made from its owner and name
+    pub async fn create_endpoint(&self, owner_id: Uuid, request: CreateEndpointRequest) -> Result<ApiEndpoint> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let endpoint = self.create_endpoint_in(&mut tx, owner_id, request).await?;
+        tx.commit().await.context("Failed to commit endpoint")?;
+
+        info!("Created API endpoint: {} (slug: {}, ID: {})", endpoint.name, endpoint.slug, endpoint.id);
+        Ok(endpoint)
+    }
+
+    /// Registers an endpoint unless its owner already has `cap` of them, returning `None` then
+    ///
+    /// The owner's user row is locked while their endpoints are counted, so concurrent
+    /// registrations are counted one after the other and cannot both take the last place.
+    /// Deleted endpoints do not count.
+    pub async fn create_endpoint_within_cap(
+        &self,
+        owner_id: Uuid,
+        request: CreateEndpointRequest,
+        cap: i64,
+    ) -> Result<Option<ApiEndpoint>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        sqlx::query("SELECT 1 FROM users WHERE id = $1 FOR UPDATE")
+            .bind(owner_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to lock endpoint owner")?;
+
+        let owned: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints WHERE owner_id = $1 AND deleted_at IS NULL")
+            .bind(owner_id)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to count endpoints")?;
+        if owned >= cap {
+            return Ok(None);
+        }
+
+        let endpoint = self.create_endpoint_in(&mut tx, owner_id, request).await?;
+        tx.commit().await.context("Failed to commit endpoint")?;
+
+        info!("Created API endpoint: {} (slug: {}, ID: {})", endpoint.name, endpoint.slug, endpoint.id);
+        Ok(Some(endpoint))
+    }
+
+    /// Inserts an endpoint within the caller's transaction
+    async fn create_endpoint_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        owner_id: Uuid,
+        request: CreateEndpointRequest,
+    ) -> Result<ApiEndpoint> {
+        let now = Utc::now();
+        let allowed_methods = request.allowed_methods.unwrap_or_else(|| vec!["GET".to_string()]);
+        let username: Option<String> = sqlx::query_scalar("SELECT username FROM users WHERE id = $1")
+            .bind(owner_id)
+            .fetch_optional(&mut **tx)
+            .await
+            .context("Failed to get endpoint owner")?
+            .flatten();
+        let slug = endpoint_slug(username.as_deref(), owner_id, &request.name);
+        
+        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            INSERT INTO api_endpoints (name, description, owner_id, upstream_url, price_per_request,
+                                     rate_limit, rate_limit_window, requires_auth, allowed_methods,
+                                     request_timeout, retry_attempts, max_request_size, bill_client_errors,
+                                     upstream_targets, path_rewrite, forward_credentials, retry_non_idempotent,
+                                     upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers, sandbox_response,
+                                     sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota, created_at, updated_at, slug,
+                                     capture_samples, sample_rate, sample_max_body_bytes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, $33, $34, $35, $36, $37)
+            RETURNING id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                      created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                      allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                      path_rewrite, forward_credentials, upstream_auth_encrypted,
+                      retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                      sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                      capture_samples, sample_rate, sample_max_body_bytes
+            "#
+        )
+        .bind(&request.name)
+        .bind(&request.description)
+        .bind(owner_id)
+        .bind(&request.upstream_url)
+        .bind(&request.price_per_request)
+        .bind(request.rate_limit)
+        .bind(request.rate_limit_window)
+        .bind(request.requires_auth.unwrap_or(true))
+        .bind(&allowed_methods)
+        .bind(request.request_timeout)
+        .bind(request.retry_attempts)
+        .bind(request.max_request_size)
+        .bind(request.bill_client_errors.unwrap_or(false))
+        .bind(Json(request.upstream_targets.unwrap_or_default()))
+        .bind(request.path_rewrite.map(Json))
+        .bind(request.forward_credentials.unwrap_or(false))
+        .bind(request.retry_non_idempotent.unwrap_or(false))
+        .bind(&request.upstream_ws_url)
+        .bind(request.pricing_model.unwrap_or_default())
+        .bind(&request.price_per_kilobyte)
+        .bind(Json(request.pricing_tiers.unwrap_or_default()))
+        .bind(request.sandbox_response.map(Json))
+        .bind(&request.sandbox_upstream_url)
+        .bind(request.tags.unwrap_or_default())
+        .bind(&request.category)
+        .bind(request.sla_max_latency_ms)
+        .bind(request.sla_error_refund.unwrap_or(false))
+        .bind(request.max_concurrent_requests)
+        .bind(request.rate_limit_algorithm.unwrap_or_default())
+        .bind(request.rate_limit_burst)
+        .bind(request.consumer_monthly_quota)
+        .bind(now)
+        .bind(now)
+        .bind(&slug)
+        .bind(request.capture_samples.unwrap_or(false))
+        .bind(request.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE))
+        .bind(request.sample_max_body_bytes.unwrap_or(DEFAULT_SAMPLE_BODY_BYTES))
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to create API endpoint")?;
+        
+        Ok(endpoint)
+    }
+    
+    /// Retrieves endpoint details by ID
+    pub async fn get_endpoint_by_id(&self, endpoint_id: Uuid) -> Result<Option<ApiEndpoint>> {
+        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                   allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                   path_rewrite, forward_credentials, upstream_auth_encrypted,
+                   retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                   sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                   capture_samples, sample_rate, sample_max_body_bytes
+            FROM api_endpoints WHERE id = $1 AND deleted_at IS NULL
+            "#
+        )
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get endpoint by ID")?;
+        
+        Ok(endpoint)
+    }
+    
+    /// Finds endpoint by the slug it is routed by
+    pub async fn get_endpoint_by_slug(&self, slug: &str) -> Result<Option<ApiEndpoint>> {
+        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                   allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                   path_rewrite, forward_credentials, upstream_auth_encrypted,
+                   retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                   sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                   capture_samples, sample_rate, sample_max_body_bytes
+            FROM api_endpoints WHERE slug = $1 AND is_active = true AND deleted_at IS NULL
+            "#
+        )
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get endpoint by slug")?;
+        
+        Ok(endpoint)
+    }
+    
+    /// Updates endpoint configuration and pricing
+    pub async fn update_endpoint(&self, endpoint_id: Uuid, request: UpdateEndpointRequest) -> Result<ApiEndpoint> {
+        let now = Utc::now();
+        
+        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            UPDATE api_endpoints SET
+                description = COALESCE($2, description),
+                upstream_url = COALESCE($3, upstream_url),
+                price_per_request = COALESCE($4, price_per_request),
+                is_active = COALESCE($5, is_active),
+                rate_limit = COALESCE($6, rate_limit),
+                rate_limit_window = COALESCE($7, rate_limit_window),
+                requires_auth = COALESCE($8, requires_auth),
+                allowed_methods = COALESCE($9, allowed_methods),
+                request_timeout = COALESCE($10, request_timeout),
+                retry_attempts = COALESCE($11, retry_attempts),
+                max_request_size = COALESCE($12, max_request_size),
+                bill_client_errors = COALESCE($13, bill_client_errors),
+                upstream_targets = COALESCE($14, upstream_targets),
+                path_rewrite = COALESCE($15, path_rewrite),
+                forward_credentials = COALESCE($16, forward_credentials),
+                retry_non_idempotent = COALESCE($17, retry_non_idempotent),
+                upstream_ws_url = COALESCE($18, upstream_ws_url),
+                pricing_model = COALESCE($19, pricing_model),
+                price_per_kilobyte = COALESCE($20, price_per_kilobyte),
+                pricing_tiers = COALESCE($21, pricing_tiers),
+                sandbox_response = COALESCE($22, sandbox_response),
+                sandbox_upstream_url = COALESCE($23, sandbox_upstream_url),
+                tags = COALESCE($24, tags),
+                category = COALESCE($25, category),
+                sla_max_latency_ms = COALESCE($26, sla_max_latency_ms),
+                sla_error_refund = COALESCE($27, sla_error_refund),
+                max_concurrent_requests = COALESCE($28, max_concurrent_requests),
+                rate_limit_algorithm = COALESCE($29, rate_limit_algorithm),
+                rate_limit_burst = COALESCE($30, rate_limit_burst),
+                consumer_monthly_quota = COALESCE($31, consumer_monthly_quota),
+                updated_at = $32,
+                capture_samples = COALESCE($33, capture_samples),
+                sample_rate = COALESCE($34, sample_rate),
+                sample_max_body_bytes = COALESCE($35, sample_max_body_bytes)
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                      created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                      allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                      path_rewrite, forward_credentials, upstream_auth_encrypted,
+                      retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                      sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                      capture_samples, sample_rate, sample_max_body_bytes
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(request.description)
+        .bind(request.upstream_url)
+        .bind(request.price_per_request)
+        .bind(request.is_active)
+        .bind(request.rate_limit)
+        .bind(request.rate_limit_window)
+        .bind(request.requires_auth)
+        .bind(request.allowed_methods)
+        .bind(request.request_timeout)
+        .bind(request.retry_attempts)
+        .bind(request.max_request_size)
+        .bind(request.bill_client_errors)
+        .bind(request.upstream_targets.map(Json))
+        .bind(request.path_rewrite.map(Json))
+        .bind(request.forward_credentials)
+        .bind(request.retry_non_idempotent)
+        .bind(request.upstream_ws_url)
+        .bind(request.pricing_model)
+        .bind(request.price_per_kilobyte)
+        .bind(request.pricing_tiers.map(Json))
+        .bind(request.sandbox_response.map(Json))
+        .bind(request.sandbox_upstream_url)
+        .bind(request.tags)
+        .bind(request.category)
+        .bind(request.sla_max_latency_ms)
+        .bind(request.sla_error_refund)
+        .bind(request.max_concurrent_requests)
+        .bind(request.rate_limit_algorithm)
+        .bind(request.rate_limit_burst)
+        .bind(request.consumer_monthly_quota)
+        .bind(now)
+        .bind(request.capture_samples)
+        .bind(request.sample_rate)
+        .bind(request.sample_max_body_bytes)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to update endpoint")?;
+        
+        Ok(endpoint)
+    }
+    
+    /// Replaces an endpoint's encrypted upstream credentials; `None` removes them
+    pub async fn set_endpoint_upstream_auth(&self, endpoint_id: Uuid, upstream_auth_encrypted: Option<String>) -> Result<ApiEndpoint> {
+        let endpoint = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            UPDATE api_endpoints SET upstream_auth_encrypted = $2, updated_at = $3
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                      created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                      allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                      path_rewrite, forward_credentials, upstream_auth_encrypted,
+                      retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                      sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                      capture_samples, sample_rate, sample_max_body_bytes
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(upstream_auth_encrypted)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to update endpoint upstream credentials")?;
+        
+        Ok(endpoint)
+    }
+    
+    /// Soft-deletes an endpoint, keeping its row for historical usage, and stops its
+    /// active subscriptions from renewing; returns false if it was already deleted
+    pub async fn delete_endpoint(&self, endpoint_id: Uuid) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let deleted = sqlx::query(
+            r#"
+            UPDATE api_endpoints SET deleted_at = $2, is_active = false, updated_at = $2
+            WHERE id = $1 AND deleted_at IS NULL
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete endpoint")?
+        .rows_affected() > 0;
+
+        if deleted {
+            sqlx::query(
+                r#"
+                UPDATE endpoint_subscriptions SET cancel_at_period_end = true, updated_at = $2
+                WHERE endpoint_id = $1 AND status = 'active'
+                "#
+            )
+            .bind(endpoint_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to cancel subscriptions of deleted endpoint")?;
+        }
+
+        tx.commit().await.context("Failed to commit endpoint deletion")?;
+        Ok(deleted)
+    }
+
+    /// Whether every endpoint ever routed by a slug has been deleted
+    pub async fn is_endpoint_slug_deleted(&self, slug: &str) -> Result<bool> {
+        let deleted: Option<bool> = sqlx::query_scalar(
+            "SELECT bool_and(deleted_at IS NOT NULL) FROM api_endpoints WHERE slug = $1"
+        )
+        .bind(slug)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check for deleted endpoint")?;
+
+        Ok(deleted.unwrap_or(false))
+    }
+
+    /// Lists endpoints with optional owner filtering and pagination
+    pub async fn list_endpoints(&self, owner_id: Option<Uuid>, params: PaginationParams) -> Result<PaginatedResponse<ApiEndpoint>> {
+        let limit = params.limit.unwrap_or(50) as i64;
+        let page = params.page.unwrap_or(1) as i64;
+        let offset = (page - 1) * limit;
+        
+        let (total_query, endpoints_query) = if let Some(owner_id) = owner_id {
+            (
+                sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints WHERE owner_id = $1 AND deleted_at IS NULL")
+                    .bind(owner_id),
+                sqlx::query_as::<_, ApiEndpoint>(
+                    r#"
+                    SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                           created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                           allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                           path_rewrite, forward_credentials, upstream_auth_encrypted,
+                           retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                           sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                           capture_samples, sample_rate, sample_max_body_bytes
+                    FROM api_endpoints 
+                    WHERE owner_id = $1 AND deleted_at IS NULL
+                    ORDER BY created_at DESC
+                    LIMIT $2 OFFSET $3
+                    "#
+                )
+                .bind(owner_id)
+                .bind(limit)
+                .bind(offset)
+            )
+        } else {
+            (
+                sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints WHERE deleted_at IS NULL"),
+                sqlx::query_as::<_, ApiEndpoint>(
+                    r#"
+                    SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                           created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                           allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                           path_rewrite, forward_credentials, upstream_auth_encrypted,
+                           retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                           sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                           capture_samples, sample_rate, sample_max_body_bytes
+                    FROM api_endpoints 
+                    WHERE deleted_at IS NULL
+                    ORDER BY created_at DESC
+                    LIMIT $1 OFFSET $2
+                    "#
+                )
+                .bind(limit)
+                .bind(offset)
+            )
+        };
+        
+        let total: i64 = total_query
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to get total endpoint count")?;
+            
+        let endpoints = endpoints_query
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to list endpoints")?;
+        
+        Ok(PaginatedResponse {
+            data: endpoints,
+            total,
+            page: page as u32,
+            limit: limit as u32,
+            total_pages: ((total + limit - 1) / limit) as u32,
+        })
+    }
+    
+    /// Searches active endpoints for the public listing, plus any of `viewer`'s own
+    ///
+    /// `query` matches the name, description or a tag as a substring, or the name and
+    /// description as English full text. Prices are compared as numbers. Popularity is
+    /// the endpoint's request count in `daily_stats` over the last 30 days.
+    pub async fn search_endpoints(&self, params: &EndpointSearchParams, viewer: Option<Uuid>) -> Result<PaginatedResponse<ApiEndpoint>> {
+        let limit = params.limit.unwrap_or(50).max(1);
+        let page = params.page.unwrap_or(1).max(1);
+        let offset = (page - 1) as i64 * limit as i64;
+        let pattern = params.q.as_deref().map(|query| format!("%{}%", escape_like(query)));
+
+        let filter = r#"
+            deleted_at IS NULL
+            AND (is_active = true OR owner_id = $7)
+            AND ($1::TEXT IS NULL
+                 OR name ILIKE $2 OR slug ILIKE $2 OR description ILIKE $2
+                 OR EXISTS (SELECT 1 FROM unnest(tags) AS tag WHERE tag ILIKE $2)
+                 OR to_tsvector('english', name || ' ' || COALESCE(description, '')) @@ plainto_tsquery('english', $1))
+            AND ($3::TEXT IS NULL OR $3 = ANY(tags))
+            AND ($4::TEXT IS NULL OR category = $4)
+            AND ($5::NUMERIC IS NULL OR price_per_request::NUMERIC >= $5::NUMERIC)
+            AND ($6::NUMERIC IS NULL OR price_per_request::NUMERIC <= $6::NUMERIC)
+        "#;
+
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM api_endpoints WHERE {}", filter))
+            .bind(&params.q)
+            .bind(&pattern)
+            .bind(&params.tag)
+            .bind(&params.category)
+            .bind(&params.min_price)
+            .bind(&params.max_price)
+            .bind(viewer)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count endpoint search results")?;
+
+        let endpoints = sqlx::query_as::<_, ApiEndpoint>(&format!(
+            r#"
+            SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                   allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                   path_rewrite, forward_credentials, upstream_auth_encrypted,
+                   retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                   sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                   capture_samples, sample_rate, sample_max_body_bytes
+            FROM api_endpoints
+            LEFT JOIN (
+                SELECT endpoint_id, SUM(total_requests) AS recent_requests
+                FROM daily_stats
+                WHERE date >= CURRENT_DATE - 30 AND endpoint_id IS NOT NULL AND user_id IS NULL
+                GROUP BY endpoint_id
+            ) popularity ON popularity.endpoint_id = api_endpoints.id
+            WHERE {}
+            ORDER BY CASE WHEN $8 THEN COALESCE(popularity.recent_requests, 0) ELSE 0 END DESC, created_at DESC
+            LIMIT $9 OFFSET $10
+            "#,
+            filter
+        ))
+        .bind(&params.q)
+        .bind(&pattern)
+        .bind(&params.tag)
+        .bind(&params.category)
+        .bind(&params.min_price)
+        .bind(&params.max_price)
+        .bind(viewer)
+        .bind(params.sort == Some(EndpointSort::Popular))
+        .bind(limit as i64)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to search endpoints")?;
+
+        Ok(PaginatedResponse::new(endpoints, total, page, limit))
+    }
+
+    // === Endpoint Specs ===
+    
+    /// Stores or replaces an endpoint's OpenAPI spec, also replacing its allowed methods
+    /// when they are derived from the spec
+    pub async fn set_endpoint_spec(
+        &self,
+        endpoint_id: Uuid,
+        format: SpecFormat,
+        content: &str,
+        summary: &SpecSummary,
+        allowed_methods: Option<&[String]>,
+    ) -> Result<EndpointSpec> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let now = Utc::now();
+
+        let spec = sqlx::query_as::<_, EndpointSpec>(
+            r#"
+            INSERT INTO endpoint_specs (endpoint_id, format, content, summary, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $5)
+            ON CONFLICT (endpoint_id) DO UPDATE
+            SET format = EXCLUDED.format, content = EXCLUDED.content,
+                summary = EXCLUDED.summary, updated_at = EXCLUDED.updated_at
+            RETURNING endpoint_id, format, content, summary, created_at, updated_at
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(format)
+        .bind(content)
+        .bind(Json(summary))
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to store endpoint spec")?;
+
+        if let Some(methods) = allowed_methods {
+            sqlx::query("UPDATE api_endpoints SET allowed_methods = $2, updated_at = $3 WHERE id = $1")
+                .bind(endpoint_id)
+                .bind(methods)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to update endpoint methods")?;
+        }
+
+        tx.commit().await.context("Failed to commit endpoint spec")?;
+        Ok(spec)
+    }
+    
+    /// Returns the OpenAPI spec attached to an endpoint
+    pub async fn get_endpoint_spec(&self, endpoint_id: Uuid) -> Result<Option<EndpointSpec>> {
+        let spec = sqlx::query_as::<_, EndpointSpec>(
+            r#"
+            SELECT endpoint_id, format, content, summary, created_at, updated_at
+            FROM endpoint_specs
+            WHERE endpoint_id = $1
+            "#
+        )
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get endpoint spec")?;
+        
+        Ok(spec)
+    }
+
+    // === Request Logging ===
+    
+    /// Logs API request details for debugging and analytics
+    ///
+    /// A call made with a named API key is also counted in the key's usage_count.
+    pub async fn create_request_log(&self, request: CreateRequestLogRequest) -> Result<RequestLog> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let log = Self::insert_request_log_in(&mut tx, &request, Utc::now())
+            .await?
+            .with_context(|| format!("Request {} is already logged", request.request_id))?;
+        tx.commit().await.context("Failed to commit request log")?;
+        Ok(log)
+    }
+
+    /// Logs a proxied call and adds its usage to the consumer's aggregates in one
+    /// transaction, so a call is never billed without being logged or logged without
+    /// being billed
+    ///
+    /// The usage is counted against the consumer's subscription allowance when one
+    /// covers the call, then priced as by [`Self::record_billable_usage`]; the entry
+    /// is logged with the cost recorded. Calls that are not billed pass no `charge`.
+    ///
+    /// `recorded_at` is when the call was first recorded, timestamping its entry and
+    /// picking its billing period. Recording is idempotent on the request ID and that
+    /// time: a retry of a write that did commit returns the entry without adding the
+    /// usage again.
+    pub async fn record_proxied_request(
+        &self,
+        request: &CreateRequestLogRequest,
+        charge: Option<&UsageCharge>,
+        recorded_at: DateTime<Utc>,
+    ) -> Result<RequestLog> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let recorded = sqlx::query_as::<_, RequestLog>(
+            r#"
+            SELECT id, user_id, endpoint_id, request_id, method, path, status_code,
+                   response_time_ms, request_size, response_size, ip_address_hash,
+                   user_agent_hash, timestamp, cost, error_message, upstream_target,
+                   stream_duration_ms, cost_breakdown, is_sandbox, api_key_id
+            FROM request_logs WHERE request_id = $1 AND timestamp = $2
+            "#
+        )
+        .bind(&request.request_id)
+        .bind(recorded_at)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to get request log")?;
+        if let Some(log) = recorded {
+            return Ok(log);
+        }
+
+        let mut request = request.clone();
+        if let Some(charge) = charge {
+            let billing_period = recorded_at.format("%Y-%m").to_string();
+            let allowance = Self::use_subscription_units_in(&mut tx, request.user_id, request.endpoint_id, charge.units, recorded_at).await?;
+            let breakdown = Self::record_billable_usage_in(
+                &mut tx,
+                request.user_id,
+                request.endpoint_id,
+                charge.api_key_id,
+                &billing_period,
+                charge.overage,
+                |prior_units| (charge.price)(prior_units, allowance.as_ref()),
+            ).await?;
+            request.cost = breakdown.total;
+            request.cost_breakdown = Some(sqlx::types::Json(breakdown));
+        }
+        // Another attempt recording the call at once wins; this one's usage is rolled back
+        let log = Self::insert_request_log_in(&mut tx, &request, recorded_at)
+            .await?
+            .with_context(|| format!("Request {} was recorded concurrently", request.request_id))?;
+
+        tx.commit().await.context("Failed to commit proxied request")?;
+        Ok(log)
+    }
+
+    /// Fills in the sizes and stream duration of a call logged before its response was
+    /// streamed, returning whether its entry was found; `since` is when the call started
+    pub async fn complete_request_log(
+        &self,
+        user_id: Uuid,
+        request_id: &str,
+        since: DateTime<Utc>,
+        request_size: Option<i64>,
+        response_size: Option<i64>,
+        stream_duration_ms: Option<i32>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE request_logs SET request_size = $4, response_size = $5, stream_duration_ms = $6
+            WHERE user_id = $1 AND request_id = $2 AND timestamp >= $3
+            "#
+        )
+        .bind(user_id)
+        .bind(request_id)
+        .bind(since)
+        .bind(request_size)
+        .bind(response_size)
+        .bind(stream_duration_ms)
+        .execute(&self.pool)
+        .await
+        .context("Failed to complete request log")?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Inserts a log entry, returning `None` when the call is already logged at `now`
+    async fn insert_request_log_in(
+        tx: &mut Transaction<'_, Postgres>,
+        request: &CreateRequestLogRequest,
+        now: DateTime<Utc>,
+    ) -> Result<Option<RequestLog>> {
+        let log = sqlx::query_as::<_, RequestLog>(
+            r#"
+            INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code,
+                                    response_time_ms, request_size, response_size, ip_address_hash,
+                                    user_agent_hash, timestamp, cost, error_message, upstream_target,
+                                    stream_duration_ms, cost_breakdown, is_sandbox, api_key_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
+            ON CONFLICT (request_id, timestamp) DO NOTHING
+            RETURNING id, user_id, endpoint_id, request_id, method, path, status_code,
+                      response_time_ms, request_size, response_size, ip_address_hash,
+                      user_agent_hash, timestamp, cost, error_message, upstream_target,
+                      stream_duration_ms, cost_breakdown, is_sandbox, api_key_id
+            "#
+        )
+        .bind(request.user_id)
+        .bind(request.endpoint_id)
+        .bind(&request.request_id)
+        .bind(&request.method)
+        .bind(&request.path)
+        .bind(request.status_code)
+        .bind(request.response_time_ms)
+        .bind(request.request_size)
+        .bind(request.response_size)
+        .bind(&request.ip_address_hash)
+        .bind(&request.user_agent_hash)
+        .bind(now)
+        .bind(request.cost)
+        .bind(&request.error_message)
+        .bind(&request.upstream_target)
+        .bind(request.stream_duration_ms)
+        .bind(&request.cost_breakdown)
+        .bind(request.is_sandbox)
+        .bind(request.api_key_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to create request log")?;
+        if log.is_none() {
+            return Ok(None);
+        }
+
+        if let Some(api_key_id) = request.api_key_id {
+            sqlx::query("UPDATE api_keys SET usage_count = usage_count + 1 WHERE id = $1")
+                .bind(api_key_id)
+                .execute(&mut **tx)
+                .await
+                .context("Failed to count API key usage")?;
+        }
+
+        Ok(log)
+    }
+
+    /// Lists request logs matching `query`, newest first, starting after `after`
+    ///
+    /// Keyset pagination on (timestamp, id): each page seeks straight to its cursor
+    /// instead of skipping the rows before it. The query's own cursor and limit are
+    /// ignored in favour of `after` and `limit`.
+    pub async fn get_request_logs(&self, query: &RequestLogQuery, after: Option<RequestLogCursor>, limit: i64) -> Result<Vec<RequestLog>> {
+        let logs = sqlx::query_as::<_, RequestLog>(
+            r#"
+            SELECT id, user_id, endpoint_id, request_id, method, path, status_code,
+                   response_time_ms, request_size, response_size, ip_address_hash,
+                   user_agent_hash, timestamp, cost, error_message, upstream_target,
+                   stream_duration_ms, cost_breakdown, is_sandbox, api_key_id
+            FROM request_logs
+            WHERE ($1::UUID IS NULL OR user_id = $1)
+                AND ($2::UUID IS NULL OR endpoint_id = $2)
+                AND ($3::INTEGER IS NULL OR status_code = $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR timestamp >= $4)
+                AND ($5::TIMESTAMPTZ IS NULL OR timestamp < $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR (timestamp, id) < ($6, $7))
+                AND ($9::INTEGER IS NULL OR status_code / 100 = $9)
+                AND (NOT $10 OR status_code >= 400 OR error_message IS NOT NULL)
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $8
+            "#
+        )
+        .bind(query.user_id)
+        .bind(query.endpoint_id)
+        .bind(query.status_code)
+        .bind(query.start)
+        .bind(query.end)
+        .bind(after.map(|cursor| cursor.timestamp))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .bind(query.status_class.map(StatusClass::digit))
+        .bind(query.errors_only)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get request logs")?;
+
+        Ok(logs)
+    }
+    
+    /// Records API usage for billing purposes
+    ///
+    /// Usage is kept as one row per user, endpoint and billing period, so the counts
+    /// are added to the period's existing row rather than inserted beside it.
+    pub async fn create_usage_record(&self, user_id: Uuid, endpoint_id: Uuid, request_count: i64, total_cost: &str, billing_period: &str) -> Result<UsageRecord> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        
+        let record = sqlx::query_as::<_, UsageRecord>(
+            r#"
+            INSERT INTO usage_records (user_id, endpoint_id, request_count, total_cost, billing_period,
+                                     status, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (user_id, endpoint_id, billing_period) DO UPDATE SET
+                request_count = usage_records.request_count + EXCLUDED.request_count,
+                total_cost = (usage_records.total_cost::NUMERIC + EXCLUDED.total_cost::NUMERIC)::TEXT,
+                timestamp = EXCLUDED.timestamp
+            RETURNING id, user_id, endpoint_id, request_count, total_cost, billing_period,
+                      status, transaction_hash, gas_used, block_number, chain_id, timestamp
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(request_count)
+        .bind(total_cost)
+        .bind(billing_period)
+        .bind(UsageStatus::Pending)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to create usage record")?;
+
+        Self::add_monthly_usage_in(&mut tx, user_id, billing_period, request_count, now).await?;
+        
+        tx.commit().await.context("Failed to commit usage record")?;
+        Ok(record)
+    }
+    
+    /// Adds a priced call to the user's usage for an endpoint and billing period
+    ///
+    /// `price` receives the billable units (requests, connection-minutes, messages or
+    /// kilobytes) already recorded this period, so volume tiers are applied from the
+    /// exact month-to-date count. The usage row stays locked until the new total is
+    /// written, so concurrent calls crossing a tier boundary are each priced once.
+    /// Usage made with a named API key is also totalled against that key, and usage
+    /// past the user's monthly limit is also totalled as overage.
+    pub async fn record_billable_usage<F>(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        api_key_id: Option<Uuid>,
+        billing_period: &str,
+        overage: bool,
+        price: F,
+    ) -> Result<CostBreakdown>
+    where
+        F: FnOnce(i64) -> Result<CostBreakdown>,
+    {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let breakdown = Self::record_billable_usage_in(
+            &mut tx, user_id, endpoint_id, api_key_id, billing_period, overage, price,
+        ).await?;
+        tx.commit().await.context("Failed to commit billable usage")?;
+        Ok(breakdown)
+    }
+
+    async fn record_billable_usage_in<F>(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        api_key_id: Option<Uuid>,
+        billing_period: &str,
+        overage: bool,
+        price: F,
+    ) -> Result<CostBreakdown>
+    where
+        F: FnOnce(i64) -> Result<CostBreakdown>,
+    {
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            INSERT INTO usage_records (user_id, endpoint_id, request_count, total_cost, billing_period,
+                                     status, timestamp)
+            VALUES ($1, $2, 0, '0', $3, $4, $5)
+            ON CONFLICT (user_id, endpoint_id, billing_period) DO NOTHING
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(billing_period)
+        .bind(UsageStatus::Pending)
+        .bind(now)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to create usage record")?;
+
+        let prior_units: i64 = sqlx::query_scalar(
+            r#"
+            SELECT request_count FROM usage_records
+            WHERE user_id = $1 AND endpoint_id = $2 AND billing_period = $3
+            FOR UPDATE
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(billing_period)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to lock usage record")?;
+
+        let breakdown = price(prior_units)?;
+        let units = breakdown.billable_units();
+        if units == 0 {
+            sqlx::query(
+                r#"
+                DELETE FROM usage_records
+                WHERE user_id = $1 AND endpoint_id = $2 AND billing_period = $3 AND request_count = 0
+                "#
+            )
+            .bind(user_id)
+            .bind(endpoint_id)
+            .bind(billing_period)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to discard empty usage record")?;
+            return Ok(breakdown);
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE usage_records SET
+                request_count = request_count + $4,
+                total_cost = (total_cost::NUMERIC + $5::NUMERIC)::TEXT,
+                timestamp = $6
+            WHERE user_id = $1 AND endpoint_id = $2 AND billing_period = $3
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(billing_period)
+        .bind(units)
+        .bind(breakdown.total)
+        .bind(now)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record billable usage")?;
+
+        Self::add_monthly_usage_in(tx, user_id, billing_period, units, now).await?;
+
+        if let Some(api_key_id) = api_key_id {
+            sqlx::query(
+                r#"
+                INSERT INTO api_key_usage (api_key_id, endpoint_id, billing_period, request_count, total_cost, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (api_key_id, endpoint_id, billing_period) DO UPDATE SET
+                    request_count = api_key_usage.request_count + EXCLUDED.request_count,
+                    total_cost = (api_key_usage.total_cost::NUMERIC + EXCLUDED.total_cost::NUMERIC)::TEXT,
+                    updated_at = EXCLUDED.updated_at
+                "#
+            )
+            .bind(api_key_id)
+            .bind(endpoint_id)
+            .bind(billing_period)
+            .bind(units)
+            .bind(breakdown.total)
+            .bind(now)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to record API key usage")?;
+        }
+
+        if overage {
+            sqlx::query(
+                r#"
+                INSERT INTO user_overage_usage (user_id, billing_period, request_count, total_cost, started_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $5)
+                ON CONFLICT (user_id, billing_period) DO UPDATE SET
+                    request_count = user_overage_usage.request_count + EXCLUDED.request_count,
+                    total_cost = (user_overage_usage.total_cost::NUMERIC + EXCLUDED.total_cost::NUMERIC)::TEXT,
+                    updated_at = EXCLUDED.updated_at
+                "#
+            )
+            .bind(user_id)
+            .bind(billing_period)
+            .bind(units)
+            .bind(breakdown.total)
+            .bind(now)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to record overage usage")?;
+        }
+
+        Ok(breakdown)
+    }
+    
+    /// Adds requests to a user's month-to-date total, after the usage record they were
+    /// counted on so both are locked in the same order by every writer
+    async fn add_monthly_usage_in(tx: &mut Transaction<'_, Postgres>, user_id: Uuid, billing_period: &str, request_count: i64, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_monthly_usage (user_id, billing_period, request_count, updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, billing_period) DO UPDATE SET
+                request_count = user_monthly_usage.request_count + EXCLUDED.request_count,
+                updated_at = EXCLUDED.updated_at
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .bind(request_count)
+        .bind(now)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to update monthly usage")?;
+
+        Ok(())
+    }
+    
+    /// Marks the billing period in which a user first went past their monthly limit,
+    /// returning false if they already had
+    pub async fn start_overage(&self, user_id: Uuid, billing_period: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO user_overage_usage (user_id, billing_period)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, billing_period) DO NOTHING
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .execute(&self.pool)
+        .await
+        .context("Failed to start overage")?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Gets a user's usage past their monthly limit in a billing period, if any
+    pub async fn get_overage_usage(&self, user_id: Uuid, billing_period: &str) -> Result<Option<OverageUsage>> {
+        let usage = sqlx::query_as::<_, OverageUsage>(
+            r#"
+            SELECT billing_period, request_count, total_cost, started_at
+            FROM user_overage_usage
+            WHERE user_id = $1 AND billing_period = $2
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get overage usage")?;
+
+        Ok(usage)
+    }
+
+    /// Totals the requests a user has made across all endpoints in a billing period,
+    /// read from the month-to-date total kept alongside their usage records
+    pub async fn get_user_request_count(&self, user_id: Uuid, billing_period: &str) -> Result<i64> {
+        let count: Option<i64> = sqlx::query_scalar(
+            "SELECT request_count FROM user_monthly_usage WHERE user_id = $1 AND billing_period = $2"
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to count user requests")?;
+        
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Rebuilds the month-to-date totals of a billing period that drifted from the usage
+    /// records, returning how many were corrected
+    ///
+    /// The period's totals are locked before the usage records are summed, so a call
+    /// recorded meanwhile is either included in the sum or added once the lock is freed.
+    pub async fn reconcile_monthly_usage(&self, billing_period: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        sqlx::query("SELECT 1 FROM user_monthly_usage WHERE billing_period = $1 FOR UPDATE")
+            .bind(billing_period)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to lock monthly usage")?;
+
+        let corrected: i64 = sqlx::query_scalar(
+            r#"
+            WITH actual AS (
+                SELECT user_id, SUM(request_count)::BIGINT AS request_count
+                FROM usage_records
+                WHERE billing_period = $1
+                GROUP BY user_id
+            ), rebuilt AS (
+                INSERT INTO user_monthly_usage (user_id, billing_period, request_count, updated_at)
+                SELECT user_id, $1, request_count, $2 FROM actual
+                ON CONFLICT (user_id, billing_period) DO UPDATE SET
+                    request_count = EXCLUDED.request_count,
+                    updated_at = EXCLUDED.updated_at
+                WHERE user_monthly_usage.request_count <> EXCLUDED.request_count
+                RETURNING 1
+            ), stale AS (
+                DELETE FROM user_monthly_usage m
+                WHERE m.billing_period = $1
+                  AND NOT EXISTS (SELECT 1 FROM actual a WHERE a.user_id = m.user_id)
+                RETURNING 1
+            )
+            SELECT (SELECT COUNT(*) FROM rebuilt) + (SELECT COUNT(*) FROM stale)
+            "#
+        )
+        .bind(billing_period)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to reconcile monthly usage")?;
+
+        tx.commit().await.context("Failed to commit monthly usage reconciliation")?;
+        Ok(corrected as u64)
+    }
+    
+    /// Retrieves a user's usage record for one endpoint and billing period
+    pub async fn get_usage_record(&self, user_id: Uuid, endpoint_id: Uuid, billing_period: &str) -> Result<Option<UsageRecord>> {
+        let record = sqlx::query_as::<_, UsageRecord>(
+            r#"
+            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
+                   status, transaction_hash, gas_used, block_number, chain_id, timestamp
+            FROM usage_records
+            WHERE user_id = $1 AND endpoint_id = $2 AND billing_period = $3
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(billing_period)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get usage record")?;
+        
+        Ok(record)
+    }
+    
+    /// Retrieves usage history for a specific user within date range
+    ///
+    /// Usage is aggregated per billing month, so this returns the records of every
+    /// month the half-open range touches.
+    pub async fn get_user_usage(&self, user_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<Vec<UsageRecord>> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let records = sqlx::query_as::<_, UsageRecord>(
+            r#"
+            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
+                   status, transaction_hash, gas_used, block_number, chain_id, timestamp
+            FROM usage_records 
+            WHERE user_id = $1 AND billing_period BETWEEN $2 AND $3
+            ORDER BY billing_period DESC, timestamp DESC
+            "#
+        )
+        .bind(user_id)
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get user usage")?;
+        
+        Ok(records)
+    }
+
+    /// Counts the distinct endpoints a user called in the billing months a range touches
+    pub async fn count_user_endpoints(&self, user_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT endpoint_id) FROM usage_records WHERE user_id = $1 AND billing_period BETWEEN $2 AND $3"
+        )
+        .bind(user_id)
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to count user endpoints")?;
+
+        Ok(count)
+    }
+
+    /// Lists the endpoints a user called most in the billing months a range touches
+    pub async fn get_user_top_endpoints(&self, user_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>, limit: i64) -> Result<Vec<EndpointUsageSummary>> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let endpoints = sqlx::query_as::<_, EndpointUsageSummary>(
+            r#"
+            SELECT ur.endpoint_id, e.name AS endpoint_name,
+                   SUM(ur.request_count)::BIGINT AS total_requests,
+                   SUM(ur.total_cost::NUMERIC)::TEXT AS total_cost
+            FROM usage_records ur
+            INNER JOIN api_endpoints e ON e.id = ur.endpoint_id
+            WHERE ur.user_id = $1 AND ur.billing_period BETWEEN $2 AND $3
+            GROUP BY ur.endpoint_id, e.name
+            ORDER BY total_requests DESC, ur.endpoint_id
+            LIMIT $4
+            "#
+        )
+        .bind(user_id)
+        .bind(first_period)
+        .bind(last_period)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get user top endpoints")?;
+
+        Ok(endpoints)
+    }
+
+    /// Totals the usage billed to a named API key per endpoint, busiest first, by
+    /// billing month like [`Self::get_user_usage`]
+    pub async fn get_api_key_usage(&self, api_key_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<Vec<EndpointUsageSummary>> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let endpoints = sqlx::query_as::<_, EndpointUsageSummary>(
+            r#"
+            SELECT ku.endpoint_id, e.name AS endpoint_name,
+                   SUM(ku.request_count)::BIGINT AS total_requests,
+                   SUM(ku.total_cost::NUMERIC)::TEXT AS total_cost
+            FROM api_key_usage ku
+            INNER JOIN api_endpoints e ON e.id = ku.endpoint_id
+            WHERE ku.api_key_id = $1 AND ku.billing_period BETWEEN $2 AND $3
+            GROUP BY ku.endpoint_id, e.name
+            ORDER BY total_requests DESC, ku.endpoint_id
+            "#
+        )
+        .bind(api_key_id)
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get API key usage")?;
+
+        Ok(endpoints)
+    }
+
+    // === Request Samples ===
+
+    /// Stores the sample of a call; a call is sampled at most once
+    pub async fn create_request_sample(&self, sample: &CreateRequestSample) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO request_samples (endpoint_id, request_id, method, path, request_headers, request_body,
+                                         request_truncated, status_code, response_headers, response_body,
+                                         response_truncated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (endpoint_id, request_id) DO NOTHING
+            "#
+        )
+        .bind(sample.endpoint_id)
+        .bind(&sample.request_id)
+        .bind(&sample.method)
+        .bind(&sample.path)
+        .bind(Json(&sample.request_headers))
+        .bind(&sample.request_body)
+        .bind(sample.request_truncated)
+        .bind(sample.status_code)
+        .bind(Json(&sample.response_headers))
+        .bind(&sample.response_body)
+        .bind(sample.response_truncated)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store request sample")?;
+
+        Ok(())
+    }
+
+    /// Gets the sample of one of an endpoint's calls, if one was captured and is still kept
+    pub async fn get_request_sample(&self, endpoint_id: Uuid, request_id: &str) -> Result<Option<RequestSample>> {
+        let sample = sqlx::query_as::<_, RequestSample>(
+            r#"
+            SELECT id, endpoint_id, request_id, method, path, request_headers, request_body, request_truncated,
+                   status_code, response_headers, response_body, response_truncated, created_at
+            FROM request_samples WHERE endpoint_id = $1 AND request_id = $2
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(request_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get request sample")?;
+
+        Ok(sample)
+    }
+
+    /// Deletes samples captured before `cutoff`; returns how many were deleted
+    pub async fn delete_request_samples_before(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM request_samples WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete old request samples")?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    // === Consumer Quotas ===
+
+    /// Reads a consumer's usage of an endpoint this billing period along with any
+    /// quota override their owner granted them
+    pub async fn get_consumer_quota_usage(&self, endpoint_id: Uuid, user_id: Uuid, billing_period: &str) -> Result<ConsumerQuotaUsage> {
+        let usage = sqlx::query_as::<_, ConsumerQuotaUsage>(
+            r#"
+            SELECT o.user_id IS NOT NULL AS overridden, o.monthly_quota AS override_quota,
+                   COALESCE((
+                       SELECT request_count FROM usage_records
+                       WHERE user_id = $2 AND endpoint_id = $1 AND billing_period = $3
+                   ), 0) AS used
+            FROM (SELECT 1) AS one
+            LEFT JOIN endpoint_consumer_overrides o ON o.endpoint_id = $1 AND o.user_id = $2
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(user_id)
+        .bind(billing_period)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to get consumer quota usage")?;
+
+        Ok(usage)
+    }
+
+    /// Grants a consumer their own monthly quota on an endpoint, replacing any earlier one
+    pub async fn set_consumer_override(&self, endpoint_id: Uuid, user_id: Uuid, monthly_quota: Option<i64>) -> Result<EndpointConsumerOverride> {
+        let now = Utc::now();
+
+        let consumer_override = sqlx::query_as::<_, EndpointConsumerOverride>(
+            r#"
+            INSERT INTO endpoint_consumer_overrides (endpoint_id, user_id, monthly_quota, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $4)
+            ON CONFLICT (endpoint_id, user_id) DO UPDATE SET
+                monthly_quota = EXCLUDED.monthly_quota,
+                updated_at = EXCLUDED.updated_at
+            RETURNING endpoint_id, user_id, monthly_quota, created_at, updated_at
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(user_id)
+        .bind(monthly_quota)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to set consumer quota override")?;
+
+        Ok(consumer_override)
+    }
+
+    /// Returns a consumer to their endpoint's default quota; false if they had no override
+    pub async fn delete_consumer_override(&self, endpoint_id: Uuid, user_id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM endpoint_consumer_overrides WHERE endpoint_id = $1 AND user_id = $2"
+        )
+        .bind(endpoint_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete consumer quota override")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // === Endpoint Plans and Subscriptions ===
+
+    /// Adds a subscription plan to an endpoint
+    pub async fn create_endpoint_plan(&self, endpoint_id: Uuid, request: CreatePlanRequest) -> Result<EndpointPlan> {
+        let now = Utc::now();
+
+        let plan = sqlx::query_as::<_, EndpointPlan>(
+            r#"
+            INSERT INTO endpoint_plans (endpoint_id, name, monthly_price, included_requests, overage_price,
+                                        created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, endpoint_id, name, monthly_price, included_requests, overage_price, is_active,
+                      created_at, updated_at
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(&request.name)
+        .bind(&request.monthly_price)
+        .bind(request.included_requests)
+        .bind(&request.overage_price)
+        .bind(now)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to create endpoint plan")?;
+
+        Ok(plan)
+    }
+
+    /// Lists an endpoint's plans, optionally including retired ones
+    pub async fn list_endpoint_plans(&self, endpoint_id: Uuid, include_retired: bool) -> Result<Vec<EndpointPlan>> {
+        let plans = sqlx::query_as::<_, EndpointPlan>(
+            r#"
+            SELECT id, endpoint_id, name, monthly_price, included_requests, overage_price, is_active,
+                   created_at, updated_at
+            FROM endpoint_plans
+            WHERE endpoint_id = $1 AND (is_active OR $2)
+            ORDER BY created_at
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(include_retired)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list endpoint plans")?;
+
+        Ok(plans)
+    }
+
+    /// Retrieves one of an endpoint's plans
+    pub async fn get_endpoint_plan(&self, endpoint_id: Uuid, plan_id: Uuid) -> Result<Option<EndpointPlan>> {
+        let plan = sqlx::query_as::<_, EndpointPlan>(
+            r#"
+            SELECT id, endpoint_id, name, monthly_price, included_requests, overage_price, is_active,
+                   created_at, updated_at
+            FROM endpoint_plans WHERE id = $1 AND endpoint_id = $2
+            "#
+        )
+        .bind(plan_id)
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get endpoint plan")?;
+
+        Ok(plan)
+    }
+
+    /// Updates one of an endpoint's plans
+    pub async fn update_endpoint_plan(&self, endpoint_id: Uuid, plan_id: Uuid, request: UpdatePlanRequest) -> Result<Option<EndpointPlan>> {
+        let plan = sqlx::query_as::<_, EndpointPlan>(
+            r#"
+            UPDATE endpoint_plans SET
+                name = COALESCE($3, name),
+                monthly_price = COALESCE($4, monthly_price),
+                included_requests = COALESCE($5, included_requests),
+                overage_price = COALESCE($6, overage_price),
+                is_active = COALESCE($7, is_active),
+                updated_at = $8
+            WHERE id = $1 AND endpoint_id = $2
+            RETURNING id, endpoint_id, name, monthly_price, included_requests, overage_price, is_active,
+                      created_at, updated_at
+            "#
+        )
+        .bind(plan_id)
+        .bind(endpoint_id)
+        .bind(request.name)
+        .bind(request.monthly_price)
+        .bind(request.included_requests)
+        .bind(request.overage_price)
+        .bind(request.is_active)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to update endpoint plan")?;
+
+        Ok(plan)
+    }
+
+    /// Finds a consumer's live subscription to an endpoint, including one cancelled
+    /// but not yet at its period end
+    pub async fn get_active_subscription(&self, user_id: Uuid, endpoint_id: Uuid) -> Result<Option<EndpointSubscription>> {
+        let subscription = sqlx::query_as::<_, EndpointSubscription>(
+            r#"
+            SELECT id, user_id, endpoint_id, plan_id, status, current_period_start, current_period_end,
+                   requests_used, cancel_at_period_end, created_at, updated_at
+            FROM endpoint_subscriptions
+            WHERE user_id = $1 AND endpoint_id = $2 AND status = 'active'
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get subscription")?;
+
+        Ok(subscription)
+    }
+
+    /// Subscribes a consumer to a plan, debiting the first period from their balance and
+    /// charging it to their usage for the billing period it starts in
+    ///
+    /// The balance row is locked while open holds are subtracted, so the subscription
+    /// is only created when the balance still covers its first period.
+    pub async fn create_subscription(
+        &self,
+        user_id: Uuid,
+        plan: &EndpointPlan,
+        price: CostAmount,
+        period_start: DateTime<Utc>,
+        period_end: DateTime<Utc>,
+    ) -> Result<SubscriptionOutcome> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let available = self.lock_available_balance_in(&mut tx, user_id).await?;
+        if available < price {
+            return Ok(SubscriptionOutcome::Insufficient { available });
+        }
+
+        let subscription = sqlx::query_as::<_, EndpointSubscription>(
+            r#"
+            INSERT INTO endpoint_subscriptions (user_id, endpoint_id, plan_id, current_period_start,
+                                                current_period_end, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $4, $4)
+            RETURNING id, user_id, endpoint_id, plan_id, status, current_period_start, current_period_end,
+                      requests_used, cancel_at_period_end, created_at, updated_at
+            "#
+        )
+        .bind(user_id)
+        .bind(plan.endpoint_id)
+        .bind(plan.id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to create subscription")?;
+
+        self.charge_subscription_in(&mut tx, &subscription, price, period_start).await?;
+
+        tx.commit().await.context("Failed to commit subscription")?;
+        Ok(SubscriptionOutcome::Active(Box::new(subscription)))
+    }
+
+    /// Debits a subscription period's price from the consumer's balance and adds it to
+    /// their usage for the billing period starting at `now`
+    async fn charge_subscription_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        subscription: &EndpointSubscription,
+        price: CostAmount,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if price > CostAmount::ZERO {
+            let balance = self.debit_balance_in(tx, subscription.user_id, price, now).await?;
+            let debit = format!("-{}", price);
+            let reference = subscription.id.to_string();
+            self.post_ledger_transaction(tx, subscription.user_id, LedgerEntryType::Charge, &debit, &balance, Some(&reference)).await?;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO usage_records (user_id, endpoint_id, request_count, total_cost, billing_period,
+                                     status, timestamp)
+            VALUES ($1, $2, 0, $3, $4, $5, $6)
+            ON CONFLICT (user_id, endpoint_id, billing_period) DO UPDATE SET
+                total_cost = (usage_records.total_cost::NUMERIC + EXCLUDED.total_cost::NUMERIC)::TEXT,
+                timestamp = EXCLUDED.timestamp
+            "#
+        )
+        .bind(subscription.user_id)
+        .bind(subscription.endpoint_id)
+        .bind(price)
+        .bind(now.format("%Y-%m").to_string())
+        .bind(UsageStatus::Pending)
+        .bind(now)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to charge subscription")?;
+
+        Ok(())
+    }
+
+    /// Stops a consumer's subscription from renewing; it keeps applying until its period end
+    pub async fn cancel_subscription(&self, user_id: Uuid, endpoint_id: Uuid) -> Result<Option<EndpointSubscription>> {
+        let subscription = sqlx::query_as::<_, EndpointSubscription>(
+            r#"
+            UPDATE endpoint_subscriptions SET cancel_at_period_end = true, updated_at = $3
+            WHERE user_id = $1 AND endpoint_id = $2 AND status = 'active'
+            RETURNING id, user_id, endpoint_id, plan_id, status, current_period_start, current_period_end,
+                      requests_used, cancel_at_period_end, created_at, updated_at
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to cancel subscription")?;
+
+        Ok(subscription)
+    }
+
+    /// Counts billable units against a consumer's subscription if one covers `now`,
+    /// returning the allowance as it stood before them
+    pub async fn use_subscription_units(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        units: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<SubscriptionAllowance>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let allowance = Self::use_subscription_units_in(&mut tx, user_id, endpoint_id, units, now).await?;
+        tx.commit().await.context("Failed to commit subscription usage")?;
+        Ok(allowance)
+    }
+
+    async fn use_subscription_units_in(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        units: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<SubscriptionAllowance>> {
+        let allowance = sqlx::query_as::<_, SubscriptionAllowance>(
+            r#"
+            UPDATE endpoint_subscriptions s SET requests_used = s.requests_used + $3
+            FROM endpoint_plans p
+            WHERE p.id = s.plan_id AND s.user_id = $1 AND s.endpoint_id = $2 AND s.status = 'active'
+              AND s.current_period_start <= $4 AND $4 < s.current_period_end
+            RETURNING s.requests_used - $3 AS used_before, p.included_requests, p.overage_price
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(units)
+        .bind(now)
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to use subscription allowance")?;
+
+        Ok(allowance)
+    }
+
+    /// Ends, renews or lapses every live subscription whose period is over
+    ///
+    /// Subscriptions that were cancelled, or whose plan was retired, end. The rest start
+    /// a new period at `now` with a fresh allowance, and its monthly price is debited
+    /// from the consumer's balance and charged to their usage; those whose balance
+    /// cannot cover it lapse instead. Returns how many were renewed, ended and lapsed.
+    pub async fn renew_due_subscriptions(&self, now: DateTime<Utc>, period_end: DateTime<Utc>) -> Result<(u64, u64, u64)> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let ended = sqlx::query(
+            r#"
+            UPDATE endpoint_subscriptions s SET status = 'cancelled', updated_at = $1
+            FROM endpoint_plans p
+            WHERE p.id = s.plan_id AND s.status = 'active' AND s.current_period_end <= $1
+              AND (s.cancel_at_period_end OR NOT p.is_active)
+            "#
+        )
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to end subscriptions")?
+        .rows_affected();
+
+        let due: Vec<(Uuid, Uuid, CostAmount)> = sqlx::query_as(
+            r#"
+            SELECT s.id, s.user_id, p.monthly_price
+            FROM endpoint_subscriptions s
+            JOIN endpoint_plans p ON p.id = s.plan_id
+            WHERE s.status = 'active' AND s.current_period_end <= $1
+            ORDER BY s.user_id, s.id
+            FOR UPDATE OF s
+            "#
+        )
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .context("Failed to get due subscriptions")?;
+
+        let (mut renewed, mut lapsed) = (0, 0);
+        for (id, user_id, price) in due {
+            let available = self.lock_available_balance_in(&mut tx, user_id).await?;
+            if available < price {
+                sqlx::query("UPDATE endpoint_subscriptions SET status = 'lapsed', updated_at = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to lapse subscription")?;
+                lapsed += 1;
+                continue;
+            }
+
+            let subscription = sqlx::query_as::<_, EndpointSubscription>(
+                r#"
+                UPDATE endpoint_subscriptions SET
+                    current_period_start = $2,
+                    current_period_end = $3,
+                    requests_used = 0,
+                    updated_at = $2
+                WHERE id = $1
+                RETURNING id, user_id, endpoint_id, plan_id, status, current_period_start, current_period_end,
+                          requests_used, cancel_at_period_end, created_at, updated_at
+                "#
+            )
+            .bind(id)
+            .bind(now)
+            .bind(period_end)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to renew subscription")?;
+
+            self.charge_subscription_in(&mut tx, &subscription, price, now).await?;
+            renewed += 1;
+        }
+
+        tx.commit().await.context("Failed to commit subscription renewals")?;
+        Ok((renewed, ended, lapsed))
+    }
+
+    // === Balances ===
+
+    /// Adds funds to a user's balance and records them in the ledger, returning the new balance
+    pub async fn credit_balance(&self, user_id: Uuid, amount: &str, reference: Option<&str>) -> Result<String> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let balance = self.credit_balance_in(&mut tx, user_id, amount, reference).await?;
+        tx.commit().await.context("Failed to commit balance credit")?;
+        Ok(balance)
+    }
+
+    /// Adds funds to a user's balance within a transaction, returning the new balance
+    pub async fn credit_balance_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        amount: &str,
+        reference: Option<&str>,
+    ) -> Result<String> {
+        let balance: String = sqlx::query_scalar(
+            r#"
+            INSERT INTO user_balances (user_id, balance, updated_at)
+            VALUES ($1, $2::NUMERIC, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                balance = user_balances.balance + EXCLUDED.balance,
+                updated_at = EXCLUDED.updated_at
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(Utc::now())
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to credit balance")?;
+
+        self.post_ledger_transaction(tx, user_id, LedgerEntryType::Deposit, amount, &balance, reference).await?;
+        Ok(balance)
+    }
+
+    /// Records a deposit a user reports making in `transaction_hash` on chain
+    /// `chain_id`, pending until the transfer's event is applied
+    ///
+    /// Reporting the same transfer again returns the deposit already recorded for it,
+    /// confirmed once its event has been applied.
+    pub async fn report_deposit(
+        &self,
+        user_id: Uuid,
+        amount: &str,
+        chain_id: u64,
+        transaction_hash: &str,
+    ) -> Result<DepositResponse> {
+        let select = r#"
+            SELECT id AS transaction_id, amount, status, created_at FROM payment_transactions
+            WHERE user_id = $1 AND transaction_type = 'deposit' AND chain_id = $2 AND transaction_hash = $3
+            ORDER BY created_at
+            LIMIT 1
+        "#;
+        let existing = sqlx::query_as::<_, DepositResponse>(select)
+            .bind(user_id)
+            .bind(chain_id as i64)
+            .bind(transaction_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to get reported deposit")?;
+        if let Some(deposit) = existing {
+            return Ok(deposit);
+        }
+
+        let inserted = sqlx::query_as::<_, DepositResponse>(
+            r#"
+            INSERT INTO payment_transactions (user_id, transaction_type, amount, status, transaction_hash, chain_id)
+            VALUES ($1, 'deposit', $2, 'pending', $3, $4)
+            ON CONFLICT (user_id, chain_id, transaction_hash)
+                WHERE transaction_type = 'deposit' AND status = 'pending' DO NOTHING
+            RETURNING id AS transaction_id, amount, status, created_at
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(transaction_hash)
+        .bind(chain_id as i64)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to record reported deposit")?;
+
+        // Reported concurrently with this report or the transfer's event
+        match inserted {
+            Some(deposit) => Ok(deposit),
+            None => sqlx::query_as::<_, DepositResponse>(select)
+                .bind(user_id)
+                .bind(chain_id as i64)
+                .bind(transaction_hash)
+                .fetch_one(&self.pool)
+                .await
+                .context("Failed to get reported deposit"),
+        }
+    }
+
+    /// Records a deposit credited from a confirmed transfer on chain `chain_id`
+    ///
+    /// A deposit the user reported for the transfer is confirmed with the amount
+    /// credited, and reports of it by other users are failed, as it was not theirs.
+    pub async fn record_deposit_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        amount: &str,
+        chain_id: u64,
+        transaction_hash: &str,
+        block_number: u64,
+    ) -> Result<Uuid> {
+        sqlx::query(
+            r#"
+            UPDATE payment_transactions SET status = 'failed', error_message = 'Deposit was made by another account'
+            WHERE transaction_type = 'deposit' AND status = 'pending' AND chain_id = $1 AND transaction_hash = $2
+              AND user_id <> $3
+            "#
+        )
+        .bind(chain_id as i64)
+        .bind(transaction_hash)
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to fail deposits reported by other accounts")?;
+
+        let reported: Option<Uuid> = sqlx::query_scalar(
+            r#"
+            UPDATE payment_transactions SET status = 'confirmed', amount = $2, block_number = $5, confirmed_at = NOW()
+            WHERE id = (
+                SELECT id FROM payment_transactions
+                WHERE user_id = $1 AND transaction_type = 'deposit' AND status = 'pending'
+                  AND transaction_hash = $3 AND chain_id = $4
+                LIMIT 1
+            )
+            RETURNING id
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(transaction_hash)
+        .bind(chain_id as i64)
+        .bind(block_number as i64)
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to confirm reported deposit")?;
+        if let Some(transaction_id) = reported {
+            return Ok(transaction_id);
+        }
+
+        let transaction_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO payment_transactions (user_id, transaction_type, amount, status, transaction_hash,
+                                              block_number, chain_id, confirmed_at)
+            VALUES ($1, 'deposit', $2, 'confirmed', $3, $4, $5, NOW())
+            RETURNING id
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(transaction_hash)
+        .bind(block_number as i64)
+        .bind(chain_id as i64)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to record deposit")?;
+
+        Ok(transaction_id)
+    }
+
+    /// Gets a user's balance, before any holds; users who never funded it have none
+    pub async fn get_balance(&self, user_id: Uuid) -> Result<String> {
+        let balance: Option<String> = sqlx::query_scalar("SELECT balance::TEXT FROM user_balances WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to get balance")?;
+
+        Ok(balance.unwrap_or_else(|| "0".to_string()))
+    }
+
+    /// Gets a user's balance together with the usage not yet billed on-chain
+    pub async fn get_user_balance(&self, user_id: Uuid) -> Result<UserBalance> {
+        let balance = sqlx::query_as::<_, UserBalance>(
+            r#"
+            SELECT u.id AS user_id,
+                   COALESCE(b.balance, 0)::TEXT AS balance,
+                   (SELECT COALESCE(SUM(total_cost::NUMERIC), 0) FROM usage_records
+                    WHERE user_id = u.id AND status = 'pending')::TEXT AS pending_charges,
+                   COALESCE(b.updated_at, u.created_at) AS last_updated
+            FROM users u
+            LEFT JOIN user_balances b ON b.user_id = u.id
+            WHERE u.id = $1
+            "#
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to get user balance")?;
+
+        Ok(balance)
+    }
+
+    /// Holds funds for a call in flight if the user's available balance covers them
+    ///
+    /// The balance row is locked while open holds are summed, so concurrent calls
+    /// can never hold more than the balance between them.
+    pub async fn reserve_balance(&self, user_id: Uuid, amount: &str, request_id: &str) -> Result<BalanceReservation> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_balances (user_id, balance, updated_at) VALUES ($1, 0, $2)
+            ON CONFLICT (user_id) DO NOTHING
+            "#
+        )
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to create balance")?;
+
+        let (available, covered): (String, bool) = sqlx::query_as(
+            r#"
+            WITH locked AS (
+                SELECT balance FROM user_balances WHERE user_id = $1 FOR UPDATE
+            ), available AS (
+                SELECT locked.balance - COALESCE((SELECT SUM(amount) FROM balance_holds WHERE user_id = $1), 0) AS amount
+                FROM locked
+            )
+            SELECT amount::TEXT, amount >= $2::NUMERIC FROM available
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to lock balance")?;
+
+        if !covered {
+            return Ok(BalanceReservation::Insufficient { available });
+        }
+
+        let hold_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO balance_holds (user_id, amount, request_id, created_at)
+            VALUES ($1, $2::NUMERIC, $3, $4)
+            RETURNING id
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(request_id)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to hold balance")?;
+
+        tx.commit().await.context("Failed to commit balance hold")?;
+        Ok(BalanceReservation::Held(hold_id))
+    }
+
+    /// Charges a call's actual cost to a user's balance and records it in the ledger,
+    /// releasing the hold placed for the call, if any
+    ///
+    /// The cost is debited even when it exceeds the hold or the hold has already been
+    /// swept as stale, so no charge is lost; a zero cost only releases the hold.
+    pub async fn charge_balance(&self, user_id: Uuid, hold_id: Option<Uuid>, cost: &str, reference: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        if let Some(hold_id) = hold_id {
+            sqlx::query("DELETE FROM balance_holds WHERE id = $1")
+                .bind(hold_id)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to release balance hold")?;
+        }
+
+        let balance: Option<String> = sqlx::query_scalar(
+            r#"
+            INSERT INTO user_balances (user_id, balance, updated_at)
+            SELECT $1, -$2::NUMERIC, $3 WHERE $2::NUMERIC > 0
+            ON CONFLICT (user_id) DO UPDATE SET
+                balance = user_balances.balance + EXCLUDED.balance,
+                updated_at = EXCLUDED.updated_at
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(cost)
+        .bind(Utc::now())
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to debit balance")?;
+
+        if let Some(balance) = balance {
+            let amount = format!("-{}", cost);
+            self.post_ledger_transaction(&mut tx, user_id, LedgerEntryType::Charge, &amount, &balance, Some(reference)).await?;
+        }
+
+        tx.commit().await.context("Failed to commit balance charge")?;
+        Ok(())
+    }
+
+    /// Releases a hold without charging anything
+    pub async fn release_balance_hold(&self, hold_id: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM balance_holds WHERE id = $1")
+            .bind(hold_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to release balance hold")?;
+
+        Ok(())
+    }
+
+    /// Drops holds older than `max_age`, left behind by calls that never settled
+    pub async fn release_stale_balance_holds(&self, max_age: Duration) -> Result<u64> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(max_age).context("Invalid hold age")?;
+        let released = sqlx::query("DELETE FROM balance_holds WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await
+            .context("Failed to release stale balance holds")?
+            .rows_affected();
+
+        Ok(released)
+    }
+
+    /// Debits a withdrawal from a user's balance and records its pending payout in `tx`,
+    /// where the payout is then queued
+    ///
+    /// The balance row is locked until `tx` ends, and open holds and the day's earlier
+    /// withdrawals are summed under the lock, so concurrent withdrawals can neither
+    /// overdraw the balance nor exceed `daily_limit` between them. Failed payouts do
+    /// not count towards the limit.
+    pub async fn begin_withdrawal_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        amount: CostAmount,
+        destination_address: &str,
+        daily_limit: Option<CostAmount>,
+    ) -> Result<WithdrawalOutcome> {
+        let now = Utc::now();
+
+        let available = self.lock_available_balance_in(tx, user_id).await?;
+        if available < amount {
+            return Ok(WithdrawalOutcome::Insufficient { available });
+        }
+
+        if let Some(limit) = daily_limit {
+            let withdrawn_today: CostAmount = sqlx::query_scalar(
+                r#"
+                SELECT COALESCE(SUM(amount::NUMERIC), 0)::TEXT FROM payment_transactions
+                WHERE user_id = $1 AND transaction_type = 'withdrawal' AND status <> 'failed'
+                    AND created_at >= date_trunc('day', $2::TIMESTAMPTZ AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+                "#
+            )
+            .bind(user_id)
+            .bind(now)
+            .fetch_one(&mut **tx)
+            .await
+            .context("Failed to sum today's withdrawals")?;
+
+            if withdrawn_today.checked_add(amount).is_none_or(|total| total > limit) {
+                return Ok(WithdrawalOutcome::DailyLimitExceeded { withdrawn_today, limit });
+            }
+        }
+
+        let balance = self.debit_balance_in(tx, user_id, amount, now).await?;
+
+        let (transaction_id, created_at): (Uuid, DateTime<Utc>) = sqlx::query_as(
+            r#"
+            INSERT INTO payment_transactions (user_id, transaction_type, amount, status, destination_address, created_at)
+            VALUES ($1, 'withdrawal', $2, 'pending', $3, $4)
+            RETURNING id, created_at
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(destination_address)
+        .bind(now)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to record withdrawal")?;
+
+        let debit = format!("-{}", amount);
+        let reference = transaction_id.to_string();
+        self.post_ledger_transaction(tx, user_id, LedgerEntryType::Withdrawal, &debit, &balance, Some(&reference)).await?;
+
+        Ok(WithdrawalOutcome::Pending { transaction_id, created_at })
+    }
+
+    /// Records the on-chain transaction that paid out a pending withdrawal, returning
+    /// the user it was paid to, or `None` if it had already settled
+    pub async fn confirm_withdrawal_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        transaction_id: Uuid,
+        transaction: &BillingTransaction,
+    ) -> Result<Option<Uuid>> {
+        let user_id: Option<Uuid> = sqlx::query_scalar(
+            r#"
+            UPDATE payment_transactions SET
+                status = 'confirmed',
+                transaction_hash = $2,
+                block_number = $3,
+                gas_used = $4,
+                confirmed_at = $5
+            WHERE id = $1 AND transaction_type = 'withdrawal' AND status = 'pending'
+            RETURNING user_id
+            "#
+        )
+        .bind(transaction_id)
+        .bind(&transaction.hash)
+        .bind(transaction.block_number)
+        .bind(transaction.gas_used.as_ref())
+        .bind(Utc::now())
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to confirm withdrawal")?;
+
+        Ok(user_id)
+    }
+
+    /// Marks a pending withdrawal's payout as failed and credits the amount back to
+    /// the user's balance; returns the user credited, or `None` for a withdrawal that
+    /// already settled, so it is never refunded twice
+    pub async fn fail_withdrawal_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        transaction_id: Uuid,
+        error_message: &str,
+    ) -> Result<Option<Uuid>> {
+        let now = Utc::now();
+
+        let failed: Option<(Uuid, CostAmount)> = sqlx::query_as(
+            r#"
+            UPDATE payment_transactions SET status = 'failed', error_message = $2
+            WHERE id = $1 AND transaction_type = 'withdrawal' AND status = 'pending'
+            RETURNING user_id, amount
+            "#
+        )
+        .bind(transaction_id)
+        .bind(error_message)
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to mark withdrawal failed")?;
+
+        let Some((user_id, amount)) = failed else {
+            return Ok(None);
+        };
+
+        self.reverse_debit_in(tx, user_id, amount, &transaction_id.to_string(), now).await?;
+        Ok(Some(user_id))
+    }
+
+    /// Locks a user's balance row for the rest of the transaction and returns what is
+    /// left of it after open holds
+    async fn lock_available_balance_in(&self, tx: &mut Transaction<'_, Postgres>, user_id: Uuid) -> Result<CostAmount> {
+        let available: Option<CostAmount> = sqlx::query_scalar(
+            r#"
+            WITH locked AS (
+                SELECT balance FROM user_balances WHERE user_id = $1 FOR UPDATE
+            )
+            SELECT (locked.balance - COALESCE((SELECT SUM(amount) FROM balance_holds WHERE user_id = $1), 0))::TEXT
+            FROM locked
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to lock balance")?;
+
+        Ok(available.unwrap_or(CostAmount::ZERO))
+    }
+
+    /// Takes `amount` off a user's balance, returning the balance left
+    async fn debit_balance_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        amount: CostAmount,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        let balance: String = sqlx::query_scalar(
+            r#"
+            UPDATE user_balances SET balance = balance - $2::NUMERIC, updated_at = $3
+            WHERE user_id = $1
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(now)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to debit balance")?;
+
+        Ok(balance)
+    }
+
+    /// Credits back an amount debited for an on-chain transaction that failed
+    async fn reverse_debit_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        amount: CostAmount,
+        reference: &str,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let balance: String = sqlx::query_scalar(
+            r#"
+            UPDATE user_balances SET balance = balance + $2::NUMERIC, updated_at = $3
+            WHERE user_id = $1
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(now)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to credit balance")?;
+
+        self.post_ledger_transaction(tx, user_id, LedgerEntryType::Reversal, &amount.to_string(), &balance, Some(reference)).await?;
+        Ok(())
+    }
+
+    /// Debits an escrow's amount from its creator's balance and records the escrow,
+    /// waiting to be opened on-chain
+    ///
+    /// The balance row is locked while open holds are subtracted, so concurrent
+    /// escrows and withdrawals cannot overdraw it between them.
+    pub async fn begin_escrow(
+        &self,
+        user_id: Uuid,
+        amount: CostAmount,
+        request: &CreateEscrowRequest,
+        recipient_address: &str,
+        chain_id: u64,
+    ) -> Result<EscrowOutcome> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let available = self.lock_available_balance_in(&mut tx, user_id).await?;
+        if available < amount {
+            return Ok(EscrowOutcome::Insufficient { available });
+        }
+
+        let balance = self.debit_balance_in(&mut tx, user_id, amount, now).await?;
+
+        let escrow = sqlx::query_as::<_, Escrow>(
+            r#"
+            INSERT INTO escrows (user_id, recipient_address, amount, release_delay_secs, description, chain_id, created_at, updated_at)
+            VALUES ($1, $2, $3::NUMERIC, $4, $5, $6, $7, $7)
+            RETURNING id, user_id, recipient_address, amount::TEXT AS amount, release_delay_secs, description,
+                      status, chain_id, escrow_id::TEXT AS escrow_id, transaction_hash, release_transaction_hash,
+                      error_message, created_at, updated_at, released_at
+            "#
+        )
+        .bind(user_id)
+        .bind(recipient_address)
+        .bind(amount)
+        .bind(request.release_delay as i64)
+        .bind(&request.description)
+        .bind(chain_id as i64)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to record escrow")?;
+
+        let debit = format!("-{}", amount);
+        let reference = escrow.id.to_string();
+        self.post_ledger_transaction(&mut tx, user_id, LedgerEntryType::Escrow, &debit, &balance, Some(&reference)).await?;
+
+        tx.commit().await.context("Failed to commit escrow")?;
+        Ok(EscrowOutcome::Pending(Box::new(escrow)))
+    }
+
+    /// Records that a pending escrow is open on-chain under the contract's `escrow_id`
+    pub async fn activate_escrow(&self, id: Uuid, escrow_id: Option<&str>, transaction_hash: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE escrows SET
+                status = 'active',
+                escrow_id = $2::NUMERIC,
+                transaction_hash = $3,
+                error_message = CASE WHEN $2 IS NULL THEN 'Escrow id not found in the creation receipt' END,
+                updated_at = $4
+            WHERE id = $1 AND status = 'pending'
+            "#
+        )
+        .bind(id)
+        .bind(escrow_id)
+        .bind(transaction_hash)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .context("Failed to activate escrow")?;
+
+        Ok(())
+    }
+
+    /// Marks a pending escrow failed and credits its amount back to the creator's
+    /// balance; returns whether anything was credited, so an escrow that was opened is
+    /// never refunded
+    pub async fn fail_escrow(&self, id: Uuid, error_message: &str) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let failed: Option<(Uuid, CostAmount)> = sqlx::query_as(
+            r#"
+            UPDATE escrows SET status = 'failed', error_message = $2, updated_at = $3
+            WHERE id = $1 AND status = 'pending'
+            RETURNING user_id, amount::TEXT
+            "#
+        )
+        .bind(id)
+        .bind(error_message)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to mark escrow failed")?;
+
+        let Some((user_id, amount)) = failed else {
+            return Ok(false);
+        };
+
+        self.reverse_debit_in(&mut tx, user_id, amount, &id.to_string(), now).await?;
+
+        tx.commit().await.context("Failed to commit escrow reversal")?;
+        Ok(true)
+    }
+
+    /// Moves a user's active escrow to releasing, so it is released only once; returns
+    /// `None` if the user has no such escrow open on-chain
+    pub async fn begin_escrow_release(&self, user_id: Uuid, id: Uuid) -> Result<Option<Escrow>> {
+        let escrow = sqlx::query_as::<_, Escrow>(
+            r#"
+            UPDATE escrows SET status = 'releasing', error_message = NULL, updated_at = $3
+            WHERE id = $1 AND user_id = $2 AND status = 'active' AND escrow_id IS NOT NULL
+            RETURNING id, user_id, recipient_address, amount::TEXT AS amount, release_delay_secs, description,
+                      status, chain_id, escrow_id::TEXT AS escrow_id, transaction_hash, release_transaction_hash,
+                      error_message, created_at, updated_at, released_at
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to begin escrow release")?;
+
+        Ok(escrow)
+    }
+
+    /// Records the confirmed release of an escrow
+    pub async fn confirm_escrow_release(&self, id: Uuid, transaction_hash: &str) -> Result<()> {
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            UPDATE escrows SET status = 'released', release_transaction_hash = $2, released_at = $3, updated_at = $3
+            WHERE id = $1 AND status = 'releasing'
+            "#
+        )
+        .bind(id)
+        .bind(transaction_hash)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .context("Failed to confirm escrow release")?;
+
+        Ok(())
+    }
+
+    /// Returns an escrow whose release failed to active, so it can be released again
+    pub async fn fail_escrow_release(&self, id: Uuid, error_message: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE escrows SET status = 'active', error_message = $2, updated_at = $3
+            WHERE id = $1 AND status = 'releasing'
+            "#
+        )
+        .bind(id)
+        .bind(error_message)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .context("Failed to record failed escrow release")?;
+
+        Ok(())
+    }
+
+    /// Gets one of a user's escrows
+    pub async fn get_escrow(&self, user_id: Uuid, id: Uuid) -> Result<Option<Escrow>> {
+        let escrow = sqlx::query_as::<_, Escrow>(
+            r#"
+            SELECT id, user_id, recipient_address, amount::TEXT AS amount, release_delay_secs, description,
+                   status, chain_id, escrow_id::TEXT AS escrow_id, transaction_hash, release_transaction_hash,
+                   error_message, created_at, updated_at, released_at
+            FROM escrows
+            WHERE id = $1 AND user_id = $2
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get escrow")?;
+
+        Ok(escrow)
+    }
+
+    /// Pages through a user's escrows, newest first
+    pub async fn list_escrows(&self, user_id: Uuid, params: PaginationParams) -> Result<PaginatedResponse<Escrow>> {
+        let limit = params.limit.unwrap_or(20).clamp(1, 100);
+        let page = params.page.unwrap_or(1).max(1);
+        let offset = (page as i64 - 1) * limit as i64;
+
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM escrows WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count escrows")?;
+
+        let escrows = sqlx::query_as::<_, Escrow>(
+            r#"
+            SELECT id, user_id, recipient_address, amount::TEXT AS amount, release_delay_secs, description,
+                   status, chain_id, escrow_id::TEXT AS escrow_id, transaction_hash, release_transaction_hash,
+                   error_message, created_at, updated_at, released_at
+            FROM escrows
+            WHERE user_id = $1
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(user_id)
+        .bind(limit as i64)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list escrows")?;
+
+        Ok(PaginatedResponse::new(escrows, total, page, limit))
+    }
+
+    /// Debits a payment stream's total from its payer's balance and records the stream,
+    /// waiting to be opened on-chain
+    pub async fn begin_payment_stream(
+        &self,
+        payer_id: Uuid,
+        total_amount: CostAmount,
+        request: &CreatePaymentStreamRequest,
+        recipient_address: &str,
+        chain_id: u64,
+    ) -> Result<PaymentStreamOutcome> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let available = self.lock_available_balance_in(&mut tx, payer_id).await?;
+        if available < total_amount {
+            return Ok(PaymentStreamOutcome::Insufficient { available });
+        }
+
+        let balance = self.debit_balance_in(&mut tx, payer_id, total_amount, now).await?;
+
+        let stream = sqlx::query_as::<_, PaymentStream>(
+            r#"
+            INSERT INTO payment_streams (payer_id, recipient_address, total_amount, duration_secs, description, chain_id, created_at, updated_at)
+            VALUES ($1, $2, $3::NUMERIC, $4, $5, $6, $7, $7)
+            RETURNING id, payer_id, recipient_address, total_amount::TEXT AS total_amount,
+                      claimed_amount::TEXT AS claimed_amount, duration_secs, description, status, chain_id,
+                      stream_id::TEXT AS stream_id, transaction_hash, error_message, started_at, last_claimed_at,
+                      created_at, updated_at
+            "#
+        )
+        .bind(payer_id)
+        .bind(recipient_address)
+        .bind(total_amount)
+        .bind(request.duration as i64)
+        .bind(&request.description)
+        .bind(chain_id as i64)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to record payment stream")?;
+
+        let debit = format!("-{}", total_amount);
+        let reference = stream.id.to_string();
+        self.post_ledger_transaction(&mut tx, payer_id, LedgerEntryType::Stream, &debit, &balance, Some(&reference)).await?;
+
+        tx.commit().await.context("Failed to commit payment stream")?;
+        Ok(PaymentStreamOutcome::Pending(Box::new(stream)))
+    }
+
+    /// Records that a pending payment stream is open on-chain under the contract's
+    /// `stream_id`, paying out from now
+    pub async fn activate_payment_stream(&self, id: Uuid, stream_id: Option<&str>, transaction_hash: &str) -> Result<()> {
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            UPDATE payment_streams SET
+                status = 'active',
+                stream_id = $2::NUMERIC,
+                transaction_hash = $3,
+                error_message = CASE WHEN $2 IS NULL THEN 'Stream id not found in the creation receipt' END,
+                started_at = $4,
+                updated_at = $4
+            WHERE id = $1 AND status = 'pending'
+            "#
+        )
+        .bind(id)
+        .bind(stream_id)
+        .bind(transaction_hash)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .context("Failed to activate payment stream")?;
+
+        Ok(())
+    }
+
+    /// Marks a pending payment stream failed and credits its total back to the payer's
+    /// balance; returns whether anything was credited, so a stream that was opened is
+    /// never refunded
+    pub async fn fail_payment_stream(&self, id: Uuid, error_message: &str) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let failed: Option<(Uuid, CostAmount)> = sqlx::query_as(
+            r#"
+            UPDATE payment_streams SET status = 'failed', error_message = $2, updated_at = $3
+            WHERE id = $1 AND status = 'pending'
+            RETURNING payer_id, total_amount::TEXT
+            "#
+        )
+        .bind(id)
+        .bind(error_message)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to mark payment stream failed")?;
+
+        let Some((payer_id, total_amount)) = failed else {
+            return Ok(false);
+        };
+
+        self.reverse_debit_in(&mut tx, payer_id, total_amount, &id.to_string(), now).await?;
+
+        tx.commit().await.context("Failed to commit payment stream reversal")?;
+        Ok(true)
+    }
+
+    /// Adds a confirmed claim to what has been claimed from a payment stream
+    pub async fn record_payment_stream_claim(&self, id: Uuid, amount: CostAmount) -> Result<()> {
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            UPDATE payment_streams SET
+                claimed_amount = claimed_amount + $2::NUMERIC,
+                last_claimed_at = $3,
+                updated_at = $3
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .bind(amount)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record payment stream claim")?;
+
+        Ok(())
+    }
+
+    /// Gets a payment stream the user pays into or that pays their wallet
+    pub async fn get_payment_stream(&self, user_id: Uuid, wallet_address: &str, id: Uuid) -> Result<Option<PaymentStream>> {
+        let stream = sqlx::query_as::<_, PaymentStream>(
+            r#"
+            SELECT id, payer_id, recipient_address, total_amount::TEXT AS total_amount,
+                   claimed_amount::TEXT AS claimed_amount, duration_secs, description, status, chain_id,
+                   stream_id::TEXT AS stream_id, transaction_hash, error_message, started_at, last_claimed_at,
+                   created_at, updated_at
+            FROM payment_streams
+            WHERE id = $1 AND (payer_id = $2 OR LOWER(recipient_address) = LOWER($3))
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(wallet_address)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get payment stream")?;
+
+        Ok(stream)
+    }
+
+    /// Lists the payment streams a user pays into, or those paying their wallet, newest
+    /// first
+    pub async fn list_payment_streams(
+        &self,
+        user_id: Uuid,
+        wallet_address: &str,
+        query: PaymentStreamQuery,
+    ) -> Result<Vec<PaymentStream>> {
+        let streams = sqlx::query_as::<_, PaymentStream>(
+            r#"
+            SELECT id, payer_id, recipient_address, total_amount::TEXT AS total_amount,
+                   claimed_amount::TEXT AS claimed_amount, duration_secs, description, status, chain_id,
+                   stream_id::TEXT AS stream_id, transaction_hash, error_message, started_at, last_claimed_at,
+                   created_at, updated_at
+            FROM payment_streams
+            WHERE ($2 AND payer_id = $1) OR (NOT $2 AND LOWER(recipient_address) = LOWER($3))
+            ORDER BY created_at DESC, id DESC
+            LIMIT $4 OFFSET $5
+            "#
+        )
+        .bind(user_id)
+        .bind(query.role == PaymentStreamRole::Payer)
+        .bind(wallet_address)
+        .bind(query.limit.unwrap_or(50).clamp(1, 500))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list payment streams")?;
+
+        Ok(streams)
+    }
+
+    /// Credits or debits a user's balance on behalf of an administrator, recording the
+    /// reason in the audit log within the same transaction; a debit may take the
+    /// balance below zero
+    ///
+    /// Returns `None` if the user does not exist or was deleted.
+    pub async fn adjust_balance(
+        &self,
+        user_id: Uuid,
+        amount: CostAmount,
+        actor: &AuditActor,
+        reason: &str,
+    ) -> Result<Option<LedgerEntry>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL)")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to look up user")?;
+        if !exists {
+            return Ok(None);
+        }
+
+        let balance: String = sqlx::query_scalar(
+            r#"
+            INSERT INTO user_balances (user_id, balance, updated_at)
+            VALUES ($1, $2::NUMERIC, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                balance = user_balances.balance + EXCLUDED.balance,
+                updated_at = EXCLUDED.updated_at
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(amount)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to adjust balance")?;
+
+        let audit_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO audit_log (actor_id, action, target_type, target_id, metadata, ip_address_hash)
+            VALUES ($1, 'admin.adjust_balance', 'user', $2, $3, $4)
+            RETURNING id
+            "#
+        )
+        .bind(actor.id)
+        .bind(user_id)
+        .bind(serde_json::json!({ "amount": amount, "reason": reason }))
+        .bind(&actor.ip_address_hash)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to write audit log entry")?;
+
+        let reference = audit_id.to_string();
+        let entry = self.post_ledger_transaction(
+            &mut tx,
+            user_id,
+            LedgerEntryType::Adjustment,
+            &amount.to_string(),
+            &balance,
+            Some(&reference),
+        ).await?;
+
+        tx.commit().await.context("Failed to commit balance adjustment")?;
+        Ok(Some(entry))
+    }
+
+    // === Balance Reconciliation ===
+
+    /// Pages through active users by id, after `after`, with their ledger balance and
+    /// the usage charged to it but not yet billed on-chain
+    ///
+    /// Users with usage queued for billing or a withdrawal awaiting payout are left
+    /// out, as their contract balance is about to change.
+    pub async fn get_reconciliation_accounts(&self, after: Option<Uuid>, limit: i64) -> Result<Vec<ReconciliationAccount>> {
+        let accounts = sqlx::query_as::<_, ReconciliationAccount>(
+            r#"
+            SELECT u.id AS user_id, u.wallet_address,
+                   COALESCE(b.balance, 0)::TEXT AS ledger_balance,
+                   (SELECT COALESCE(SUM(total_cost::NUMERIC), 0) FROM usage_records
+                    WHERE user_id = u.id AND status = 'pending')::TEXT AS pending_charges
+            FROM users u
+            LEFT JOIN user_balances b ON b.user_id = u.id
+            WHERE u.is_active AND u.deleted_at IS NULL
+              AND ($1::UUID IS NULL OR u.id > $1)
+              AND NOT EXISTS (
+                  SELECT 1 FROM usage_records
+                  WHERE user_id = u.id AND status = 'pending' AND blockchain_tx_id IS NOT NULL
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM payment_transactions
+                  WHERE user_id = u.id AND transaction_type = 'withdrawal' AND status = 'pending'
+              )
+            ORDER BY u.id
+            LIMIT $2
+            "#
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get accounts to reconcile")?;
+
+        Ok(accounts)
+    }
+
+    /// Records a drift between a user's ledger and contract balances
+    ///
+    /// A corrected drift is added to the ledger balance in the same transaction, with
+    /// an adjustment entry referencing the report. A flagged drift replaces any drift
+    /// of the user's still awaiting review.
+    pub async fn create_reconciliation_report(
+        &self,
+        account: &ReconciliationAccount,
+        block_number: u64,
+        chain_balance: CostAmount,
+        drift: CostAmount,
+        status: ReconciliationStatus,
+    ) -> Result<ReconciliationReport> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let report = sqlx::query_as::<_, ReconciliationReport>(
+            r#"
+            INSERT INTO reconciliation_reports (user_id, wallet_address, block_number, chain_balance,
+                                                pending_charges, ledger_balance, drift, status)
+            VALUES ($1, $2, $3, $4::NUMERIC, $5::NUMERIC, $6::NUMERIC, $7::NUMERIC, $8)
+            ON CONFLICT (user_id) WHERE status = 'flagged' DO UPDATE SET
+                block_number = EXCLUDED.block_number,
+                chain_balance = EXCLUDED.chain_balance,
+                pending_charges = EXCLUDED.pending_charges,
+                ledger_balance = EXCLUDED.ledger_balance,
+                drift = EXCLUDED.drift,
+                updated_at = NOW()
+            RETURNING id, user_id, wallet_address, block_number, chain_balance::TEXT AS chain_balance,
+                      pending_charges::TEXT AS pending_charges, ledger_balance::TEXT AS ledger_balance,
+                      drift::TEXT AS drift, status, resolved_by, resolution_note, resolved_at,
+                      created_at, updated_at
+            "#
+        )
+        .bind(account.user_id)
+        .bind(&account.wallet_address)
+        .bind(block_number as i64)
+        .bind(chain_balance)
+        .bind(account.pending_charges)
+        .bind(account.ledger_balance)
+        .bind(drift)
+        .bind(status)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to record reconciliation report")?;
+
+        if status == ReconciliationStatus::Corrected {
+            let balance: String = sqlx::query_scalar(
+                r#"
+                INSERT INTO user_balances (user_id, balance, updated_at)
+                VALUES ($1, $2::NUMERIC, $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    balance = user_balances.balance + EXCLUDED.balance,
+                    updated_at = EXCLUDED.updated_at
+                RETURNING balance::TEXT
+                "#
+            )
+            .bind(account.user_id)
+            .bind(drift)
+            .bind(Utc::now())
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to correct balance")?;
+
+            let reference = report.id.to_string();
+            self.post_ledger_transaction(
+                &mut tx,
+                account.user_id,
+                LedgerEntryType::Adjustment,
+                &drift.to_string(),
+                &balance,
+                Some(&reference),
+            ).await?;
+        }
+
+        tx.commit().await.context("Failed to commit reconciliation report")?;
+        Ok(report)
+    }
+
+    /// Lists reconciliation reports, newest first
+    pub async fn list_reconciliation_reports(&self, query: ReconciliationQuery) -> Result<Vec<ReconciliationReport>> {
+        let reports = sqlx::query_as::<_, ReconciliationReport>(
+            r#"
+            SELECT id, user_id, wallet_address, block_number, chain_balance::TEXT AS chain_balance,
+                   pending_charges::TEXT AS pending_charges, ledger_balance::TEXT AS ledger_balance,
+                   drift::TEXT AS drift, status, resolved_by, resolution_note, resolved_at,
+                   created_at, updated_at
+            FROM reconciliation_reports
+            WHERE ($1::reconciliation_status IS NULL OR status = $1)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(query.status)
+        .bind(query.limit.unwrap_or(50).clamp(1, 500))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list reconciliation reports")?;
+
+        Ok(reports)
+    }
+
+    /// Marks a flagged drift reviewed; returns `None` if there is no such drift
+    /// awaiting review
+    pub async fn resolve_reconciliation_report(
+        &self,
+        id: Uuid,
+        admin_id: Uuid,
+        note: &str,
+    ) -> Result<Option<ReconciliationReport>> {
+        let report = sqlx::query_as::<_, ReconciliationReport>(
+            r#"
+            UPDATE reconciliation_reports SET
+                status = 'resolved', resolved_by = $2, resolution_note = $3,
+                resolved_at = NOW(), updated_at = NOW()
+            WHERE id = $1 AND status = 'flagged'
+            RETURNING id, user_id, wallet_address, block_number, chain_balance::TEXT AS chain_balance,
+                      pending_charges::TEXT AS pending_charges, ledger_balance::TEXT AS ledger_balance,
+                      drift::TEXT AS drift, status, resolved_by, resolution_note, resolved_at,
+                      created_at, updated_at
+            "#
+        )
+        .bind(id)
+        .bind(admin_id)
+        .bind(note)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to resolve reconciliation report")?;
+
+        Ok(report)
+    }
+
+    /// Lists a user's ledger entries, newest first, each with the balance it left
+    pub async fn list_ledger_entries(&self, user_id: Uuid, query: LedgerQuery) -> Result<Vec<LedgerEntry>> {
+        let entries = sqlx::query_as::<_, LedgerEntry>(
+            r#"
+            SELECT l.id, l.transaction_id, l.entry_type, l.amount::TEXT AS amount,
+                   l.balance_after::TEXT AS balance_after, l.reference, l.created_at,
+                   p.status AS transaction_status, p.transaction_hash
+            FROM balance_ledger l
+            LEFT JOIN payment_transactions p
+                ON l.entry_type IN ('withdrawal', 'reversal') AND p.id::TEXT = l.reference AND p.user_id = l.user_id
+            WHERE l.user_id = $1
+            ORDER BY l.created_at DESC, l.id DESC
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(user_id)
+        .bind(query.limit.unwrap_or(50).clamp(1, 500))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list ledger entries")?;
+
+        Ok(entries)
+    }
+
+    /// Posts a change to a user's balance as a ledger transaction: the user's entry and
+    /// the opposite entry on the platform account the funds moved from or to, which
+    /// the database checks sum to zero when `tx` commits
+    async fn post_ledger_transaction(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        user_id: Uuid,
+        entry_type: LedgerEntryType,
+        amount: &str,
+        balance_after: &str,
+        reference: Option<&str>,
+    ) -> Result<LedgerEntry> {
+        let transaction_id = Uuid::new_v4();
+        let now = Utc::now();
+
+        let entry = sqlx::query_as::<_, LedgerEntry>(
+            r#"
+            INSERT INTO balance_ledger (transaction_id, user_id, entry_type, amount, balance_after, reference, created_at)
+            VALUES ($1, $2, $3, $4::NUMERIC, $5::NUMERIC, $6, $7)
+            RETURNING id, transaction_id, entry_type, amount::TEXT AS amount, balance_after::TEXT AS balance_after,
+                      reference, created_at
+            "#
+        )
+        .bind(transaction_id)
+        .bind(user_id)
+        .bind(entry_type)
+        .bind(amount)
+        .bind(balance_after)
+        .bind(reference)
+        .bind(now)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to record ledger entry")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO balance_ledger (transaction_id, account, entry_type, amount, reference, created_at)
+            VALUES ($1, $2, $3, -$4::NUMERIC, $5, $6)
+            "#
+        )
+        .bind(transaction_id)
+        .bind(entry_type.platform_account())
+        .bind(entry_type)
+        .bind(amount)
+        .bind(reference)
+        .bind(now)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record platform ledger entry")?;
+
+        Ok(entry)
+    }
+
+    // === Contract Events ===
+
+    /// Last block whose contract events have all been applied, if any have been
+    pub async fn get_chain_event_cursor(&self, name: &str) -> Result<Option<u64>> {
+        let block: Option<i64> = sqlx::query_scalar("SELECT last_block FROM chain_event_cursors WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to get contract event cursor")?;
+
+        Ok(block.map(|block| block as u64))
+    }
+
+    /// Records the last block whose contract events have all been applied
+    pub async fn set_chain_event_cursor_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        name: &str,
+        last_block: u64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO chain_event_cursors (name, last_block, updated_at)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (name) DO UPDATE SET last_block = EXCLUDED.last_block, updated_at = EXCLUDED.updated_at
+            "#
+        )
+        .bind(name)
+        .bind(last_block as i64)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to set contract event cursor")?;
+
+        Ok(())
+    }
+
+    /// Records a contract event as applied; returns false if it already was, so a
+    /// replayed block range never applies an event twice
+    pub async fn record_chain_event_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        chain_id: u64,
+        transaction_hash: &str,
+        log_index: u64,
+        block_number: u64,
+        event_name: &str,
+    ) -> Result<bool> {
+        let recorded = sqlx::query(
+            r#"
+            INSERT INTO chain_events (transaction_hash, log_index, block_number, event_name, chain_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (transaction_hash, log_index) DO NOTHING
+            "#
+        )
+        .bind(transaction_hash)
+        .bind(log_index as i64)
+        .bind(block_number as i64)
+        .bind(event_name)
+        .bind(chain_id as i64)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record contract event")?
+        .rows_affected() > 0;
+
+        Ok(recorded)
+    }
+
+    /// Gets the account registered with a wallet, whatever the case of the address
+    pub async fn get_user_id_by_wallet_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        wallet_address: &str,
+    ) -> Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar("SELECT id FROM users WHERE LOWER(wallet_address) = LOWER($1) AND deleted_at IS NULL")
+            .bind(wallet_address)
+            .fetch_optional(&mut **tx)
+            .await
+            .context("Failed to get user by wallet address")?;
+
+        Ok(user_id)
+    }
+
+    /// Marks the oldest pending usage record matching usage billed on-chain as billed
+    /// by `transaction`; returns false when there is none, as when the billing run
+    /// already recorded it
+    pub async fn bill_recorded_usage_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        wallet_address: &str,
+        endpoint_name: &str,
+        request_count: i64,
+        transaction: &BillingTransaction,
+    ) -> Result<bool> {
+        let billed = sqlx::query(
+            r#"
+            UPDATE usage_records SET status = $4, transaction_hash = $5, block_number = $6, chain_id = $8
+            WHERE id = (
+                SELECT ur.id FROM usage_records ur
+                JOIN users u ON u.id = ur.user_id
+                JOIN api_endpoints e ON e.id = ur.endpoint_id
+                WHERE LOWER(u.wallet_address) = LOWER($1) AND e.name = $2 AND ur.request_count = $3 AND ur.status = $7
+                ORDER BY ur.billing_period, ur.timestamp
+                LIMIT 1
+                FOR UPDATE OF ur SKIP LOCKED
+            )
+            "#
+        )
+        .bind(wallet_address)
+        .bind(endpoint_name)
+        .bind(request_count)
+        .bind(UsageStatus::Billed)
+        .bind(&transaction.hash)
+        .bind(transaction.block_number)
+        .bind(UsageStatus::Pending)
+        .bind(transaction.chain_id)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to mark usage billed on-chain")?
+        .rows_affected() > 0;
+
+        Ok(billed)
+    }
+
+    // === Blockchain Transaction Queue ===
+
+    /// Queues an operation to be sent to the chain, linking the pending usage it bills
+    pub async fn enqueue_blockchain_tx_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        operation: &str,
+        payload: &serde_json::Value,
+        usage_ids: &[Uuid],
+    ) -> Result<Uuid> {
+        let id: Uuid = sqlx::query_scalar("INSERT INTO blockchain_txs (operation, payload) VALUES ($1, $2) RETURNING id")
+            .bind(operation)
+            .bind(payload)
+            .fetch_one(&mut **tx)
+            .await
+            .context("Failed to queue blockchain transaction")?;
+
+        sqlx::query("UPDATE usage_records SET blockchain_tx_id = $1 WHERE id = ANY($2)")
+            .bind(id)
+            .bind(usage_ids)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to link usage to blockchain transaction")?;
+
+        Ok(id)
+    }
+
+    /// Claims the queued transaction due to be sent that has waited longest, counting
+    /// the attempt
+    ///
+    /// The entry is locked until `tx` ends and entries locked by another worker are
+    /// skipped, so no entry is sent twice at once.
+    pub async fn claim_due_blockchain_tx_in(&self, tx: &mut Transaction<'_, Postgres>) -> Result<Option<BlockchainTx>> {
+        let entry = sqlx::query_as::<_, BlockchainTx>(
+            r#"
+            UPDATE blockchain_txs SET attempts = attempts + 1, updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM blockchain_txs
+                WHERE status IN ('pending', 'failed') AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, operation, payload, status, tx_hash, block_number, gas_used, attempts,
+                      last_error, next_attempt_at, created_at, updated_at
+            "#
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to claim queued blockchain transaction")?;
+
+        Ok(entry)
+    }
+
+    /// Records that a queued transaction was sent as `tx_hash`
+    pub async fn mark_blockchain_tx_submitted_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        tx_hash: &str,
+    ) -> Result<()> {
+        sqlx::query("UPDATE blockchain_txs SET status = $2, tx_hash = $3, updated_at = NOW() WHERE id = $1")
+            .bind(id)
+            .bind(BlockchainTxStatus::Submitted)
+            .bind(tx_hash)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to mark blockchain transaction submitted")?;
+
+        Ok(())
+    }
+
+    /// Gets queued transactions that were sent but have not settled, oldest first
+    pub async fn get_submitted_blockchain_txs(&self, limit: i64) -> Result<Vec<BlockchainTx>> {
+        let entries = sqlx::query_as::<_, BlockchainTx>(
+            r#"
+            SELECT id, operation, payload, status, tx_hash, block_number, gas_used, attempts,
+                   last_error, next_attempt_at, created_at, updated_at
+            FROM blockchain_txs
+            WHERE status = 'submitted'
+            ORDER BY updated_at
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get submitted blockchain transactions")?;
+
+        Ok(entries)
+    }
+
+    /// Settles a queued transaction as confirmed, marking the usage it carried billed
+    /// and completing the billing records of that usage's periods
+    ///
+    /// A period with usage still pending, carried by another entry or given up on,
+    /// only has its totals refreshed; it is completed when that usage is billed.
+    ///
+    /// Returns the usage records billed for each user, or `None` if the entry was not
+    /// waiting on confirmation, as when another worker already settled it.
+    pub async fn confirm_blockchain_tx_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        transaction: &BillingTransaction,
+    ) -> Result<Option<Vec<SettledUsage>>> {
+        let settled = sqlx::query(
+            r#"
+            UPDATE blockchain_txs SET status = $2, tx_hash = $3, gas_used = $4, block_number = $5,
+                                      last_error = NULL, updated_at = NOW()
+            WHERE id = $1 AND status = 'submitted'
+            "#
+        )
+        .bind(id)
+        .bind(BlockchainTxStatus::Confirmed)
+        .bind(&transaction.hash)
+        .bind(transaction.gas_used.as_ref())
+        .bind(transaction.block_number)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to confirm blockchain transaction")?
+        .rows_affected() > 0;
+        if !settled {
+            return Ok(None);
+        }
+
+        let billed: Vec<(Uuid, Uuid, String)> = sqlx::query_as(
+            r#"
+            UPDATE usage_records SET status = $2, transaction_hash = $3, gas_used = $4, block_number = $5,
+                                     chain_id = $6
+            WHERE blockchain_tx_id = $1 AND status = 'pending'
+            RETURNING id, user_id, billing_period
+            "#
+        )
+        .bind(id)
+        .bind(UsageStatus::Billed)
+        .bind(&transaction.hash)
+        .bind(transaction.gas_used.as_ref())
+        .bind(transaction.block_number)
+        .bind(transaction.chain_id)
+        .fetch_all(&mut **tx)
+        .await
+        .context("Failed to mark queued usage billed")?;
+
+        let (settled, periods) = settled_usage(billed);
+        for (user_id, billing_period) in periods {
+            let unsettled: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM usage_records WHERE user_id = $1 AND billing_period = $2 AND status = 'pending')"
+            )
+            .bind(user_id)
+            .bind(&billing_period)
+            .fetch_one(&mut **tx)
+            .await
+            .context("Failed to check unbilled usage")?;
+            if !unsettled {
+                self.update_billing_record(tx, user_id, &billing_period, Ok(transaction)).await?;
+                continue;
+            }
+
+            sqlx::query(
+                r#"
+                UPDATE billing_records SET (total_requests, total_cost, updated_at) = (
+                    SELECT COALESCE(SUM(request_count), 0), COALESCE(SUM(total_cost::NUMERIC), 0)::TEXT, NOW()
+                    FROM usage_records
+                    WHERE user_id = $1 AND billing_period = $2 AND status = 'billed'
+                )
+                WHERE user_id = $1 AND billing_period = $2
+                "#
+            )
+            .bind(user_id)
+            .bind(&billing_period)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to update billing totals")?;
+        }
+
+        Ok(Some(settled))
+    }
+
+    /// Records a failed attempt of a queued transaction
+    ///
+    /// The entry is sent again at `retry_at`, or given up on when that is `None`: the
+    /// usage it carried is then unlinked, so the next billing run queues it again, and
+    /// the billing records of that usage's periods are failed with `error`.
+    ///
+    /// Returns the usage records released for each user, none while it is retried.
+    pub async fn fail_blockchain_tx_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SettledUsage>> {
+        let status = if retry_at.is_some() { BlockchainTxStatus::Failed } else { BlockchainTxStatus::Abandoned };
+        sqlx::query(
+            r#"
+            UPDATE blockchain_txs SET status = $2, last_error = $3,
+                                      next_attempt_at = COALESCE($4, next_attempt_at), updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .bind(retry_at)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record blockchain transaction failure")?;
+        if retry_at.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let released: Vec<(Uuid, Uuid, String)> = sqlx::query_as(
+            r#"
+            UPDATE usage_records SET blockchain_tx_id = NULL
+            WHERE blockchain_tx_id = $1 AND status = 'pending'
+            RETURNING id, user_id, billing_period
+            "#
+        )
+        .bind(id)
+        .fetch_all(&mut **tx)
+        .await
+        .context("Failed to release queued usage")?;
+
+        let (settled, periods) = settled_usage(released);
+        for (user_id, billing_period) in periods {
+            self.update_billing_record(tx, user_id, &billing_period, Err(error)).await?;
+        }
+
+        Ok(settled)
+    }
+
+    // === SLA Refunds ===
+
+    /// Flags a billed call that breached its endpoint's SLA for refund against the
+    /// consumer's usage record; a call already flagged is left as it is
+    pub async fn create_sla_refund(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        billing_period: &str,
+        request_id: &str,
+        amount: &str,
+        breach: SlaBreach,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sla_refunds (usage_record_id, user_id, endpoint_id, request_id, amount, breach, created_at)
+            SELECT id, user_id, endpoint_id, $4, $5::NUMERIC, $6, $7 FROM usage_records
+            WHERE user_id = $1 AND endpoint_id = $2 AND billing_period = $3
+            ON CONFLICT (request_id) DO NOTHING
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(billing_period)
+        .bind(request_id)
+        .bind(amount)
+        .bind(breach)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .context("Failed to flag SLA refund")?;
+
+        Ok(())
+    }
+
+    /// Gets the oldest refunds not yet credited to their consumers
+    pub async fn get_pending_sla_refunds(&self, limit: i64) -> Result<Vec<SlaRefund>> {
+        let refunds = sqlx::query_as::<_, SlaRefund>(
+            r#"
+            SELECT id, usage_record_id, user_id, endpoint_id, request_id, amount::TEXT AS amount, breach,
+                   created_at, refunded_at
+            FROM sla_refunds
+            WHERE refunded_at IS NULL
+            ORDER BY created_at
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get pending SLA refunds")?;
+
+        Ok(refunds)
+    }
+
+    /// Credits a flagged refund back to the consumer's balance and deducts it from their
+    /// usage record, which becomes refunded once nothing is left to bill
+    ///
+    /// Returns false when the refund had already been applied.
+    pub async fn apply_sla_refund(&self, refund_id: Uuid) -> Result<bool> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let refund: Option<(Uuid, Uuid, String, String)> = sqlx::query_as(
+            r#"
+            UPDATE sla_refunds SET refunded_at = $2
+            WHERE id = $1 AND refunded_at IS NULL
+            RETURNING user_id, usage_record_id, amount::TEXT, request_id
+            "#
+        )
+        .bind(refund_id)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to claim SLA refund")?;
+
+        let Some((user_id, usage_record_id, amount, request_id)) = refund else {
+            return Ok(false);
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE usage_records SET
+                total_cost = GREATEST(total_cost::NUMERIC - $2::NUMERIC, 0)::TEXT,
+                status = CASE WHEN total_cost::NUMERIC <= $2::NUMERIC THEN $3 ELSE status END
+            WHERE id = $1
+            "#
+        )
+        .bind(usage_record_id)
+        .bind(&amount)
+        .bind(UsageStatus::Refunded)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to deduct SLA refund from usage")?;
+
+        let balance: String = sqlx::query_scalar(
+            r#"
+            INSERT INTO user_balances (user_id, balance, updated_at)
+            VALUES ($1, $2::NUMERIC, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                balance = user_balances.balance + EXCLUDED.balance,
+                updated_at = EXCLUDED.updated_at
+            RETURNING balance::TEXT
+            "#
+        )
+        .bind(user_id)
+        .bind(&amount)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to credit SLA refund")?;
+
+        self.post_ledger_transaction(&mut tx, user_id, LedgerEntryType::Refund, &amount, &balance, Some(&request_id)).await?;
+
+        tx.commit().await.context("Failed to commit SLA refund")?;
+        Ok(true)
+    }
+
+    /// Totals an endpoint's SLA refunds flagged over a date range
+    pub async fn get_sla_refund_stats(&self, endpoint_id: Uuid, range: StatsRange) -> Result<SlaRefundStats> {
+        let (start_at, end_at) = range_bounds(range);
+
+        let stats = sqlx::query_as::<_, SlaRefundStats>(
+            r#"
+            SELECT COUNT(*) FILTER (WHERE refunded_at IS NOT NULL) AS refunded_requests,
+                   COALESCE(SUM(amount) FILTER (WHERE refunded_at IS NOT NULL), 0)::TEXT AS refunded_amount,
+                   COUNT(*) FILTER (WHERE refunded_at IS NULL) AS pending_requests,
+                   COALESCE(SUM(amount) FILTER (WHERE refunded_at IS NULL), 0)::TEXT AS pending_amount,
+                   COUNT(*) FILTER (WHERE breach = 'latency') AS latency_breaches,
+                   COUNT(*) FILTER (WHERE breach = 'server_error') AS error_breaches
+            FROM sla_refunds
+            WHERE endpoint_id = $1
+                AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+                AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(start_at)
+        .bind(end_at)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to get SLA refund stats")?;
+
+        Ok(stats)
+    }
+
+    // === Idempotency Keys ===
+    
+    /// Claims an idempotency key for a new request, or returns the live record already
+    /// holding it. Expired records for the key are replaced.
+    pub async fn reserve_idempotency_key(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        idempotency_key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyReservation> {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::from_std(ttl).context("Invalid idempotency TTL")?;
+        
+        let reserved: Option<Uuid> = sqlx::query_scalar(
+            r#"
+            INSERT INTO idempotency_keys (user_id, endpoint_id, idempotency_key, request_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id, endpoint_id, idempotency_key) DO UPDATE SET
+                request_hash = EXCLUDED.request_hash,
+                status_code = NULL,
+                response_headers = NULL,
+                response_body = NULL,
+                cost = NULL,
+                created_at = EXCLUDED.created_at,
+                expires_at = EXCLUDED.expires_at
+            WHERE idempotency_keys.expires_at < EXCLUDED.created_at
+            RETURNING id
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(idempotency_key)
+        .bind(request_hash)
+        .bind(now)
+        .bind(expires_at)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to reserve idempotency key")?;
+        
+        if let Some(id) = reserved {
+            return Ok(IdempotencyReservation::Reserved(id));
+        }
+        
+        let existing = sqlx::query_as::<_, IdempotencyRecord>(
+            r#"
+            SELECT id, user_id, endpoint_id, idempotency_key, request_hash, status_code,
+                   response_headers, response_body, cost, created_at, expires_at
+            FROM idempotency_keys
+            WHERE user_id = $1 AND endpoint_id = $2 AND idempotency_key = $3
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(idempotency_key)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get idempotency key")?;
+        
+        // The holder released the key between the insert and the lookup
+        existing
+            .map(IdempotencyReservation::Existing)
+            .ok_or_else(|| anyhow::anyhow!("Idempotency key was released concurrently"))
+    }
+    
+    /// Stores the response for a reserved idempotency key so repeats can replay it
+    pub async fn complete_idempotency_key(
+        &self,
+        id: Uuid,
+        status_code: i32,
+        response_headers: Vec<(String, String)>,
+        response_body: &[u8],
+        cost: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE idempotency_keys SET
+                status_code = $2, response_headers = $3, response_body = $4, cost = $5
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(Json(response_headers))
+        .bind(response_body)
+        .bind(cost)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store idempotent response")?;
+        
+        Ok(())
+    }
+    
+    /// Frees an in-flight idempotency key whose request produced nothing worth replaying
+    pub async fn release_idempotency_key(&self, id: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE id = $1 AND status_code IS NULL")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to release idempotency key")?;
+        
+        Ok(())
+    }
+    
+    /// Removes stored idempotent responses whose TTL ended before `now`
+    pub async fn cleanup_expired_idempotency_keys(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .context("Failed to cleanup expired idempotency keys")?;
+        
+        Ok(result.rows_affected())
+    }
+
+    // === Endpoint Health ===
+    
+    /// Lists every active endpoint, for background health probing
+    pub async fn list_active_endpoints(&self) -> Result<Vec<ApiEndpoint>> {
+        let endpoints = sqlx::query_as::<_, ApiEndpoint>(
+            r#"
+            SELECT id, name, slug, description, owner_id, upstream_url, price_per_request, is_active,
+                   created_at, updated_at, rate_limit, rate_limit_window, requires_auth,
+                   allowed_methods, request_timeout, retry_attempts, max_request_size, bill_client_errors, is_degraded, upstream_targets,
+                   path_rewrite, forward_credentials, upstream_auth_encrypted,
+                   retry_non_idempotent, upstream_ws_url, pricing_model, price_per_kilobyte, pricing_tiers,
+                   sandbox_response, sandbox_upstream_url, tags, category, sla_max_latency_ms, sla_error_refund, max_concurrent_requests, rate_limit_algorithm, rate_limit_burst, consumer_monthly_quota,
+                   capture_samples, sample_rate, sample_max_body_bytes
+            FROM api_endpoints WHERE is_active = true AND deleted_at IS NULL
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list active endpoints")?;
+        
+        Ok(endpoints)
+    }
+    
+    /// Stores a health probe result and updates the endpoint's degraded flag.
+    /// The endpoint becomes degraded once `failure_threshold` probes in a row have
+    /// failed (and is deactivated too when `auto_deactivate` is set); any healthy
+    /// probe clears the flag and resets the failure count.
+    pub async fn record_endpoint_health(
+        &self,
+        check: CreateEndpointHealthCheckRequest,
+        failure_threshold: i32,
+        auto_deactivate: bool,
+    ) -> Result<EndpointHealthState> {
+        let mut tx = self.pool.begin().await?;
+        
+        sqlx::query(
+            r#"
+            INSERT INTO endpoint_health (endpoint_id, healthy, status_code, latency_ms, error_message, checked_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#
+        )
+        .bind(check.endpoint_id)
+        .bind(check.healthy)
+        .bind(check.status_code)
+        .bind(check.latency_ms)
+        .bind(&check.error_message)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record endpoint health")?;
+        
+        let was_degraded: bool = sqlx::query_scalar(
+            "SELECT is_degraded FROM api_endpoints WHERE id = $1 FOR UPDATE"
+        )
+        .bind(check.endpoint_id)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to load endpoint health state")?;
+        
+        let (is_degraded, is_active, consecutive_failures): (bool, bool, i32) = sqlx::query_as(
+            r#"
+            UPDATE api_endpoints SET
+                consecutive_health_failures = CASE WHEN $2 THEN 0 ELSE consecutive_health_failures + 1 END,
+                is_degraded = CASE WHEN $2 THEN false
+                                   ELSE is_degraded OR consecutive_health_failures + 1 >= $3 END,
+                is_active = CASE WHEN NOT $2 AND $4 AND consecutive_health_failures + 1 >= $3 THEN false
+                                 ELSE is_active END
+            WHERE id = $1
+            RETURNING is_degraded, is_active, consecutive_health_failures
+            "#
+        )
+        .bind(check.endpoint_id)
+        .bind(check.healthy)
+        .bind(failure_threshold)
+        .bind(auto_deactivate)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to update endpoint health state")?;
+        
+        tx.commit().await?;
+        
+        Ok(EndpointHealthState {
+            was_degraded,
+            is_degraded,
+            is_active,
+            consecutive_failures,
+        })
+    }
+    
+    /// Returns the most recent health probe for an endpoint
+    pub async fn get_latest_endpoint_health(&self, endpoint_id: Uuid) -> Result<Option<EndpointHealthCheck>> {
+        let check = sqlx::query_as::<_, EndpointHealthCheck>(
+            r#"
+            SELECT id, endpoint_id, healthy, status_code, latency_ms, error_message, checked_at
+            FROM endpoint_health
+            WHERE endpoint_id = $1
+            ORDER BY checked_at DESC
+            LIMIT 1
+            "#
+        )
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get endpoint health")?;
+        
+        Ok(check)
+    }
+
+    // === Usage Exports ===
+
+    /// Counts the requests a usage export would contain
+    pub async fn count_request_logs(&self, user_id: Option<Uuid>, start: Option<DateTime<Utc>>, end: DateTime<Utc>) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM request_logs
+            WHERE ($1::UUID IS NULL OR user_id = $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2) AND timestamp < $3
+            "#
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to count request logs")?;
+
+        Ok(count)
+    }
+
+    /// Streams the requests of a usage export oldest first, fetching rows as the
+    /// stream is read rather than loading them all
+    pub fn stream_request_logs(&self, user_id: Option<Uuid>, start: Option<DateTime<Utc>>, end: DateTime<Utc>) -> BoxStream<'_, Result<UsageExportRow>> {
+        sqlx::query_as::<_, UsageExportRow>(
+            r#"
+            SELECT l.timestamp, l.user_id, l.api_key_id, e.name AS endpoint_name, l.method,
+                   l.status_code, l.response_time_ms, l.cost
+            FROM request_logs l
+            INNER JOIN api_endpoints e ON e.id = l.endpoint_id
+            WHERE ($1::UUID IS NULL OR l.user_id = $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR l.timestamp >= $2) AND l.timestamp < $3
+            ORDER BY l.timestamp, l.id
+            "#
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .fetch(self.reader())
+        .map(|row| row.context("Failed to read request log"))
+        .boxed()
+    }
+
+    /// Records a usage export to be generated in the background
+    pub async fn create_usage_export(
+        &self,
+        requested_by: Uuid,
+        user_id: Option<Uuid>,
+        format: ExportFormat,
+        start: Option<DateTime<Utc>>,
+        end: DateTime<Utc>,
+    ) -> Result<UsageExport> {
+        let export = sqlx::query_as::<_, UsageExport>(
+            r#"
+            INSERT INTO usage_exports (requested_by, user_id, format, start_time, end_time, status, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, requested_by, user_id, format, start_time, end_time, status, row_count,
+                      error_message, created_at, completed_at
+            "#
+        )
+        .bind(requested_by)
+        .bind(user_id)
+        .bind(format)
+        .bind(start)
+        .bind(end)
+        .bind(ExportStatus::Pending)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to create usage export")?;
+
+        Ok(export)
+    }
+
+    /// Retrieves a usage export by id
+    pub async fn get_usage_export(&self, export_id: Uuid) -> Result<Option<UsageExport>> {
+        let export = sqlx::query_as::<_, UsageExport>(
+            r#"
+            SELECT id, requested_by, user_id, format, start_time, end_time, status, row_count,
+                   error_message, created_at, completed_at
+            FROM usage_exports WHERE id = $1
+            "#
+        )
+        .bind(export_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get usage export")?;
+
+        Ok(export)
+    }
+
+    /// Marks a pending usage export as generated, or as failed with the reason
+    pub async fn complete_usage_export(&self, export_id: Uuid, outcome: std::result::Result<i64, String>) -> Result<()> {
+        let (status, row_count, error_message) = match outcome {
+            Ok(row_count) => (ExportStatus::Ready, Some(row_count), None),
+            Err(error_message) => (ExportStatus::Failed, None, Some(error_message)),
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE usage_exports SET status = $2, row_count = $3, error_message = $4, completed_at = $5
+            WHERE id = $1 AND status = 'pending'
+            "#
+        )
+        .bind(export_id)
+        .bind(status)
+        .bind(row_count)
+        .bind(error_message)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .context("Failed to complete usage export")?;
+
+        Ok(())
+    }
+
+    // === Webhooks ===
+
+    /// Queues an event for delivery to each of the user's active webhooks subscribed
+    /// to it, returning how many deliveries were queued
+    pub async fn enqueue_webhook_event(&self, user_id: Uuid, event_type: &str, payload: &serde_json::Value) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (webhook_id, event_type, payload, status, next_retry)
+            SELECT id, $2, $3, $4, $5
+            FROM webhook_endpoints
+            WHERE user_id = $1 AND is_active = true AND $2 = ANY(events)
+            "#
+        )
+        .bind(user_id)
+        .bind(event_type)
+        .bind(payload)
+        .bind(WebhookStatus::Pending)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .context("Failed to queue webhook event")?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Registers a webhook endpoint for a user's events, signed with `secret`
+    pub async fn create_webhook_endpoint(
+        &self,
+        user_id: Uuid,
+        request: &CreateWebhookEndpointRequest,
+        secret: &str,
+    ) -> Result<WebhookEndpoint> {
+        let webhook = sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            INSERT INTO webhook_endpoints (user_id, url, events, secret)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, url, events, secret, is_active, created_at, last_triggered,
+                      failure_count, max_retries
+            "#
+        )
+        .bind(user_id)
+        .bind(&request.url)
+        .bind(&request.events)
+        .bind(secret)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to create webhook endpoint")?;
+
+        Ok(webhook)
+    }
+
+    /// Lists a user's webhook endpoints, newest first
+    pub async fn list_webhook_endpoints(&self, user_id: Uuid) -> Result<Vec<WebhookEndpoint>> {
+        let webhooks = sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            SELECT id, user_id, url, events, secret, is_active, created_at, last_triggered,
+                   failure_count, max_retries
+            FROM webhook_endpoints WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list webhook endpoints")?;
+
+        Ok(webhooks)
+    }
+
+    /// Updates one of a user's webhook endpoints, returning `None` if it does not exist
+    ///
+    /// Deliveries still queued for an endpoint being deactivated are cancelled, so
+    /// reactivating it does not send a backlog of stale events.
+    pub async fn update_webhook_endpoint(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+        request: &UpdateWebhookEndpointRequest,
+    ) -> Result<Option<WebhookEndpoint>> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        let webhook = sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            UPDATE webhook_endpoints SET
+                url = COALESCE($3, url),
+                events = COALESCE($4, events),
+                is_active = COALESCE($5, is_active),
+                failure_count = CASE WHEN $5 THEN 0 ELSE failure_count END
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, url, events, secret, is_active, created_at, last_triggered,
+                      failure_count, max_retries
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(request.url.as_deref())
+        .bind(request.events.as_deref())
+        .bind(request.is_active)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to update webhook endpoint")?;
+
+        if webhook.as_ref().is_some_and(|webhook| !webhook.is_active) {
+            sqlx::query("UPDATE webhook_deliveries SET status = $2, next_retry = NULL WHERE webhook_id = $1 AND status = 'pending'")
+                .bind(id)
+                .bind(WebhookStatus::Cancelled)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to cancel queued webhook deliveries")?;
+        }
+
+        tx.commit().await.context("Failed to commit webhook endpoint update")?;
+        Ok(webhook)
+    }
+
+    /// Deletes one of a user's webhook endpoints with its deliveries, returning false
+    /// if it does not exist
+    pub async fn delete_webhook_endpoint(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM webhook_endpoints WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete webhook endpoint")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Claims the queued delivery to an active endpoint that has waited longest
+    ///
+    /// The delivery is locked until `tx` ends and deliveries locked by another worker
+    /// are skipped, so no event is sent twice at once.
+    pub async fn claim_due_webhook_delivery_in(&self, tx: &mut Transaction<'_, Postgres>) -> Result<Option<DueWebhookDelivery>> {
+        let delivery = sqlx::query_as::<_, DueWebhookDelivery>(
+            r#"
+            SELECT d.id, d.webhook_id, d.event_type, d.payload, d.created_at, d.retry_count,
+                   w.url, w.secret, w.max_retries
+            FROM webhook_deliveries d
+            INNER JOIN webhook_endpoints w ON w.id = d.webhook_id
+            WHERE d.status = 'pending' AND d.next_retry <= NOW() AND w.is_active = true
+            ORDER BY d.next_retry
+            LIMIT 1
+            FOR UPDATE OF d SKIP LOCKED
+            "#
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .context("Failed to claim queued webhook delivery")?;
+
+        Ok(delivery)
+    }
+
+    /// Records that an endpoint accepted a delivery
+    pub async fn mark_webhook_delivered_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        delivery: &DueWebhookDelivery,
+        response_code: i32,
+        response_body: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries SET status = $2, response_code = $3, response_body = $4,
+                                          delivered_at = NOW(), next_retry = NULL
+            WHERE id = $1
+            "#
+        )
+        .bind(delivery.id)
+        .bind(WebhookStatus::Delivered)
+        .bind(response_code)
+        .bind(response_body)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to mark webhook delivered")?;
+
+        sqlx::query("UPDATE webhook_endpoints SET last_triggered = NOW(), failure_count = 0 WHERE id = $1")
+            .bind(delivery.webhook_id)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to update webhook endpoint")?;
+
+        Ok(())
+    }
+
+    /// Records a failed delivery attempt, queueing the delivery again at `retry_at` or
+    /// giving up on it when unset
+    pub async fn mark_webhook_failed_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        delivery: &DueWebhookDelivery,
+        response_code: Option<i32>,
+        response_body: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let status = match retry_at {
+            Some(_) => WebhookStatus::Pending,
+            None => WebhookStatus::Failed,
+        };
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries SET status = $2, response_code = $3, response_body = $4,
+                                          retry_count = retry_count + 1, next_retry = $5
+            WHERE id = $1
+            "#
+        )
+        .bind(delivery.id)
+        .bind(status)
+        .bind(response_code)
+        .bind(response_body)
+        .bind(retry_at)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record failed webhook delivery")?;
+
+        sqlx::query("UPDATE webhook_endpoints SET last_triggered = NOW(), failure_count = failure_count + 1 WHERE id = $1")
+            .bind(delivery.webhook_id)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to update webhook endpoint")?;
+
+        Ok(())
+    }
+
+    // === Usage Alerts ===
+
+    /// Counts each user's non-sandbox requests to each endpoint since `since`, next to
+    /// their average hourly requests over the `baseline_days` days before `baseline_end`
+    /// as cached in `daily_stats`. Days without cached traffic count as idle.
+    pub async fn get_endpoint_request_rates(
+        &self,
+        since: DateTime<Utc>,
+        baseline_end: NaiveDate,
+        baseline_days: u32,
+    ) -> Result<Vec<EndpointRequestRate>> {
+        let baseline_start = baseline_end - chrono::Duration::days(baseline_days as i64);
+
+        let rates = sqlx::query_as::<_, EndpointRequestRate>(
+            r#"
+            WITH recent AS (
+                SELECT user_id, endpoint_id, COUNT(*) AS hourly_requests
+                FROM request_logs
+                WHERE NOT is_sandbox AND timestamp >= $1
+                GROUP BY user_id, endpoint_id
+            ), baseline AS (
+                SELECT user_id, endpoint_id, SUM(total_requests)::FLOAT8 / ($4 * 24) AS baseline_hourly
+                FROM daily_stats
+                WHERE endpoint_id IS NOT NULL AND user_id IS NOT NULL AND date >= $2 AND date < $3
+                GROUP BY user_id, endpoint_id
+            )
+            SELECT recent.user_id, recent.endpoint_id, recent.hourly_requests,
+                   COALESCE(baseline.baseline_hourly, 0) AS baseline_hourly
+            FROM recent
+            LEFT JOIN baseline USING (user_id, endpoint_id)
+            "#
+        )
+        .bind(since)
+        .bind(baseline_start)
+        .bind(baseline_end)
+        .bind(baseline_days as f64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get endpoint request rates")?;
+
+        Ok(rates)
+    }
+
+    /// Lists every user's active usage alerts
+    pub async fn list_active_usage_alerts(&self) -> Result<Vec<UsageAlert>> {
+        let alerts = sqlx::query_as::<_, UsageAlert>(
+            r#"
+            SELECT a.id, a.user_id, a.endpoint_id, e.name AS endpoint_name, a.status, a.hourly_requests,
+                   a.peak_hourly_requests, a.baseline_hourly, a.created_at, a.last_seen_at, a.resolved_at
+            FROM usage_alerts a
+            JOIN api_endpoints e ON e.id = a.endpoint_id
+            WHERE a.status = $1
+            "#
+        )
+        .bind(AlertStatus::Active)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list active usage alerts")?;
+
+        Ok(alerts)
+    }
+
+    /// Raises an alert for a spiking request rate, unless the user already has an active
+    /// alert for the endpoint or had one raised after `cooldown_start`
+    pub async fn raise_usage_alert(
+        &self,
+        rate: &EndpointRequestRate,
+        now: DateTime<Utc>,
+        cooldown_start: DateTime<Utc>,
+    ) -> Result<Option<UsageAlert>> {
+        let alert = sqlx::query_as::<_, UsageAlert>(
+            r#"
+            WITH raised AS (
+                INSERT INTO usage_alerts (user_id, endpoint_id, status, hourly_requests, peak_hourly_requests,
+                                          baseline_hourly, created_at, last_seen_at)
+                SELECT $1, $2, $3, $4, $4, $5, $6, $6
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM usage_alerts WHERE user_id = $1 AND endpoint_id = $2 AND created_at > $7
+                )
+                ON CONFLICT (user_id, endpoint_id) WHERE status = 'active' DO NOTHING
+                RETURNING *
+            )
+            SELECT raised.id, raised.user_id, raised.endpoint_id, e.name AS endpoint_name, raised.status,
+                   raised.hourly_requests, raised.peak_hourly_requests, raised.baseline_hourly,
+                   raised.created_at, raised.last_seen_at, raised.resolved_at
+            FROM raised
+            JOIN api_endpoints e ON e.id = raised.endpoint_id
+            "#
+        )
+        .bind(rate.user_id)
+        .bind(rate.endpoint_id)
+        .bind(AlertStatus::Active)
+        .bind(rate.hourly_requests)
+        .bind(rate.baseline_hourly)
+        .bind(now)
+        .bind(cooldown_start)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to raise usage alert")?;
+
+        Ok(alert)
+    }
+
+    /// Notes that an active alert's spike is still going on
+    pub async fn touch_usage_alert(&self, alert_id: Uuid, hourly_requests: i64, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE usage_alerts SET
+                peak_hourly_requests = GREATEST(peak_hourly_requests, $2),
+                last_seen_at = $3
+            WHERE id = $1
+            "#
+        )
+        .bind(alert_id)
+        .bind(hourly_requests)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update usage alert")?;
+
+        Ok(())
+    }
+
+    /// Resolves an active alert, returning it unless it was already resolved
+    pub async fn resolve_usage_alert(&self, alert_id: Uuid, now: DateTime<Utc>) -> Result<Option<UsageAlert>> {
+        let alert = sqlx::query_as::<_, UsageAlert>(
+            r#"
+            WITH resolved AS (
+                UPDATE usage_alerts SET status = $2, resolved_at = $3
+                WHERE id = $1 AND status = $4
+                RETURNING *
+            )
+            SELECT resolved.id, resolved.user_id, resolved.endpoint_id, e.name AS endpoint_name, resolved.status,
+                   resolved.hourly_requests, resolved.peak_hourly_requests, resolved.baseline_hourly,
+                   resolved.created_at, resolved.last_seen_at, resolved.resolved_at
+            FROM resolved
+            JOIN api_endpoints e ON e.id = resolved.endpoint_id
+            "#
+        )
+        .bind(alert_id)
+        .bind(AlertStatus::Resolved)
+        .bind(now)
+        .bind(AlertStatus::Active)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to resolve usage alert")?;
+
+        Ok(alert)
+    }
+
+    /// Lists a user's usage alerts, newest first
+    pub async fn list_user_alerts(&self, user_id: Uuid, query: AlertQuery) -> Result<Vec<UsageAlert>> {
+        let alerts = sqlx::query_as::<_, UsageAlert>(
+            r#"
+            SELECT a.id, a.user_id, a.endpoint_id, e.name AS endpoint_name, a.status, a.hourly_requests,
+                   a.peak_hourly_requests, a.baseline_hourly, a.created_at, a.last_seen_at, a.resolved_at
+            FROM usage_alerts a
+            JOIN api_endpoints e ON e.id = a.endpoint_id
+            WHERE a.user_id = $1 AND ($2::alert_status IS NULL OR a.status = $2)
+            ORDER BY a.created_at DESC
+            LIMIT $3 OFFSET $4
+            "#
+        )
+        .bind(user_id)
+        .bind(query.status)
+        .bind(query.limit.unwrap_or(50).clamp(1, 500))
+        .bind(query.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list user alerts")?;
+
+        Ok(alerts)
+    }
+
+    // === Analytics ===
+    
+    /// Counts non-sandbox API requests across all endpoints in a time range. Whole
+    /// days before `today` are read from `daily_stats` where cached, the rest from
+    /// `request_logs`.
+    pub async fn get_total_requests(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>, today: NaiveDate) -> Result<i64> {
+        let (first_day, end_day) = cached_days(start_date, end_date, today);
+        let count = sqlx::query_scalar(
+            r#"
+            WITH cached AS (
+                SELECT date, SUM(total_requests) AS requests
+                FROM daily_stats
+                WHERE endpoint_id IS NOT NULL AND user_id IS NULL AND date >= $3 AND date < $4
+                GROUP BY date
+            )
+            SELECT (SELECT COALESCE(SUM(requests), 0) FROM cached)::BIGINT
+                 + (SELECT COUNT(*) FROM request_logs
+                    WHERE NOT is_sandbox AND timestamp >= $1 AND timestamp < $2
+                        AND (timestamp AT TIME ZONE 'UTC')::DATE NOT IN (SELECT date FROM cached))
+            "#
+        )
+        .bind(start_date)
+        .bind(end_date)
+        .bind(first_day)
+        .bind(end_day)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to get total requests")?;
+        
+        Ok(count)
+    }
+
+    /// Calculates total platform revenue from API usage in the billing months the range touches
+    pub async fn get_total_revenue(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<CostAmount> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let revenue = sqlx::query_scalar(
+            "SELECT COALESCE(SUM(total_cost::NUMERIC), 0)::TEXT FROM usage_records WHERE billing_period BETWEEN $1 AND $2"
+        )
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to get total revenue")?;
+        
+        Ok(revenue)
+    }
+
+    /// Counts new user registrations in date range
+    pub async fn get_new_users(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM users WHERE created_at >= $1 AND created_at < $2"
+        )
+        .bind(start_date)
+        .bind(end_date)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to get new users count")?;
+        
+        Ok(count)
+    }
+
+    /// Counts users who made non-sandbox API calls in a time range, reading cached days
+    /// like [`Self::get_total_requests`]
+    pub async fn get_active_users(&self, start_date: DateTime<Utc>, end_date: DateTime<Utc>, today: NaiveDate) -> Result<i64> {
+        let (first_day, end_day) = cached_days(start_date, end_date, today);
+        let count = sqlx::query_scalar(
+            r#"
+            WITH cached AS (
+                SELECT DISTINCT date FROM daily_stats
+                WHERE endpoint_id IS NOT NULL AND user_id IS NULL AND date >= $3 AND date < $4
+            )
+            SELECT COUNT(DISTINCT user_id) FROM (
+                SELECT user_id FROM daily_stats
+                WHERE user_id IS NOT NULL AND endpoint_id IS NULL AND date IN (SELECT date FROM cached)
+                UNION
+                SELECT user_id FROM request_logs
+                WHERE NOT is_sandbox AND timestamp >= $1 AND timestamp < $2
+                    AND (timestamp AT TIME ZONE 'UTC')::DATE NOT IN (SELECT date FROM cached)
+            ) active
+            "#
+        )
+        .bind(start_date)
+        .bind(end_date)
+        .bind(first_day)
+        .bind(end_day)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to get active users count")?;
+        
+        Ok(count)
+    }
+
+    /// Finds users with unpaid usage records for billing
+    pub async fn get_users_with_outstanding_usage(&self) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT DISTINCT u.id, u.wallet_address, u.api_key, u.email, u.username, u.is_active, 
+                           u.created_at, u.updated_at, u.last_login, u.tier, u.monthly_limit, u.rate_limit_override, u.email_verified, u.overage_policy
+            FROM users u
+            INNER JOIN usage_records ur ON u.id = ur.user_id
+            WHERE ur.status = 'pending'
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get users with outstanding usage")?;
+        
+        Ok(users)
+    }
+    
+    /// Retrieves usage statistics for a specific endpoint, by billing month like [`Self::get_user_usage`]
+    pub async fn get_endpoint_usage(&self, endpoint_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<Vec<UsageRecord>> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let records = sqlx::query_as::<_, UsageRecord>(
+            r#"
+            SELECT id, user_id, endpoint_id, request_count, total_cost, billing_period,
+                   status, transaction_hash, gas_used, block_number, chain_id, timestamp
+            FROM usage_records 
+            WHERE endpoint_id = $1 AND billing_period BETWEEN $2 AND $3
+            ORDER BY billing_period DESC, timestamp DESC
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get endpoint usage")?;
+        
+        Ok(records)
+    }
+    
+    /// Counts the distinct users who called an endpoint in the billing months a range touches
+    pub async fn count_endpoint_users(&self, endpoint_id: Uuid, start_date: DateTime<Utc>, end_date: DateTime<Utc>) -> Result<i64> {
+        let (first_period, last_period) = billing_periods(start_date, end_date);
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT user_id) FROM usage_records WHERE endpoint_id = $1 AND billing_period BETWEEN $2 AND $3"
+        )
+        .bind(endpoint_id)
+        .bind(first_period)
+        .bind(last_period)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to count endpoint users")?;
+
+        Ok(count)
+    }
+    
+    /// Claims pending usage of billing periods before `before_period` for billing,
+    /// leaving out usage already queued in a blockchain transaction
+    ///
+    /// Rows are locked until `tx` ends and rows locked by a concurrent run are
+    /// skipped, so no usage is claimed twice. Records in `skip` are left out, letting a
+    /// run move past usage it failed to bill. Records come grouped by user and period.
+    pub async fn get_pending_billing(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        before_period: &str,
+        skip: &[Uuid],
+        limit: i64,
+    ) -> Result<Vec<BillableUsage>> {
+        let records = sqlx::query_as::<_, BillableUsage>(
+            r#"
+            SELECT ur.id, ur.user_id, u.wallet_address, e.name AS endpoint_name,
+                   ur.request_count, ur.total_cost, ur.billing_period
+            FROM usage_records ur
+            INNER JOIN users u ON u.id = ur.user_id
+            INNER JOIN api_endpoints e ON e.id = ur.endpoint_id
+            WHERE ur.status = 'pending' AND ur.blockchain_tx_id IS NULL
+              AND ur.billing_period < $1 AND ur.id <> ALL($2)
+            ORDER BY ur.user_id, ur.billing_period, ur.id
+            LIMIT $3
+            FOR UPDATE OF ur SKIP LOCKED
+            "#
+        )
+        .bind(before_period)
+        .bind(skip)
+        .bind(limit)
+        .fetch_all(&mut **tx)
+        .await
+        .context("Failed to get pending billing records")?;
+        
+        Ok(records)
+    }
+    
+    /// Updates usage record status after blockchain transaction
+    pub async fn update_usage_status(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        record_id: Uuid,
+        status: UsageStatus,
+        transaction: Option<&BillingTransaction>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE usage_records SET
+                status = $2,
+                transaction_hash = $3,
+                gas_used = $4,
+                block_number = $5,
+                chain_id = $6,
+                updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(record_id)
+        .bind(status)
+        .bind(transaction.map(|t| &t.hash))
+        .bind(transaction.and_then(|t| t.gas_used.as_ref()))
+        .bind(transaction.and_then(|t| t.block_number))
+        .bind(transaction.map(|t| t.chain_id))
+        .execute(&mut **tx)
+        .await
+        .context("Failed to update usage status")?;
+        
+        Ok(())
+    }
+
+    /// Records how billing a user's period went, totalling the usage billed so far
+    ///
+    /// A failure keeps the transaction of the last successful attempt and counts
+    /// towards the record's retries.
+    pub async fn update_billing_record(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        billing_period: &str,
+        outcome: std::result::Result<&BillingTransaction, &str>,
+    ) -> Result<BillingRecord> {
+        let now = Utc::now();
+        let (status, transaction, error_message) = match outcome {
+            Ok(transaction) => (BillingStatus::Completed, Some(transaction), None),
+            Err(e) => (BillingStatus::Failed, None, Some(e)),
+        };
+
+        let record = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            INSERT INTO billing_records (user_id, billing_period, total_requests, total_cost, status,
+                                         created_at, processed_at, transaction_hash, gas_used,
+                                         block_number, chain_id, retry_count, error_message, updated_at)
+            SELECT $1, $2, COALESCE(SUM(request_count), 0), COALESCE(SUM(total_cost::NUMERIC), 0)::TEXT,
+                   $3, $4, $5, $6, $7, $8, $11, $9, $10, $4
+            FROM usage_records
+            WHERE user_id = $1 AND billing_period = $2 AND status = 'billed'
+            ON CONFLICT (user_id, billing_period) DO UPDATE SET
+                updated_at = EXCLUDED.updated_at,
+                total_requests = EXCLUDED.total_requests,
+                total_cost = EXCLUDED.total_cost,
+                status = EXCLUDED.status,
+                processed_at = COALESCE(EXCLUDED.processed_at, billing_records.processed_at),
+                transaction_hash = COALESCE(EXCLUDED.transaction_hash, billing_records.transaction_hash),
+                gas_used = COALESCE(EXCLUDED.gas_used, billing_records.gas_used),
+                block_number = COALESCE(EXCLUDED.block_number, billing_records.block_number),
+                chain_id = COALESCE(EXCLUDED.chain_id, billing_records.chain_id),
+                retry_count = billing_records.retry_count + EXCLUDED.retry_count,
+                error_message = EXCLUDED.error_message
+            RETURNING id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                      processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                      updated_at
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .bind(status)
+        .bind(now)
+        .bind(transaction.map(|_| now))
+        .bind(transaction.map(|t| &t.hash))
+        .bind(transaction.and_then(|t| t.gas_used.as_ref()))
+        .bind(transaction.and_then(|t| t.block_number))
+        .bind(i32::from(error_message.is_some()))
+        .bind(error_message)
+        .bind(transaction.map(|t| t.chain_id))
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to update billing record")?;
+
+        Ok(record)
+    }
+
+    /// Opens a pending billing record for a user's period, or returns the one it has
+    pub async fn create_billing_record(&self, user_id: Uuid, billing_period: &str) -> Result<BillingRecord> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let record = self.create_billing_record_in(&mut tx, user_id, billing_period).await?;
+        tx.commit().await.context("Failed to commit billing record")?;
+        Ok(record)
+    }
+
+    /// Opens a pending billing record for a user's period within a transaction, or
+    /// returns the one it has
+    pub async fn create_billing_record_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        billing_period: &str,
+    ) -> Result<BillingRecord> {
+        let now = Utc::now();
+        let record = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            INSERT INTO billing_records (user_id, billing_period, status, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $4)
+            ON CONFLICT (user_id, billing_period) DO UPDATE SET user_id = EXCLUDED.user_id
+            RETURNING id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                      processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                      updated_at
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .bind(BillingStatus::Pending)
+        .bind(now)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to create billing record")?;
+
+        Ok(record)
+    }
+
+    /// Moves a billing record to `status`
+    ///
+    /// A failure counts towards the record's retries and keeps `error_message`;
+    /// completion marks the record processed and clears the last error.
+    pub async fn update_billing_status(
+        &self,
+        record_id: Uuid,
+        status: BillingStatus,
+        error_message: Option<&str>,
+    ) -> Result<BillingRecord> {
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        let record = self.update_billing_status_in(&mut tx, record_id, status, error_message).await?;
+        tx.commit().await.context("Failed to commit billing status")?;
+        Ok(record)
+    }
+
+    /// Moves a billing record to `status` within a transaction
+    pub async fn update_billing_status_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        record_id: Uuid,
+        status: BillingStatus,
+        error_message: Option<&str>,
+    ) -> Result<BillingRecord> {
+        let record = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            UPDATE billing_records SET
+                status = $2,
+                updated_at = $3,
+                retry_count = retry_count + CASE WHEN $2 = 'failed' THEN 1 ELSE 0 END,
+                error_message = CASE WHEN $2 = 'completed' THEN NULL ELSE COALESCE($4, error_message) END,
+                processed_at = CASE WHEN $2 = 'completed' THEN $3 ELSE processed_at END
+            WHERE id = $1
+            RETURNING id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                      processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                      updated_at
+            "#
+        )
+        .bind(record_id)
+        .bind(status)
+        .bind(Utc::now())
+        .bind(error_message)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to update billing status")?;
+
+        Ok(record)
+    }
+
+    /// Gets the billing record of a user's period
+    pub async fn get_billing_record(&self, user_id: Uuid, billing_period: &str) -> Result<Option<BillingRecord>> {
+        let record = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            SELECT id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                   processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                   updated_at
+            FROM billing_records
+            WHERE user_id = $1 AND billing_period = $2
+            "#
+        )
+        .bind(user_id)
+        .bind(billing_period)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get billing record")?;
+
+        Ok(record)
+    }
+
+    /// Pages through a user's billing records, most recent period first
+    pub async fn get_billing_records_by_user(&self, user_id: Uuid, params: PaginationParams) -> Result<PaginatedResponse<BillingRecord>> {
+        let limit = params.limit.unwrap_or(20).clamp(1, 100);
+        let page = params.page.unwrap_or(1).max(1);
+        let offset = (page as i64 - 1) * limit as i64;
+
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM billing_records WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count billing records")?;
+
+        let records = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            SELECT id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                   processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                   updated_at
+            FROM billing_records
+            WHERE user_id = $1
+            ORDER BY billing_period DESC
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(user_id)
+        .bind(limit as i64)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get billing records")?;
+
+        Ok(PaginatedResponse::new(records, total, page, limit))
+    }
+
+    /// Gets billing records in `status` that have not changed since `updated_before`,
+    /// oldest first
+    pub async fn get_billing_records_by_status(
+        &self,
+        status: BillingStatus,
+        updated_before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<BillingRecord>> {
+        let records = sqlx::query_as::<_, BillingRecord>(
+            r#"
+            SELECT id, user_id, billing_period, total_requests, total_cost, status, created_at,
+                   processed_at, transaction_hash, gas_used, block_number, chain_id, retry_count, error_message,
+                   updated_at
+            FROM billing_records
+            WHERE status = $1 AND updated_at < $2
+            ORDER BY updated_at
+            LIMIT $3
+            "#
+        )
+        .bind(status)
+        .bind(updated_before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get billing records by status")?;
+
+        Ok(records)
+    }
+    
+    // === Rate Limiting ===
+    
+    /// Checks current rate limit status for user-endpoint combination
+    pub async fn check_rate_limit(&self, user_id: Uuid, endpoint_id: Uuid, window_duration: Duration) -> Result<(i32, i32)> {
+        let window_start = Utc::now() - chrono::Duration::from_std(window_duration)
+            .context("Invalid window duration")?;
+        
+        let current_count: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM request_logs 
+            WHERE user_id = $1 AND endpoint_id = $2 AND created_at >= $3 AND NOT is_sandbox
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(window_start)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check current request count")?;
+        
+        // Get rate limit from endpoint or user override
+        let rate_limit: Option<i32> = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(u.rate_limit_override, e.rate_limit) 
+            FROM users u, api_endpoints e 
+            WHERE u.id = $1 AND e.id = $2
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get rate limit")?;
+        
+        let limit = rate_limit.unwrap_or(100); // Default rate limit
+        Ok((current_count as i32, limit))
+    }
+    
+    /// Counts a request against a persisted rate limit window, creating the window on
+    /// its first request; a refused request is not counted but marks the window exceeded
+    pub async fn upsert_rate_limit_window(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+        allowed: bool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO rate_limit_entries (user_id, endpoint_id, window_start, window_end, request_count, limit_exceeded)
+            VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN 1 ELSE 0 END, NOT $5)
+            ON CONFLICT (user_id, endpoint_id, window_start) DO UPDATE SET
+                request_count = rate_limit_entries.request_count + EXCLUDED.request_count,
+                limit_exceeded = rate_limit_entries.limit_exceeded OR EXCLUDED.limit_exceeded
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(window_start)
+        .bind(window_end)
+        .bind(allowed)
+        .execute(&self.pool)
+        .await
+        .context("Failed to persist rate limit window")?;
+        
+        Ok(())
+    }
+    
+    /// Gets the persisted rate limit window starting at `window_start`
+    pub async fn get_rate_limit_window(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        window_start: DateTime<Utc>,
+    ) -> Result<Option<RateLimitEntry>> {
+        let entry = sqlx::query_as::<_, RateLimitEntry>(
+            r#"
+            SELECT id, user_id, endpoint_id, window_start, window_end, request_count, limit_exceeded,
+                   created_at, updated_at
+            FROM rate_limit_entries
+            WHERE user_id = $1 AND endpoint_id = $2 AND window_start = $3
+            "#
+        )
+        .bind(user_id)
+        .bind(endpoint_id)
+        .bind(window_start)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get rate limit window")?;
+        
+        Ok(entry)
+    }
+    
+    /// Deletes persisted rate limit windows that have ended, returning how many were removed
+    pub async fn delete_expired_rate_limit_windows(&self) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM rate_limit_entries WHERE window_end <= NOW()")
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired rate limit windows")?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    // === Daily Statistics ===
+
+    /// Aggregates the traffic of one endpoint, or of all endpoints, over a date range
+    ///
+    /// Past days are read from the endpoint rows cached in `daily_stats`; today, and any
+    /// day not aggregated yet, are computed from `request_logs`. Sandbox requests are
+    /// left out. Averages are weighted by each day's request count.
+    pub async fn get_traffic_stats(&self, endpoint_id: Option<Uuid>, range: StatsRange, today: NaiveDate) -> Result<TrafficStats> {
+        let (start_at, end_at) = range_bounds(range);
+
+        let stats = sqlx::query_as::<_, TrafficStats>(
+            r#"
+            WITH cached AS (
+                SELECT endpoint_id, date, total_requests AS requests, total_cost::NUMERIC AS revenue,
+                       avg_response_time * total_requests AS response_time,
+                       error_rate * total_requests AS errors
+                FROM daily_stats
+                WHERE endpoint_id IS NOT NULL AND user_id IS NULL
+                    AND ($1::UUID IS NULL OR endpoint_id = $1)
+                    AND ($2::DATE IS NULL OR date >= $2)
+                    AND ($3::DATE IS NULL OR date <= $3)
+                    AND date < $4
+            ), live AS (
+                SELECT endpoint_id, (timestamp AT TIME ZONE 'UTC')::DATE AS date, COUNT(*) AS requests,
+                       COALESCE(SUM(cost::NUMERIC), 0) AS revenue,
+                       SUM(response_time_ms)::FLOAT8 AS response_time,
+                       COUNT(*) FILTER (WHERE status_code >= 400)::FLOAT8 AS errors
+                FROM request_logs
+                WHERE NOT is_sandbox
+                    AND ($1::UUID IS NULL OR endpoint_id = $1)
+                    AND ($5::TIMESTAMPTZ IS NULL OR timestamp >= $5)
+                    AND ($6::TIMESTAMPTZ IS NULL OR timestamp < $6)
+                GROUP BY 1, 2
+            ), combined AS (
+                SELECT * FROM cached
+                UNION ALL
+                SELECT * FROM live
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM cached WHERE cached.endpoint_id = live.endpoint_id AND cached.date = live.date
+                )
+            )
+            SELECT COALESCE(SUM(requests), 0)::BIGINT AS total_requests,
+                   COALESCE(SUM(requests) FILTER (WHERE date = $4), 0)::BIGINT AS requests_today,
+                   COALESCE(SUM(response_time) / NULLIF(SUM(requests), 0)::FLOAT8, 0) AS avg_response_time,
+                   COALESCE(SUM(errors) / NULLIF(SUM(requests), 0)::FLOAT8, 0) AS error_rate,
+                   COALESCE(SUM(revenue), 0)::TEXT AS revenue
+            FROM combined
+            "#
+        )
+        .bind(endpoint_id)
+        .bind(range.start)
+        .bind(range.end)
+        .bind(today)
+        .bind(start_at)
+        .bind(end_at)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to get traffic stats")?;
+
+        Ok(stats)
+    }
+
+    /// Counts the endpoints currently accepting traffic
+    pub async fn count_active_endpoints(&self) -> Result<i64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_endpoints WHERE is_active = true AND deleted_at IS NULL")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count active endpoints")?;
+
+        Ok(count)
+    }
+    
+    /// Aggregates one UTC day of traffic into `daily_stats`: a row per endpoint, per
+    /// user and per user and endpoint pair, replacing any already cached for the day.
+    /// Returns how many rows were written.
+    pub async fn aggregate_daily_stats(&self, date: NaiveDate) -> Result<u64> {
+        let rows: i32 = sqlx::query_scalar("SELECT aggregate_daily_stats($1)")
+            .bind(date)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to aggregate daily stats")?;
+
+        Ok(rows as u64)
+    }
+
+    /// Aggregates each day in `[start, end)` that has no endpoint rows cached yet,
+    /// returning the days aggregated. Only finished days should be aggregated, since
+    /// cached days are no longer read from `request_logs`.
+    pub async fn backfill_daily_stats(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<NaiveDate>> {
+        let cached: Vec<NaiveDate> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT date FROM daily_stats
+            WHERE endpoint_id IS NOT NULL AND user_id IS NULL AND date >= $1 AND date < $2
+            "#
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load cached daily stats dates")?;
+
+        let mut aggregated = Vec::new();
+        for date in start.iter_days().take_while(|date| *date < end) {
+            if !cached.contains(&date) {
+                self.aggregate_daily_stats(date).await?;
+                aggregated.push(date);
+            }
+        }
+
+        Ok(aggregated)
+    }
+    
+    /// Retrieves the cached statistics of an endpoint, a user, or a user's calls to an
+    /// endpoint for one day
+    pub async fn get_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<Option<DailyStats>> {
+        let stats = sqlx::query_as::<_, DailyStats>(
+            r#"
+            SELECT id, date, endpoint_id, user_id, total_requests, total_cost, unique_users,
+                   avg_response_time, error_rate, created_at, updated_at
+            FROM daily_stats 
+            WHERE date = $1 AND endpoint_id IS NOT DISTINCT FROM $2 AND user_id IS NOT DISTINCT FROM $3
+            "#
+        )
+        .bind(date)
+        .bind(endpoint_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to get daily stats")?;
+        
+        Ok(stats)
+    }
+    
+    /// Generates and caches the statistics of an endpoint, a user, or a user's calls to
+    /// an endpoint for one day
+    pub async fn create_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<DailyStats> {
+        let (total_requests, total_cost, unique_users, avg_response_time, error_rate) = 
+            self.calculate_daily_stats(date, endpoint_id, user_id).await?;
+        
+        let stats = sqlx::query_as::<_, DailyStats>(
+            r#"
+            INSERT INTO daily_stats (date, endpoint_id, user_id, total_requests, total_cost,
+                                   unique_users, avg_response_time, error_rate)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (date, COALESCE(endpoint_id, '00000000-0000-0000-0000-000000000000'::UUID),
+                         COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::UUID)) DO UPDATE SET
+                total_requests = EXCLUDED.total_requests,
+                total_cost = EXCLUDED.total_cost,
+                unique_users = EXCLUDED.unique_users,
+                avg_response_time = EXCLUDED.avg_response_time,
+                error_rate = EXCLUDED.error_rate,
+                updated_at = NOW()
+            RETURNING id, date, endpoint_id, user_id, total_requests, total_cost, unique_users,
+                      avg_response_time, error_rate, created_at, updated_at
+            "#
+        )
+        .bind(date)
+        .bind(endpoint_id)
+        .bind(user_id)
+        .bind(total_requests)
+        .bind(&total_cost)
+        .bind(unique_users)
+        .bind(avg_response_time)
+        .bind(error_rate)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to create daily stats")?;
+        
+        Ok(stats)
+    }
+    
+    /// Calculates daily metrics from raw usage data, leaving out sandbox requests
+    async fn calculate_daily_stats(&self, date: NaiveDate, endpoint_id: Option<Uuid>, user_id: Option<Uuid>) -> Result<(i64, String, i32, f64, f64)> {
+        let start_of_day = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let end_of_day = start_of_day + chrono::Duration::days(1);
+        
+        let row = sqlx::query(
+            r#"
+            SELECT
+                COUNT(*) as total_requests,
+                COALESCE(SUM(cost::numeric), 0)::text as total_cost,
+                COUNT(DISTINCT user_id) as unique_users,
+                COALESCE(AVG(response_time_ms), 0)::float8 as avg_response_time,
+                COALESCE(AVG(CASE WHEN status_code >= 400 THEN 1.0 ELSE 0.0 END), 0)::float8 as error_rate
+            FROM request_logs
+            WHERE timestamp >= $1 AND timestamp < $2 AND NOT is_sandbox
+                AND ($3::uuid IS NULL OR endpoint_id = $3)
+                AND ($4::uuid IS NULL OR user_id = $4)
+            "#
+        )
+        .bind(start_of_day)
+        .bind(end_of_day)
+        .bind(endpoint_id)
+        .bind(user_id)
+        .fetch_one(self.reader())
+        .await
+        .context("Failed to calculate daily stats")?;
+        
+        Ok((
+            row.get::<i64, _>("total_requests"),
+            row.get::<String, _>("total_cost"),
+            row.get::<i64, _>("unique_users") as i32,
+            row.get::<f64, _>("avg_response_time"),
+            row.get::<f64, _>("error_rate"),
+        ))
+    }
+    
+    // === Transaction Management ===
+    
+    /// Starts a database transaction for atomic operations
+    pub async fn begin_transaction(&self) -> Result<Transaction<'_, Postgres>> {
+        self.pool.begin().await.context("Failed to begin transaction")
+    }
+    
+    // === Maintenance ===
+    
+    /// Removes request logs older than `days` in batches, so no single statement
+    /// holds locks for long; partitioned installs drop whole partitions instead
+    pub async fn cleanup_old_logs(&self, days: i32) -> Result<u64> {
+        let cutoff_date = Utc::now() - chrono::Duration::days(days as i64);
+        
+        let mut deleted = 0;
+        loop {
+            let batch = sqlx::query(
+                r#"
+                DELETE FROM request_logs
+                WHERE timestamp < $1
+                  AND id IN (SELECT id FROM request_logs WHERE timestamp < $1 LIMIT $2)
+                "#
+            )
+            .bind(cutoff_date)
+            .bind(LOG_CLEANUP_BATCH_SIZE)
+            .execute(&self.pool)
+            .await
+            .context("Failed to cleanup old logs")?
+            .rows_affected();
+            
+            deleted += batch;
+            if batch < LOG_CLEANUP_BATCH_SIZE as u64 {
+                break;
+            }
+        }
+        
+        info!("Cleaned up {} old request logs", deleted);
+        Ok(deleted)
+    }
+    
+    /// Whether `request_logs` is partitioned by month
+    pub async fn is_request_log_partitioned(&self) -> Result<bool> {
+        let partitioned = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pg_partitioned_table WHERE partrelid = to_regclass('request_logs'))"
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check request log partitioning")?;
+        
+        Ok(partitioned)
+    }
+    
+    /// Creates the monthly request log partitions for the `months` months starting
+    /// with the one containing `from`, returning the names of those not already there
+    pub async fn create_request_log_partitions(&self, from: NaiveDate, months: u32) -> Result<Vec<String>> {
+        let first = from.with_day(1).context("Invalid partition month")?;
+        let mut created = Vec::new();
+        for offset in 0..months {
+            let month = first + chrono::Months::new(offset);
+            let is_new: bool = sqlx::query_scalar("SELECT create_request_log_partition($1)")
+                .bind(month)
+                .fetch_one(&self.pool)
+                .await
+                .context("Failed to create request log partition")?;
+            if is_new {
+                created.push(request_log_partition_name(month));
+            }
+        }
+        
+        Ok(created)
+    }
+    
+    /// Drops the monthly request log partitions whose whole month is before `before`,
+    /// returning their names
+    pub async fn drop_request_log_partitions(&self, before: NaiveDate) -> Result<Vec<String>> {
+        let partitions: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT c.relname::TEXT FROM pg_inherits i
+            INNER JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = to_regclass('request_logs')
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list request log partitions")?;
+        
+        let mut dropped = Vec::new();
+        for name in partitions {
+            let Some(month) = request_log_partition_month(&name) else { continue };
+            if month + chrono::Months::new(1) > before {
+                continue;
+            }
+            // The name was parsed from the request_logs_YYYY_MM pattern, so it is safe to inline
+            sqlx::query(&format!("DROP TABLE {}", name))
+                .execute(&self.pool)
+                .await
+                .with_context(|| format!("Failed to drop request log partition {}", name))?;
+            dropped.push(name);
+        }
+        
+        Ok(dropped)
+    }
+    
+    /// Optimizes database performance with vacuum and analyze
+    pub async fn vacuum_analyze(&self) -> Result<()> {
+        // Note: VACUUM and ANALYZE cannot be run in a transaction
+        sqlx::query("VACUUM ANALYZE")
+            .execute(&self.pool)
+            .await
+            .context("Failed to vacuum analyze database")?;
+        
+        info!("Database vacuum analyze completed");
+        Ok(())
+    }
+}
+
+/// Name of the request log partition holding the UTC month of `month`
+fn request_log_partition_name(month: NaiveDate) -> String {
+    month.format("request_logs_%Y_%m").to_string()
+}
+
+/// First day of the month a request log partition holds, or `None` for tables that
+/// are not monthly partitions, such as the default partition
+fn request_log_partition_month(name: &str) -> Option<NaiveDate> {
+    let suffix = name.strip_prefix("request_logs_")?;
+    if suffix.len() != 7 || !suffix.chars().all(|c| c.is_ascii_digit() || c == '_') {
+        return None;
+    }
+    NaiveDate::parse_from_str(&format!("{}_01", suffix), "%Y_%m_%d").ok()
+}
+
+/// Converts an inclusive date range into half-open UTC timestamp bounds
+fn range_bounds(range: StatsRange) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    (range.start.map(midnight), range.end.and_then(|date| date.succ_opt()).map(midnight))
+}
+
+/// The whole UTC days `[first, end)` inside a time range that may be read from
+/// `daily_stats`; today is never cached
+fn cached_days(start: DateTime<Utc>, end: DateTime<Utc>, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let first = if start.time() == chrono::NaiveTime::MIN {
+        start.date_naive()
+    } else {
+        start.date_naive().succ_opt().unwrap_or(NaiveDate::MAX)
+    };
+    (first, end.date_naive().min(today))
+}
+
+/// First and last billing periods, like "2024-01", touched by a half-open UTC range
+fn billing_periods(start: DateTime<Utc>, end: DateTime<Utc>) -> (String, String) {
+    let last = (end - chrono::Duration::nanoseconds(1)).max(start);
+    (start.format("%Y-%m").to_string(), last.format("%Y-%m").to_string())
+}
+
+/// Slug routing an endpoint's calls: its owner's username, or the start of their ID
+/// when they have none, then the endpoint's name, like `acme.weather`
+///
+/// Usernames are reduced to the characters endpoint names allow, so the `.` always
+/// separates owner from name.
+fn endpoint_slug(username: Option<&str>, owner_id: Uuid, name: &str) -> String {
+    let owner = username
+        .map(|username| {
+            username
+                .to_lowercase()
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .filter(|owner| !owner.is_empty())
+        .unwrap_or_else(|| owner_id.simple().to_string()[..8].to_string());
+    format!("{}.{}", owner, name)
+}
+
+/// Groups usage records settled by a queued transaction, as `(id, user_id, period)`,
+/// by user, alongside each distinct user and billing period they touch
+fn settled_usage(records: Vec<(Uuid, Uuid, String)>) -> (Vec<SettledUsage>, Vec<(Uuid, String)>) {
+    let mut settled: Vec<SettledUsage> = Vec::new();
+    let mut periods = Vec::new();
+    for (id, user_id, billing_period) in records {
+        match settled.iter_mut().find(|usage| usage.user_id == user_id) {
+            Some(usage) => usage.usage_record_ids.push(id),
+            None => settled.push(SettledUsage { user_id, usage_record_ids: vec![id] }),
+        }
+        periods.push((user_id, billing_period));
+    }
+    periods.sort();
+    periods.dedup();
+    (settled, periods)
+}
+
+/// Escapes the wildcards of a LIKE pattern so user input only matches literally
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
     
     async fn setup_test_db() -> Database {
-        let config = Config::load().unwrap();
-        let db = Database::new(&config.database_url, 1).await.unwrap();
+        let config = test_support::config();
+        let db = Database::new(&config.database_url, &config.database_pool).await.unwrap();
         db.migrate().await.unwrap();
         db
     }
-    
+    
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_user_crud() {
+        let db = setup_test_db().await;
+        
+        let create_request = CreateUserRequest {
+            wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
+            email: Some("test@example.com".to_string()),
+            username: Some("testuser".to_string()),
+            tier: Some(UserTier::Free),
+        };
+        
+        let user = db.create_user(create_request).await.unwrap();
+        assert_eq!(user.wallet_address, "0x1234567890123456789012345678901234567890");
+        assert_eq!(user.email, Some("test@example.com".to_string()));
+        
+        // Test user retrieval
+        let retrieved_user = db.get_user_by_id(user.id).await.unwrap();
+        assert!(retrieved_user.is_some());
+        assert_eq!(retrieved_user.unwrap().id, user.id);
+        
+        // Test user update
+        let update_request = UpdateUserRequest {
+            email: Some("updated@example.com".to_string()),
+            username: None,
+            is_active: Some(false),
+            tier: None,
+            monthly_limit: None,
+            overage_policy: None,
+            rate_limit_override: None,
+        };
+        
+        let updated_user = db.update_user(user.id, update_request).await.unwrap();
+        assert_eq!(updated_user.email, Some("updated@example.com".to_string()));
+        assert!(!updated_user.is_active);
+    }
+
+    /// Tests that expired nonces, refresh tokens and revocations are purged and live ones kept
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_cleanup_expired_auth_rows() {
+        let db = setup_test_db().await;
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let now = Utc::now();
+        let expired = now - chrono::Duration::minutes(1);
+        let live = now + chrono::Duration::minutes(5);
+
+        let (expired_nonce, live_nonce) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        db.create_auth_nonce(&expired_nonce, &user.wallet_address, expired).await.unwrap();
+        db.create_auth_nonce(&live_nonce, &user.wallet_address, live).await.unwrap();
+        let (expired_hash, live_hash) = (Uuid::new_v4().simple().to_string(), Uuid::new_v4().simple().to_string());
+        db.create_refresh_token(user.id, &expired_hash, Uuid::new_v4(), expired).await.unwrap();
+        db.create_refresh_token(user.id, &live_hash, Uuid::new_v4(), live).await.unwrap();
+        let (expired_jti, live_jti) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        db.revoke_access_token(&expired_jti, user.id, expired).await.unwrap();
+        db.revoke_access_token(&live_jti, user.id, live).await.unwrap();
+
+        let removed = db.cleanup_expired_auth_rows(now).await.unwrap();
+        assert!(removed.nonces >= 1 && removed.refresh_tokens >= 1 && removed.revoked_tokens >= 1);
+
+        assert!(db.consume_auth_nonce(&expired_nonce).await.unwrap().is_none());
+        assert!(db.consume_auth_nonce(&live_nonce).await.unwrap().is_some());
+        assert!(db.get_refresh_token_by_hash(&expired_hash).await.unwrap().is_none());
+        assert!(db.get_refresh_token_by_hash(&live_hash).await.unwrap().is_some());
+        assert!(!db.is_access_token_revoked(&expired_jti, user.id, expired).await.unwrap());
+        assert!(db.is_access_token_revoked(&live_jti, user.id, now).await.unwrap());
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_endpoint_crud() {
+        let db = setup_test_db().await;
+        
+        // First create a user to own the endpoint
+        let create_user_request = CreateUserRequest {
+            wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
+            email: Some("owner@example.com".to_string()),
+            username: Some("owner".to_string()),
+            tier: Some(UserTier::Pro),
+        };
+        
+        let user = db.create_user(create_user_request).await.unwrap();
+        
+        // Test endpoint creation
+        let create_request = CreateEndpointRequest {
+            name: "test-api".to_string(),
+            description: Some("Test API endpoint".to_string()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "0.001".to_string(),
+            rate_limit: Some(1000),
+            rate_limit_window: Some(3600),
+            requires_auth: Some(true),
+            allowed_methods: Some(vec!["GET".to_string(), "POST".to_string()]),
+            request_timeout: Some(30),
+            retry_attempts: Some(3),
+            ..Default::default()
+        };
+        
+        let endpoint = db.create_endpoint(user.id, create_request).await.unwrap();
+        assert_eq!(endpoint.name, "test-api");
+        assert_eq!(endpoint.owner_id, user.id);
+    }
+
+    /// Tests that concurrent registrations against an endpoint cap never exceed it
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_create_endpoint_within_cap() {
+        let db = std::sync::Arc::new(setup_test_db().await);
+        let owner_id = db.create_user(test_support::user_request()).await.unwrap().id;
+
+        let registrations = (0..6).map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                db.create_endpoint_within_cap(owner_id, CreateEndpointRequest {
+                    name: format!("capped-{}", Uuid::new_v4().simple()),
+                    upstream_url: "https://api.example.com".to_string(),
+                    price_per_request: "1000".to_string(),
+                    ..Default::default()
+                }, 2).await.unwrap()
+            })
+        });
+        let created = futures::future::join_all(registrations).await.into_iter()
+            .filter(|registration| registration.as_ref().unwrap().is_some())
+            .count();
+        assert_eq!(created, 2);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_search_endpoints() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+
+        // A tag unique to this run keeps other endpoints out of the results
+        let tag = format!("test-{}", Uuid::new_v4().simple());
+        let create = |name: &str, description: &str, price: &str, category: &str| CreateEndpointRequest {
+            name: format!("{}-{}", name, Uuid::new_v4().simple()),
+            description: Some(description.to_string()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: price.to_string(),
+            tags: Some(vec![tag.clone()]),
+            category: Some(category.to_string()),
+            ..Default::default()
+        };
+        let quotes = db.create_endpoint(user.id, create("quotes", "Stock quotes", "50", "finance")).await.unwrap();
+        let weather = db.create_endpoint(user.id, create("weather", "Hourly forecasts", "5", "data")).await.unwrap();
+
+        sqlx::query("INSERT INTO daily_stats (date, endpoint_id, total_requests) VALUES (CURRENT_DATE, $1, 500)")
+            .bind(quotes.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let search = |params: EndpointSearchParams| {
+            let db = &db;
+            async move {
+                let ids: Vec<Uuid> = db.search_endpoints(&params, None).await.unwrap().data.iter().map(|e| e.id).collect();
+                ids
+            }
+        };
+        let tagged = || EndpointSearchParams { tag: Some(tag.clone()), ..Default::default() };
+
+        assert_eq!(search(tagged()).await, [weather.id, quotes.id]);
+        assert_eq!(search(EndpointSearchParams { q: Some("forecast".to_string()), ..tagged() }).await, [weather.id]);
+        assert_eq!(search(EndpointSearchParams { q: Some("STOCK".to_string()), ..tagged() }).await, [quotes.id]);
+        assert_eq!(search(EndpointSearchParams { category: Some("data".to_string()), ..tagged() }).await, [weather.id]);
+        assert_eq!(search(EndpointSearchParams { min_price: Some("10".to_string()), ..tagged() }).await, [quotes.id]);
+        assert_eq!(search(EndpointSearchParams { max_price: Some("10".to_string()), ..tagged() }).await, [weather.id]);
+        assert_eq!(search(EndpointSearchParams { q: Some("%".to_string()), ..tagged() }).await, Vec::<Uuid>::new());
+
+        let popular = search(EndpointSearchParams { sort: Some(EndpointSort::Popular), ..tagged() }).await;
+        assert_eq!(popular, [quotes.id, weather.id]);
+
+        let page = db.search_endpoints(&EndpointSearchParams { limit: Some(1), page: Some(2), ..tagged() }, None).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.total_pages, 2);
+        assert_eq!(page.data[0].id, quotes.id);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_traffic_stats() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("stats-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        let today = Utc::now().date_naive();
+        let yesterday = today.pred_opt().unwrap();
+        let two_days_ago = yesterday.pred_opt().unwrap();
+
+        let log = |date: NaiveDate, status_code: i32, response_time_ms: i32, cost: &str, is_sandbox: bool| {
+            sqlx::query(
+                "INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code, response_time_ms,
+                                           ip_address_hash, timestamp, cost, is_sandbox)
+                 VALUES ($1, $2, $3, 'GET', '/', $4, $5, 'hash', $6, $7, $8)"
+            )
+            .bind(user.id)
+            .bind(endpoint.id)
+            .bind(Uuid::new_v4().to_string())
+            .bind(status_code)
+            .bind(response_time_ms)
+            .bind(date.and_hms_opt(0, 0, 1).unwrap().and_utc())
+            .bind(cost.to_string())
+            .bind(is_sandbox)
+            .execute(&db.pool)
+        };
+
+        // Today is always computed live; sandbox traffic never counts
+        log(today, 200, 100, "10", false).await.unwrap();
+        log(today, 500, 300, "0", false).await.unwrap();
+        log(today, 404, 200, "10", false).await.unwrap();
+        log(today, 200, 900, "0", true).await.unwrap();
+        // Yesterday is cached, so its raw log is superseded by the daily_stats row
+        log(yesterday, 500, 5000, "10", false).await.unwrap();
+        sqlx::query(
+            "INSERT INTO daily_stats (date, endpoint_id, total_requests, total_cost, avg_response_time, error_rate)
+             VALUES ($1, $2, 6, '60', 100, 0.5)"
+        )
+        .bind(yesterday)
+        .bind(endpoint.id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        // Two days ago was never aggregated and falls back to the raw logs
+        log(two_days_ago, 200, 400, "5", false).await.unwrap();
+
+        let stats = db.get_traffic_stats(Some(endpoint.id), StatsRange::default(), today).await.unwrap();
+        assert_eq!(stats, TrafficStats {
+            total_requests: 10,
+            requests_today: 3,
+            avg_response_time: 160.0,
+            error_rate: 0.5,
+            revenue: "85".to_string(),
+        });
+
+        let recent = db.get_traffic_stats(Some(endpoint.id), StatsRange { start: Some(yesterday), end: None }, today).await.unwrap();
+        assert_eq!(recent.total_requests, 9);
+        assert_eq!(recent.requests_today, 3);
+        assert!((recent.avg_response_time - 1200.0 / 9.0).abs() < 1e-9);
+        assert!((recent.error_rate - 5.0 / 9.0).abs() < 1e-9);
+        assert_eq!(recent.revenue, "80");
+
+        let old = db.get_traffic_stats(Some(endpoint.id), StatsRange { start: None, end: Some(two_days_ago) }, today).await.unwrap();
+        assert_eq!(old, TrafficStats {
+            total_requests: 1,
+            requests_today: 0,
+            avg_response_time: 400.0,
+            error_rate: 0.0,
+            revenue: "5".to_string(),
+        });
+
+        // An endpoint without traffic reports zeros rather than NULLs
+        let empty = db.get_traffic_stats(Some(Uuid::new_v4()), StatsRange::default(), today).await.unwrap();
+        assert_eq!(empty.total_requests, 0);
+        assert_eq!(empty.error_rate, 0.0);
+        assert_eq!(empty.revenue, "0");
+    }
+
+    /// Tests that aggregating a day caches one row per endpoint, user and pair however
+    /// often it runs, and that analytics read the same totals from the cache
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_daily_stats_aggregation() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("daily-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "1000".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        // A day of its own in the past, so other tests' traffic does not interfere
+        let day = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()
+            + chrono::Duration::days((Uuid::new_v4().as_u128() % 3650) as i64);
+        let today = day + chrono::Duration::days(2);
+        for (hour, status_code, is_sandbox) in [(1, 200, false), (12, 500, false), (23, 200, false), (23, 200, true)] {
+            sqlx::query(
+                "INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code, response_time_ms,
+                                           ip_address_hash, timestamp, cost, is_sandbox)
+                 VALUES ($1, $2, $3, 'GET', '/', $4, 100, 'hash', $5, '10', $6)"
+            )
+            .bind(user.id)
+            .bind(endpoint.id)
+            .bind(Uuid::new_v4().to_string())
+            .bind(status_code)
+            .bind(day.and_hms_opt(hour, 30, 0).unwrap().and_utc())
+            .bind(is_sandbox)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        let start = day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let end = start + chrono::Duration::days(1);
+        let live_requests = db.get_total_requests(start, end, today).await.unwrap();
+        let live_users = db.get_active_users(start, end, today).await.unwrap();
+
+        assert_eq!(db.backfill_daily_stats(day, day.succ_opt().unwrap()).await.unwrap(), vec![day]);
+        db.aggregate_daily_stats(day).await.unwrap();
+        assert!(db.backfill_daily_stats(day, day.succ_opt().unwrap()).await.unwrap().is_empty());
+
+        let endpoint_rows: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM daily_stats WHERE date = $1 AND endpoint_id = $2 AND user_id IS NULL"
+        )
+        .bind(day)
+        .bind(endpoint.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(endpoint_rows, 1);
+
+        let by_endpoint = db.get_daily_stats(day, Some(endpoint.id), None).await.unwrap().unwrap();
+        assert_eq!(by_endpoint.total_requests, 3);
+        assert_eq!(by_endpoint.total_cost, "30");
+        assert_eq!(by_endpoint.unique_users, 1);
+        assert!((by_endpoint.error_rate - 1.0 / 3.0).abs() < 1e-9);
+        let by_user = db.get_daily_stats(day, None, Some(user.id)).await.unwrap().unwrap();
+        assert_eq!(by_user.total_requests, 3);
+        let by_pair = db.get_daily_stats(day, Some(endpoint.id), Some(user.id)).await.unwrap().unwrap();
+        assert_eq!(by_pair.total_requests, 3);
+
+        // Recalculating a single scope updates its row in place
+        let recalculated = db.create_daily_stats(day, Some(endpoint.id), None).await.unwrap();
+        assert_eq!(recalculated.id, by_endpoint.id);
+        assert_eq!(recalculated.total_requests, 3);
+        assert!((recalculated.avg_response_time - 100.0).abs() < 1e-9);
+
+        // Cached and live totals agree
+        assert_eq!(db.get_total_requests(start, end, today).await.unwrap(), live_requests);
+        assert_eq!(db.get_active_users(start, end, today).await.unwrap(), live_users);
+        assert!(live_requests >= 3);
+    }
+
     #[tokio::test]
     #[ignore] // Requires database connection
-    async fn test_user_crud() {
+    async fn test_usage_records_aggregate() {
         let db = setup_test_db().await;
-        
-        let create_request = CreateUserRequest {
-            wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
-            email: Some("test@example.com".to_string()),
-            username: Some("testuser".to_string()),
-            tier: Some(UserTier::Free),
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("usage-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        // Repeated usage in a period lands on the same row
+        let first = db.create_usage_record(user.id, endpoint.id, 1, "10", "2024-01").await.unwrap();
+        let second = db.create_usage_record(user.id, endpoint.id, 2, "20.5", "2024-01").await.unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.request_count, 3);
+        assert_eq!(second.total_cost, "30.5");
+
+        // A new period starts a row of its own
+        let next = db.create_usage_record(user.id, endpoint.id, 1, "10", "2024-02").await.unwrap();
+        assert_ne!(next.id, first.id);
+
+        let january = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let march = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let usage = db.get_user_usage(user.id, january, march).await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage.iter().map(|r| r.request_count).sum::<i64>(), 4);
+    }
+
+    /// Tests which billing months a half-open range touches
+    #[test]
+    fn test_billing_periods() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let periods = |start: &str, end: &str| billing_periods(at(start), at(end));
+
+        assert_eq!(periods("2024-01-01T00:00:00Z", "2024-02-01T00:00:00Z"), ("2024-01".to_string(), "2024-01".to_string()));
+        assert_eq!(periods("2024-01-31T23:00:00Z", "2024-02-01T00:00:01Z"), ("2024-01".to_string(), "2024-02".to_string()));
+        assert_eq!(periods("2024-10-01T00:00:00Z", "2025-01-01T00:00:00Z"), ("2024-10".to_string(), "2024-12".to_string()));
+        assert_eq!(periods("2024-03-05T00:00:00Z", "2024-03-05T00:00:00Z"), ("2024-03".to_string(), "2024-03".to_string()));
+    }
+
+    /// Tests that partition names round-trip to their month and other tables are skipped
+    #[test]
+    fn test_request_log_partition_names() {
+        let march = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(request_log_partition_name(NaiveDate::from_ymd_opt(2024, 3, 17).unwrap()), "request_logs_2024_03");
+        assert_eq!(request_log_partition_month("request_logs_2024_03"), Some(march));
+        assert_eq!(request_log_partition_month("request_logs_default"), None);
+        assert_eq!(request_log_partition_month("request_logs_2024_13"), None);
+        assert_eq!(request_log_partition_month("request_logs_2024_03; DROP TABLE users"), None);
+    }
+
+    /// Tests that slugs name the owner by a sanitized username, or their ID without one
+    #[test]
+    fn test_endpoint_slug() {
+        let owner_id = Uuid::parse_str("3f2a9c1b-0000-4000-8000-000000000000").unwrap();
+        assert_eq!(endpoint_slug(Some("acme"), owner_id, "weather"), "acme.weather");
+        assert_eq!(endpoint_slug(Some("Jane Doe.Labs"), owner_id, "weather"), "jane-doe-labs.weather");
+        assert_eq!(endpoint_slug(Some("..."), owner_id, "weather"), "3f2a9c1b.weather");
+        assert_eq!(endpoint_slug(None, owner_id, "weather_v2"), "3f2a9c1b.weather_v2");
+    }
+
+    /// Tests that settled usage is grouped by user and each period is touched once
+    #[test]
+    fn test_settled_usage() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let (settled, periods) = settled_usage(vec![
+            (ids[0], alice, "2024-01".to_string()),
+            (ids[1], bob, "2024-01".to_string()),
+            (ids[2], alice, "2024-01".to_string()),
+            (ids[3], alice, "2024-02".to_string()),
+        ]);
+
+        assert_eq!(settled, vec![
+            SettledUsage { user_id: alice, usage_record_ids: vec![ids[0], ids[2], ids[3]] },
+            SettledUsage { user_id: bob, usage_record_ids: vec![ids[1]] },
+        ]);
+        assert_eq!(periods.len(), 3);
+        assert!(periods.contains(&(alice, "2024-02".to_string())));
+        assert!(settled_usage(Vec::new()).0.is_empty());
+    }
+
+    /// Tests that the admin user list filters on search terms, tier, status and
+    /// registration date, and sorts by the allowed columns
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_list_users_filters() {
+        let db = setup_test_db().await;
+
+        let marker = Uuid::new_v4().simple().to_string();
+        let mut users = Vec::new();
+        for (name, tier) in [("alpha", UserTier::Pro), ("bravo", UserTier::Free), ("charlie", UserTier::Pro)] {
+            users.push(db.create_user(CreateUserRequest {
+                wallet_address: format!("0x{:0>40}", Uuid::new_v4().simple()),
+                email: Some(format!("{}-{}@example.com", name, marker)),
+                username: Some(format!("{}_{}", name, marker)),
+                tier: Some(tier),
+            }).await.unwrap());
+        }
+        sqlx::query("UPDATE users SET is_active = false WHERE id = $1")
+            .bind(users[2].id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let params = || PaginationParams { sort_by: Some("username".to_string()), sort_order: Some(SortOrder::Asc), ..Default::default() };
+        let search = |q: &str| UserListQuery { q: Some(q.to_string()), ..Default::default() };
+        let ids = |page: &PaginatedResponse<User>| page.data.iter().map(|user| user.id).collect::<Vec<_>>();
+
+        let all = db.list_users(&search(&marker), params()).await.unwrap();
+        assert_eq!(all.total, 3);
+        assert_eq!(ids(&all), [users[0].id, users[1].id, users[2].id]);
+
+        let descending = PaginationParams { sort_order: Some(SortOrder::Desc), ..params() };
+        assert_eq!(ids(&db.list_users(&search(&marker), descending).await.unwrap()), [users[2].id, users[1].id, users[0].id]);
+        assert_eq!(ids(&db.list_users(&search(&format!("bravo-{}@", marker)), params()).await.unwrap()), [users[1].id]);
+        assert_eq!(ids(&db.list_users(&search(&users[0].wallet_address.to_uppercase()), params()).await.unwrap()), [users[0].id]);
+        assert!(db.list_users(&search(&format!("{}%", marker)), params()).await.unwrap().data.is_empty());
+
+        let active_pro = UserListQuery { tier: Some(UserTier::Pro), is_active: Some(true), ..search(&marker) };
+        assert_eq!(ids(&db.list_users(&active_pro, params()).await.unwrap()), [users[0].id]);
+
+        let later = UserListQuery { created_after: Some(users[1].created_at), ..search(&marker) };
+        assert_eq!(ids(&db.list_users(&later, params()).await.unwrap()), [users[1].id, users[2].id]);
+        let earlier = UserListQuery { created_before: Some(users[1].created_at), ..search(&marker) };
+        assert_eq!(ids(&db.list_users(&earlier, params()).await.unwrap()), [users[0].id]);
+
+        let second_page = PaginationParams { page: Some(2), limit: Some(2), ..params() };
+        let page = db.list_users(&search(&marker), second_page).await.unwrap();
+        assert_eq!((page.total, page.total_pages), (3, 2));
+        assert_eq!(ids(&page), [users[2].id]);
+    }
+
+    /// Tests that the month-to-date total follows usage writes and is rebuilt on drift
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_monthly_usage_totals() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("monthly-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        let period = "2024-01";
+        db.create_usage_record(user.id, endpoint.id, 2, "20", period).await.unwrap();
+        db.record_billable_usage(user.id, endpoint.id, None, period, false, |_| Ok(CostBreakdown {
+            pricing_model: PricingModel::PerRequest,
+            requests: 1,
+            request_cost: "10".parse().unwrap(),
+            kilobytes: 0,
+            kilobyte_cost: CostAmount::ZERO,
+            total: "10".parse().unwrap(),
+        })).await.unwrap();
+        assert_eq!(db.get_user_request_count(user.id, period).await.unwrap(), 3);
+        assert_eq!(db.get_user_request_count(user.id, "2024-02").await.unwrap(), 0);
+
+        // A total that drifted is rebuilt from the usage records, and only once
+        sqlx::query("UPDATE user_monthly_usage SET request_count = 40 WHERE user_id = $1 AND billing_period = $2")
+            .bind(user.id)
+            .bind(period)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.get_user_request_count(user.id, period).await.unwrap(), 40);
+        assert!(db.reconcile_monthly_usage(period).await.unwrap() >= 1);
+        assert_eq!(db.get_user_request_count(user.id, period).await.unwrap(), 3);
+        assert_eq!(db.reconcile_monthly_usage(period).await.unwrap(), 0);
+    }
+
+    /// Tests that usage made with a named API key is attributed to that key
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_api_key_usage_attribution() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("keyed-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+        let api_key = db.create_api_key(user.id, &Uuid::new_v4().to_string(), &CreateApiKeyRequest {
+            name: "ci".to_string(),
+            permissions: None,
+            expires_at: None,
+            rate_limit_override: None,
+            allowed_ips: None,
+        }).await.unwrap();
+
+        let january = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let period = "2024-01";
+        let breakdown = CostBreakdown {
+            pricing_model: PricingModel::PerRequest,
+            requests: 1,
+            request_cost: "10".parse().unwrap(),
+            kilobytes: 0,
+            kilobyte_cost: CostAmount::ZERO,
+            total: "10".parse().unwrap(),
         };
-        
-        let user = db.create_user(create_request).await.unwrap();
-        assert_eq!(user.wallet_address, "0x1234567890123456789012345678901234567890");
-        assert_eq!(user.email, Some("test@example.com".to_string()));
-        
-        // Test user retrieval
-        let retrieved_user = db.get_user_by_id(user.id).await.unwrap();
-        assert!(retrieved_user.is_some());
-        assert_eq!(retrieved_user.unwrap().id, user.id);
-        
-        // Test user update
-        let update_request = UpdateUserRequest {
-            email: Some("updated@example.com".to_string()),
+        for api_key_id in [Some(api_key.id), Some(api_key.id), None] {
+            db.record_billable_usage(user.id, endpoint.id, api_key_id, period, false, |_| Ok(breakdown.clone())).await.unwrap();
+        }
+
+        // Only the keyed calls count towards the key; the account total has all three
+        let usage = db.get_api_key_usage(api_key.id, january, january + chrono::Duration::days(1)).await.unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].endpoint_id, endpoint.id);
+        assert_eq!(usage[0].total_requests, 2);
+        assert_eq!(usage[0].total_cost, "20".parse::<CostAmount>().unwrap());
+        assert_eq!(db.get_user_request_count(user.id, period).await.unwrap(), 3);
+        let later = january + chrono::Duration::days(40);
+        assert!(db.get_api_key_usage(api_key.id, later, later + chrono::Duration::days(1)).await.unwrap().is_empty());
+
+        // Logging a keyed request bumps the key's usage count
+        let log = db.create_request_log(CreateRequestLogRequest {
+            user_id: user.id,
+            endpoint_id: endpoint.id,
+            request_id: Uuid::new_v4().to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status_code: 200,
+            response_time_ms: 50,
+            request_size: None,
+            response_size: None,
+            ip_address_hash: "hash".to_string(),
+            user_agent_hash: None,
+            cost: "10".parse().unwrap(),
+            error_message: None,
+            upstream_target: None,
+            stream_duration_ms: None,
+            cost_breakdown: None,
+            is_sandbox: false,
+            api_key_id: Some(api_key.id),
+        }).await.unwrap();
+        assert_eq!(log.api_key_id, Some(api_key.id));
+        let api_key = db.get_api_key(api_key.id, user.id).await.unwrap().unwrap();
+        assert_eq!(api_key.usage_count, 1);
+    }
+
+    /// Tests that overage starts once per period and is announced only to subscribed webhooks
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_overage_start_and_webhooks() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        assert_eq!(user.overage_policy.0, OveragePolicy::Block);
+        let user = db.update_user(user.id, UpdateUserRequest {
+            email: None,
             username: None,
-            is_active: Some(false),
+            is_active: None,
             tier: None,
-            monthly_limit: None,
+            monthly_limit: Some(100),
+            overage_policy: Some(OveragePolicy::GraceRequests { requests: 5 }),
             rate_limit_override: None,
+        }).await.unwrap();
+        assert_eq!(user.overage_policy.0, OveragePolicy::GraceRequests { requests: 5 });
+
+        assert!(db.get_overage_usage(user.id, "2024-01").await.unwrap().is_none());
+        assert!(db.start_overage(user.id, "2024-01").await.unwrap());
+        assert!(!db.start_overage(user.id, "2024-01").await.unwrap());
+        let overage = db.get_overage_usage(user.id, "2024-01").await.unwrap().unwrap();
+        assert_eq!(overage.request_count, 0);
+        assert_eq!(overage.total_cost, CostAmount::ZERO);
+
+        for events in [vec!["usage.overage_started"], vec!["billing.completed"]] {
+            sqlx::query("INSERT INTO webhook_endpoints (user_id, url, events, secret) VALUES ($1, 'https://hooks.example.com', $2, 'secret')")
+                .bind(user.id)
+                .bind(events)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        let payload = serde_json::json!({ "billing_period": "2024-01" });
+        assert_eq!(db.enqueue_webhook_event(user.id, "usage.overage_started", &payload).await.unwrap(), 1);
+        let (status, queued): (WebhookStatus, serde_json::Value) = sqlx::query_as(
+            "SELECT d.status, d.payload FROM webhook_deliveries d
+             INNER JOIN webhook_endpoints w ON w.id = d.webhook_id
+             WHERE w.user_id = $1"
+        )
+        .bind(user.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert!(matches!(status, WebhookStatus::Pending));
+        assert_eq!(queued, payload);
+    }
+
+    /// Tests that a failed webhook delivery is retried with backoff until the
+    /// endpoint's retries are used up, and deactivating an endpoint cancels what it
+    /// has queued
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_webhook_delivery_retries() {
+        use crate::webhooks::{WebhookDispatcher, WebhookService};
+        use httpmock::prelude::*;
+
+        let db = std::sync::Arc::new(setup_test_db().await);
+        let mut config = test_support::config();
+        config.features.enable_webhooks = true;
+        let webhooks = WebhookService::new(&config, db.clone());
+        let dispatcher = WebhookDispatcher::new(&config, db.clone());
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+
+        let server = MockServer::start_async().await;
+        let broken = server.mock_async(|when, then| {
+            when.method(POST).path("/broken");
+            then.status(500);
+        }).await;
+        let created = webhooks.create(user.id, CreateWebhookEndpointRequest {
+            url: server.url("/broken"),
+            events: vec!["transaction.failed".to_string()],
+        }).await.unwrap();
+        assert!(created.secret.starts_with("whsec_"));
+        let webhook_id = created.webhook.id;
+        sqlx::query("UPDATE webhook_endpoints SET max_retries = 1 WHERE id = $1")
+            .bind(webhook_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        db.enqueue_webhook_event(user.id, "transaction.failed", &serde_json::json!({})).await.unwrap();
+        let delivery = "SELECT status, retry_count FROM webhook_deliveries WHERE webhook_id = $1";
+
+        dispatcher.drain().await.unwrap();
+        let (status, retries): (WebhookStatus, i32) = sqlx::query_as(delivery).bind(webhook_id).fetch_one(&db.pool).await.unwrap();
+        assert!(matches!(status, WebhookStatus::Pending));
+        assert_eq!(retries, 1);
+
+        // Nothing is sent again until the backoff has passed
+        dispatcher.drain().await.unwrap();
+        broken.assert_hits_async(1).await;
+
+        sqlx::query("UPDATE webhook_deliveries SET next_retry = NOW() WHERE webhook_id = $1")
+            .bind(webhook_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        dispatcher.drain().await.unwrap();
+        broken.assert_hits_async(2).await;
+        let (status, retries): (WebhookStatus, i32) = sqlx::query_as(delivery).bind(webhook_id).fetch_one(&db.pool).await.unwrap();
+        assert!(matches!(status, WebhookStatus::Failed));
+        assert_eq!(retries, 2);
+
+        let listed = webhooks.list(user.id).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].failure_count, 2);
+
+        db.enqueue_webhook_event(user.id, "transaction.failed", &serde_json::json!({})).await.unwrap();
+        let updated = webhooks.update(user.id, webhook_id, UpdateWebhookEndpointRequest {
+            is_active: Some(false),
+            ..Default::default()
+        }).await.unwrap();
+        assert!(!updated.is_active);
+        let cancelled: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_deliveries WHERE webhook_id = $1 AND status = 'cancelled'")
+            .bind(webhook_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(cancelled, 1);
+
+        webhooks.delete(user.id, webhook_id).await.unwrap();
+        assert!(webhooks.list(user.id).await.unwrap().is_empty());
+        assert!(webhooks.delete(user.id, webhook_id).await.is_err());
+    }
+
+    /// Tests that usage stats count distinct endpoints and users, not usage rows
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_usage_distinct_counts() {
+        let db = setup_test_db().await;
+
+        let mut users = Vec::new();
+        for _ in 0..2 {
+            users.push(db.create_user(test_support::user_request()).await.unwrap());
+        }
+        let mut endpoints = Vec::new();
+        for name in ["busy", "quiet"] {
+            endpoints.push(db.create_endpoint(users[0].id, CreateEndpointRequest {
+                name: format!("{}-{}", name, Uuid::new_v4().simple()),
+                upstream_url: "https://api.example.com".to_string(),
+                price_per_request: "10".to_string(),
+                ..Default::default()
+            }).await.unwrap());
+        }
+        let (busy, quiet) = (&endpoints[0], &endpoints[1]);
+
+        // The first user calls the busy endpoint in two months, so it has two rows
+        db.create_usage_record(users[0].id, busy.id, 3, "30", "2024-01").await.unwrap();
+        db.create_usage_record(users[0].id, busy.id, 4, "40", "2024-02").await.unwrap();
+        db.create_usage_record(users[0].id, quiet.id, 1, "10", "2024-02").await.unwrap();
+        db.create_usage_record(users[1].id, busy.id, 2, "20", "2024-02").await.unwrap();
+
+        let january = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let march = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        assert_eq!(db.count_user_endpoints(users[0].id, january, march).await.unwrap(), 2);
+        assert_eq!(db.count_endpoint_users(busy.id, january, march).await.unwrap(), 2);
+        assert_eq!(db.count_endpoint_users(quiet.id, january, march).await.unwrap(), 1);
+
+        let top = db.get_user_top_endpoints(users[0].id, january, march, 5).await.unwrap();
+        assert_eq!(top.iter().map(|e| e.endpoint_id).collect::<Vec<_>>(), vec![busy.id, quiet.id]);
+        assert_eq!(top[0].endpoint_name, busy.name);
+        assert_eq!(top[0].total_requests, 7);
+        assert_eq!(top[0].total_cost, "70");
+
+        let limited = db.get_user_top_endpoints(users[0].id, january, march, 1).await.unwrap();
+        assert_eq!(limited.len(), 1);
+
+        // January alone only saw the busy endpoint
+        let february = january + chrono::Months::new(1);
+        assert_eq!(db.count_user_endpoints(users[0].id, january, february).await.unwrap(), 1);
+        assert_eq!(db.count_endpoint_users(busy.id, january, february).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_usage_export_queries() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("export-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        let january = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        for (hours, status_code) in [(2, 500), (1, 200), (30 * 24, 200)] {
+            sqlx::query(
+                "INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code, response_time_ms,
+                                           ip_address_hash, timestamp, cost)
+                 VALUES ($1, $2, $3, 'GET', '/', $4, 50, 'hash', $5, '10')"
+            )
+            .bind(user.id)
+            .bind(endpoint.id)
+            .bind(Uuid::new_v4().to_string())
+            .bind(status_code)
+            .bind(january + chrono::Duration::hours(hours))
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        // The end of the range is exclusive and rows come back oldest first
+        let end = january + chrono::Duration::days(30);
+        assert_eq!(db.count_request_logs(Some(user.id), Some(january), end).await.unwrap(), 2);
+        let rows: Vec<_> = db.stream_request_logs(Some(user.id), Some(january), end)
+            .collect::<Vec<_>>().await
+            .into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(rows.iter().map(|row| row.status_code).collect::<Vec<_>>(), vec![200, 500]);
+        assert_eq!(rows[0].endpoint_name, endpoint.name);
+        assert_eq!(rows[0].cost, "10");
+        assert_eq!(db.count_request_logs(Some(user.id), None, end + chrono::Duration::hours(1)).await.unwrap(), 3);
+
+        // A background export records its outcome only once
+        let export = db.create_usage_export(user.id, Some(user.id), ExportFormat::Jsonl, None, end).await.unwrap();
+        assert_eq!(export.status, ExportStatus::Pending);
+        db.complete_usage_export(export.id, Ok(2)).await.unwrap();
+        db.complete_usage_export(export.id, Err("late failure".to_string())).await.unwrap();
+        let export = db.get_usage_export(export.id).await.unwrap().unwrap();
+        assert_eq!(export.status, ExportStatus::Ready);
+        assert_eq!(export.row_count, Some(2));
+        assert!(export.error_message.is_none());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_user_balance() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+
+        // A user who never funded their account has nothing, rather than no balance
+        let empty = db.get_user_balance(user.id).await.unwrap();
+        assert_eq!((empty.user_id, empty.balance, empty.pending_charges), (user.id, CostAmount::ZERO, CostAmount::ZERO));
+
+        db.credit_balance(user.id, "1000", Some("0xdeposit")).await.unwrap();
+        db.charge_balance(user.id, None, "250.5", "request-1").await.unwrap();
+        let balance = db.get_user_balance(user.id).await.unwrap();
+        assert_eq!(balance.balance, "749.5");
+
+        // Pending charges count only usage that has not been billed yet
+        let endpoint = sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO api_endpoints (name, owner_id, upstream_url, price_per_request)
+             VALUES ($1, $2, 'https://api.example.com', '10') RETURNING id"
+        )
+        .bind(format!("balance-{}", Uuid::new_v4().simple()))
+        .bind(user.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        db.create_usage_record(user.id, endpoint, 3, "30", "2024-01").await.unwrap();
+        let billed = db.create_usage_record(user.id, endpoint, 1, "10", "2024-02").await.unwrap();
+        sqlx::query("UPDATE usage_records SET status = 'billed' WHERE id = $1")
+            .bind(billed.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.get_user_balance(user.id).await.unwrap().pending_charges, "30");
+
+        // The ledger explains every change to the balance and cannot be rewritten
+        let entries: Vec<(LedgerEntryType, String, String)> = sqlx::query_as(
+            "SELECT entry_type, amount::TEXT, balance_after::TEXT FROM balance_ledger WHERE user_id = $1 ORDER BY created_at"
+        )
+        .bind(user.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, LedgerEntryType::Charge);
+        assert!(sqlx::query("UPDATE balance_ledger SET amount = 0 WHERE user_id = $1").bind(user.id).execute(&db.pool).await.is_err());
+        assert!(sqlx::query("DELETE FROM balance_ledger WHERE user_id = $1").bind(user.id).execute(&db.pool).await.is_err());
+
+        // Deleting the user still takes their ledger with them
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&db.pool).await.unwrap();
+    }
+
+    /// Tests that every balance change is balanced by a platform entry, that
+    /// adjustments are audited and that the ledger lists the running balance
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_balance_adjustments() {
+        let db = setup_test_db().await;
+
+        let mut users = Vec::new();
+        for _ in 0..2 {
+            users.push(db.create_user(test_support::user_request()).await.unwrap());
+        }
+        let (user, admin) = (&users[0], &users[1]);
+        let actor = AuditActor { id: Some(admin.id), ip_address_hash: None };
+
+        db.credit_balance(user.id, "1000", Some("0xdeposit")).await.unwrap();
+        db.charge_balance(user.id, None, "300", "request-1").await.unwrap();
+        let credit = db.adjust_balance(user.id, "50".parse().unwrap(), &actor, "Goodwill credit").await.unwrap().unwrap();
+        assert_eq!((credit.entry_type, credit.amount, credit.balance_after), (LedgerEntryType::Adjustment, "50".parse().unwrap(), "750".parse().unwrap()));
+        let debit = db.adjust_balance(user.id, "-900".parse().unwrap(), &actor, "Billing correction").await.unwrap().unwrap();
+        assert_eq!(debit.balance_after, "-150");
+        assert_eq!(db.get_balance(user.id).await.unwrap(), "-150");
+
+        // Newest first, each with the balance it left
+        let entries = db.list_ledger_entries(user.id, LedgerQuery::default()).await.unwrap();
+        let balances: Vec<String> = entries.iter().map(|entry| entry.balance_after.to_string()).collect();
+        assert_eq!(balances, ["-150", "750", "700", "1000"]);
+
+        // Each transaction nets to zero against its platform account
+        let unbalanced: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM (SELECT transaction_id FROM balance_ledger WHERE transaction_id = ANY($1)
+             GROUP BY transaction_id HAVING SUM(amount) <> 0 OR COUNT(*) <> 2) t"
+        )
+        .bind(entries.iter().map(|entry| entry.transaction_id).collect::<Vec<_>>())
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(unbalanced, 0);
+        let account: PlatformAccount = sqlx::query_scalar(
+            "SELECT account FROM balance_ledger WHERE transaction_id = $1 AND account IS NOT NULL"
+        )
+        .bind(credit.transaction_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(account, PlatformAccount::Adjustments);
+
+        // An entry without its counterpart is rejected at commit
+        let lone = sqlx::query(
+            "INSERT INTO balance_ledger (transaction_id, user_id, entry_type, amount, balance_after) VALUES ($1, $2, 'adjustment', 1, 0)"
+        )
+        .bind(Uuid::new_v4())
+        .bind(user.id)
+        .execute(&db.pool)
+        .await;
+        assert!(lone.is_err());
+
+        // The reason is audited and referenced from the entry
+        let (action, reason): (String, String) = sqlx::query_as(
+            "SELECT action, metadata->>'reason' FROM audit_log WHERE id::TEXT = $1 AND actor_id = $2"
+        )
+        .bind(debit.reference.as_deref().unwrap())
+        .bind(admin.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!((action.as_str(), reason.as_str()), ("admin.adjust_balance", "Billing correction"));
+
+        assert!(db.adjust_balance(Uuid::new_v4(), "1".parse().unwrap(), &actor, "Missing").await.unwrap().is_none());
+    }
+
+    /// Tests that subscriptions are debited from the ledger balance when created and
+    /// renewed, and lapse when the balance cannot cover a renewal
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_subscription_charges() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO api_endpoints (name, owner_id, upstream_url, price_per_request)
+             VALUES ($1, $2, 'https://api.example.com', '10') RETURNING id"
+        )
+        .bind(format!("plans-{}", Uuid::new_v4().simple()))
+        .bind(user.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        let plan = db.create_endpoint_plan(endpoint, CreatePlanRequest {
+            name: "Starter".to_string(),
+            monthly_price: "100".to_string(),
+            included_requests: 10,
+            overage_price: "5".to_string(),
+        }).await.unwrap();
+        let price: CostAmount = "100".parse().unwrap();
+        let start = Utc::now();
+        let end = start + chrono::Months::new(1);
+
+        // Nothing is created while the balance falls short
+        db.credit_balance(user.id, "150", None).await.unwrap();
+        let BalanceReservation::Held(hold_id) = db.reserve_balance(user.id, "60", "request-1").await.unwrap() else {
+            panic!("balance not held");
         };
-        
-        let updated_user = db.update_user(user.id, update_request).await.unwrap();
-        assert_eq!(updated_user.email, Some("updated@example.com".to_string()));
-        assert!(!updated_user.is_active);
+        let outcome = db.create_subscription(user.id, &plan, price, start, end).await.unwrap();
+        assert!(matches!(outcome, SubscriptionOutcome::Insufficient { available } if available == "90"));
+        assert!(db.get_active_subscription(user.id, endpoint).await.unwrap().is_none());
+
+        db.release_balance_hold(hold_id).await.unwrap();
+        let SubscriptionOutcome::Active(subscription) = db.create_subscription(user.id, &plan, price, start, end).await.unwrap() else {
+            panic!("subscription not created");
+        };
+        assert_eq!(db.get_balance(user.id).await.unwrap(), "50");
+        let entries = db.list_ledger_entries(user.id, LedgerQuery::default()).await.unwrap();
+        assert_eq!(entries[0].entry_type, LedgerEntryType::Charge);
+        assert_eq!(entries[0].reference, Some(subscription.id.to_string()));
+
+        // The next period costs more than is left, so the subscription lapses uncharged
+        let after_period = end + chrono::Duration::seconds(1);
+        let (_, _, lapsed) = db.renew_due_subscriptions(after_period, after_period + chrono::Months::new(1)).await.unwrap();
+        assert!(lapsed >= 1);
+        assert!(db.get_active_subscription(user.id, endpoint).await.unwrap().is_none());
+        assert_eq!(db.get_balance(user.id).await.unwrap(), "50");
     }
-    
+
+    /// Tests that concurrent billing runs claim disjoint usage and that usage claimed by
+    /// a run that rolls back can be claimed again
     #[tokio::test]
     #[ignore] // Requires database connection
-    async fn test_endpoint_crud() {
+    async fn test_pending_billing_claims() {
         let db = setup_test_db().await;
-        
-        // First create a user to own the endpoint
-        let create_user_request = CreateUserRequest {
-            wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
-            email: Some("owner@example.com".to_string()),
-            username: Some("owner".to_string()),
-            tier: Some(UserTier::Pro),
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let mut ours = Vec::new();
+        for _ in 0..4 {
+            let endpoint = sqlx::query_scalar::<_, Uuid>(
+                "INSERT INTO api_endpoints (name, owner_id, upstream_url, price_per_request)
+                 VALUES ($1, $2, 'https://api.example.com', '10') RETURNING id"
+            )
+            .bind(format!("claims-{}", Uuid::new_v4().simple()))
+            .bind(user.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+            ours.push(db.create_usage_record(user.id, endpoint, 1, "10", "2024-01").await.unwrap().id);
+        }
+        let claimed = |records: &[BillableUsage]| -> Vec<Uuid> {
+            ours.iter().copied().filter(|id| records.iter().any(|record| record.id == *id)).collect()
         };
-        
-        let user = db.create_user(create_user_request).await.unwrap();
-        
-        // Test endpoint creation
-        let create_request = CreateEndpointRequest {
-            name: "test-api".to_string(),
-            description: Some("Test API endpoint".to_string()),
+
+        // The first run leaves half of our usage to the second, which runs alongside it
+        let mut first = db.begin_transaction().await.unwrap();
+        let first_claim = db.get_pending_billing(&mut first, "2024-02", &ours[..2], 10_000).await.unwrap();
+        let mut second = db.begin_transaction().await.unwrap();
+        let second_claim = db.get_pending_billing(&mut second, "2024-02", &[], 10_000).await.unwrap();
+
+        let first_ids: Vec<Uuid> = first_claim.iter().map(|record| record.id).collect();
+        assert!(second_claim.iter().all(|record| !first_ids.contains(&record.id)));
+        assert_eq!(claimed(&first_claim), &ours[2..]);
+        assert_eq!(claimed(&second_claim), &ours[..2]);
+
+        // Rolling back releases the first run's claim while the second still holds its own
+        first.rollback().await.unwrap();
+        let mut third = db.begin_transaction().await.unwrap();
+        let third_claim = db.get_pending_billing(&mut third, "2024-02", &[], 10_000).await.unwrap();
+        assert_eq!(claimed(&third_claim), &ours[2..]);
+
+        third.rollback().await.unwrap();
+        second.rollback().await.unwrap();
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&db.pool).await.unwrap();
+    }
+
+    /// Tests that a billing record moves through processing to failed and completed,
+    /// counting retries, and shows up in the user's history and by status
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_billing_record_lifecycle() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+
+        let record = db.create_billing_record(user.id, "2024-01").await.unwrap();
+        assert!(matches!(record.status, BillingStatus::Pending));
+        assert_eq!(db.create_billing_record(user.id, "2024-01").await.unwrap().id, record.id);
+        db.create_billing_record(user.id, "2024-02").await.unwrap();
+
+        let processing = db.update_billing_status(record.id, BillingStatus::Processing, None).await.unwrap();
+        assert!(matches!(processing.status, BillingStatus::Processing));
+        let stuck = db.get_billing_records_by_status(BillingStatus::Processing, Utc::now() + chrono::Duration::seconds(1), 1000)
+            .await
+            .unwrap();
+        assert!(stuck.iter().any(|stuck| stuck.id == record.id));
+        let stuck = db.get_billing_records_by_status(BillingStatus::Processing, processing.updated_at, 1000).await.unwrap();
+        assert!(stuck.iter().all(|stuck| stuck.id != record.id));
+
+        let failed = db.update_billing_status(record.id, BillingStatus::Failed, Some("reverted")).await.unwrap();
+        assert_eq!((failed.retry_count, failed.error_message.as_deref()), (1, Some("reverted")));
+        db.update_billing_status(record.id, BillingStatus::Processing, None).await.unwrap();
+        let completed = db.update_billing_status(record.id, BillingStatus::Completed, None).await.unwrap();
+        assert!(matches!(completed.status, BillingStatus::Completed));
+        assert_eq!(completed.retry_count, 1);
+        assert!(completed.error_message.is_none() && completed.processed_at.is_some());
+
+        let params = PaginationParams { page: Some(1), limit: Some(1), ..Default::default() };
+        let history = db.get_billing_records_by_user(user.id, params).await.unwrap();
+        assert_eq!((history.total, history.total_pages), (2, 2));
+        assert_eq!(history.data[0].billing_period, "2024-02");
+        let params = PaginationParams { page: Some(2), limit: Some(1), ..Default::default() };
+        assert_eq!(db.get_billing_records_by_user(user.id, params).await.unwrap().data[0].id, record.id);
+    }
+
+    /// Tests that a new partition takes over its month's rows from the default partition
+    /// and that dropping partitions removes only months wholly before the cutoff
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_request_log_partitions() {
+        let db = setup_test_db().await;
+        assert!(db.is_request_log_partitioned().await.unwrap());
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("partitions-{}", Uuid::new_v4().simple()),
             upstream_url: "https://api.example.com".to_string(),
-            price_per_request: "0.001".to_string(),
-            rate_limit: Some(1000),
-            rate_limit_window: Some(3600),
-            requires_auth: Some(true),
-            allowed_methods: Some(vec!["GET".to_string(), "POST".to_string()]),
-            request_timeout: Some(30),
-            retry_attempts: Some(3),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        // A month long before any partition lands in the default partition
+        let month = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap();
+        sqlx::query(
+            "INSERT INTO request_logs (user_id, endpoint_id, request_id, method, path, status_code, response_time_ms,
+                                       ip_address_hash, timestamp, cost)
+             VALUES ($1, $2, $3, 'GET', '/', 200, 50, 'hash', $4, '10')"
+        )
+        .bind(user.id)
+        .bind(endpoint.id)
+        .bind(Uuid::new_v4().to_string())
+        .bind(month.and_hms_opt(12, 0, 0).unwrap().and_utc())
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(db.create_request_log_partitions(month, 2).await.unwrap(), ["request_logs_1990_01", "request_logs_1990_02"]);
+        assert!(db.create_request_log_partitions(month, 2).await.unwrap().is_empty());
+        let moved: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM request_logs_1990_01")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(moved, 1);
+
+        // February is not over before the cutoff, so it stays
+        let cutoff = NaiveDate::from_ymd_opt(1990, 2, 15).unwrap();
+        assert_eq!(db.drop_request_log_partitions(cutoff).await.unwrap(), ["request_logs_1990_01"]);
+        let query = RequestLogQuery { user_id: Some(user.id), ..Default::default() };
+        assert!(db.get_request_logs(&query, None, 10).await.unwrap().is_empty());
+        assert_eq!(db.drop_request_log_partitions(NaiveDate::from_ymd_opt(1990, 3, 1).unwrap()).await.unwrap(), ["request_logs_1990_02"]);
+    }
+
+    /// Tests that a proxied call is logged with the usage it adds, and that neither is
+    /// written when pricing it fails
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_record_proxied_request() {
+        let db = setup_test_db().await;
+
+        let user = db.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = db.create_endpoint(user.id, serde_json::from_value(serde_json::json!({
+            "name": format!("proxied-{}", Uuid::new_v4().simple()),
+            "upstream_url": "https://api.example.com",
+            "price_per_request": "10",
+        })).unwrap()).await.unwrap();
+        let log_request = |request_id: &str| CreateRequestLogRequest {
+            user_id: user.id,
+            endpoint_id: endpoint.id,
+            request_id: request_id.to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status_code: 200,
+            response_time_ms: 50,
+            request_size: None,
+            response_size: None,
+            ip_address_hash: "hash".to_string(),
+            user_agent_hash: None,
+            cost: CostAmount::ZERO,
+            error_message: None,
+            upstream_target: None,
+            stream_duration_ms: None,
+            cost_breakdown: None,
+            is_sandbox: false,
+            api_key_id: None,
         };
-        
-        let endpoint = db.create_endpoint(user.id, create_request).await.unwrap();
-        assert_eq!(endpoint.name, "test-api");
-        assert_eq!(endpoint.owner_id, user.id);
+        let charge = |price: UsagePricer| UsageCharge { api_key_id: None, overage: false, units: 1, price };
+        let period = Utc::now().format("%Y-%m").to_string();
+
+        let billed = charge(std::sync::Arc::new(|_, _| Ok(CostBreakdown {
+            pricing_model: PricingModel::PerRequest,
+            requests: 1,
+            request_cost: "10".parse().unwrap(),
+            kilobytes: 0,
+            kilobyte_cost: CostAmount::ZERO,
+            total: "10".parse().unwrap(),
+        })));
+        let request_id = Uuid::new_v4().to_string();
+        let recorded_at = Utc::now();
+        let log = db.record_proxied_request(&log_request(&request_id), Some(&billed), recorded_at).await.unwrap();
+        assert_eq!(log.cost, "10".parse::<CostAmount>().unwrap());
+        assert_eq!(log.cost_breakdown.clone().unwrap().requests, 1);
+        assert_eq!(db.get_user_request_count(user.id, &period).await.unwrap(), 1);
+
+        // Retrying a write that did commit returns its entry without billing it again
+        let retried = db.record_proxied_request(&log_request(&request_id), Some(&billed), recorded_at).await.unwrap();
+        assert_eq!(retried.id, log.id);
+        assert_eq!(db.get_user_request_count(user.id, &period).await.unwrap(), 1);
+
+        // Sizes are filled in once the response has been streamed
+        let since = log.timestamp - chrono::Duration::seconds(1);
+        assert!(db.complete_request_log(user.id, &request_id, since, Some(12), Some(34), None).await.unwrap());
+        assert!(!db.complete_request_log(user.id, "unknown", since, Some(12), Some(34), None).await.unwrap());
+
+        let unpriceable = charge(std::sync::Arc::new(|_, _| Err(anyhow::anyhow!("Invalid pricing configuration"))));
+        assert!(db.record_proxied_request(&log_request(&Uuid::new_v4().to_string()), Some(&unpriceable), Utc::now()).await.is_err());
+        assert_eq!(db.get_user_request_count(user.id, &period).await.unwrap(), 1);
+        let query = RequestLogQuery { user_id: Some(user.id), ..Default::default() };
+        let logs = db.get_request_logs(&query, None, 10).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].response_size, Some(34));
+
+        db.record_proxied_request(&log_request(&Uuid::new_v4().to_string()), None, Utc::now()).await.unwrap();
+        assert_eq!(db.get_request_logs(&query, None, 10).await.unwrap().len(), 2);
+        assert_eq!(db.get_user_request_count(user.id, &period).await.unwrap(), 1);
     }
-}
\ No newline at end of file
+}
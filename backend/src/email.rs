@@ -0,0 +1,27 @@
+//! Outbound email delivery for AugustCredits
+//!
+//! Defines the sender abstraction used by the auth service for verification
+//! emails, so deployments can plug in a real mail provider and tests can
+//! capture messages instead of sending them.
+
+use anyhow::Result;
+use tracing::info;
+
+/// Delivers transactional emails to users
+#[axum::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Sends the single-use token that verifies ownership of `email`
+    async fn send_verification_email(&self, email: &str, token: &str) -> Result<()>;
+}
+
+/// Development sender that writes verification tokens to the log instead of mailing them
+#[derive(Debug, Default, Clone)]
+pub struct LogEmailSender;
+
+#[axum::async_trait]
+impl EmailSender for LogEmailSender {
+    async fn send_verification_email(&self, email: &str, token: &str) -> Result<()> {
+        info!("Verification email for {}: POST /auth/verify-email/{}", email, token);
+        Ok(())
+    }
+}
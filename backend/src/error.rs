@@ -3,11 +3,13 @@
 //! Centralized error management system providing consistent error types,
 //! HTTP status code mapping, and automatic error logging for the entire platform.
 
+use crate::{blockchain::ReadOnlyMode, metering::RateLimitInfo};
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
 use serde_json::json;
 use std::fmt;
 use tracing::error;
@@ -21,10 +23,30 @@ pub enum AppError {
     Blockchain(anyhow::Error),
     /// Authentication/authorization errors
     Auth(String),
+    /// Authenticated but not allowed to perform the action
+    Forbidden(String),
+    /// Action requires a verified email address
+    EmailNotVerified(String),
     /// Validation errors
     Validation(String),
     /// Rate limiting errors
     RateLimit(String),
+    /// Per-endpoint request quota exhausted; the response carries Retry-After
+    RateLimitExceeded { message: String, info: RateLimitInfo },
+    /// Consumer already has the endpoint's maximum number of calls in flight
+    ConcurrencyLimitExceeded { limit: u32 },
+    /// User's monthly request allowance used up until `period_end`
+    MonthlyLimitExceeded { current: i64, limit: i64, period_end: DateTime<Utc> },
+    /// Consumer's monthly quota on one endpoint used up until `period_end`
+    ConsumerQuotaExceeded { current: i64, limit: i64, period_end: DateTime<Utc> },
+    /// HTTP method the endpoint does not accept; the response lists the allowed ones
+    MethodNotAllowed { message: String, allowed: Vec<String> },
+    /// Request body exceeds the allowed size
+    PayloadTooLarge(String),
+    /// Request conflicts with one still being processed
+    Conflict(String),
+    /// Well-formed request that cannot be processed, e.g. a reused idempotency key
+    Unprocessable(String),
     /// Payment/billing errors
     Payment(String),
     /// External service errors
@@ -33,6 +55,10 @@ pub enum AppError {
     Config(String),
     /// Not found errors
     NotFound(String),
+    /// Resource existed but has been permanently removed
+    Gone(String),
+    /// Gateway is in maintenance mode and not proxying requests
+    Maintenance(String),
     /// Internal server errors
     Internal(String),
 }
@@ -43,12 +69,36 @@ impl fmt::Display for AppError {
             AppError::Database(err) => write!(f, "Database error: {}", err),
             AppError::Blockchain(err) => write!(f, "Blockchain error: {}", err),
             AppError::Auth(msg) => write!(f, "Authentication error: {}", msg),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            AppError::EmailNotVerified(msg) => write!(f, "Forbidden: {}", msg),
             AppError::Validation(msg) => write!(f, "Validation error: {}", msg),
             AppError::RateLimit(msg) => write!(f, "Rate limit error: {}", msg),
+            AppError::RateLimitExceeded { message, .. } => write!(f, "Rate limit error: {}", message),
+            AppError::ConcurrencyLimitExceeded { limit } => write!(
+                f,
+                "Too many concurrent requests: at most {} in flight per endpoint",
+                limit
+            ),
+            AppError::MonthlyLimitExceeded { current, limit, period_end } => write!(
+                f,
+                "Monthly limit exceeded: {}/{}, resets at {}",
+                current, limit, period_end.to_rfc3339()
+            ),
+            AppError::ConsumerQuotaExceeded { current, limit, period_end } => write!(
+                f,
+                "Endpoint monthly quota exceeded: {}/{}, resets at {}",
+                current, limit, period_end.to_rfc3339()
+            ),
+            AppError::MethodNotAllowed { message, .. } => write!(f, "Method not allowed: {}", message),
+            AppError::PayloadTooLarge(msg) => write!(f, "Payload too large: {}", msg),
+            AppError::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            AppError::Unprocessable(msg) => write!(f, "Unprocessable request: {}", msg),
             AppError::Payment(msg) => write!(f, "Payment error: {}", msg),
             AppError::ExternalService(msg) => write!(f, "External service error: {}", msg),
             AppError::Config(msg) => write!(f, "Configuration error: {}", msg),
             AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            AppError::Gone(msg) => write!(f, "Gone: {}", msg),
+            AppError::Maintenance(msg) => write!(f, "Maintenance: {}", msg),
             AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
         }
     }
@@ -64,6 +114,9 @@ impl IntoResponse for AppError {
                 error!("Database error: {}", self);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string(), "DATABASE_ERROR")
             }
+            AppError::Blockchain(err) if err.is::<ReadOnlyMode>() => {
+                (StatusCode::SERVICE_UNAVAILABLE, err.to_string(), "BLOCKCHAIN_READ_ONLY")
+            }
             AppError::Blockchain(_) => {
                 error!("Blockchain error: {}", self);
                 (StatusCode::BAD_GATEWAY, "Blockchain service unavailable".to_string(), "BLOCKCHAIN_ERROR")
@@ -71,12 +124,42 @@ impl IntoResponse for AppError {
             AppError::Auth(msg) => {
                 (StatusCode::UNAUTHORIZED, msg.clone(), "AUTH_ERROR")
             }
+            AppError::Forbidden(msg) => {
+                (StatusCode::FORBIDDEN, msg.clone(), "FORBIDDEN")
+            }
+            AppError::EmailNotVerified(msg) => {
+                (StatusCode::FORBIDDEN, msg.clone(), "EMAIL_NOT_VERIFIED")
+            }
             AppError::Validation(msg) => {
                 (StatusCode::BAD_REQUEST, msg.clone(), "VALIDATION_ERROR")
             }
             AppError::RateLimit(msg) => {
                 (StatusCode::TOO_MANY_REQUESTS, msg.clone(), "RATE_LIMIT_ERROR")
             }
+            AppError::RateLimitExceeded { message, .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, message.clone(), "RATE_LIMIT_ERROR")
+            }
+            AppError::ConcurrencyLimitExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, self.to_string(), "CONCURRENCY_LIMIT_EXCEEDED")
+            }
+            AppError::MonthlyLimitExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, self.to_string(), "MONTHLY_LIMIT_EXCEEDED")
+            }
+            AppError::ConsumerQuotaExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, self.to_string(), "ENDPOINT_QUOTA_EXCEEDED")
+            }
+            AppError::MethodNotAllowed { message, .. } => {
+                (StatusCode::METHOD_NOT_ALLOWED, message.clone(), "METHOD_NOT_ALLOWED")
+            }
+            AppError::PayloadTooLarge(msg) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, msg.clone(), "PAYLOAD_TOO_LARGE")
+            }
+            AppError::Conflict(msg) => {
+                (StatusCode::CONFLICT, msg.clone(), "CONFLICT")
+            }
+            AppError::Unprocessable(msg) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, msg.clone(), "UNPROCESSABLE_ENTITY")
+            }
             AppError::Payment(msg) => {
                 (StatusCode::PAYMENT_REQUIRED, msg.clone(), "PAYMENT_ERROR")
             }
@@ -91,22 +174,73 @@ impl IntoResponse for AppError {
             AppError::NotFound(msg) => {
                 (StatusCode::NOT_FOUND, msg.clone(), "NOT_FOUND")
             }
+            AppError::Gone(msg) => {
+                (StatusCode::GONE, msg.clone(), "GONE")
+            }
+            AppError::Maintenance(msg) => {
+                (StatusCode::SERVICE_UNAVAILABLE, msg.clone(), "MAINTENANCE")
+            }
             AppError::Internal(msg) => {
                 error!("Internal error: {}", self);
                 (StatusCode::INTERNAL_SERVER_ERROR, msg.clone(), "INTERNAL_ERROR")
             }
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "success": false,
             "error": {
                 "code": error_code,
                 "message": error_message
             },
             "timestamp": chrono::Utc::now()
-        }));
+        });
+
+        // Limit errors expose their counters separately so SDKs don't have to parse the message
+        match &self {
+            AppError::RateLimitExceeded { info, .. } => {
+                body["error"]["current"] = json!(info.limit.saturating_sub(info.remaining));
+                body["error"]["limit"] = json!(info.limit);
+                body["error"]["reset_time"] = json!(info.reset_time);
+                body["error"]["algorithm"] = json!(info.algorithm);
+            }
+            AppError::ConcurrencyLimitExceeded { limit } => {
+                body["error"]["limit"] = json!(limit);
+            }
+            AppError::MonthlyLimitExceeded { current, limit, period_end }
+            | AppError::ConsumerQuotaExceeded { current, limit, period_end } => {
+                body["error"]["current"] = json!(current);
+                body["error"]["limit"] = json!(limit);
+                body["error"]["period_end"] = json!(period_end.to_rfc3339());
+            }
+            _ => {}
+        }
 
-        (status, body).into_response()
+        let mut response = (status, Json(body)).into_response();
+        match &self {
+            AppError::RateLimitExceeded { info, .. } => {
+                info.apply_headers(response.headers_mut());
+                response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(info.retry_after()));
+            }
+            AppError::MonthlyLimitExceeded { current, limit, period_end } => {
+                let retry_after = (*period_end - Utc::now()).num_seconds().max(1) as u64;
+                let headers = response.headers_mut();
+                headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+                headers.insert("x-augustcredits-monthly-limit", HeaderValue::from(*limit));
+                headers.insert("x-augustcredits-monthly-used", HeaderValue::from(*current));
+                headers.insert("x-augustcredits-monthly-remaining", HeaderValue::from(0));
+            }
+            AppError::ConsumerQuotaExceeded { period_end, .. } => {
+                let retry_after = (*period_end - Utc::now()).num_seconds().max(1) as u64;
+                response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            }
+            AppError::MethodNotAllowed { allowed, .. } => {
+                if let Ok(allow) = HeaderValue::from_str(&allowed.join(", ")) {
+                    response.headers_mut().insert(header::ALLOW, allow);
+                }
+            }
+            _ => {}
+        }
+        response
     }
 }
 
@@ -115,10 +249,11 @@ impl IntoResponse for AppError {
 pub type AppResult<T> = Result<T, AppError>;
 
 /// Convert anyhow::Error to AppError::Database
-/// Converts generic anyhow errors to application errors
+/// Converts generic anyhow errors to application errors, keeping constraint
+/// violations and missing rows from the database as client errors
 impl From<anyhow::Error> for AppError {
     fn from(err: anyhow::Error) -> Self {
-        AppError::Database(err)
+        database_error(err)
     }
 }
 
@@ -126,10 +261,60 @@ impl From<anyhow::Error> for AppError {
 /// Converts database errors to application errors
 impl From<sqlx::Error> for AppError {
     fn from(err: sqlx::Error) -> Self {
-        AppError::Database(anyhow::Error::from(err))
+        database_error(anyhow::Error::from(err))
+    }
+}
+
+/// Maps an error whose chain holds a database error to the client error it stands
+/// for; anything else stays an internal database error
+fn database_error(err: anyhow::Error) -> AppError {
+    let Some(sqlx_error) = err.chain().find_map(|cause| cause.downcast_ref::<sqlx::Error>()) else {
+        return AppError::Database(err);
+    };
+    if matches!(sqlx_error, sqlx::Error::RowNotFound) {
+        return AppError::NotFound("Record not found".to_string());
+    }
+    match sqlx_error.as_database_error() {
+        Some(db) if db.is_unique_violation() => unique_violation(db.constraint().unwrap_or_default()),
+        Some(db) if db.is_foreign_key_violation() => foreign_key_violation(db.constraint().unwrap_or_default()),
+        _ => AppError::Database(err),
+    }
+}
+
+/// Describes which record already exists, by the unique constraint it collided with
+fn unique_violation(constraint: &str) -> AppError {
+    match constraint {
+        "idx_users_live_wallet_address" => AppError::Validation("Wallet address is already registered".to_string()),
+        "idx_api_endpoints_live_owner_name" => {
+            AppError::Conflict("You already have an endpoint with this name".to_string())
+        }
+        "idx_api_endpoints_live_slug" => AppError::Conflict("An endpoint with this slug already exists".to_string()),
+        "endpoint_plans_endpoint_id_name_key" => {
+            AppError::Conflict("The endpoint already has a plan with this name".to_string())
+        }
+        "idx_endpoint_subscriptions_active" => AppError::Conflict("Already subscribed to this endpoint".to_string()),
+        _ => AppError::Validation("A record with the same details already exists".to_string()),
     }
 }
 
+/// Describes which referenced record is missing, by the foreign key column; keys
+/// follow Postgres' `<table>_<column>_fkey` naming
+fn foreign_key_violation(constraint: &str) -> AppError {
+    let column = constraint.strip_suffix("_fkey").unwrap_or(constraint);
+    let record = if column.ends_with("_endpoint_id") {
+        "Endpoint"
+    } else if column.ends_with("_plan_id") {
+        "Plan"
+    } else if column.ends_with("_api_key_id") {
+        "API key"
+    } else if column.ends_with("_user_id") || column.ends_with("_owner_id") {
+        "User"
+    } else {
+        return AppError::Validation("Request references a record that does not exist".to_string());
+    };
+    AppError::NotFound(format!("{} not found", record))
+}
+
 /// Convert serde_json::Error to AppError::Validation
 /// Converts JSON serialization errors to application errors
 impl From<serde_json::Error> for AppError {
@@ -150,7 +335,15 @@ impl From<reqwest::Error> for AppError {
 /// Converts authentication errors to application errors
 impl From<crate::auth::AuthError> for AppError {
     fn from(err: crate::auth::AuthError) -> Self {
-        AppError::Auth(err.to_string())
+        match err {
+            crate::auth::AuthError::Validation(msg) => AppError::Validation(msg),
+            crate::auth::AuthError::InsufficientPermissions | crate::auth::AuthError::IpNotAllowed => {
+                AppError::Forbidden(err.to_string())
+            }
+            crate::auth::AuthError::AccountLocked { .. } => AppError::RateLimit(err.to_string()),
+            crate::auth::AuthError::EmailNotVerified => AppError::EmailNotVerified(err.to_string()),
+            other => AppError::Auth(other.to_string()),
+        }
     }
 }
 
@@ -201,4 +394,134 @@ macro_rules! payment_error {
     ($msg:expr) => {
         $crate::error::AppError::Payment($msg.to_string())
     };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metering::start_of_next_month, models::RateLimitAlgorithm};
+    use axum::http::HeaderMap;
+
+    /// Reads an error response into its status, headers and JSON body
+    async fn error_response(error: AppError) -> (StatusCode, HeaderMap, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// Tests that rate limit errors carry Retry-After and structured counters
+    #[tokio::test]
+    async fn test_rate_limit_exceeded_response() {
+        let reset_time = Utc::now().timestamp() as u64 + 30;
+        let (status, headers, body) = error_response(AppError::RateLimitExceeded {
+            message: "Rate limit exceeded".to_string(),
+            info: RateLimitInfo {
+                limit: 100,
+                remaining: 0,
+                reset_time,
+                window_seconds: 60,
+                algorithm: RateLimitAlgorithm::TokenBucket,
+            },
+        }).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = headers[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((29..=30).contains(&retry_after));
+        assert_eq!(body["error"]["code"], "RATE_LIMIT_ERROR");
+        assert_eq!(body["error"]["current"], 100);
+        assert_eq!(body["error"]["limit"], 100);
+        assert_eq!(body["error"]["reset_time"], reset_time);
+        assert_eq!(body["error"]["algorithm"], "token_bucket");
+    }
+
+    /// Tests that monthly limit errors report the period end and retry once it passes
+    #[tokio::test]
+    async fn test_monthly_limit_exceeded_response() {
+        let period_end = start_of_next_month(Utc::now());
+        let (status, headers, body) = error_response(AppError::MonthlyLimitExceeded {
+            current: 10_000,
+            limit: 10_000,
+            period_end,
+        }).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: i64 = headers[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=31 * 24 * 3600).contains(&retry_after));
+        assert_eq!(headers["x-augustcredits-monthly-used"], "10000");
+        assert_eq!(headers["x-augustcredits-monthly-remaining"], "0");
+        assert_eq!(body["error"]["code"], "MONTHLY_LIMIT_EXCEEDED");
+        assert_eq!(body["error"]["current"], 10_000);
+        assert_eq!(body["error"]["limit"], 10_000);
+        assert_eq!(body["error"]["period_end"], period_end.to_rfc3339());
+
+        // Other errors keep the plain shape
+        let (_, headers, body) = error_response(AppError::ExternalService("timed out".to_string())).await;
+        assert!(!headers.contains_key(header::RETRY_AFTER));
+        assert!(body["error"].get("limit").is_none());
+    }
+
+    /// Tests that endpoint quota errors have their own code and retry at the period end
+    #[tokio::test]
+    async fn test_consumer_quota_exceeded_response() {
+        let period_end = start_of_next_month(Utc::now());
+        let (status, headers, body) = error_response(AppError::ConsumerQuotaExceeded {
+            current: 500,
+            limit: 500,
+            period_end,
+        }).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key(header::RETRY_AFTER));
+        assert!(!headers.contains_key("x-augustcredits-monthly-limit"));
+        assert_eq!(body["error"]["code"], "ENDPOINT_QUOTA_EXCEEDED");
+        assert_eq!(body["error"]["current"], 500);
+        assert_eq!(body["error"]["limit"], 500);
+        assert_eq!(body["error"]["period_end"], period_end.to_rfc3339());
+    }
+
+    /// Tests that concurrency limit errors have their own code and report the limit
+    #[tokio::test]
+    async fn test_concurrency_limit_exceeded_response() {
+        let (status, _, body) = error_response(AppError::ConcurrencyLimitExceeded { limit: 4 }).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["code"], "CONCURRENCY_LIMIT_EXCEEDED");
+        assert_eq!(body["error"]["limit"], 4);
+    }
+
+    /// Tests that disallowed methods answer 405 with the accepted methods
+    #[tokio::test]
+    async fn test_method_not_allowed_response() {
+        let (status, headers, body) = error_response(AppError::MethodNotAllowed {
+            message: "Method PATCH not allowed for this endpoint".to_string(),
+            allowed: vec!["GET".to_string(), "POST".to_string()],
+        }).await;
+
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(headers[header::ALLOW], "GET, POST");
+        assert_eq!(body["error"]["code"], "METHOD_NOT_ALLOWED");
+    }
+
+    /// Tests that database errors are told apart from client errors through added context
+    #[test]
+    fn test_database_error_mapping() {
+        use anyhow::Context;
+
+        let missing: anyhow::Result<()> = Err(sqlx::Error::RowNotFound).context("Failed to get endpoint");
+        assert!(matches!(AppError::from(missing.unwrap_err()), AppError::NotFound(_)));
+        assert!(matches!(AppError::from(sqlx::Error::PoolTimedOut), AppError::Database(_)));
+        assert!(matches!(AppError::from(anyhow::anyhow!("connection reset")), AppError::Database(_)));
+
+        assert!(matches!(unique_violation("idx_users_live_wallet_address"), AppError::Validation(_)));
+        assert!(matches!(unique_violation("idx_api_endpoints_live_owner_name"), AppError::Conflict(_)));
+        assert!(matches!(unique_violation("idx_api_endpoints_live_slug"), AppError::Conflict(_)));
+        assert!(matches!(unique_violation("some_other_key"), AppError::Validation(_)));
+
+        let AppError::NotFound(msg) = foreign_key_violation("endpoint_subscriptions_plan_id_fkey") else { panic!() };
+        assert_eq!(msg, "Plan not found");
+        let AppError::NotFound(msg) = foreign_key_violation("api_endpoints_owner_id_fkey") else { panic!() };
+        assert_eq!(msg, "User not found");
+        assert!(matches!(foreign_key_violation("sla_refunds_usage_record_id_fkey"), AppError::Validation(_)));
+    }
+}
@@ -0,0 +1,429 @@
+//! Escrow payments for AugustCredits
+//!
+//! Users lock part of their balance in an escrow on the payments contract, to be
+//! released to a recipient later. The amount is debited from the ledger before the
+//! contract is called, so it cannot be spent twice, and credited back if the escrow
+//! cannot be opened. Once released, the recipient is credited when the contract's
+//! `EscrowReleased` event is applied. Nothing is available while the escrow feature
+//! is disabled.
+
+use crate::{
+    address,
+    blockchain::{EscrowContract, TransactionResult, TransactionStatus},
+    config::Config,
+    database::Database,
+    error::{AppError, AppResult},
+    models::{
+        CostAmount, CreateEscrowRequest, Escrow, EscrowChainState, EscrowDetails, EscrowOutcome,
+        PaginatedResponse, PaginationParams,
+    },
+};
+use anyhow::Result;
+use chrono::DateTime;
+use ethers::types::{Address, H256, U256};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Longest an escrow may be held before it can be released
+const MAX_RELEASE_DELAY_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Longest description accepted, as it is stored on-chain
+const MAX_DESCRIPTION_LEN: usize = 256;
+
+/// Opens, releases and looks up users' escrows
+#[derive(Clone)]
+pub struct EscrowService {
+    database: Arc<Database>,
+    contract: Arc<dyn EscrowContract>,
+    enabled: bool,
+    amount_decimals: u32,
+    /// Escrows are opened on the settlement chain
+    chain_id: u64,
+}
+
+impl EscrowService {
+    pub fn new(config: &Config, database: Arc<Database>, contract: Arc<dyn EscrowContract>) -> Self {
+        Self {
+            database,
+            contract,
+            enabled: config.is_feature_enabled("escrow"),
+            amount_decimals: config.blockchain.amount_decimals(),
+            chain_id: config.blockchain.settlement_chain().chain_id,
+        }
+    }
+
+    /// Debits the escrow's amount from the user's balance and opens it on-chain in the
+    /// background; the escrow is returned pending
+    pub async fn create(&self, user_id: Uuid, request: CreateEscrowRequest) -> AppResult<Escrow> {
+        self.ensure_enabled()?;
+        let recipient = parse_recipient(&request.recipient)?;
+        let amount = request.amount.parse::<CostAmount>().ok()
+            .filter(|amount| amount.fits_decimals(self.amount_decimals) && !amount.is_zero() && !amount.is_sign_negative())
+            .ok_or_else(|| AppError::Validation(format!(
+                "Escrow amount must be positive, with at most {} decimal places: {}",
+                self.amount_decimals, request.amount
+            )))?;
+        let value = amount.to_base_units(self.amount_decimals)
+            .ok_or_else(|| AppError::Validation(format!("Escrow amount is too large: {}", amount)))?;
+        validate_terms(&request)?;
+
+        let recipient_address = address::checksummed(recipient);
+        let outcome = self.database
+            .begin_escrow(user_id, amount, &request, &recipient_address, self.chain_id)
+            .await?;
+        let escrow = match outcome {
+            EscrowOutcome::Pending(escrow) => *escrow,
+            EscrowOutcome::Insufficient { available } => {
+                return Err(AppError::Payment(format!(
+                    "Insufficient balance: {} requested, {} available",
+                    amount, available
+                )));
+            }
+        };
+        info!("Escrow {} of {} for user {} to {}", escrow.id, amount, user_id, recipient_address);
+
+        let database = self.database.clone();
+        let contract = self.contract.clone();
+        let id = escrow.id;
+        let release_delay = U256::from(request.release_delay);
+        tokio::spawn(async move {
+            settle_creation(&database, contract.as_ref(), id, recipient, value, release_delay, request.description).await;
+        });
+
+        Ok(escrow)
+    }
+
+    /// Releases one of the user's active escrows to its recipient in the background;
+    /// the escrow is returned releasing
+    pub async fn release(&self, user_id: Uuid, id: Uuid) -> AppResult<Escrow> {
+        self.ensure_enabled()?;
+        let Some(escrow) = self.database.begin_escrow_release(user_id, id).await? else {
+            return match self.database.get_escrow(user_id, id).await? {
+                Some(_) => Err(AppError::Conflict("Only escrows open on-chain can be released".to_string())),
+                None => Err(AppError::NotFound("Escrow not found".to_string())),
+            };
+        };
+        let escrow_id = contract_escrow_id(&escrow)
+            .ok_or_else(|| AppError::Internal(format!("Escrow {} has an invalid contract id", escrow.id)))?;
+        info!("Releasing escrow {} of user {}", escrow.id, user_id);
+
+        let database = self.database.clone();
+        let contract = self.contract.clone();
+        tokio::spawn(async move {
+            settle_release(&database, contract.as_ref(), id, escrow_id).await;
+        });
+
+        Ok(escrow)
+    }
+
+    /// Gets one of the user's escrows, with its state on the contract once it is open
+    pub async fn details(&self, user_id: Uuid, id: Uuid) -> AppResult<EscrowDetails> {
+        self.ensure_enabled()?;
+        let escrow = self.database.get_escrow(user_id, id).await?
+            .ok_or_else(|| AppError::NotFound("Escrow not found".to_string()))?;
+
+        let on_chain = match contract_escrow_id(&escrow) {
+            Some(escrow_id) => {
+                let deposit = self.contract.escrow_details(escrow_id).await.map_err(AppError::Blockchain)?;
+                let release_time = u64::try_from(deposit.release_time).ok()
+                    .and_then(|secs| i64::try_from(secs).ok())
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .ok_or_else(|| AppError::Blockchain(anyhow::anyhow!(
+                        "Escrow {} has an out of range release time", escrow_id
+                    )))?;
+                Some(EscrowChainState { release_time, released: deposit.is_released, disputed: deposit.is_disputed })
+            }
+            None => None,
+        };
+
+        Ok(EscrowDetails { escrow, on_chain })
+    }
+
+    /// Pages through the user's escrows, newest first
+    pub async fn list(&self, user_id: Uuid, params: PaginationParams) -> AppResult<PaginatedResponse<Escrow>> {
+        self.ensure_enabled()?;
+        Ok(self.database.list_escrows(user_id, params).await?)
+    }
+
+    fn ensure_enabled(&self) -> AppResult<()> {
+        if self.enabled {
+            Ok(())
+        } else {
+            Err(AppError::NotFound("Escrow payments are not enabled".to_string()))
+        }
+    }
+}
+
+/// Parses the recipient of an escrow or payment stream, refusing mistyped and zero
+/// addresses
+pub(crate) fn parse_recipient(address: &str) -> AppResult<Address> {
+    let recipient = address::parse_field("recipient", address)?;
+    if recipient.is_zero() {
+        return Err(AppError::Validation("Recipient cannot be the zero address".to_string()));
+    }
+    Ok(recipient)
+}
+
+/// Checks an escrow's release delay and description
+fn validate_terms(request: &CreateEscrowRequest) -> AppResult<()> {
+    if request.release_delay > MAX_RELEASE_DELAY_SECS {
+        return Err(AppError::Validation(format!(
+            "Release delay must be at most {} seconds",
+            MAX_RELEASE_DELAY_SECS
+        )));
+    }
+    validate_description(&request.description)
+}
+
+/// Checks the description of an escrow or payment stream, which is stored on-chain
+pub(crate) fn validate_description(description: &str) -> AppResult<()> {
+    let description = description.trim();
+    if description.is_empty() || description.chars().count() > MAX_DESCRIPTION_LEN {
+        return Err(AppError::Validation(format!(
+            "Description must be between 1 and {} characters",
+            MAX_DESCRIPTION_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// The contract's id for an escrow, once its creation is confirmed
+fn contract_escrow_id(escrow: &Escrow) -> Option<U256> {
+    escrow.escrow_id.as_deref().and_then(|id| U256::from_dec_str(id).ok())
+}
+
+/// How a payments contract transaction settled
+pub(crate) enum Settlement {
+    Confirmed(H256),
+    /// Sent but not yet confirmed; left as is rather than undone
+    Pending(H256),
+    Failed(String),
+}
+
+pub(crate) fn settlement(result: Result<TransactionResult>) -> Settlement {
+    match result {
+        Ok(result) => match result.status {
+            TransactionStatus::Confirmed => Settlement::Confirmed(result.hash),
+            TransactionStatus::Pending => Settlement::Pending(result.hash),
+            TransactionStatus::Failed => Settlement::Failed(format!("Transaction {:?} failed", result.hash)),
+            TransactionStatus::Reverted(reason) => {
+                Settlement::Failed(format!("Transaction {:?} reverted: {}", result.hash, reason))
+            }
+        },
+        Err(e) => Settlement::Failed(format!("Transaction could not be sent: {:#}", e)),
+    }
+}
+
+/// Opens an escrow on-chain and records how it settled, crediting the amount back
+/// when the transaction fails or reverts
+async fn settle_creation(
+    database: &Database,
+    contract: &dyn EscrowContract,
+    id: Uuid,
+    recipient: Address,
+    amount: U256,
+    release_delay: U256,
+    description: String,
+) {
+    let (result, escrow_id) = match contract.open_escrow(recipient, amount, release_delay, description).await {
+        Ok((result, escrow_id)) => (Ok(result), escrow_id),
+        Err(e) => (Err(e), None),
+    };
+
+    let failure = match settlement(result) {
+        Settlement::Confirmed(hash) => {
+            if escrow_id.is_none() {
+                warn!("Escrow {} opened as {:?} but the contract's id for it was not found", id, hash);
+            }
+            let escrow_id = escrow_id.map(|escrow_id| escrow_id.to_string());
+            if let Err(e) = database.activate_escrow(id, escrow_id.as_deref(), &format!("{:?}", hash)).await {
+                error!("Failed to activate escrow {}: {}", id, e);
+            }
+            return;
+        }
+        Settlement::Pending(hash) => {
+            warn!("Escrow {} sent as {:?} but not yet confirmed", id, hash);
+            return;
+        }
+        Settlement::Failed(failure) => failure,
+    };
+
+    warn!("Escrow {} could not be opened: {}", id, failure);
+    match database.fail_escrow(id, &failure).await {
+        Ok(true) => info!("Escrow {} credited back", id),
+        Ok(false) => {}
+        Err(e) => error!("Failed to credit back escrow {}: {}", id, e),
+    }
+}
+
+/// Releases an escrow on-chain and records how it settled; a failed release leaves
+/// the escrow active so it can be released again
+async fn settle_release(database: &Database, contract: &dyn EscrowContract, id: Uuid, escrow_id: U256) {
+    match settlement(contract.release(escrow_id).await) {
+        Settlement::Confirmed(hash) => {
+            if let Err(e) = database.confirm_escrow_release(id, &format!("{:?}", hash)).await {
+                error!("Failed to confirm release of escrow {}: {}", id, e);
+            }
+        }
+        Settlement::Pending(hash) => warn!("Release of escrow {} sent as {:?} but not yet confirmed", id, hash),
+        Settlement::Failed(failure) => {
+            warn!("Escrow {} could not be released: {}", id, failure);
+            if let Err(e) = database.fail_escrow_release(id, &failure).await {
+                error!("Failed to record failed release of escrow {}: {}", id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::{blockchain::EscrowDeposit, models::EscrowStatus};
+    use std::str::FromStr;
+
+    /// Payments contract settling every transaction with a fixed outcome
+    struct StubContract(TransactionStatus);
+
+    impl StubContract {
+        fn result(&self) -> TransactionResult {
+            TransactionResult {
+                hash: H256::repeat_byte(0xab),
+                block_number: Some(1),
+                gas_used: None,
+                status: self.0.clone(),
+                confirmations: 1,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EscrowContract for StubContract {
+        async fn open_escrow(
+            &self,
+            _recipient: Address,
+            _amount: U256,
+            _release_delay: U256,
+            _description: String,
+        ) -> Result<(TransactionResult, Option<U256>)> {
+            Ok((self.result(), Some(U256::from(Uuid::new_v4().as_u128()))))
+        }
+
+        async fn release(&self, _escrow_id: U256) -> Result<TransactionResult> {
+            Ok(self.result())
+        }
+
+        async fn escrow_details(&self, escrow_id: U256) -> Result<EscrowDeposit> {
+            Ok(EscrowDeposit {
+                id: escrow_id,
+                user: Address::zero(),
+                recipient: Address::zero(),
+                amount: U256::zero(),
+                release_time: U256::from(1_700_000_000u64),
+                is_released: false,
+                is_disputed: false,
+            })
+        }
+    }
+
+    fn first_page() -> PaginationParams {
+        PaginationParams { page: None, limit: None, sort_by: None, sort_order: None }
+    }
+
+    fn request(amount: &str) -> CreateEscrowRequest {
+        CreateEscrowRequest {
+            recipient: "0x52908400098527886E0F7030069857D2E4169EE7".to_string(),
+            amount: amount.to_string(),
+            release_delay: 3600,
+            description: "Integration milestone".to_string(),
+        }
+    }
+
+    /// Tests which recipients and terms an escrow can be opened with
+    #[test]
+    fn test_validate_escrow_request() {
+        assert!(parse_recipient("0x52908400098527886E0F7030069857D2E4169EE7").is_ok());
+        assert!(parse_recipient("52908400098527886E0F7030069857D2E4169EE7").is_err());
+        assert!(parse_recipient("0x0000000000000000000000000000000000000000").is_err());
+
+        assert!(validate_terms(&request("1")).is_ok());
+        assert!(validate_terms(&CreateEscrowRequest { release_delay: MAX_RELEASE_DELAY_SECS + 1, ..request("1") }).is_err());
+        assert!(validate_terms(&CreateEscrowRequest { description: "  ".to_string(), ..request("1") }).is_err());
+        assert!(validate_terms(&CreateEscrowRequest { description: "x".repeat(MAX_DESCRIPTION_LEN + 1), ..request("1") }).is_err());
+    }
+
+    /// Tests that escrow operations are not found while the feature is disabled
+    #[tokio::test]
+    async fn test_escrow_disabled() {
+        let mut config = test_support::config();
+        config.features.enable_escrow = false;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        let escrows = EscrowService::new(&config, database, Arc::new(StubContract(TransactionStatus::Confirmed)));
+
+        assert!(matches!(escrows.create(Uuid::new_v4(), request("1")).await, Err(AppError::NotFound(_))));
+        assert!(matches!(escrows.release(Uuid::new_v4(), Uuid::new_v4()).await, Err(AppError::NotFound(_))));
+        assert!(matches!(escrows.list(Uuid::new_v4(), first_page()).await, Err(AppError::NotFound(_))));
+    }
+
+    /// Tests that an escrow is checked against the balance and debited from it, credited
+    /// back when it cannot be opened, and released only once it is open
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_escrow_lifecycle() {
+        let mut config = test_support::config();
+        config.features.enable_escrow = true;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        database.credit_balance(user.id, "1000", None).await.unwrap();
+        let escrows = EscrowService::new(&config, database.clone(), Arc::new(StubContract(TransactionStatus::Confirmed)));
+        let contract = |status| StubContract(status);
+
+        assert!(matches!(escrows.create(user.id, request("2000")).await, Err(AppError::Payment(_))));
+        assert!(matches!(escrows.create(user.id, request("-5")).await, Err(AppError::Validation(_))));
+
+        // A confirmed escrow is opened and can be released once
+        let EscrowOutcome::Pending(escrow) = database
+            .begin_escrow(user.id, CostAmount::from_str("300").unwrap(), &request("300"), "0x52908400098527886e0f7030069857d2e4169ee7", 1)
+            .await
+            .unwrap()
+        else {
+            panic!("escrow was not accepted");
+        };
+        assert_eq!(escrow.status, EscrowStatus::Pending);
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "700");
+        assert!(matches!(escrows.release(user.id, escrow.id).await, Err(AppError::Conflict(_))));
+
+        settle_creation(&database, &contract(TransactionStatus::Confirmed), escrow.id, Address::zero(), U256::from(300), U256::zero(), String::new()).await;
+        assert!(!database.fail_escrow(escrow.id, "late failure").await.unwrap());
+        let details = escrows.details(user.id, escrow.id).await.unwrap();
+        assert_eq!(details.escrow.status, EscrowStatus::Active);
+        assert!(details.on_chain.is_some_and(|state| !state.released));
+
+        let releasing = database.begin_escrow_release(user.id, escrow.id).await.unwrap().unwrap();
+        assert!(database.begin_escrow_release(user.id, escrow.id).await.unwrap().is_none());
+        settle_release(&database, &contract(TransactionStatus::Confirmed), escrow.id, contract_escrow_id(&releasing).unwrap()).await;
+        let released = database.get_escrow(user.id, escrow.id).await.unwrap().unwrap();
+        assert_eq!(released.status, EscrowStatus::Released);
+        assert!(released.released_at.is_some());
+
+        // A failed escrow is credited back exactly once
+        let EscrowOutcome::Pending(escrow) = database
+            .begin_escrow(user.id, CostAmount::from_str("200").unwrap(), &request("200"), "0x52908400098527886e0f7030069857d2e4169ee7", 1)
+            .await
+            .unwrap()
+        else {
+            panic!("escrow was not accepted");
+        };
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "500");
+        settle_creation(&database, &contract(TransactionStatus::Failed), escrow.id, Address::zero(), U256::from(200), U256::zero(), String::new()).await;
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "700");
+        assert!(!database.fail_escrow(escrow.id, "again").await.unwrap());
+
+        // Other users cannot see the user's escrows
+        assert!(matches!(escrows.details(Uuid::new_v4(), escrow.id).await, Err(AppError::NotFound(_))));
+        let listed = escrows.list(user.id, first_page()).await.unwrap();
+        assert_eq!(listed.total, 2);
+    }
+}
@@ -0,0 +1,318 @@
+//! Usage exports for AugustCredits
+//!
+//! Streams raw request logs as CSV or JSON Lines so finance teams can reconcile
+//! usage. Small exports are streamed straight into the response; larger ones are
+//! written to disk in the background and downloaded by id once ready.
+
+use crate::{
+    config::Config,
+    database::Database,
+    error::{AppError, AppResult},
+    models::{ExportFormat, ExportStatus, UsageExport, UsageExportQuery, UsageExportRow},
+};
+use axum::body::Body;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use std::{io, path::PathBuf, sync::Arc};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Rendered rows are sent on once this many bytes have built up
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Chunks rendered ahead of the client or file reading them
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// How a usage export reaches the client
+pub enum ExportDelivery {
+    /// Small enough to stream in the response
+    Stream { format: ExportFormat, body: Body },
+    /// Being written in the background; download it by id once ready
+    Scheduled(UsageExport),
+}
+
+/// Generates usage exports from the request logs
+#[derive(Clone)]
+pub struct ExportService {
+    database: Arc<Database>,
+    directory: PathBuf,
+    async_row_threshold: u64,
+}
+
+impl ExportService {
+    /// Creates an export service writing background exports to the configured directory
+    pub fn new(config: &Config, database: Arc<Database>) -> Self {
+        Self {
+            database,
+            directory: PathBuf::from(&config.exports.directory),
+            async_row_threshold: config.exports.async_row_threshold,
+        }
+    }
+
+    /// Exports the requests selected by `query` on behalf of `requested_by`
+    ///
+    /// `query.user_id` must already be restricted to what the requester may see. An
+    /// open-ended range is closed at the time of the request, so a background export
+    /// holds exactly the rows that were counted.
+    pub async fn export(&self, requested_by: Uuid, query: UsageExportQuery) -> AppResult<ExportDelivery> {
+        let format = query.format.unwrap_or_default();
+        let end = query.end.unwrap_or_else(Utc::now);
+        if query.start.is_some_and(|start| start >= end) {
+            return Err(AppError::Validation("Export range must end after it starts".to_string()));
+        }
+
+        let rows = self.database.count_request_logs(query.user_id, query.start, end).await?;
+        if rows as u64 <= self.async_row_threshold {
+            let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+            let database = self.database.clone();
+            tokio::spawn(async move {
+                if let Err(e) = write_rows(&database, query.user_id, query.start, end, format, tx).await {
+                    error!("Usage export stream failed: {:#}", e);
+                }
+            });
+            return Ok(ExportDelivery::Stream { format, body: Body::from_stream(ReceiverStream::new(rx)) });
+        }
+
+        let export = self.database
+            .create_usage_export(requested_by, query.user_id, format, query.start, end)
+            .await?;
+        info!("Usage export {} of {} rows scheduled for user {}", export.id, rows, requested_by);
+
+        let service = self.clone();
+        let scheduled = export.clone();
+        tokio::spawn(async move {
+            let outcome = service.write_file(&scheduled).await.map_err(|e| format!("{:#}", e));
+            if let Err(e) = &outcome {
+                error!("Usage export {} failed: {}", scheduled.id, e);
+            }
+            if let Err(e) = service.database.complete_usage_export(scheduled.id, outcome).await {
+                error!("Failed to record outcome of usage export {}: {}", scheduled.id, e);
+            }
+        });
+
+        Ok(ExportDelivery::Scheduled(export))
+    }
+
+    /// Gets a background export made by `requested_by`
+    pub async fn get_export(&self, requested_by: Uuid, export_id: Uuid) -> AppResult<UsageExport> {
+        self.database.get_usage_export(export_id).await?
+            .filter(|export| export.requested_by == requested_by)
+            .ok_or_else(|| AppError::NotFound("Export not found".to_string()))
+    }
+
+    /// Opens a finished background export made by `requested_by` for download
+    pub async fn download(&self, requested_by: Uuid, export_id: Uuid) -> AppResult<(UsageExport, Body)> {
+        let export = self.get_export(requested_by, export_id).await?;
+        match export.status {
+            ExportStatus::Pending => {
+                return Err(AppError::Conflict("Export is still being generated".to_string()));
+            }
+            ExportStatus::Failed => {
+                return Err(AppError::Unprocessable(format!(
+                    "Export failed: {}",
+                    export.error_message.as_deref().unwrap_or("unknown error")
+                )));
+            }
+            ExportStatus::Ready => {}
+        }
+
+        let file = File::open(self.path(&export)).await
+            .map_err(|_| AppError::Gone("Export file is no longer available".to_string()))?;
+        Ok((export, Body::from_stream(read_chunks(file))))
+    }
+
+    /// Writes a background export to its file, returning the rows written
+    async fn write_file(&self, export: &UsageExport) -> anyhow::Result<i64> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let mut file = File::create(self.path(export)).await?;
+
+        let (tx, mut rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        let rows = write_rows(&self.database, export.user_id, export.start_time, export.end_time, export.format, tx);
+        let written = async move {
+            while let Some(chunk) = rx.recv().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await
+        };
+
+        let (rows, written) = tokio::join!(rows, written);
+        written?;
+        rows
+    }
+
+    fn path(&self, export: &UsageExport) -> PathBuf {
+        self.directory.join(format!("{}.{}", export.id, extension(export.format)))
+    }
+}
+
+/// Renders the selected requests into chunks sent on `tx`, returning the rows rendered
+///
+/// A database error is also sent on, so a streamed response is cut off rather than
+/// ending as if complete.
+async fn write_rows(
+    database: &Database,
+    user_id: Option<Uuid>,
+    start: Option<DateTime<Utc>>,
+    end: DateTime<Utc>,
+    format: ExportFormat,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<i64> {
+    let mut chunk = String::from(header(format));
+    let mut rows = 0;
+    let mut logs = database.stream_request_logs(user_id, start, end);
+
+    while let Some(row) = logs.next().await {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::other(format!("{:#}", e)))).await;
+                return Err(e);
+            }
+        };
+        chunk.push_str(&render_row(&row, format));
+        rows += 1;
+
+        if chunk.len() >= CHUNK_BYTES {
+            let full = std::mem::take(&mut chunk);
+            if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                anyhow::bail!("Export reader went away after {} rows", rows);
+            }
+        }
+    }
+
+    if !chunk.is_empty() && tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+        anyhow::bail!("Export reader went away after {} rows", rows);
+    }
+    Ok(rows)
+}
+
+/// Streams a file in chunks without reading it into memory
+fn read_chunks(file: File) -> impl futures::Stream<Item = io::Result<Bytes>> {
+    futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buffer = vec![0; CHUNK_BYTES];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(Bytes::from(buffer)), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+/// Content type of an export format
+pub fn content_type(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+        ExportFormat::Jsonl => "application/x-ndjson",
+    }
+}
+
+/// File extension of an export format
+pub fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "csv",
+        ExportFormat::Jsonl => "jsonl",
+    }
+}
+
+/// First line of an export, before any rows
+fn header(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "timestamp,user_id,api_key_id,endpoint,method,status_code,response_time_ms,cost\n",
+        ExportFormat::Jsonl => "",
+    }
+}
+
+/// Renders one request as a line of the export
+fn render_row(row: &UsageExportRow, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => format!(
+            "{},{},{},{},{},{},{},{}\n",
+            row.timestamp.to_rfc3339(),
+            row.user_id,
+            row.api_key_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&row.endpoint_name),
+            csv_field(&row.method),
+            row.status_code,
+            row.response_time_ms,
+            row.cost,
+        ),
+        ExportFormat::Jsonl => {
+            let line = serde_json::json!({
+                "timestamp": row.timestamp,
+                "user_id": row.user_id,
+                "api_key_id": row.api_key_id,
+                "endpoint": row.endpoint_name,
+                "method": row.method,
+                "status_code": row.status_code,
+                "response_time_ms": row.response_time_ms,
+                "cost": row.cost,
+            });
+            format!("{}\n", line)
+        }
+    }
+}
+
+/// Quotes a CSV field when it holds a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(endpoint_name: &str) -> UsageExportRow {
+        UsageExportRow {
+            timestamp: DateTime::parse_from_rfc3339("2024-03-01T12:30:00Z").unwrap().with_timezone(&Utc),
+            user_id: Uuid::nil(),
+            api_key_id: None,
+            endpoint_name: endpoint_name.to_string(),
+            method: "GET".to_string(),
+            status_code: 200,
+            response_time_ms: 42,
+            cost: "1000.5".parse().unwrap(),
+        }
+    }
+
+    /// Tests that rows render as CSV lines, quoting fields that need it
+    #[test]
+    fn test_render_csv_row() {
+        assert_eq!(
+            render_row(&row("quotes"), ExportFormat::Csv),
+            "2024-03-01T12:30:00+00:00,00000000-0000-0000-0000-000000000000,,quotes,GET,200,42,1000.5\n"
+        );
+        let keyed = UsageExportRow { api_key_id: Some(Uuid::nil()), ..row("quotes") };
+        assert!(render_row(&keyed, ExportFormat::Csv).contains(",00000000-0000-0000-0000-000000000000,quotes,"));
+        assert!(render_row(&row("a,\"b\""), ExportFormat::Csv).contains(",\"a,\"\"b\"\"\",GET,"));
+        assert_eq!(header(ExportFormat::Csv).matches(',').count(), 7);
+    }
+
+    /// Tests that rows render as one JSON object per line with exact costs
+    #[test]
+    fn test_render_jsonl_row() {
+        let line = render_row(&row("quotes"), ExportFormat::Jsonl);
+        assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["endpoint"], "quotes");
+        assert_eq!(value["status_code"], 200);
+        assert_eq!(value["cost"], "1000.5");
+        assert!(value["api_key_id"].is_null());
+        assert_eq!(header(ExportFormat::Jsonl), "");
+    }
+}
@@ -0,0 +1,229 @@
+//! Upstream health checks for AugustCredits
+//!
+//! Background task that periodically probes every active endpoint's upstream,
+//! records latency and status, and flags endpoints as degraded after repeated
+//! failures so consumers are not billed full price for a broken upstream.
+
+use crate::{
+    config::{Config, HealthCheckConfig},
+    database::Database,
+    models::{ApiEndpoint, CreateEndpointHealthCheckRequest, EndpointHealthState},
+};
+use anyhow::Result;
+use reqwest::Client;
+use std::{sync::Arc, time::{Duration, Instant}};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Probes endpoint upstreams and maintains their degraded flag
+#[derive(Clone)]
+pub struct HealthChecker {
+    client: Client,
+    database: Arc<Database>,
+    config: HealthCheckConfig,
+}
+
+impl HealthChecker {
+    /// Creates a health checker using the configured probe timeout
+    pub fn new(config: &Config, database: Arc<Database>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.health_check.timeout_secs))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            client,
+            database,
+            config: config.health_check.clone(),
+        }
+    }
+
+    /// Runs health checks on the configured interval until the process exits
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.check_all().await {
+                    error!("Endpoint health check run failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Probes every active endpoint once
+    pub async fn check_all(&self) -> Result<()> {
+        let endpoints = self.database.list_active_endpoints().await?;
+        let checks = endpoints.iter().map(|endpoint| self.check_endpoint(endpoint));
+
+        for (endpoint, result) in endpoints.iter().zip(futures::future::join_all(checks).await) {
+            if let Err(e) = result {
+                error!("Failed to record health for endpoint {}: {}", endpoint.name, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Probes a single endpoint and records the result
+    pub async fn check_endpoint(&self, endpoint: &ApiEndpoint) -> Result<EndpointHealthState> {
+        let check = self.probe(endpoint).await;
+        let state = self.database.record_endpoint_health(
+            check,
+            self.config.failure_threshold as i32,
+            self.config.auto_deactivate,
+        ).await?;
+
+        if state.is_degraded && !state.was_degraded {
+            warn!(
+                "Endpoint {} marked degraded after {} failed health checks{}",
+                endpoint.name,
+                state.consecutive_failures,
+                if state.is_active { "" } else { "; endpoint deactivated" }
+            );
+        } else if state.was_degraded && !state.is_degraded {
+            info!("Endpoint {} recovered", endpoint.name);
+        }
+
+        Ok(state)
+    }
+
+    /// Sends a cheap HEAD request to the upstream. Anything other than a
+    /// connection failure, timeout or 5xx counts as healthy.
+    async fn probe(&self, endpoint: &ApiEndpoint) -> CreateEndpointHealthCheckRequest {
+        let start = Instant::now();
+        let result = self.client
+            .head(&endpoint.upstream_url)
+            .header("x-forwarded-by", "august-credits")
+            .send()
+            .await;
+        let latency_ms = start.elapsed().as_millis() as i32;
+
+        let (healthy, status_code, error_message) = match result {
+            Ok(response) if response.status().is_server_error() => {
+                (false, Some(response.status().as_u16() as i32), Some(format!("HTTP {}", response.status().as_u16())))
+            }
+            Ok(response) => (true, Some(response.status().as_u16() as i32), None),
+            Err(e) => (false, None, Some(e.to_string())),
+        };
+
+        CreateEndpointHealthCheckRequest {
+            endpoint_id: endpoint.id,
+            healthy,
+            status_code,
+            latency_ms,
+            error_message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::models::{CreateEndpointRequest, UpdateEndpointRequest};
+    use httpmock::prelude::*;
+    use uuid::Uuid;
+
+    fn test_checker(failure_threshold: u32) -> HealthChecker {
+        let mut config = test_support::config();
+        config.health_check.failure_threshold = failure_threshold;
+        config.health_check.timeout_secs = 1;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        HealthChecker::new(&config, database)
+    }
+
+    fn test_endpoint(upstream_url: String) -> ApiEndpoint {
+        test_support::endpoint("probed", &upstream_url)
+    }
+
+    /// Tests that only server errors and connection failures count as unhealthy
+    #[tokio::test]
+    async fn test_probe_classifies_responses() {
+        let checker = test_checker(3);
+        let server = MockServer::start_async().await;
+        for (path, status) in [("/ok", 200), ("/missing", 404), ("/broken", 503)] {
+            server.mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD).path(path);
+                then.status(status);
+            }).await;
+        }
+
+        let check = checker.probe(&test_endpoint(server.url("/ok"))).await;
+        assert!(check.healthy);
+        assert_eq!(check.status_code, Some(200));
+
+        let check = checker.probe(&test_endpoint(server.url("/missing"))).await;
+        assert!(check.healthy);
+
+        let check = checker.probe(&test_endpoint(server.url("/broken"))).await;
+        assert!(!check.healthy);
+        assert_eq!(check.status_code, Some(503));
+        assert_eq!(check.error_message.as_deref(), Some("HTTP 503"));
+
+        let check = checker.probe(&test_endpoint("http://127.0.0.1:1".to_string())).await;
+        assert!(!check.healthy);
+        assert_eq!(check.status_code, None);
+        assert!(check.error_message.is_some());
+    }
+
+    /// Tests that repeated failures degrade an endpoint and a healthy probe clears it
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_endpoint_degrades_and_recovers() {
+        let checker = test_checker(2);
+        checker.database.migrate().await.unwrap();
+
+        let server = MockServer::start_async().await;
+        let broken = server.mock_async(|when, then| {
+            when.method(httpmock::Method::HEAD).path("/");
+            then.status(500);
+        }).await;
+
+        let user = checker.database.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = checker.database.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("health-{}", Uuid::new_v4().simple()),
+            upstream_url: server.base_url(),
+            price_per_request: "1000".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        let state = checker.check_endpoint(&endpoint).await.unwrap();
+        assert!(!state.is_degraded);
+        assert_eq!(state.consecutive_failures, 1);
+
+        let state = checker.check_endpoint(&endpoint).await.unwrap();
+        assert!(state.is_degraded && !state.was_degraded);
+        assert!(state.is_active);
+
+        let latest = checker.database.get_latest_endpoint_health(endpoint.id).await.unwrap().unwrap();
+        assert!(!latest.healthy);
+        assert_eq!(latest.status_code, Some(500));
+        let stored = checker.database.get_endpoint_by_id(endpoint.id).await.unwrap().unwrap();
+        assert!(stored.is_degraded);
+
+        broken.delete_async().await;
+        server.mock_async(|when, then| {
+            when.method(httpmock::Method::HEAD).path("/");
+            then.status(200);
+        }).await;
+
+        let state = checker.check_endpoint(&endpoint).await.unwrap();
+        assert!(state.was_degraded && !state.is_degraded);
+        assert_eq!(state.consecutive_failures, 0);
+
+        // With auto-deactivation the endpoint is switched off once degraded
+        let mut checker = checker;
+        checker.config.auto_deactivate = true;
+        checker.config.failure_threshold = 1;
+        checker.database.update_endpoint(endpoint.id, UpdateEndpointRequest {
+            upstream_url: Some("http://127.0.0.1:1".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        let endpoint = checker.database.get_endpoint_by_id(endpoint.id).await.unwrap().unwrap();
+
+        let state = checker.check_endpoint(&endpoint).await.unwrap();
+        assert!(state.is_degraded);
+        assert!(!state.is_active);
+    }
+}
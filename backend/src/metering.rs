@@ -5,78 +5,69 @@
 //! for the monetization platform.
 
 use crate::{
+    address,
+    blockchain::{GasEstimator, TxOperation},
     database::Database,
     error::{AppError, AppResult},
+    metrics::MetricsService,
     models::*,
+    pricing,
+    rate_limiter::{InMemoryRateLimiter, RateLimitPolicy, RateLimiter, WindowStatus},
+    system_config::SystemConfigService,
+    tx_queue,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, HeaderValue};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use ethers::types::{Address, H256, U256};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{atomic::Ordering, Arc},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::RwLock;
-use tracing::{debug, info, warn};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-/// Sliding window rate limiter for tracking request timestamps
-#[derive(Debug, Clone)]
-struct RateLimitWindow {
-    requests: Vec<u64>, // timestamps
-    limit: u32,
-    window_seconds: u32,
-}
+/// Age after which a balance hold that was never settled is released by the billing run
+const STALE_HOLD_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 
-impl RateLimitWindow {
-    /// Creates a new rate limiting window with specified limits
-    fn new(limit: u32, window_seconds: u32) -> Self {
-        Self {
-            requests: Vec::new(),
-            limit,
-            window_seconds,
-        }
-    }
+/// Number of SLA refunds credited per batch by the billing run
+const SLA_REFUND_BATCH_SIZE: i64 = 500;
 
-    /// Checks if a new request can be made within rate limits
-    fn can_make_request(&mut self) -> bool {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        // Remove old requests outside the window
-        self.requests.retain(|&timestamp| now - timestamp < self.window_seconds as u64);
-        
-        // Check if we can make another request
-        if self.requests.len() < self.limit as usize {
-            self.requests.push(now);
-            true
-        } else {
-            false
-        }
-    }
+/// Number of usage records claimed per batch by the billing run, and so the most
+/// billed in one batch transaction
+const BILLING_BATCH_SIZE: i64 = 100;
 
-    /// Returns the number of requests remaining in the current window
-    fn remaining_requests(&self) -> u32 {
-        self.limit.saturating_sub(self.requests.len() as u32)
-    }
+/// Number of endpoints broken out in a user's usage statistics
+const TOP_ENDPOINTS_LIMIT: i64 = 5;
 
-    /// Returns the timestamp when the rate limit window resets
-    fn reset_time(&self) -> Option<u64> {
-        self.requests.first().map(|&first| first + self.window_seconds as u64)
-    }
-}
+/// Webhook event sent when a user's calls first go past their monthly limit in a period
+pub const OVERAGE_STARTED_EVENT: &str = "usage.overage_started";
 
 /// Core metering service for usage tracking and rate limiting
 #[derive(Clone)]
 pub struct MeteringService {
     database: Arc<Database>,
-    // In-memory rate limiting cache
-    rate_limits: Arc<RwLock<HashMap<String, RateLimitWindow>>>,
+    rate_limiter: Arc<dyn RateLimiter>,
     // Default rate limits
     default_rate_limit: u32,
     default_window_seconds: u32,
+    default_burst_size: u32,
+    withdrawals: bool,
+    withdrawal_daily_limit: Option<CostAmount>,
+    amount_decimals: u32,
+    chain_ids: Vec<u64>,
+    billing: bool,
+    batch_billing: bool,
+    gas_estimator: Option<Arc<dyn GasEstimator>>,
+    batch_gas_budget: u64,
+    webhooks: bool,
+    system_config: Option<Arc<SystemConfigService>>,
 }
 
 impl MeteringService {
@@ -84,14 +75,96 @@ impl MeteringService {
     pub fn new(database: Arc<Database>) -> Self {
         Self {
             database,
-            rate_limits: Arc::new(RwLock::new(HashMap::new())),
+            rate_limiter: Arc::new(InMemoryRateLimiter::new()),
             default_rate_limit: 1000, // 1000 requests per hour by default
             default_window_seconds: 3600, // 1 hour
+            default_burst_size: 100,
+            withdrawals: false,
+            withdrawal_daily_limit: None,
+            amount_decimals: 0,
+            chain_ids: Vec::new(),
+            billing: false,
+            batch_billing: true,
+            gas_estimator: None,
+            batch_gas_budget: 0,
+            webhooks: false,
+            system_config: None,
         }
     }
 
-    /// Validates if a user can make a request within their rate limits
-    pub async fn check_rate_limit(&self, user_id: Uuid, endpoint_id: Uuid) -> AppResult<()> {
+    /// Keeps rate limit windows in the given limiter instead of process memory
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<dyn RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Sets the token bucket capacity of endpoints that do not choose their own
+    pub fn with_default_burst_size(mut self, default_burst_size: u32) -> Self {
+        self.default_burst_size = default_burst_size;
+        self
+    }
+
+    /// Enables withdrawals, queueing their payouts to be sent by the transaction queue
+    pub fn with_withdrawals(mut self, withdrawals: bool) -> Self {
+        self.withdrawals = withdrawals;
+        self
+    }
+
+    /// Caps how much each user may withdraw per UTC day
+    pub fn with_withdrawal_daily_limit(mut self, limit: Option<CostAmount>) -> Self {
+        self.withdrawal_daily_limit = limit;
+        self
+    }
+
+    /// Allows amounts down to `decimals` decimal places, as balances kept in an ERC-20
+    /// token are; amounts are whole wei otherwise
+    pub fn with_amount_decimals(mut self, decimals: u32) -> Self {
+        self.amount_decimals = decimals;
+        self
+    }
+
+    /// Sets the chains deposits are accepted on
+    pub fn with_chain_ids(mut self, chain_ids: Vec<u64>) -> Self {
+        self.chain_ids = chain_ids;
+        self
+    }
+
+    /// Queues closed periods' usage to be billed on-chain by the transaction queue
+    /// during billing runs
+    pub fn with_billing(mut self, billing: bool) -> Self {
+        self.billing = billing;
+        self
+    }
+
+    /// Chooses between one transaction per batch of usage and one per usage record
+    pub fn with_batch_billing(mut self, batch_billing: bool) -> Self {
+        self.batch_billing = batch_billing;
+        self
+    }
+
+    /// Splits batch billing transactions until each is estimated to use at most
+    /// `budget` gas; batches are only limited by size otherwise
+    pub fn with_batch_gas_budget(mut self, estimator: Arc<dyn GasEstimator>, budget: u64) -> Self {
+        self.gas_estimator = Some(estimator);
+        self.batch_gas_budget = budget;
+        self
+    }
+
+    /// Queues usage events, such as a user entering overage, for their webhooks
+    pub fn with_webhooks(mut self, webhooks: bool) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Lets the `default_rate_limit` runtime setting override the built-in default
+    pub fn with_system_config(mut self, system_config: Arc<SystemConfigService>) -> Self {
+        self.system_config = Some(system_config);
+        self
+    }
+
+    /// Validates if a user can make a request within their rate limits and
+    /// returns the limit status after counting it
+    pub async fn check_rate_limit(&self, user_id: Uuid, endpoint_id: Uuid) -> AppResult<RateLimitInfo> {
         // Get endpoint configuration
         let endpoint = self.database
             .get_endpoint_by_id(endpoint_id)
@@ -105,33 +178,130 @@ impl MeteringService {
             .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
         // Determine rate limit
-        let (limit, window) = self.get_rate_limit(&user, &endpoint);
+        let policy = self.get_rate_limit(&user, &endpoint, self.default_rate_limit().await?);
         
-        // Create cache key
-        let cache_key = format!("{}:{}", user_id, endpoint_id);
-        
-        // Check rate limit
-        let mut rate_limits = self.rate_limits.write().await;
-        let window_entry = rate_limits
-            .entry(cache_key.clone())
-            .or_insert_with(|| RateLimitWindow::new(limit, window));
-        
-        if !window_entry.can_make_request() {
-            let reset_time = window_entry.reset_time().unwrap_or(0);
-            return Err(AppError::RateLimit(format!(
-                "Rate limit exceeded. Limit: {} requests per {} seconds. Reset at: {}",
-                limit, window, reset_time
-            )));
+        let status = self.rate_limiter
+            .hit(user_id, endpoint_id, &policy)
+            .await
+            .map_err(|e| AppError::ExternalService(format!("Rate limiter unavailable: {:#}", e)))?;
+        let info = RateLimitInfo::new(&policy, status);
+
+        if !status.allowed {
+            return Err(AppError::RateLimitExceeded {
+                message: format!(
+                    "Rate limit exceeded. Limit: {} requests per {} seconds. Reset at: {}",
+                    policy.limit, policy.window_seconds, info.reset_time
+                ),
+                info,
+            });
         }
 
         debug!(
             "Rate limit check passed for user {} on endpoint {} ({} remaining)",
-            user_id, endpoint_id, window_entry.remaining_requests()
+            user_id, endpoint_id, info.remaining
         );
 
+        Ok(info)
+    }
+
+    /// Rejects a request once the user has used up their monthly request allowance,
+    /// unless their overage policy admits it, returning their standing counting this
+    /// request; users without a limit are not looked up
+    pub async fn check_monthly_limit(
+        &self,
+        user_id: Uuid,
+        monthly_limit: Option<i64>,
+        policy: &OveragePolicy,
+    ) -> AppResult<Option<MonthlyUsage>> {
+        let Some(limit) = monthly_limit else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        let billing_period = now.format("%Y-%m").to_string();
+        let period_end = start_of_next_month(now);
+        let used = self.database.get_user_request_count(user_id, &billing_period).await?;
+        let overage_rate = match admit_monthly(policy, used, limit)? {
+            MonthlyAdmission::Within => None,
+            MonthlyAdmission::Overage(rate) => {
+                self.start_overage(user_id, &billing_period, limit, policy).await;
+                Some(rate)
+            }
+            MonthlyAdmission::Refused => {
+                warn!("Monthly limit exceeded for user {}: {}/{}", user_id, used, limit);
+                return Err(AppError::MonthlyLimitExceeded { current: used, limit, period_end });
+            }
+        };
+
+        Ok(Some(MonthlyUsage {
+            overage_rate,
+            ..MonthlyUsage::new(billing_period, monthly_limit, used + 1, period_end)
+        }))
+    }
+
+    /// Notes that a user went past their monthly limit this period and, the first
+    /// time, tells their webhooks; the call is admitted even if this fails
+    async fn start_overage(&self, user_id: Uuid, billing_period: &str, limit: i64, policy: &OveragePolicy) {
+        match self.database.start_overage(user_id, billing_period).await {
+            Ok(true) => info!("User {} went past their monthly limit of {} for {}", user_id, limit, billing_period),
+            Ok(false) => return,
+            Err(e) => {
+                warn!("Failed to record overage for user {}: {}", user_id, e);
+                return;
+            }
+        }
+
+        if !self.webhooks {
+            return;
+        }
+        let payload = serde_json::json!({
+            "user_id": user_id,
+            "billing_period": billing_period,
+            "monthly_limit": limit,
+            "overage_policy": policy,
+        });
+        if let Err(e) = self.database.enqueue_webhook_event(user_id, OVERAGE_STARTED_EVENT, &payload).await {
+            error!("Failed to queue overage webhook for user {}: {}", user_id, e);
+        }
+    }
+
+    /// Rejects a request once the consumer has used up the endpoint's monthly quota,
+    /// or the quota its owner granted them instead; endpoints without a quota are not
+    /// looked up
+    pub async fn check_consumer_quota(&self, user_id: Uuid, endpoint: &ApiEndpoint) -> AppResult<()> {
+        let Some(endpoint_quota) = endpoint.consumer_monthly_quota else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        let billing_period = now.format("%Y-%m").to_string();
+        let usage = self.database.get_consumer_quota_usage(endpoint.id, user_id, &billing_period).await?;
+        let quota = if usage.overridden { usage.override_quota } else { Some(endpoint_quota) };
+        if let Some(limit) = quota.filter(|limit| usage.used >= *limit) {
+            warn!("Endpoint quota exceeded for user {} on {}: {}/{}", user_id, endpoint.id, usage.used, limit);
+            return Err(AppError::ConsumerQuotaExceeded {
+                current: usage.used,
+                limit,
+                period_end: start_of_next_month(now),
+            });
+        }
+
         Ok(())
     }
 
+    /// Returns how much of their monthly request allowance a user has used this period,
+    /// and what they used past it
+    pub async fn get_monthly_usage(&self, user_id: Uuid, monthly_limit: Option<i64>) -> AppResult<MonthlyUsage> {
+        let now = Utc::now();
+        let billing_period = now.format("%Y-%m").to_string();
+        let used = self.database.get_user_request_count(user_id, &billing_period).await?;
+        let overage = self.database.get_overage_usage(user_id, &billing_period).await?;
+        Ok(MonthlyUsage {
+            overage,
+            ..MonthlyUsage::new(billing_period, monthly_limit, used, start_of_next_month(now))
+        })
+    }
+
     /// Record a request for billing and analytics
     /// Records a completed API request for billing and analytics
     pub async fn record_request(
@@ -157,6 +327,206 @@ impl MeteringService {
         Ok(())
     }
 
+    /// Adds a billed call to the consumer's month-to-date usage of an endpoint,
+    /// charging `share` of its price
+    ///
+    /// `price` gives the call's list price after a number of billable units already
+    /// used this billing period, so volume tiers are charged from the exact count even
+    /// when concurrent calls cross a tier boundary. Subscribers draw on their plan's
+    /// allowance instead and pay its overage price past it. Usage made with a named
+    /// API key is attributed to it as well. A call admitted past the user's monthly
+    /// limit carries the `overage_rate` its price is multiplied by and is totalled as
+    /// overage. Returns the cost recorded.
+    pub async fn record_usage<F>(
+        &self,
+        user_id: Uuid,
+        endpoint_id: Uuid,
+        api_key_id: Option<Uuid>,
+        share: Decimal,
+        overage_rate: Option<Decimal>,
+        price: F,
+    ) -> AppResult<CostBreakdown>
+    where
+        F: Fn(i64) -> AppResult<CostBreakdown>,
+    {
+        let now = Utc::now();
+        let billing_period = now.format("%Y-%m").to_string();
+        let overage = overage_rate.is_some();
+        let share = share * overage_rate.unwrap_or(Decimal::ONE);
+
+        let units = price(0)?.billable_units();
+        let allowance = self.database
+            .use_subscription_units(user_id, endpoint_id, units, now)
+            .await?;
+        let breakdown = self.database
+            .record_billable_usage(user_id, endpoint_id, api_key_id, &billing_period, overage, |prior_units| {
+                Ok(usage_cost(&price, prior_units, allowance.as_ref(), share)?)
+            })
+            .await?;
+        Ok(breakdown)
+    }
+
+    /// Describes the usage a proxied call adds when it is logged, charging `share` of
+    /// the price `price` gives after a number of billable units already used this
+    /// billing period
+    ///
+    /// Priced as by [`Self::record_usage`] once the usage is locked, inside the
+    /// transaction that logs the call.
+    pub fn usage_charge<F>(
+        api_key_id: Option<Uuid>,
+        share: Decimal,
+        overage_rate: Option<Decimal>,
+        price: F,
+    ) -> AppResult<UsageCharge>
+    where
+        F: Fn(i64) -> AppResult<CostBreakdown> + Send + Sync + 'static,
+    {
+        let units = price(0)?.billable_units();
+        let share = share * overage_rate.unwrap_or(Decimal::ONE);
+        Ok(UsageCharge {
+            api_key_id,
+            overage: overage_rate.is_some(),
+            units,
+            price: Arc::new(move |prior_units, allowance| Ok(usage_cost(&price, prior_units, allowance, share)?)),
+        })
+    }
+
+    /// Subscribes a consumer to an endpoint plan, debiting the first month from their
+    /// balance up front
+    ///
+    /// Refuses with a payment error when the available balance does not cover the
+    /// first period's price.
+    pub async fn subscribe(&self, user_id: Uuid, plan: &EndpointPlan) -> AppResult<EndpointSubscription> {
+        if !plan.is_active {
+            return Err(AppError::Validation("Plan is no longer offered".to_string()));
+        }
+        if self.database.get_active_subscription(user_id, plan.endpoint_id).await?.is_some() {
+            return Err(AppError::Conflict("Already subscribed to this endpoint".to_string()));
+        }
+        let price = CostAmount::from_str(&plan.monthly_price)
+            .map_err(|_| AppError::Internal("Invalid pricing configuration".to_string()))?;
+
+        let now = Utc::now();
+        match self.database.create_subscription(user_id, plan, price, now, now + Months::new(1)).await? {
+            SubscriptionOutcome::Active(subscription) => {
+                info!("User {} subscribed to plan {} ({})", user_id, plan.name, plan.id);
+                Ok(*subscription)
+            }
+            SubscriptionOutcome::Insufficient { available } => Err(AppError::Payment(format!(
+                "Insufficient balance: {} required for the first period, {} available",
+                price, available
+            ))),
+        }
+    }
+
+    /// Cancels a consumer's subscription to an endpoint; it stops renewing but keeps
+    /// applying until the end of the period already paid for
+    pub async fn cancel_subscription(&self, user_id: Uuid, endpoint_id: Uuid) -> AppResult<EndpointSubscription> {
+        self.database.cancel_subscription(user_id, endpoint_id).await?
+            .ok_or_else(|| AppError::NotFound("No active subscription to this endpoint".to_string()))
+    }
+
+    /// Holds enough of the consumer's balance to pay for a call before it is forwarded
+    ///
+    /// `price` gives the call's smallest list price after a number of billable units
+    /// already used this billing period; subscribers are quoted their plan's price
+    /// for it instead. Calls quoted nothing need no hold. Refuses the call with a
+    /// payment error naming the shortfall when the available balance is too low.
+    pub async fn hold_funds<F>(&self, user_id: Uuid, endpoint_id: Uuid, request_id: &str, price: F) -> AppResult<Option<Uuid>>
+    where
+        F: Fn(i64) -> AppResult<CostBreakdown>,
+    {
+        let now = Utc::now();
+        let billing_period = now.format("%Y-%m").to_string();
+
+        let subscription = self.database.get_active_subscription(user_id, endpoint_id).await?
+            .filter(|subscription| subscription.current_period_end > now);
+        let plan = match &subscription {
+            Some(subscription) => self.database.get_endpoint_plan(endpoint_id, subscription.plan_id).await?,
+            None => None,
+        };
+        let quote = match (subscription, plan) {
+            (Some(subscription), Some(plan)) => pricing::subscription_cost(price(0)?, &SubscriptionAllowance {
+                used_before: subscription.requests_used,
+                included_requests: plan.included_requests,
+                overage_price: plan.overage_price,
+            })?,
+            _ => {
+                let prior_units = self.database
+                    .get_usage_record(user_id, endpoint_id, &billing_period)
+                    .await?
+                    .map_or(0, |record| record.request_count);
+                price(prior_units)?
+            }
+        };
+
+        let amount = quote.total.as_decimal();
+        if amount.is_zero() {
+            return Ok(None);
+        }
+
+        match self.database.reserve_balance(user_id, &quote.total.to_string(), request_id).await? {
+            BalanceReservation::Held(hold_id) => Ok(Some(hold_id)),
+            BalanceReservation::Insufficient { available } => {
+                let shortfall = Decimal::from_str(&available).map_or(amount, |available| amount - available);
+                warn!("Insufficient balance for user {}: {} required, {} available", user_id, quote.total, available);
+                Err(AppError::Payment(format!(
+                    "Insufficient balance: {} required, {} available ({} short)",
+                    quote.total, available, shortfall.normalize()
+                )))
+            }
+        }
+    }
+
+    /// Debits the cost a call was actually charged from the consumer's balance,
+    /// replacing the hold placed for it
+    pub async fn settle_funds(&self, user_id: Uuid, hold: Option<Uuid>, cost: CostAmount, request_id: &str) {
+        if let Err(e) = self.database.charge_balance(user_id, hold, &cost.to_string(), request_id).await {
+            error!("Failed to charge balance for request {}: {}", request_id, e);
+        }
+    }
+
+    /// Releases the hold placed for a call that was never charged
+    pub async fn release_funds(&self, hold: Option<Uuid>) {
+        if let Some(hold_id) = hold {
+            if let Err(e) = self.database.release_balance_hold(hold_id).await {
+                error!("Failed to release balance hold: {}", e);
+            }
+        }
+    }
+
+    /// Flags a billed call for refund when it breached its endpoint's SLA; the billing
+    /// run credits the consumer back
+    pub async fn flag_sla_breach(
+        &self,
+        user_id: Uuid,
+        endpoint: &ApiEndpoint,
+        status_code: i32,
+        response_time_ms: i32,
+        cost: CostAmount,
+        request_id: &str,
+    ) {
+        let Some(breach) = sla_breach(endpoint, status_code, response_time_ms) else {
+            return;
+        };
+        if cost.is_zero() {
+            return;
+        }
+
+        let billing_period = Utc::now().format("%Y-%m").to_string();
+        if let Err(e) = self.database
+            .create_sla_refund(user_id, endpoint.id, &billing_period, request_id, &cost.to_string(), breach)
+            .await
+        {
+            error!("Failed to flag SLA refund for request {}: {}", request_id, e);
+        }
+    }
+
+    /// Drops the cached rate limit windows of a deleted endpoint
+    pub async fn forget_endpoint(&self, endpoint_id: Uuid) {
+        self.rate_limiter.forget_endpoint(endpoint_id).await;
+    }
+
     /// Get rate limit information for a user/endpoint combination
     /// Retrieves current rate limit status for a user-endpoint combination
     pub async fn get_rate_limit_info(
@@ -174,26 +544,13 @@ impl MeteringService {
             .await?
             .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-        let (limit, window) = self.get_rate_limit(&user, &endpoint);
-        let cache_key = format!("{}:{}", user_id, endpoint_id);
-        
-        let rate_limits = self.rate_limits.read().await;
-        let remaining = rate_limits
-            .get(&cache_key)
-            .map(|w| w.remaining_requests())
-            .unwrap_or(limit);
-        
-        let reset_time = rate_limits
-            .get(&cache_key)
-            .and_then(|w| w.reset_time())
-            .unwrap_or(0);
+        let policy = self.get_rate_limit(&user, &endpoint, self.default_rate_limit().await?);
+        let status = self.rate_limiter
+            .peek(user_id, endpoint_id, &policy)
+            .await
+            .map_err(|e| AppError::ExternalService(format!("Rate limiter unavailable: {:#}", e)))?;
 
-        Ok(RateLimitInfo {
-            limit,
-            remaining,
-            reset_time,
-            window_seconds: window,
-        })
+        Ok(RateLimitInfo::new(&policy, status))
     }
 
     /// Get usage statistics for a user
@@ -210,39 +567,171 @@ impl MeteringService {
             .await?;
 
         let total_requests: i64 = usage_records.iter().map(|r| r.request_count).sum();
-        let total_cost = usage_records
-            .iter()
-            .map(|r| r.total_cost.parse::<f64>().unwrap_or(0.0))
-            .sum::<f64>()
-            .to_string();
+        let total_cost = CostAmount::checked_sum(usage_records.iter().map(|r| r.total_cost))
+            .ok_or_else(|| AppError::Internal("Usage cost total overflowed".to_string()))?;
+        let unique_endpoints = self.database
+            .count_user_endpoints(user_id, start_date, end_date)
+            .await?;
+        let top_endpoints = self.database
+            .get_user_top_endpoints(user_id, start_date, end_date, TOP_ENDPOINTS_LIMIT)
+            .await?;
 
         Ok(UserUsageStats {
             user_id,
-            period: format!("{:?}", period),
+            period: period.name().to_string(),
+            total_requests,
+            total_cost,
+            unique_endpoints: unique_endpoints as u32,
+            top_endpoints,
+            start_date,
+            end_date,
+        })
+    }
+
+    /// Gets usage statistics for one of a user's named API keys over a time period
+    pub async fn get_api_key_usage(
+        &self,
+        user_id: Uuid,
+        api_key_id: Uuid,
+        period: UsagePeriod,
+    ) -> AppResult<ApiKeyUsageStats> {
+        let api_key = self.database
+            .get_api_key(api_key_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("API key not found".to_string()))?;
+        let (start_date, end_date) = self.get_period_dates(period);
+
+        let endpoints = self.database
+            .get_api_key_usage(api_key_id, start_date, end_date)
+            .await?;
+
+        let total_requests: i64 = endpoints.iter().map(|e| e.total_requests).sum();
+        let total_cost = CostAmount::checked_sum(endpoints.iter().map(|e| e.total_cost))
+            .ok_or_else(|| AppError::Internal("Usage cost total overflowed".to_string()))?;
+
+        Ok(ApiKeyUsageStats {
+            api_key_id,
+            name: api_key.name,
+            period: period.name().to_string(),
             total_requests,
             total_cost,
-            unique_endpoints: usage_records.len() as u32,
+            endpoints,
             start_date,
             end_date,
         })
     }
 
     /// Retrieves the current balance for a user account
-    pub async fn get_user_balance(&self, _user_id: Uuid) -> AppResult<crate::models::UserBalance> {
-        // Placeholder implementation
-        Err(AppError::Database(anyhow::anyhow!("Not implemented")))
+    pub async fn get_user_balance(&self, user_id: Uuid) -> AppResult<crate::models::UserBalance> {
+        Ok(self.database.get_user_balance(user_id).await?)
     }
 
-    /// Processes a balance deposit for a user account
-    pub async fn deposit_balance(&self, _user_id: Uuid, _payload: crate::models::DepositRequest) -> AppResult<crate::models::DepositResponse> {
-        // Placeholder implementation
-        Err(AppError::Database(anyhow::anyhow!("Not implemented")))
+    /// Records a deposit the user made on-chain, pending until its transfer's event
+    /// is applied
+    ///
+    /// The balance is only credited by the contract event, with the amount actually
+    /// transferred; the report lets the user follow the deposit in the meantime.
+    pub async fn deposit_balance(&self, user_id: Uuid, payload: crate::models::DepositRequest) -> AppResult<crate::models::DepositResponse> {
+        if !self.chain_ids.contains(&payload.chain_id) {
+            return Err(AppError::Validation(format!("Deposits are not accepted on chain {}", payload.chain_id)));
+        }
+        let amount = payload.amount.parse::<CostAmount>().ok()
+            .filter(|amount| amount.fits_decimals(self.amount_decimals) && !amount.is_zero() && !amount.is_sign_negative())
+            .ok_or_else(|| AppError::Validation(format!(
+                "Deposit amount must be positive, with at most {} decimal places: {}",
+                self.amount_decimals, payload.amount
+            )))?;
+        let transaction_hash = H256::from_str(payload.transaction_hash.trim())
+            .map_err(|_| AppError::Validation(format!("Invalid transaction hash: {}", payload.transaction_hash)))?;
+
+        let deposit = self.database
+            .report_deposit(user_id, &amount.to_string(), payload.chain_id, &format!("{:?}", transaction_hash))
+            .await?;
+        info!("Deposit {:?} on chain {} reported by user {}", transaction_hash, payload.chain_id, user_id);
+        Ok(deposit)
     }
 
     /// Processes a balance withdrawal for a user account
-    pub async fn withdraw_balance(&self, _user_id: Uuid, _payload: crate::models::WithdrawRequest) -> AppResult<crate::models::WithdrawResponse> {
-        // Placeholder implementation
-        Err(AppError::Database(anyhow::anyhow!("Not implemented")))
+    ///
+    /// The amount is debited from the balance in the transaction that queues its
+    /// payout, so it cannot be spent twice; the transaction queue sends the payout,
+    /// retrying it as it does billing, and credits back one it gives up on. Funds held for calls in flight cannot be withdrawn, while calls already
+    /// charged have been debited from the balance as they were made.
+    pub async fn withdraw_balance(&self, user_id: Uuid, payload: crate::models::WithdrawRequest) -> AppResult<crate::models::WithdrawResponse> {
+        if !self.withdrawals {
+            return Err(AppError::ExternalService("Withdrawals are not available".to_string()));
+        }
+        let amount = payload.amount.parse::<CostAmount>().ok()
+            .filter(|amount| amount.fits_decimals(self.amount_decimals) && !amount.is_zero() && !amount.is_sign_negative())
+            .ok_or_else(|| AppError::Validation(format!(
+                "Withdrawal amount must be positive, with at most {} decimal places: {}",
+                self.amount_decimals, payload.amount
+            )))?;
+        let value = amount.to_base_units(self.amount_decimals)
+            .ok_or_else(|| AppError::Validation(format!("Withdrawal amount is too large: {}", amount)))?;
+        let destination = parse_destination_address(&payload.destination_address)?;
+        let destination_address = address::checksummed(destination);
+
+        let mut tx = self.database.begin_transaction().await?;
+        let outcome = self.database
+            .begin_withdrawal_in(&mut tx, user_id, amount, &destination_address, self.withdrawal_daily_limit)
+            .await?;
+        let (transaction_id, created_at) = match outcome {
+            WithdrawalOutcome::Pending { transaction_id, created_at } => (transaction_id, created_at),
+            WithdrawalOutcome::Insufficient { available } => {
+                return Err(AppError::Payment(format!(
+                    "Insufficient balance: {} requested, {} available",
+                    amount, available
+                )));
+            }
+            WithdrawalOutcome::DailyLimitExceeded { withdrawn_today, limit } => {
+                return Err(AppError::RateLimit(format!(
+                    "Daily withdrawal limit of {} reached: {} already withdrawn today",
+                    limit, withdrawn_today
+                )));
+            }
+        };
+        let payout = TxOperation::Payout { withdrawal_id: transaction_id, destination, amount: value };
+        tx_queue::enqueue(&self.database, &mut tx, &payout, &[]).await?;
+        tx.commit().await.context("Failed to commit withdrawal")?;
+        info!("Withdrawal {} of {} for user {} to {}", transaction_id, amount, user_id, destination_address);
+
+        Ok(crate::models::WithdrawResponse {
+            transaction_id,
+            amount: amount.to_string(),
+            destination_address,
+            status: TransactionStatus::Pending,
+            created_at,
+        })
+    }
+
+    /// Credits or debits a user's balance on behalf of an administrator; the reason is
+    /// written to the audit log alongside the ledger entry
+    ///
+    /// Returns `None` if the user does not exist.
+    pub async fn adjust_balance(
+        &self,
+        user_id: Uuid,
+        actor: &AuditActor,
+        payload: BalanceAdjustmentRequest,
+    ) -> AppResult<Option<LedgerEntry>> {
+        let reason = crate::auth::validate_admin_reason(&payload.reason)?;
+        let amount = payload.amount.parse::<CostAmount>().ok()
+            .filter(|amount| amount.fits_decimals(self.amount_decimals) && !amount.is_zero() && !amount.is_sign_negative())
+            .ok_or_else(|| AppError::Validation(format!(
+                "Adjustment amount must be positive, with at most {} decimal places: {}",
+                self.amount_decimals, payload.amount
+            )))?;
+        let amount = match payload.direction {
+            AdjustmentDirection::Credit => amount,
+            AdjustmentDirection::Debit => CostAmount::new(-amount.as_decimal()),
+        };
+
+        let entry = self.database.adjust_balance(user_id, amount, actor, reason).await?;
+        if entry.is_some() {
+            warn!("Balance of user {} adjusted by {} by {:?}", user_id, amount, actor.id);
+        }
+        Ok(entry)
     }
 
     /// Get usage statistics for an endpoint
@@ -259,54 +748,91 @@ impl MeteringService {
             .await?;
 
         let total_requests: i64 = usage_records.iter().map(|r| r.request_count).sum();
-        let total_revenue = usage_records
-            .iter()
-            .map(|r| r.total_cost.parse::<f64>().unwrap_or(0.0))
-            .sum::<f64>()
-            .to_string();
+        let total_revenue = CostAmount::checked_sum(usage_records.iter().map(|r| r.total_cost))
+            .ok_or_else(|| AppError::Internal("Usage revenue total overflowed".to_string()))?;
+        let unique_users = self.database
+            .count_endpoint_users(endpoint_id, start_date, end_date)
+            .await?;
 
         Ok(EndpointUsageStats {
             endpoint_id,
-            period: format!("{:?}", period),
+            period: period.name().to_string(),
             total_requests,
             total_revenue,
-            unique_users: usage_records.len() as u32,
+            unique_users: unique_users as u32,
             start_date,
             end_date,
         })
     }
 
     /// Clean up old rate limit entries
-    /// Removes expired rate limit entries from memory to prevent memory leaks
+    /// Removes expired rate limit entries from memory to prevent memory leaks; windows
+    /// kept in Redis expire on their own
     pub async fn cleanup_rate_limits(&self) {
-        let mut rate_limits = self.rate_limits.write().await;
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        rate_limits.retain(|_, window| {
-            window.requests.retain(|&timestamp| now - timestamp < window.window_seconds as u64);
-            !window.requests.is_empty()
-        });
-        
-        debug!("Cleaned up rate limit cache, {} entries remaining", rate_limits.len());
+        self.rate_limiter.cleanup().await;
+    }
+
+    /// Cleans up rate limits on the given interval until the process exits, reporting
+    /// how many entries remain in memory as the `rate_limit_cache_entries` gauge
+    pub fn spawn_rate_limit_cleanup(&self, interval: Duration, metrics: Arc<MetricsService>) -> JoinHandle<()> {
+        let metering = self.clone();
+        tokio::spawn(async move {
+            let cache_entries = metrics.gauge("rate_limit_cache_entries").await;
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                metering.cleanup_rate_limits().await;
+                cache_entries.store(metering.rate_limiter.cached_entries().await as i64, Ordering::Relaxed);
+            }
+        })
+    }
+
+    /// Runs the billing cycle on the given interval until the process exits
+    pub fn spawn_billing(&self, interval: Duration) -> JoinHandle<()> {
+        let metering = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = metering.process_billing(metering.database.clone()).await {
+                    error!("Scheduled billing run failed: {:#}", e);
+                }
+            }
+        })
+    }
+
+    /// Limit applied to endpoints without their own, from runtime settings when set
+    async fn default_rate_limit(&self) -> AppResult<u32> {
+        let configured = match &self.system_config {
+            Some(system_config) => system_config.default_rate_limit().await?,
+            None => None,
+        };
+        Ok(configured.unwrap_or(self.default_rate_limit))
     }
 
     /// Get effective rate limit for a user/endpoint combination
     /// Determines rate limits for a user-endpoint combination based on tier and overrides
-    fn get_rate_limit(&self, user: &User, endpoint: &ApiEndpoint) -> (u32, u32) {
+    fn get_rate_limit(&self, user: &User, endpoint: &ApiEndpoint, default_limit: u32) -> RateLimitPolicy {
         // Priority: user override > endpoint limit > default
         let limit = user.rate_limit_override
             .map(|l| l as u32)
             .or_else(|| endpoint.rate_limit.map(|l| l as u32))
-            .unwrap_or(self.default_rate_limit);
+            .unwrap_or(default_limit);
         
         let window = endpoint.rate_limit_window
             .map(|w| w as u32)
             .unwrap_or(self.default_window_seconds);
         
-        (limit, window)
+        let burst = endpoint.rate_limit_burst
+            .map(|b| b as u32)
+            .unwrap_or(self.default_burst_size);
+        
+        RateLimitPolicy {
+            algorithm: endpoint.rate_limit_algorithm,
+            limit,
+            window_seconds: window,
+            burst,
+        }
     }
 
     /// Record metrics for analytics
@@ -334,39 +860,173 @@ impl MeteringService {
     }
 
     /// Get date range for a usage period
-    /// Calculates start and end dates for a given usage period
+    /// Calculates the start and exclusive end of a usage period as of now
     fn get_period_dates(&self, period: UsagePeriod) -> (chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>) {
-        let now = chrono::Utc::now();
-        
-        match period {
-            UsagePeriod::Hour => {
-                let start = now - chrono::Duration::hours(1);
-                (start, now)
-            }
-            UsagePeriod::Day => {
-                let start = now - chrono::Duration::days(1);
-                (start, now)
-            }
-            UsagePeriod::Week => {
-                let start = now - chrono::Duration::weeks(1);
-                (start, now)
-            }
-            UsagePeriod::Month => {
-                let start = now - chrono::Duration::days(30);
-                (start, now)
-            }
-        }
+        period.bounds(Utc::now())
     }
 }
 
+/// Charges `share` of a call's price: against the subscription allowance it drew on
+/// when there is one, otherwise at its list price after `prior_units`
+fn usage_cost<F>(price: &F, prior_units: i64, allowance: Option<&SubscriptionAllowance>, share: Decimal) -> AppResult<CostBreakdown>
+where
+    F: Fn(i64) -> AppResult<CostBreakdown>,
+{
+    let breakdown = match allowance {
+        Some(allowance) => pricing::subscription_cost(price(0)?, allowance)?,
+        None => price(prior_units)?,
+    };
+    breakdown.scaled(share)
+}
+
+/// Returns midnight UTC on the first day of the month after `now`, when monthly limits reset
+pub fn start_of_next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = if now.month() == 12 {
+        (now.year() + 1, 1)
+    } else {
+        (now.year(), now.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+}
+
+/// How a call is admitted against its user's monthly request limit
+#[derive(Debug, PartialEq)]
+enum MonthlyAdmission {
+    Within,
+    /// Past the limit, charged its price times the rate
+    Overage(Decimal),
+    Refused,
+}
+
+/// Decides whether a call can be made after `used` of a `limit` allowance under
+/// the user's overage policy
+fn admit_monthly(policy: &OveragePolicy, used: i64, limit: i64) -> AppResult<MonthlyAdmission> {
+    if used < limit {
+        return Ok(MonthlyAdmission::Within);
+    }
+
+    Ok(match policy {
+        OveragePolicy::Block => MonthlyAdmission::Refused,
+        OveragePolicy::AllowWithOverageRate { multiplier } => {
+            let rate = Decimal::from_str(multiplier)
+                .ok()
+                .filter(|rate| !rate.is_sign_negative())
+                .ok_or_else(|| AppError::Internal("Invalid overage policy".to_string()))?;
+            MonthlyAdmission::Overage(rate)
+        }
+        OveragePolicy::GraceRequests { requests } if used < limit.saturating_add(*requests) => {
+            MonthlyAdmission::Overage(Decimal::ONE)
+        }
+        OveragePolicy::GraceRequests { .. } => MonthlyAdmission::Refused,
+    })
+}
+
 /// Rate limit information
 /// Current rate limit status information for API responses
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitInfo {
     pub limit: u32,
     pub remaining: u32,
     pub reset_time: u64,
     pub window_seconds: u32,
+    pub algorithm: RateLimitAlgorithm,
+}
+
+impl RateLimitInfo {
+    fn new(policy: &RateLimitPolicy, status: WindowStatus) -> Self {
+        Self {
+            limit: policy.limit,
+            remaining: status.remaining,
+            reset_time: status.reset_time,
+            window_seconds: policy.window_seconds,
+            algorithm: policy.algorithm,
+        }
+    }
+
+    /// Seconds until the window resets, at least 1 so clients always back off
+    pub fn retry_after(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.reset_time.saturating_sub(now).max(1)
+    }
+
+    /// Adds X-RateLimit-Limit, X-RateLimit-Remaining and X-RateLimit-Reset
+    /// (Unix seconds) to a response
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(self.reset_time));
+    }
+}
+
+/// A user's standing against their monthly request allowance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonthlyUsage {
+    pub billing_period: String,
+    pub limit: Option<i64>, // None when the user has no monthly limit
+    pub used: i64,
+    pub remaining: Option<i64>,
+    pub period_end: DateTime<Utc>,
+    /// Usage past the limit this period, if the user's overage policy admitted any
+    #[serde(default)]
+    pub overage: Option<OverageUsage>,
+    /// Multiplier on the price of a call admitted past the limit
+    #[serde(skip)]
+    pub overage_rate: Option<Decimal>,
+}
+
+impl MonthlyUsage {
+    fn new(billing_period: String, limit: Option<i64>, used: i64, period_end: DateTime<Utc>) -> Self {
+        Self {
+            billing_period,
+            limit,
+            used,
+            remaining: limit.map(|limit| (limit - used).max(0)),
+            period_end,
+            overage: None,
+            overage_rate: None,
+        }
+    }
+
+    /// Adds X-AugustCredits-Monthly-Limit, X-AugustCredits-Monthly-Used and
+    /// X-AugustCredits-Monthly-Remaining to a response when the user has a limit
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let (Some(limit), Some(remaining)) = (self.limit, self.remaining) else {
+            return;
+        };
+        headers.insert("x-augustcredits-monthly-limit", HeaderValue::from(limit));
+        headers.insert("x-augustcredits-monthly-used", HeaderValue::from(self.used));
+        headers.insert("x-augustcredits-monthly-remaining", HeaderValue::from(remaining));
+    }
+}
+
+/// Usage limit standing after a call was admitted, reported on its response
+#[derive(Debug, Clone)]
+pub struct UsageLimits {
+    pub rate_limit: RateLimitInfo,
+    pub monthly: Option<MonthlyUsage>,
+}
+
+impl UsageLimits {
+    /// Multiplier on the call's price when it was admitted past the monthly limit
+    pub fn overage_rate(&self) -> Option<Decimal> {
+        self.monthly.as_ref().and_then(|monthly| monthly.overage_rate)
+    }
+
+    /// Adds the rate limit headers and, for users with a monthly limit, the monthly
+    /// quota headers to a response
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        self.rate_limit.apply_headers(headers);
+        if let Some(monthly) = &self.monthly {
+            monthly.apply_headers(headers);
+        }
+    }
 }
 
 /// User usage statistics
@@ -376,8 +1036,24 @@ pub struct UserUsageStats {
     pub user_id: Uuid,
     pub period: String,
     pub total_requests: i64,
-    pub total_cost: String,
+    pub total_cost: CostAmount,
     pub unique_endpoints: u32,
+    /// Endpoints with the most requests, busiest first
+    pub top_endpoints: Vec<EndpointUsageSummary>,
+    pub start_date: chrono::DateTime<chrono::Utc>,
+    pub end_date: chrono::DateTime<chrono::Utc>,
+}
+
+/// Usage statistics for a single named API key over a time period
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyUsageStats {
+    pub api_key_id: Uuid,
+    pub name: String,
+    pub period: String,
+    pub total_requests: i64,
+    pub total_cost: CostAmount,
+    /// Per-endpoint breakdown, busiest first
+    pub endpoints: Vec<EndpointUsageSummary>,
     pub start_date: chrono::DateTime<chrono::Utc>,
     pub end_date: chrono::DateTime<chrono::Utc>,
 }
@@ -389,41 +1065,455 @@ pub struct EndpointUsageStats {
     pub endpoint_id: Uuid,
     pub period: String,
     pub total_requests: i64,
-    pub total_revenue: String,
+    pub total_revenue: CostAmount,
     pub unique_users: u32,
     pub start_date: chrono::DateTime<chrono::Utc>,
     pub end_date: chrono::DateTime<chrono::Utc>,
 }
 
-/// Usage period enumeration
+/// Parses a withdrawal's destination, refusing mistyped and zero addresses
+fn parse_destination_address(address: &str) -> AppResult<Address> {
+    let destination = address::parse_field("destination_address", address)?;
+    if destination.is_zero() {
+        return Err(AppError::Validation("Cannot withdraw to the zero address".to_string()));
+    }
+    Ok(destination)
+}
+
+/// Running totals of a billing run
+#[derive(Default)]
+struct BillingRun {
+    summary: BillingRunSummary,
+    users: HashSet<Uuid>,
+    /// Usage that could not be queued, left pending for the next run
+    skip: Vec<Uuid>,
+}
+
+impl BillingRun {
+    /// Marks a user's period as being billed once its usage is queued, in the same
+    /// transaction, so a rolled back batch leaves no record in processing
+    async fn queued(
+        &mut self,
+        db: &Database,
+        tx: &mut Transaction<'_, Postgres>,
+        group: &[BillableUsage],
+    ) -> Result<()> {
+        let record = db.create_billing_record_in(tx, group[0].user_id, &group[0].billing_period).await?;
+        db.update_billing_status_in(tx, record.id, BillingStatus::Processing, None).await?;
+
+        self.users.insert(group[0].user_id);
+        for record in group {
+            self.summary.records_billed += 1;
+            self.summary.total_requests += record.request_count;
+            self.summary.total_cost = self.summary.total_cost
+                .checked_add(record.total_cost)
+                .context("Billed total overflowed")?;
+        }
+        Ok(())
+    }
+
+    async fn failed(
+        &mut self,
+        db: &Database,
+        tx: &mut Transaction<'_, Postgres>,
+        unbilled: &[BillableUsage],
+        error: String,
+    ) -> Result<()> {
+        let (user_id, billing_period) = (unbilled[0].user_id, unbilled[0].billing_period.clone());
+        warn!("Billing {} for user {} failed: {}", billing_period, user_id, error);
+        db.update_billing_record(tx, user_id, &billing_period, Err(&error)).await?;
+        self.skip.extend(unbilled.iter().map(|record| record.id));
+        self.summary.failures.push(BillingFailure { user_id, billing_period, error });
+        Ok(())
+    }
+}
+
+/// Usage record billed in a batch transaction, with the wallet it is billed to and
+/// the index of its user's period among the batch's groups
+struct BatchEntry<'a> {
+    group: usize,
+    user: Address,
+    record: &'a BillableUsage,
+}
+
+fn batch_operation(entries: &[BatchEntry<'_>]) -> TxOperation {
+    TxOperation::BatchBilling {
+        users: entries.iter().map(|entry| entry.user).collect(),
+        endpoints: entries.iter().map(|entry| entry.record.endpoint_name.clone()).collect(),
+        request_counts: entries.iter().map(|entry| U256::from(entry.record.request_count.max(0) as u64)).collect(),
+    }
+}
+
+/// Queues a batch of users' usage to be billed in a single transaction, or with a gas
+/// budget, in as many transactions as it takes for each to fit it
+///
+/// Each transaction is linked to the usage it carries only, so one that fails leaves
+/// the rest to be billed. A user's period is queued whole or not at all.
+async fn queue_batch(
+    db: &Database,
+    tx: &mut Transaction<'_, Postgres>,
+    groups: Vec<Vec<BillableUsage>>,
+    run: &mut BillingRun,
+    gas: Option<(&dyn GasEstimator, u64)>,
+) -> Result<()> {
+    let mut queued = Vec::new();
+    for group in groups {
+        match parse_wallet(&group[0].wallet_address) {
+            Ok(address) => queued.push((address, group)),
+            Err(e) => run.failed(db, tx, &group, e).await?,
+        }
+    }
+    let entries: Vec<BatchEntry> = queued.iter().enumerate()
+        .flat_map(|(group, (user, records))| records.iter().map(move |record| BatchEntry { group, user: *user, record }))
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let (batches, rejected) = match gas {
+        Some((estimator, budget)) => split_by_gas(estimator, U256::from(budget), entries).await,
+        None => (vec![entries], HashMap::new()),
+    };
+    for batch in batches {
+        let batch: Vec<BatchEntry> = batch.into_iter().filter(|entry| !rejected.contains_key(&entry.group)).collect();
+        if batch.is_empty() {
+            continue;
+        }
+        let usage_ids: Vec<Uuid> = batch.iter().map(|entry| entry.record.id).collect();
+        tx_queue::enqueue(db, tx, &batch_operation(&batch), &usage_ids).await?;
+    }
+    for (index, (_, group)) in queued.iter().enumerate() {
+        match rejected.get(&index) {
+            Some(error) => run.failed(db, tx, group, error.clone()).await?,
+            None => run.queued(db, tx, group).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Splits usage into batches estimated to use at most `budget` gas each, halving those
+/// over it
+///
+/// A record over budget on its own, or whose gas cannot be estimated even on its own,
+/// rejects its group with the reason.
+async fn split_by_gas<'a>(
+    estimator: &dyn GasEstimator,
+    budget: U256,
+    entries: Vec<BatchEntry<'a>>,
+) -> (Vec<Vec<BatchEntry<'a>>>, HashMap<usize, String>) {
+    let (mut batches, mut rejected) = (Vec::new(), HashMap::new());
+    let mut pending = vec![entries];
+    while let Some(mut batch) = pending.pop() {
+        batch.retain(|entry| !rejected.contains_key(&entry.group));
+        if batch.is_empty() {
+            continue;
+        }
+
+        match estimator.estimate_operation_gas(&batch_operation(&batch)).await {
+            Ok(gas) if gas <= budget => batches.push(batch),
+            _ if batch.len() > 1 => {
+                // The first half is popped first, so batches keep the usage's order
+                let second = batch.split_off(batch.len() / 2);
+                pending.push(second);
+                pending.push(batch);
+            }
+            Ok(gas) => {
+                let error = format!(
+                    "Billing {} is estimated at {} gas, over the batch gas budget of {}",
+                    batch[0].record.endpoint_name, gas, budget
+                );
+                rejected.insert(batch[0].group, error);
+            }
+            Err(e) => {
+                let error = format!("Failed to estimate gas of billing {}: {:#}", batch[0].record.endpoint_name, e);
+                rejected.insert(batch[0].group, error);
+            }
+        }
+    }
+    (batches, rejected)
+}
+
+/// Queues a user's period to be billed one usage record at a time
+async fn queue_records(
+    db: &Database,
+    tx: &mut Transaction<'_, Postgres>,
+    group: &[BillableUsage],
+    run: &mut BillingRun,
+) -> Result<()> {
+    let address = match parse_wallet(&group[0].wallet_address) {
+        Ok(address) => address,
+        Err(e) => return run.failed(db, tx, group, e).await,
+    };
+
+    for record in group {
+        let operation = TxOperation::RecordUsage {
+            user: address,
+            endpoint: record.endpoint_name.clone(),
+            request_count: U256::from(record.request_count.max(0) as u64),
+        };
+        tx_queue::enqueue(db, tx, &operation, &[record.id]).await?;
+    }
+    run.queued(db, tx, group).await
+}
+
+/// Splits usage ordered by user and period into one group per user and period
+fn group_by_user_period(records: Vec<BillableUsage>) -> Vec<Vec<BillableUsage>> {
+    let mut groups: Vec<Vec<BillableUsage>> = Vec::new();
+    for record in records {
+        match groups.last_mut() {
+            Some(group) if group[0].user_id == record.user_id && group[0].billing_period == record.billing_period => {
+                group.push(record);
+            }
+            _ => groups.push(vec![record]),
+        }
+    }
+    groups
+}
+
+fn parse_wallet(wallet_address: &str) -> std::result::Result<Address, String> {
+    Address::from_str(wallet_address).map_err(|_| format!("Invalid wallet address: {}", wallet_address))
+}
+
+/// How a call breached its endpoint's SLA, if it did
+pub fn sla_breach(endpoint: &ApiEndpoint, status_code: i32, response_time_ms: i32) -> Option<SlaBreach> {
+    if endpoint.sla_error_refund && status_code >= 500 {
+        return Some(SlaBreach::ServerError);
+    }
+    match endpoint.sla_max_latency_ms {
+        Some(max_latency_ms) if response_time_ms > max_latency_ms => Some(SlaBreach::Latency),
+        _ => None,
+    }
+}
+
 /// Time periods for usage statistics and analytics
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+///
+/// Hour, Day and Week are trailing windows ending now. Month, Quarter and Year are the
+/// UTC calendar periods containing now, so they line up with billing periods like "2024-01".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UsagePeriod {
     Hour,
     Day,
     Week,
     Month,
+    Quarter,
+    Year,
+    /// Half-open UTC range from `start` up to, but excluding, `end`
+    Custom { start: DateTime<Utc>, end: DateTime<Utc> },
+}
+
+impl UsagePeriod {
+    /// Start and exclusive end of the period as of `now`
+    pub fn bounds(self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        match self {
+            UsagePeriod::Hour => (now - chrono::Duration::hours(1), now),
+            UsagePeriod::Day => (now - chrono::Duration::days(1), now),
+            UsagePeriod::Week => (now - chrono::Duration::weeks(1), now),
+            UsagePeriod::Month | UsagePeriod::Quarter | UsagePeriod::Year => {
+                let months = self.calendar_months().unwrap();
+                let start = start_of_calendar_period(now, months);
+                (start, start + Months::new(months))
+            }
+            UsagePeriod::Custom { start, end } => (start, end),
+        }
+    }
+
+    /// Name reported alongside usage statistics
+    pub fn name(self) -> &'static str {
+        match self {
+            UsagePeriod::Hour => "Hour",
+            UsagePeriod::Day => "Day",
+            UsagePeriod::Week => "Week",
+            UsagePeriod::Month => "Month",
+            UsagePeriod::Quarter => "Quarter",
+            UsagePeriod::Year => "Year",
+            UsagePeriod::Custom { .. } => "Custom",
+        }
+    }
+
+    /// Length in months of a calendar-aligned period
+    fn calendar_months(self) -> Option<u32> {
+        match self {
+            UsagePeriod::Month => Some(1),
+            UsagePeriod::Quarter => Some(3),
+            UsagePeriod::Year => Some(12),
+            _ => None,
+        }
+    }
+}
+
+/// Midnight UTC on the first day of the calendar period of `months` months containing `at`
+fn start_of_calendar_period(at: DateTime<Utc>, months: u32) -> DateTime<Utc> {
+    let month0 = at.month0() / months * months;
+    NaiveDate::from_ymd_opt(at.year(), month0 + 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+}
+
+/// Period names accepted by usage and analytics queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsagePeriodName {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    Custom,
+}
+
+impl UsagePeriodName {
+    /// The period this name selects on its own; a custom period needs a range
+    fn period(self) -> Option<UsagePeriod> {
+        match self {
+            UsagePeriodName::Hour => Some(UsagePeriod::Hour),
+            UsagePeriodName::Day => Some(UsagePeriod::Day),
+            UsagePeriodName::Week => Some(UsagePeriod::Week),
+            UsagePeriodName::Month => Some(UsagePeriod::Month),
+            UsagePeriodName::Quarter => Some(UsagePeriod::Quarter),
+            UsagePeriodName::Year => Some(UsagePeriod::Year),
+            UsagePeriodName::Custom => None,
+        }
+    }
+}
+
+/// Query parameters selecting a usage period, e.g. `?period=quarter` or
+/// `?period=month&start=2024-01-01T00:00:00Z&end=2024-03-01T00:00:00Z`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageQuery {
+    pub period: Option<UsagePeriodName>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl UsageQuery {
+    /// Resolves the query into a usage period as of `now`
+    ///
+    /// Without a range the named period is used, defaulting to the current month. A
+    /// range ends now unless `end` is given; with a month, quarter or year period it is
+    /// widened to the whole calendar periods it touches.
+    pub fn into_period(self, now: DateTime<Utc>) -> AppResult<UsagePeriod> {
+        let default_name = if self.start.is_some() { UsagePeriodName::Custom } else { UsagePeriodName::Month };
+        let name = self.period.unwrap_or(default_name);
+        let Some(start) = self.start else {
+            if self.end.is_some() {
+                return Err(AppError::Validation("A usage range with an end requires a start".to_string()));
+            }
+            return name.period()
+                .ok_or_else(|| AppError::Validation("A custom period requires a start".to_string()));
+        };
+        let end = self.end.unwrap_or(now);
+        if start >= end {
+            return Err(AppError::Validation("Usage range must end after it starts".to_string()));
+        }
+
+        match name.period().map(UsagePeriod::calendar_months) {
+            None => Ok(UsagePeriod::Custom { start, end }),
+            Some(Some(months)) => {
+                let last_start = start_of_calendar_period(end, months);
+                let end = if last_start == end { end } else { last_start + Months::new(months) };
+                Ok(UsagePeriod::Custom { start: start_of_calendar_period(start, months), end })
+            }
+            Some(None) => Err(AppError::Validation(format!(
+                "A usage range can only be combined with a month, quarter, year or custom period, not {:?}",
+                name
+            ))),
+        }
+    }
 }
 
 impl MeteringService {
     /// Processes pending billing records and updates blockchain state
-    pub async fn process_billing(&self, db: Arc<Database>) -> Result<()> {
-        // In a real implementation, this would be a complex process involving:
-        // - Fetching all users with outstanding balances
-        // - Calculating usage since last billing cycle
-        // - Interacting with the blockchain to process payments
-        // - Sending notifications to users
+    ///
+    /// Usage is queued to be billed on-chain once its month has closed, grouped into
+    /// one billing record per user and period, and the run returns without waiting
+    /// for the chain: the transaction queue marks the usage billed once its
+    /// transaction confirms. Usage that cannot be queued stays pending for the next run
+    /// and is listed in the returned summary.
+    pub async fn process_billing(&self, db: Arc<Database>) -> Result<BillingRunSummary> {
         info!("Processing billing cycle...");
 
-        let users_to_bill = db.get_users_with_outstanding_usage().await?;
+        // Renewals are charged before usage is collected so they are billed this cycle
+        let now = Utc::now();
+        let (renewed, ended, lapsed) = db.renew_due_subscriptions(now, now + Months::new(1)).await?;
+        if renewed + ended + lapsed > 0 {
+            info!("Renewed {} subscriptions, ended {}, lapsed {}", renewed, ended, lapsed);
+        }
 
-        for user in users_to_bill {
-            info!("Processing billing for user: {}", user.id);
-            // ... billing logic here ...
+        // Holds left by calls that never settled, e.g. across a restart, are returned
+        let released = db.release_stale_balance_holds(STALE_HOLD_AGE).await?;
+        if released > 0 {
+            warn!("Released {} stale balance holds", released);
         }
 
-        info!("Billing cycle completed.");
-        Ok(())
+        // Calls that breached their SLA are refunded before usage is billed
+        let mut refunded = 0;
+        loop {
+            let refunds = db.get_pending_sla_refunds(SLA_REFUND_BATCH_SIZE).await?;
+            for refund in &refunds {
+                if db.apply_sla_refund(refund.id).await? {
+                    refunded += 1;
+                }
+            }
+            if (refunds.len() as i64) < SLA_REFUND_BATCH_SIZE {
+                break;
+            }
+        }
+        if refunded > 0 {
+            info!("Credited {} SLA refunds", refunded);
+        }
+
+        // Month-to-date totals read by the monthly limit check are rebuilt if they drifted
+        let corrected = db.reconcile_monthly_usage(&now.format("%Y-%m").to_string()).await?;
+        if corrected > 0 {
+            warn!("Rebuilt {} month-to-date usage totals that drifted", corrected);
+        }
+
+        let summary = match self.billing {
+            true => self.queue_closed_usage(&db, &now.format("%Y-%m").to_string()).await?,
+            false => {
+                warn!("No billing contract configured, leaving usage pending");
+                BillingRunSummary::default()
+            }
+        };
+
+        info!(
+            "Billing cycle completed: {} users queued for billing {} across {} records, {} failures",
+            summary.users_billed, summary.total_cost, summary.records_billed, summary.failures.len()
+        );
+        Ok(summary)
+    }
+
+    /// Queues the pending usage of periods before `current_period`, one batch at a time
+    ///
+    /// Each batch is claimed under row locks and linked to its queued transaction in
+    /// the same database transaction, so concurrent or repeated runs never queue the
+    /// same usage twice.
+    async fn queue_closed_usage(&self, db: &Database, current_period: &str) -> Result<BillingRunSummary> {
+        let mut run = BillingRun::default();
+        loop {
+            let mut tx = db.begin_transaction().await?;
+            let records = db.get_pending_billing(&mut tx, current_period, &run.skip, BILLING_BATCH_SIZE).await?;
+            if records.is_empty() {
+                break;
+            }
+
+            let groups = group_by_user_period(records);
+            if self.batch_billing {
+                let gas = self.gas_estimator.as_deref().map(|estimator| (estimator, self.batch_gas_budget));
+                queue_batch(db, &mut tx, groups, &mut run, gas).await?;
+            } else {
+                for group in groups {
+                    queue_records(db, &mut tx, &group, &mut run).await?;
+                }
+            }
+            tx.commit().await.context("Failed to commit billing batch")?;
+        }
+
+        run.summary.users_billed = run.users.len() as i64;
+        Ok(run.summary)
     }
 
     /// Generates comprehensive analytics data for the specified period
@@ -431,14 +1521,15 @@ impl MeteringService {
         info!("Fetching analytics for period: {:?}", period);
 
         let (start_date, end_date) = self.get_period_dates(period);
+        let today = Utc::now().date_naive();
 
-        let total_requests = db.get_total_requests(start_date, end_date).await?;
+        let total_requests = db.get_total_requests(start_date, end_date, today).await?;
         let total_revenue = db.get_total_revenue(start_date, end_date).await?;
         let new_users = db.get_new_users(start_date, end_date).await?;
-        let active_users = db.get_active_users(start_date, end_date).await?;
+        let active_users = db.get_active_users(start_date, end_date, today).await?;
 
         Ok(AnalyticsData {
-            period: format!("{:?}", period),
+            period: period.name().to_string(),
             total_requests,
             total_revenue,
             new_users,
@@ -447,4 +1538,527 @@ impl MeteringService {
             end_date,
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::{
+        blockchain::{TransactionResult, TransactionStatus, TxSubmitter},
+        tx_queue::TxQueueJob,
+    };
+
+    /// Tests that calls breach the SLA past its latency bound, or on a 5xx when opted in
+    #[test]
+    fn test_sla_breach() {
+        let endpoint = ApiEndpoint {
+            sla_max_latency_ms: Some(500),
+            ..test_support::endpoint("quotes", "https://api.example.com")
+        };
+
+        assert_eq!(sla_breach(&endpoint, 200, 500), None);
+        assert_eq!(sla_breach(&endpoint, 200, 501), Some(SlaBreach::Latency));
+        assert_eq!(sla_breach(&endpoint, 503, 100), None);
+
+        let endpoint = ApiEndpoint { sla_error_refund: true, sla_max_latency_ms: None, ..endpoint };
+        assert_eq!(sla_breach(&endpoint, 503, 100), Some(SlaBreach::ServerError));
+        assert_eq!(sla_breach(&endpoint, 200, 60_000), None);
+    }
+
+    /// Tests the rate limit response headers and Retry-After calculation
+    #[test]
+    fn test_rate_limit_info_headers() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let info = RateLimitInfo {
+            limit: 100,
+            remaining: 42,
+            reset_time: now + 30,
+            window_seconds: 60,
+            algorithm: RateLimitAlgorithm::SlidingWindow,
+        };
+
+        let mut headers = HeaderMap::new();
+        info.apply_headers(&mut headers);
+        assert_eq!(headers["x-ratelimit-limit"], "100");
+        assert_eq!(headers["x-ratelimit-remaining"], "42");
+        assert_eq!(headers["x-ratelimit-reset"], (now + 30).to_string());
+
+        assert!((29..=30).contains(&info.retry_after()));
+        assert_eq!(RateLimitInfo { reset_time: now - 5, ..info }.retry_after(), 1);
+    }
+
+    /// Tests the monthly quota headers, which are only sent to users with a limit
+    #[test]
+    fn test_monthly_usage_headers() {
+        let period_end = start_of_next_month(Utc::now());
+        let usage = MonthlyUsage::new("2026-10".to_string(), Some(100), 40, period_end);
+        assert_eq!(usage.remaining, Some(60));
+
+        let mut headers = HeaderMap::new();
+        usage.apply_headers(&mut headers);
+        assert_eq!(headers["x-augustcredits-monthly-limit"], "100");
+        assert_eq!(headers["x-augustcredits-monthly-used"], "40");
+        assert_eq!(headers["x-augustcredits-monthly-remaining"], "60");
+
+        assert_eq!(MonthlyUsage::new("2026-10".to_string(), Some(100), 120, period_end).remaining, Some(0));
+
+        let mut headers = HeaderMap::new();
+        MonthlyUsage::new("2026-10".to_string(), None, 40, period_end).apply_headers(&mut headers);
+        assert!(headers.is_empty());
+    }
+
+    /// Tests how each overage policy admits calls past the monthly limit
+    #[test]
+    fn test_admit_monthly() {
+        let block = OveragePolicy::Block;
+        assert_eq!(admit_monthly(&block, 99, 100).unwrap(), MonthlyAdmission::Within);
+        assert_eq!(admit_monthly(&block, 100, 100).unwrap(), MonthlyAdmission::Refused);
+
+        let rate = OveragePolicy::AllowWithOverageRate { multiplier: "1.5".to_string() };
+        assert_eq!(admit_monthly(&rate, 99, 100).unwrap(), MonthlyAdmission::Within);
+        assert_eq!(admit_monthly(&rate, 5000, 100).unwrap(), MonthlyAdmission::Overage(Decimal::new(15, 1)));
+        let invalid = OveragePolicy::AllowWithOverageRate { multiplier: "-1".to_string() };
+        assert!(admit_monthly(&invalid, 100, 100).is_err());
+        assert_eq!(admit_monthly(&invalid, 0, 100).unwrap(), MonthlyAdmission::Within);
+
+        // Grace requests are charged their usual price until they run out
+        let grace = OveragePolicy::GraceRequests { requests: 10 };
+        assert_eq!(admit_monthly(&grace, 100, 100).unwrap(), MonthlyAdmission::Overage(Decimal::ONE));
+        assert_eq!(admit_monthly(&grace, 109, 100).unwrap(), MonthlyAdmission::Overage(Decimal::ONE));
+        assert_eq!(admit_monthly(&grace, 110, 100).unwrap(), MonthlyAdmission::Refused);
+        let unbounded = OveragePolicy::GraceRequests { requests: i64::MAX };
+        assert_eq!(admit_monthly(&unbounded, 100, 100).unwrap(), MonthlyAdmission::Overage(Decimal::ONE));
+    }
+
+    /// Tests that overage policies are stored as tagged JSON
+    #[test]
+    fn test_overage_policy_json() {
+        let policy: OveragePolicy = serde_json::from_str(r#"{"mode": "grace_requests", "requests": 50}"#).unwrap();
+        assert_eq!(policy, OveragePolicy::GraceRequests { requests: 50 });
+        assert_eq!(
+            serde_json::to_value(OveragePolicy::AllowWithOverageRate { multiplier: "2".to_string() }).unwrap(),
+            serde_json::json!({ "mode": "allow_with_overage_rate", "multiplier": "2" })
+        );
+        assert_eq!(serde_json::to_value(OveragePolicy::default()).unwrap(), serde_json::json!({ "mode": "block" }));
+    }
+
+    /// Tests the monthly reset boundary, including the year rollover
+    #[test]
+    fn test_start_of_next_month() {
+        let mid_year = NaiveDate::from_ymd_opt(2024, 6, 15).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc();
+        assert_eq!(start_of_next_month(mid_year).to_rfc3339(), "2024-07-01T00:00:00+00:00");
+
+        let december = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap().and_hms_opt(23, 59, 59).unwrap().and_utc();
+        assert_eq!(start_of_next_month(december).to_rfc3339(), "2025-01-01T00:00:00+00:00");
+    }
+
+    /// Tests that calendar periods cover the UTC month, quarter or year containing now
+    #[test]
+    fn test_usage_period_bounds() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let bounds = |period: UsagePeriod, now: &str| {
+            let (start, end) = period.bounds(at(now));
+            (start.to_rfc3339(), end.to_rfc3339())
+        };
+
+        let month = bounds(UsagePeriod::Month, "2024-02-29T23:59:59Z");
+        assert_eq!(month, ("2024-02-01T00:00:00+00:00".to_string(), "2024-03-01T00:00:00+00:00".to_string()));
+        let quarter = bounds(UsagePeriod::Quarter, "2024-11-15T08:00:00Z");
+        assert_eq!(quarter, ("2024-10-01T00:00:00+00:00".to_string(), "2025-01-01T00:00:00+00:00".to_string()));
+        let quarter = bounds(UsagePeriod::Quarter, "2024-04-01T00:00:00Z");
+        assert_eq!(quarter, ("2024-04-01T00:00:00+00:00".to_string(), "2024-07-01T00:00:00+00:00".to_string()));
+        let year = bounds(UsagePeriod::Year, "2024-12-31T23:59:59Z");
+        assert_eq!(year, ("2024-01-01T00:00:00+00:00".to_string(), "2025-01-01T00:00:00+00:00".to_string()));
+
+        let (start, end) = UsagePeriod::Day.bounds(at("2024-03-01T12:00:00Z"));
+        assert_eq!((start, end), (at("2024-02-29T12:00:00Z"), at("2024-03-01T12:00:00Z")));
+        let custom = UsagePeriod::Custom { start: at("2024-01-15T00:00:00Z"), end: at("2024-01-16T00:00:00Z") };
+        assert_eq!(custom.bounds(at("2025-01-01T00:00:00Z")), (at("2024-01-15T00:00:00Z"), at("2024-01-16T00:00:00Z")));
+    }
+
+    /// Tests how usage query parameters resolve into a period
+    #[test]
+    fn test_usage_query_into_period() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let now = at("2024-05-20T10:00:00Z");
+        let query = |period: Option<UsagePeriodName>, start: Option<&str>, end: Option<&str>| UsageQuery {
+            period,
+            start: start.map(at),
+            end: end.map(at),
+        };
+
+        assert_eq!(UsageQuery::default().into_period(now).unwrap(), UsagePeriod::Month);
+        assert_eq!(query(Some(UsagePeriodName::Year), None, None).into_period(now).unwrap(), UsagePeriod::Year);
+        assert_eq!(
+            query(None, Some("2024-01-10T00:00:00Z"), None).into_period(now).unwrap(),
+            UsagePeriod::Custom { start: at("2024-01-10T00:00:00Z"), end: now }
+        );
+        assert_eq!(
+            query(Some(UsagePeriodName::Month), Some("2024-01-10T00:00:00Z"), Some("2024-03-01T00:00:00Z")).into_period(now).unwrap(),
+            UsagePeriod::Custom { start: at("2024-01-01T00:00:00Z"), end: at("2024-03-01T00:00:00Z") }
+        );
+        assert_eq!(
+            query(Some(UsagePeriodName::Quarter), Some("2024-02-10T00:00:00Z"), None).into_period(now).unwrap(),
+            UsagePeriod::Custom { start: at("2024-01-01T00:00:00Z"), end: at("2024-07-01T00:00:00Z") }
+        );
+
+        for invalid in [
+            query(Some(UsagePeriodName::Custom), None, None),
+            query(None, None, Some("2024-03-01T00:00:00Z")),
+            query(None, Some("2024-03-01T00:00:00Z"), Some("2024-03-01T00:00:00Z")),
+            query(Some(UsagePeriodName::Hour), Some("2024-03-01T00:00:00Z"), None),
+        ] {
+            assert!(matches!(invalid.into_period(now), Err(AppError::Validation(_))));
+        }
+    }
+
+    /// Tests that usage periods parse from query strings
+    #[test]
+    fn test_usage_query_deserialize() {
+        let query: UsageQuery = serde_urlencoded::from_str("period=month&start=2024-01-01T00:00:00Z&end=2024-02-01T00:00:00Z").unwrap();
+        assert_eq!(query.period, Some(UsagePeriodName::Month));
+        assert_eq!(query.start.unwrap().to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(query.end.unwrap().to_rfc3339(), "2024-02-01T00:00:00+00:00");
+    }
+
+    /// Tests which withdrawal destinations are accepted
+    #[test]
+    fn test_parse_destination_address() {
+        let address = "0x52908400098527886E0F7030069857D2E4169EE7";
+        assert_eq!(format!("{:?}", parse_destination_address(address).unwrap()), address.to_lowercase());
+
+        assert!(parse_destination_address("52908400098527886E0F7030069857D2E4169EE7").is_err());
+        assert!(parse_destination_address("0x52908400098527886E0F7030069857D2E4169E").is_err());
+        assert!(parse_destination_address("0x52908400098527886E0F7030069857D2E4169EZ").is_err());
+        assert!(parse_destination_address(&format!("0x{}", "0".repeat(40))).is_err());
+        // A mistyped mixed-case address fails its checksum
+        assert!(parse_destination_address("0x52908400098527886E0F7030069857D2E4169Ee7").is_err());
+    }
+
+    /// Chain on which every transaction settles with a fixed outcome
+    struct StubPayouts(TransactionStatus);
+
+    #[async_trait::async_trait]
+    impl TxSubmitter for StubPayouts {
+        async fn send(&self, _operation: &TxOperation) -> Result<ethers::types::H256> {
+            Ok(ethers::types::H256::random())
+        }
+
+        async fn confirm(&self, tx_hash: ethers::types::H256) -> Result<TransactionResult> {
+            Ok(TransactionResult {
+                hash: tx_hash,
+                block_number: Some(1),
+                gas_used: None,
+                status: self.0.clone(),
+                confirmations: 1,
+            })
+        }
+
+        async fn is_dropped(&self, _tx_hash: ethers::types::H256) -> Result<bool> {
+            Ok(false)
+        }
+    }
+
+    /// Tests that withdrawals debit the balance, respect the daily cap, are paid out
+    /// by the transaction queue and are credited back when the payout is given up on
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_withdraw_balance() {
+        let mut config = test_support::config();
+        config.blockchain.tx_max_attempts = 1;
+        let database = Arc::new(Database::new(&config.database_url, &config.database_pool).await.unwrap());
+        database.migrate().await.unwrap();
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        database.credit_balance(user.id, "1000", None).await.unwrap();
+
+        let metering = |limit: Option<&str>| MeteringService::new(database.clone())
+            .with_withdrawals(true)
+            .with_withdrawal_daily_limit(limit.map(|limit| CostAmount::from_str(limit).unwrap()));
+        let request = |amount: &str| WithdrawRequest {
+            amount: amount.to_string(),
+            destination_address: "0x52908400098527886E0F7030069857D2E4169EE7".to_string(),
+        };
+        let drain = |status| TxQueueJob::new(&config, database.clone(), Arc::new(StubPayouts(status)));
+        let credit_back = |transaction_id| {
+            let database = database.clone();
+            async move {
+                let mut tx = database.begin_transaction().await.unwrap();
+                let credited = database.fail_withdrawal_in(&mut tx, transaction_id, "late failure").await.unwrap();
+                tx.commit().await.unwrap();
+                credited
+            }
+        };
+
+        assert!(matches!(MeteringService::new(database.clone()).withdraw_balance(user.id, request("1")).await, Err(AppError::ExternalService(_))));
+        assert!(matches!(metering(Some("700")).withdraw_balance(user.id, request("0.5")).await, Err(AppError::Validation(_))));
+        assert!(matches!(metering(Some("700")).withdraw_balance(user.id, request("2000")).await, Err(AppError::Payment(_))));
+
+        let withdrawal = metering(Some("700")).withdraw_balance(user.id, request("600")).await.unwrap();
+        assert_eq!(withdrawal.amount, "600");
+        assert_eq!(withdrawal.destination_address, "0x52908400098527886E0F7030069857D2E4169EE7");
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "400");
+
+        // Withdrawals count towards the daily cap unless their payout failed
+        assert!(matches!(metering(Some("700")).withdraw_balance(user.id, request("200")).await, Err(AppError::RateLimit(_))));
+
+        // A confirmed payout is settled and can no longer be credited back
+        drain(TransactionStatus::Confirmed).drain().await.unwrap();
+        assert!(credit_back(withdrawal.transaction_id).await.is_none());
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "400");
+
+        // A payout given up on is credited back exactly once
+        let withdrawal = metering(None).withdraw_balance(user.id, request("100")).await.unwrap();
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "300");
+        drain(TransactionStatus::Failed).drain().await.unwrap();
+        assert_eq!(database.get_balance(user.id).await.unwrap(), "400");
+        assert!(credit_back(withdrawal.transaction_id).await.is_none());
+
+        // How each payout settled shows on the user's transactions
+        let entries = database.list_ledger_entries(user.id, LedgerQuery::default()).await.unwrap();
+        assert!(matches!(entries[0].entry_type, LedgerEntryType::Reversal));
+        assert!(matches!(entries[0].transaction_status, Some(crate::models::TransactionStatus::Failed)));
+        assert!(matches!(entries[1].transaction_status, Some(crate::models::TransactionStatus::Failed)));
+        assert!(matches!(entries[2].transaction_status, Some(crate::models::TransactionStatus::Confirmed)));
+        assert!(entries[2].transaction_hash.is_some());
+    }
+
+    /// Tests that reported deposits stay pending until their transfer is credited, and
+    /// that a transfer reported by someone other than its payer is failed
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_deposit_balance() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new(&config.database_url, &config.database_pool).await.unwrap());
+        database.migrate().await.unwrap();
+        let payer = database.create_user(test_support::user_request()).await.unwrap();
+        let other = database.create_user(test_support::user_request()).await.unwrap();
+        let metering = MeteringService::new(database.clone()).with_chain_ids(vec![1]);
+
+        let transaction_hash = format!("{:?}", H256::random());
+        let request = |amount: &str, chain_id| crate::models::DepositRequest {
+            amount: amount.to_string(),
+            transaction_hash: transaction_hash.to_uppercase().replacen("0X", "0x", 1),
+            chain_id,
+        };
+        assert!(matches!(metering.deposit_balance(payer.id, request("250", 5)).await, Err(AppError::Validation(_))));
+        assert!(matches!(metering.deposit_balance(payer.id, request("-1", 1)).await, Err(AppError::Validation(_))));
+
+        let reported = metering.deposit_balance(payer.id, request("250", 1)).await.unwrap();
+        assert!(matches!(reported.status, crate::models::TransactionStatus::Pending));
+        assert_eq!(metering.deposit_balance(payer.id, request("250", 1)).await.unwrap().transaction_id, reported.transaction_id);
+        let claimed = metering.deposit_balance(other.id, request("250", 1)).await.unwrap();
+        assert_ne!(claimed.transaction_id, reported.transaction_id);
+        assert_eq!(database.get_balance(payer.id).await.unwrap(), "0");
+
+        // The event credits what was actually transferred
+        let mut tx = database.begin_transaction().await.unwrap();
+        database.credit_balance_in(&mut tx, payer.id, "240", Some(&transaction_hash)).await.unwrap();
+        let credited = database.record_deposit_in(&mut tx, payer.id, "240", 1, &transaction_hash, 7).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(credited, reported.transaction_id);
+
+        let confirmed = metering.deposit_balance(payer.id, request("250", 1)).await.unwrap();
+        assert!(matches!(confirmed.status, crate::models::TransactionStatus::Confirmed));
+        assert_eq!(confirmed.amount, "240");
+        assert_eq!(database.get_balance(payer.id).await.unwrap(), "240");
+        let claimed = metering.deposit_balance(other.id, request("250", 1)).await.unwrap();
+        assert!(matches!(claimed.status, crate::models::TransactionStatus::Failed));
+        assert_eq!(database.get_balance(other.id).await.unwrap(), "0");
+    }
+
+    fn billable(user_id: Uuid, billing_period: &str) -> BillableUsage {
+        BillableUsage {
+            id: Uuid::new_v4(),
+            user_id,
+            wallet_address: "0x52908400098527886E0F7030069857D2E4169EE7".to_string(),
+            endpoint_name: "quotes".to_string(),
+            request_count: 1,
+            total_cost: CostAmount::from_str("10").unwrap(),
+            billing_period: billing_period.to_string(),
+        }
+    }
+
+    /// Tests that usage ordered by user and period splits into one group per pair
+    #[test]
+    fn test_group_by_user_period() {
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let records = vec![
+            billable(first, "2024-01"),
+            billable(first, "2024-01"),
+            billable(first, "2024-02"),
+            billable(second, "2024-02"),
+        ];
+
+        let groups = group_by_user_period(records);
+        let shape: Vec<_> = groups.iter()
+            .map(|group| (group[0].user_id, group[0].billing_period.as_str(), group.len()))
+            .collect();
+        assert_eq!(shape, vec![(first, "2024-01", 2), (first, "2024-02", 1), (second, "2024-02", 1)]);
+        assert!(group_by_user_period(Vec::new()).is_empty());
+    }
+
+    /// Gas estimates of 21000 per transaction and 30000 per entry, failing for batches
+    /// billing the `broken` endpoint
+    struct StubGas;
+
+    #[async_trait::async_trait]
+    impl GasEstimator for StubGas {
+        async fn estimate_operation_gas(&self, operation: &TxOperation) -> Result<U256> {
+            let TxOperation::BatchBilling { endpoints, .. } = operation else {
+                anyhow::bail!("not a batch");
+            };
+            if endpoints.iter().any(|endpoint| endpoint == "broken") {
+                anyhow::bail!("execution reverted");
+            }
+            Ok(U256::from(21000 + 30000 * endpoints.len()))
+        }
+    }
+
+    /// Tests that batches are halved until they fit the gas budget, in order, and that
+    /// records which cannot be billed on their own reject their user's period
+    #[tokio::test]
+    async fn test_split_by_gas() {
+        let user = Uuid::new_v4();
+        let mut records: Vec<BillableUsage> = (0..5).map(|_| billable(user, "2024-01")).collect();
+        records[3].endpoint_name = "broken".to_string();
+        let entries = || records.iter().zip([0, 0, 1, 2, 2])
+            .map(|(record, group)| BatchEntry { group, user: Address::zero(), record })
+            .collect::<Vec<_>>();
+
+        let (batches, rejected) = split_by_gas(&StubGas, U256::from(100_000), entries()).await;
+        let ids: Vec<Vec<Uuid>> = batches.iter().map(|batch| batch.iter().map(|entry| entry.record.id).collect()).collect();
+        assert_eq!(ids, vec![vec![records[0].id, records[1].id], vec![records[2].id]]);
+        assert_eq!(rejected.keys().collect::<Vec<_>>(), vec![&2]);
+        assert!(rejected[&2].contains("execution reverted"));
+
+        // Every record over budget on its own rejects its period
+        let (batches, rejected) = split_by_gas(&StubGas, U256::from(40_000), entries()).await;
+        assert!(batches.is_empty());
+        assert_eq!(rejected.len(), 3);
+        assert!(rejected[&0].contains("over the batch gas budget"));
+    }
+
+    /// Stub chain that reverts transactions billing the given endpoints, recording
+    /// which endpoints were sent
+    #[derive(Default)]
+    struct StubBilling {
+        failing: Vec<String>,
+        submitted: std::sync::Mutex<Vec<String>>,
+        reverted: std::sync::Mutex<HashSet<ethers::types::H256>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TxSubmitter for StubBilling {
+        async fn send(&self, operation: &TxOperation) -> Result<ethers::types::H256> {
+            let endpoints = match operation {
+                TxOperation::BatchBilling { endpoints, .. } => endpoints.clone(),
+                TxOperation::RecordUsage { endpoint, .. } => vec![endpoint.clone()],
+                TxOperation::Payout { .. } => Vec::new(),
+            };
+            let tx_hash = ethers::types::H256::random();
+            if endpoints.iter().any(|endpoint| self.failing.contains(endpoint)) {
+                self.reverted.lock().unwrap().insert(tx_hash);
+            }
+            self.submitted.lock().unwrap().extend(endpoints);
+            Ok(tx_hash)
+        }
+
+        async fn confirm(&self, tx_hash: ethers::types::H256) -> Result<TransactionResult> {
+            let status = match self.reverted.lock().unwrap().contains(&tx_hash) {
+                true => TransactionStatus::Reverted("endpoint rejected".to_string()),
+                false => TransactionStatus::Confirmed,
+            };
+            Ok(TransactionResult {
+                hash: tx_hash,
+                block_number: Some(1),
+                gas_used: Some(U256::from(50000)),
+                status,
+                confirmations: 1,
+            })
+        }
+
+        async fn is_dropped(&self, _tx_hash: ethers::types::H256) -> Result<bool> {
+            Ok(false)
+        }
+    }
+
+    /// Tests that a billing run queues closed periods only, that usage whose
+    /// transaction is given up on stays pending, and that billed usage is never
+    /// submitted again
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_process_billing() {
+        let mut config = test_support::config();
+        config.blockchain.tx_max_attempts = 1;
+        let database = Arc::new(Database::new(&config.database_url, &config.database_pool).await.unwrap());
+        database.migrate().await.unwrap();
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        let mut endpoints = Vec::new();
+        for name in ["steady", "flaky"] {
+            endpoints.push(database.create_endpoint(user.id, CreateEndpointRequest {
+                name: format!("{}-{}", name, Uuid::new_v4().simple()),
+                upstream_url: "https://api.example.com".to_string(),
+                price_per_request: "10".to_string(),
+                ..Default::default()
+            }).await.unwrap());
+        }
+        let (steady, flaky) = (&endpoints[0], &endpoints[1]);
+        let ours = [steady.name.clone(), flaky.name.clone()];
+        let current_period = Utc::now().format("%Y-%m").to_string();
+
+        database.create_usage_record(user.id, steady.id, 3, "30", "2024-01").await.unwrap();
+        database.create_usage_record(user.id, flaky.id, 2, "20", "2024-01").await.unwrap();
+        database.create_usage_record(user.id, steady.id, 1, "10", &current_period).await.unwrap();
+
+        let run = |batch_billing, billing: Arc<StubBilling>| {
+            let metering = MeteringService::new(database.clone())
+                .with_billing(true)
+                .with_batch_billing(batch_billing);
+            let queue = TxQueueJob::new(&config, database.clone(), billing);
+            let database = database.clone();
+            async move {
+                let summary = metering.process_billing(database).await.unwrap();
+                queue.drain().await.unwrap();
+                summary
+            }
+        };
+
+        // One record at a time, the flaky endpoint's transaction reverts and its usage
+        // stays pending
+        let billing = Arc::new(StubBilling { failing: vec![flaky.name.clone()], ..Default::default() });
+        run(false, billing.clone()).await;
+
+        let record = |endpoint_id, period: &str| {
+            let database = database.clone();
+            let period = period.to_string();
+            async move { database.get_usage_record(user.id, endpoint_id, &period).await.unwrap().unwrap() }
+        };
+        let billed = record(steady.id, "2024-01").await;
+        assert!(matches!(billed.status, UsageStatus::Billed));
+        assert!(billed.transaction_hash.is_some() && billed.block_number == Some(1));
+        assert!(matches!(record(flaky.id, "2024-01").await.status, UsageStatus::Pending));
+        assert!(matches!(record(steady.id, &current_period).await.status, UsageStatus::Pending));
+
+        let billing_record = database.get_billing_record(user.id, "2024-01").await.unwrap().unwrap();
+        assert!(matches!(billing_record.status, BillingStatus::Failed));
+        assert_eq!((billing_record.total_requests, billing_record.total_cost.as_str()), (3, "30"));
+        assert_eq!(billing_record.retry_count, 1);
+        assert!(billing_record.error_message.unwrap().contains("rejected"));
+
+        // The retry only submits what is still pending
+        let billing = Arc::new(StubBilling::default());
+        run(true, billing.clone()).await;
+        let submitted: Vec<_> = billing.submitted.lock().unwrap().iter().filter(|name| ours.contains(name)).cloned().collect();
+        assert_eq!(submitted, vec![flaky.name.clone()]);
+
+        let billing_record = database.get_billing_record(user.id, "2024-01").await.unwrap().unwrap();
+        assert!(matches!(billing_record.status, BillingStatus::Completed));
+        assert_eq!((billing_record.total_requests, billing_record.total_cost.as_str()), (5, "50"));
+        assert!(billing_record.error_message.is_none() && billing_record.processed_at.is_some());
+
+        // Nothing of ours is left to bill until the current month closes
+        let billing = Arc::new(StubBilling::default());
+        run(true, billing.clone()).await;
+        assert!(!billing.submitted.lock().unwrap().iter().any(|name| ours.contains(name)));
+    }
+}
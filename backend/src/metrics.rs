@@ -13,7 +13,7 @@ use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicI64, AtomicU64, Ordering},
         Arc,
     },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
@@ -30,6 +30,8 @@ pub struct MetricsService {
     counters: Arc<RwLock<HashMap<String, AtomicU64>>>,
     // Request latency tracking
     latencies: Arc<RwLock<HashMap<String, Vec<Duration>>>>,
+    // Gauges of current levels, shared with the components that move them
+    gauges: Arc<RwLock<HashMap<String, Arc<AtomicI64>>>>,
     // Service start time
     start_time: Instant,
 }
@@ -41,6 +43,7 @@ impl MetricsService {
             database,
             counters: Arc::new(RwLock::new(HashMap::new())),
             latencies: Arc::new(RwLock::new(HashMap::new())),
+            gauges: Arc::new(RwLock::new(HashMap::new())),
             start_time: Instant::now(),
         }
     }
@@ -56,6 +59,18 @@ impl MetricsService {
         debug!("Incremented counter '{}' by {}", name, value);
     }
 
+    /// Returns the named gauge, created at zero, for the caller to raise and lower as the
+    /// level it tracks changes
+    pub async fn gauge(&self, name: &str) -> Arc<AtomicI64> {
+        if let Some(gauge) = self.gauges.read().await.get(name) {
+            return gauge.clone();
+        }
+        self.gauges.write().await
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(AtomicI64::new(0)))
+            .clone()
+    }
+
     /// Records a latency measurement for performance tracking
     pub async fn record_latency(&self, name: &str, duration: Duration) {
         let mut latencies = self.latencies.write().await;
@@ -139,6 +154,17 @@ impl MetricsService {
         self.increment_counter("rate_limit_checks_total", 1).await;
     }
 
+    /// Record IP allowlist decision
+    /// Tracks requests accepted or rejected by per-API-key IP allowlists; the rejected
+    /// user is logged by the caller rather than given a counter of their own
+    pub async fn record_ip_allowlist_event(&self, allowed: bool) {
+        if allowed {
+            self.increment_counter("ip_allowlist_allowed_total", 1).await;
+        } else {
+            self.increment_counter("ip_allowlist_denied_total", 1).await;
+        }
+    }
+
     /// Get current metrics snapshot
     /// Creates a snapshot of all current metrics for monitoring systems
     pub async fn get_metrics_snapshot(&self) -> MetricsSnapshot {
@@ -150,6 +176,18 @@ impl MetricsService {
             counter_values.insert(name.clone(), counter.load(Ordering::Relaxed));
         }
         
+        let mut gauge_values: HashMap<String, i64> = self.gauges.read().await
+            .iter()
+            .map(|(name, gauge)| (name.clone(), gauge.load(Ordering::Relaxed)))
+            .collect();
+        let (pool_size, pool_idle) = self.database.pool_status();
+        gauge_values.insert("database_pool_connections".to_string(), pool_size as i64);
+        gauge_values.insert("database_pool_idle_connections".to_string(), pool_idle as i64);
+        if let Some((read_size, read_idle)) = self.database.read_pool_status() {
+            gauge_values.insert("database_read_pool_connections".to_string(), read_size as i64);
+            gauge_values.insert("database_read_pool_idle_connections".to_string(), read_idle as i64);
+        }
+
         let mut latency_stats = HashMap::new();
         for (name, durations) in latencies.iter() {
             if !durations.is_empty() {
@@ -165,6 +203,7 @@ impl MetricsService {
                 .as_secs(),
             uptime_seconds: self.start_time.elapsed().as_secs(),
             counters: counter_values,
+            gauges: gauge_values,
             latencies: latency_stats,
         }
     }
@@ -239,7 +278,8 @@ impl MetricsService {
     }
 
     /// Reset all metrics (useful for testing)
-    /// Resets all in-memory metrics counters and latency data
+    /// Resets all in-memory metrics counters and latency data; gauges track live
+    /// levels and are kept
     pub async fn reset_metrics(&self) {
         let mut counters = self.counters.write().await;
         let mut latencies = self.latencies.write().await;
@@ -309,6 +349,7 @@ pub struct MetricsSnapshot {
     pub timestamp: u64,
     pub uptime_seconds: u64,
     pub counters: HashMap<String, u64>,
+    pub gauges: HashMap<String, i64>,
     pub latencies: HashMap<String, LatencyStats>,
 }
 
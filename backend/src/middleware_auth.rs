@@ -4,21 +4,15 @@
 //! credentials (API keys, JWT tokens) and inject authenticated user context
 //! into request handlers for secure API access.
 
-use crate::{
-    auth::{AuthMethod, AuthService},
-    error::AppResult,
-    models::User,
-    auth_error,
-};
+use crate::auth::{enforce_ip_allowlist, AuthMethod};
 use axum::{
-    extract::{Request, State},
-    http::{HeaderMap, StatusCode},
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use jsonwebtoken::Validation;
+use std::net::SocketAddr;
 use tracing::warn;
-use uuid::Uuid;
 
 
 
@@ -27,7 +21,7 @@ pub async fn auth_middleware(
     State(state): State<crate::AppState>,
     request: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, Response> {
     let auth_service = &state.auth;
     let mut request = request;
     let headers = request.headers();
@@ -37,27 +31,34 @@ pub async fn auth_middleware(
         Some(method) => method,
         None => {
             warn!("No authentication provided");
-            return Err(StatusCode::UNAUTHORIZED);
+            return Err(StatusCode::UNAUTHORIZED.into_response());
         }
     };
 
     // Authenticate user
     let user = match auth_method {
         AuthMethod::ApiKey(api_key) => {
-            match auth_service.authenticate_api_key(&api_key, &state.database).await {
+            let user = match auth_service.authenticate_api_key(&api_key, &state.database).await {
                 Ok(user) => user,
                 Err(e) => {
                     warn!("API key authentication failed: {}", e);
-                    return Err(StatusCode::UNAUTHORIZED);
+                    return Err(StatusCode::UNAUTHORIZED.into_response());
                 }
-            }
+            };
+            
+            // Restricted keys are only honoured from their allowlisted networks
+            let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+            enforce_ip_allowlist(&state, &user, peer, request.headers())
+                .await
+                .map_err(IntoResponse::into_response)?;
+            user
         }
         AuthMethod::Jwt(token) => {
             match auth_service.authenticate_jwt(&token, &state.database).await {
                 Ok(user) => user,
                 Err(e) => {
                     warn!("JWT authentication failed: {}", e);
-                    return Err(StatusCode::UNAUTHORIZED);
+                    return Err(StatusCode::UNAUTHORIZED.into_response());
                 }
             }
         }
@@ -70,42 +71,40 @@ pub async fn auth_middleware(
     Ok(next.run(request).await)
 }
 
-
-
-// get_user_from_request function removed as it was unused
-
-/// Extracts and validates user ID from JWT token in Authorization header
-pub fn extract_user_id(headers: &HeaderMap) -> AppResult<Uuid> {
-    let auth_header = headers
-        .get("Authorization")
-        .ok_or_else(|| auth_error!("Missing Authorization header"))?
-        .to_str()
-        .map_err(|_| auth_error!("Invalid Authorization header"))?;
-
-    if !auth_header.starts_with("Bearer ") {
-        return Err(auth_error!("Invalid token format"));
+/// Authentication for /proxy routes
+///
+/// Browsers send CORS preflights without credentials, so when preflights are relayed
+/// upstream they pass through unauthenticated; everything else goes through
+/// [`auth_middleware`].
+pub async fn proxy_auth_middleware(
+    State(state): State<crate::AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if state.config.gateway.forward_cors_preflight && is_cors_preflight(request.method(), request.headers()) {
+        return Ok(next.run(request).await);
     }
+    auth_middleware(State(state), request, next).await
+}
 
-    let token = &auth_header[7..]; // Skip "Bearer " prefix
-    let claims = jsonwebtoken::decode::<crate::auth::Claims>(
-        token,
-        &jsonwebtoken::DecodingKey::from_secret(b"your-secret-key"), // This should come from config
-        &Validation::default(),
-    )
-    .map_err(|e| auth_error!(format!("Invalid token: {}", e)))?;
-
-    Uuid::parse_str(&claims.claims.sub)
-        .map_err(|_| auth_error!("Invalid user ID in token"))
+/// Whether a request is a CORS preflight rather than a plain OPTIONS call
+pub fn is_cors_preflight(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::OPTIONS
+        && headers.contains_key(header::ORIGIN)
+        && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support;
+    use crate::{auth::AuthService, models::User};
+    use uuid::Uuid;
 
     /// Tests JWT token generation and validation flow
     #[tokio::test]
     async fn test_jwt_token_generation_and_validation() {
-        let config = crate::config::Config::load().unwrap();
+        let config = test_support::config();
         let auth_service = AuthService::new(&config).unwrap();
         
         let user = User {
@@ -120,7 +119,9 @@ mod tests {
             last_login: None,
             tier: crate::models::UserTier::Free,
             monthly_limit: None,
+            overage_policy: sqlx::types::Json(crate::models::OveragePolicy::Block),
             rate_limit_override: None,
+            email_verified: false,
         };
 
         // Generate token
@@ -136,7 +137,7 @@ mod tests {
     /// Tests authentication method extraction from headers
     #[tokio::test]
     async fn test_auth_method_extraction() {
-        let config = crate::config::Config::load().unwrap();
+        let config = test_support::config();
         let auth_service = AuthService::new(&config).unwrap();
         
         let mut headers = HeaderMap::new();
@@ -160,4 +161,16 @@ mod tests {
             panic!("Expected JWT auth method");
         }
     }
-}
\ No newline at end of file
+
+    /// Tests that only OPTIONS requests carrying CORS request headers count as preflights
+    #[test]
+    fn test_is_cors_preflight() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_cors_preflight(&Method::OPTIONS, &headers));
+
+        headers.insert(header::ORIGIN, "https://app.example.com".parse().unwrap());
+        headers.insert(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH".parse().unwrap());
+        assert!(is_cors_preflight(&Method::OPTIONS, &headers));
+        assert!(!is_cors_preflight(&Method::PATCH, &headers));
+    }
+}
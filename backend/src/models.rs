@@ -4,9 +4,19 @@
 //! API endpoint monetization, usage tracking, billing automation, and payment processing.
 //! All models are designed for PostgreSQL with proper serialization support.
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, Type};
+use ethers::types::U256;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    types::Json,
+    Decode, Encode, FromRow, Postgres, Type,
+};
+use std::{collections::BTreeMap, fmt, str::FromStr};
 use uuid::Uuid;
 
 /// User account management and authentication
@@ -26,6 +36,8 @@ pub struct User {
     pub tier: UserTier,
     pub monthly_limit: Option<i64>,
     pub rate_limit_override: Option<i32>,
+    pub email_verified: bool,
+    pub overage_policy: Json<OveragePolicy>, // what happens past monthly_limit
 }
 
 /// User subscription tiers with different access levels and limits
@@ -55,9 +67,33 @@ pub struct UpdateUserRequest {
     pub is_active: Option<bool>,
     pub tier: Option<UserTier>,
     pub monthly_limit: Option<i64>,
+    pub overage_policy: Option<OveragePolicy>,
     pub rate_limit_override: Option<i32>,
 }
 
+/// What happens to a user's calls once they pass their monthly request limit
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OveragePolicy {
+    /// Calls past the limit are refused
+    #[default]
+    Block,
+    /// Calls past the limit go through, charged their price times `multiplier`
+    AllowWithOverageRate { multiplier: String },
+    /// Up to `requests` calls past the limit go through at their usual price before
+    /// further calls are refused
+    GraceRequests { requests: i64 },
+}
+
+/// A user's usage past their monthly request limit in one billing period
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OverageUsage {
+    pub billing_period: String,
+    pub request_count: i64,
+    pub total_cost: CostAmount,
+    pub started_at: DateTime<Utc>, // when the first call past the limit was admitted
+}
+
 /// API endpoint registration and monetization
 
 /// Monetizable API endpoint with pricing and access controls
@@ -65,6 +101,7 @@ pub struct UpdateUserRequest {
 pub struct ApiEndpoint {
     pub id: Uuid,
     pub name: String,
+    pub slug: String, // routes /proxy/{slug}; unique across owners, unlike name
     pub description: Option<String>,
     pub owner_id: Uuid,
     pub upstream_url: String,
@@ -78,10 +115,149 @@ pub struct ApiEndpoint {
     pub allowed_methods: Vec<String>,
     pub request_timeout: Option<i32>, // seconds
     pub retry_attempts: Option<i32>,
+    pub max_request_size: Option<i64>, // bytes; platform default when unset
+    pub bill_client_errors: bool, // whether upstream 4xx responses are charged
+    pub is_degraded: bool, // set by health checks after repeated upstream failures
+    pub upstream_targets: Json<Vec<UpstreamTarget>>, // load-balanced backends; upstream_url alone when empty
+    pub path_rewrite: Option<Json<PathRewrite>>, // applied to the request path before forwarding
+    pub forward_credentials: bool, // pass the consumer's AugustCredits key through to the upstream
+    #[serde(skip)]
+    pub upstream_auth_encrypted: Option<String>, // sealed UpstreamAuth; never returned by the API
+    pub retry_non_idempotent: bool, // retry POST/PUT/PATCH/DELETE without an Idempotency-Key
+    pub upstream_ws_url: Option<String>, // ws:// or wss:// upstream for /proxy-ws; HTTP only when unset
+    pub pricing_model: PricingModel, // how traffic is billed
+    pub price_per_kilobyte: Option<String>, // charged per started response kilobyte by byte-based models
+    pub pricing_tiers: Json<Vec<PricingTier>>, // volume discounts on the billable unit; list price only when empty
+    pub sandbox_response: Option<Json<SandboxResponse>>, // canned answer to sandbox requests
+    pub sandbox_upstream_url: Option<String>, // serves sandbox requests when no canned response is set
+    pub tags: Vec<String>, // lowercase search keywords for the public listing
+    pub category: Option<String>,
+    pub sla_max_latency_ms: Option<i32>, // billed calls slower than this are refunded
+    pub sla_error_refund: bool, // refund billed calls that fail with a 5xx
+    pub max_concurrent_requests: Option<i32>, // calls a consumer may have in flight at once
+    pub rate_limit_algorithm: RateLimitAlgorithm, // how rate_limit is enforced
+    pub rate_limit_burst: Option<i32>, // token bucket capacity; platform default when unset
+    pub consumer_monthly_quota: Option<i64>, // billable units any one consumer may use per month
+    pub capture_samples: bool, // keep redacted request/response samples for debugging
+    pub sample_rate: f64, // fraction of calls sampled while capture_samples is on
+    pub sample_max_body_bytes: i32, // bytes of each body kept in a sample
 }
 
-/// Request payload for registering new API endpoints
+impl ApiEndpoint {
+    /// Upstream targets eligible to serve requests, falling back to `upstream_url`
+    /// when no weighted targets are configured
+    pub fn targets(&self) -> Vec<UpstreamTarget> {
+        let targets: Vec<UpstreamTarget> = self.upstream_targets.iter()
+            .filter(|target| target.weight > 0)
+            .cloned()
+            .collect();
+
+        if targets.is_empty() {
+            vec![UpstreamTarget { url: self.upstream_url.clone(), weight: 1 }]
+        } else {
+            targets
+        }
+    }
+}
+
+/// How an endpoint's traffic is billed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "pricing_model", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+pub enum PricingModel {
+    /// `price_per_request` for each HTTP request, or each WebSocket connection
+    #[default]
+    PerRequest,
+    /// `price_per_request` for each started minute a WebSocket connection stays open
+    PerConnectionMinute,
+    /// `price_per_request` for each text or binary WebSocket message, in either direction
+    PerMessage,
+    /// `price_per_kilobyte` for each started kilobyte of response body, or of WebSocket
+    /// traffic in either direction
+    PerKilobyte,
+    /// `price_per_request` for each request plus `price_per_kilobyte` for each started
+    /// kilobyte of response body
+    PerRequestPlusBytes,
+}
+
+/// How an endpoint's rate limit is enforced
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "rate_limit_algorithm", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    /// At most `rate_limit` requests in any `rate_limit_window` seconds
+    #[default]
+    SlidingWindow,
+    /// Tokens refill at `rate_limit` per `rate_limit_window` seconds and a request
+    /// spends one; up to `rate_limit_burst` can be saved up for bursts
+    TokenBucket,
+}
+
+/// Volume discount on an endpoint's billable unit
+///
+/// Prices the consumer's units this billing period up to and including `up_to`,
+/// after those covered by earlier tiers. The last tier may leave `up_to` unset to
+/// cover all remaining units; otherwise units past it are charged the list price.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PricingTier {
+    pub up_to: Option<i64>,
+    pub price: String,
+}
+
+/// Canned response returned to sandbox requests instead of calling an upstream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SandboxResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub body: serde_json::Value,
+}
+
+/// Upstream backend of an endpoint; traffic is spread in proportion to `weight`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamTarget {
+    pub url: String,
+    #[serde(default = "default_target_weight")]
+    pub weight: u32,
+}
+
+fn default_target_weight() -> u32 {
+    1
+}
+
+/// Credentials the gateway attaches to every upstream request on the owner's behalf
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpstreamAuth {
+    /// Sends `value` in the header `name`, e.g. `X-Api-Key`
+    Header { name: String, value: String },
+    /// Sends HTTP basic auth
+    Basic { username: String, password: String },
+}
+
+/// Request payload for setting or clearing an endpoint's upstream credentials
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateUpstreamAuthRequest {
+    /// New credentials; `null` stops the gateway from sending any
+    pub upstream_auth: Option<UpstreamAuth>,
+}
+
+/// Rewrites the incoming request path before it is appended to the upstream URL.
+/// Steps run in order: `strip_prefix`, then the `pattern` substitution, then `add_prefix`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathRewrite {
+    pub strip_prefix: Option<String>,
+    pub add_prefix: Option<String>,
+    /// Regex replaced at its first match; `replacement` may use `$1`-style groups
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+/// Request payload for registering new API endpoints
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateEndpointRequest {
     pub name: String,
     pub description: Option<String>,
@@ -93,10 +269,79 @@ pub struct CreateEndpointRequest {
     pub allowed_methods: Option<Vec<String>>,
     pub request_timeout: Option<i32>,
     pub retry_attempts: Option<i32>,
+    /// Largest accepted request body in bytes
+    #[serde(default)]
+    pub max_request_size: Option<i64>,
+    /// Charge consumers for upstream 4xx responses (defaults to false)
+    #[serde(default)]
+    pub bill_client_errors: Option<bool>,
+    /// Weighted upstreams to load balance across instead of `upstream_url` alone
+    #[serde(default)]
+    pub upstream_targets: Option<Vec<UpstreamTarget>>,
+    /// Rewrite applied to the request path before forwarding
+    #[serde(default)]
+    pub path_rewrite: Option<PathRewrite>,
+    /// Pass the consumer's AugustCredits credentials through to the upstream (defaults to false)
+    #[serde(default)]
+    pub forward_credentials: Option<bool>,
+    /// Retry non-idempotent methods on upstream failures (defaults to false)
+    #[serde(default)]
+    pub retry_non_idempotent: Option<bool>,
+    /// ws:// or wss:// upstream served at /proxy-ws
+    #[serde(default)]
+    pub upstream_ws_url: Option<String>,
+    /// How traffic is billed (defaults to per request)
+    #[serde(default)]
+    pub pricing_model: Option<PricingModel>,
+    /// Price per started kilobyte of response, required by byte-based pricing models
+    #[serde(default)]
+    pub price_per_kilobyte: Option<String>,
+    /// Volume discounts by month-to-date usage, in ascending order of `up_to`
+    #[serde(default)]
+    pub pricing_tiers: Option<Vec<PricingTier>>,
+    /// Canned response for requests sent with X-AugustCredits-Sandbox: true
+    #[serde(default)]
+    pub sandbox_response: Option<SandboxResponse>,
+    /// Upstream for sandbox requests when no canned response is configured
+    #[serde(default)]
+    pub sandbox_upstream_url: Option<String>,
+    /// Keywords the endpoint can be found by in the public listing
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Category the endpoint is listed under
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Latency bound, in milliseconds, past which billed calls are refunded
+    #[serde(default)]
+    pub sla_max_latency_ms: Option<i32>,
+    /// Whether billed calls failing with a 5xx are refunded
+    #[serde(default)]
+    pub sla_error_refund: Option<bool>,
+    /// Calls a consumer may have in flight at once
+    #[serde(default)]
+    pub max_concurrent_requests: Option<i32>,
+    /// How the rate limit is enforced
+    #[serde(default)]
+    pub rate_limit_algorithm: Option<RateLimitAlgorithm>,
+    /// Requests a token bucket lets through in a burst
+    #[serde(default)]
+    pub rate_limit_burst: Option<i32>,
+    /// Billable units any one consumer may use per month, unless overridden for them
+    #[serde(default)]
+    pub consumer_monthly_quota: Option<i64>,
+    /// Keep redacted request/response samples of calls for debugging
+    #[serde(default)]
+    pub capture_samples: Option<bool>,
+    /// Fraction of calls sampled, between 0 (exclusive) and 1
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    /// Bytes of each request and response body kept in a sample
+    #[serde(default)]
+    pub sample_max_body_bytes: Option<i32>,
 }
 
 /// Request payload for updating endpoint configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UpdateEndpointRequest {
     pub description: Option<String>,
     pub upstream_url: Option<String>,
@@ -108,10 +353,366 @@ pub struct UpdateEndpointRequest {
     pub allowed_methods: Option<Vec<String>>,
     pub request_timeout: Option<i32>,
     pub retry_attempts: Option<i32>,
+    /// Largest accepted request body in bytes
+    #[serde(default)]
+    pub max_request_size: Option<i64>,
+    /// Charge consumers for upstream 4xx responses (defaults to false)
+    #[serde(default)]
+    pub bill_client_errors: Option<bool>,
+    /// Weighted upstreams to load balance across instead of `upstream_url` alone
+    #[serde(default)]
+    pub upstream_targets: Option<Vec<UpstreamTarget>>,
+    /// Rewrite applied to the request path before forwarding
+    #[serde(default)]
+    pub path_rewrite: Option<PathRewrite>,
+    /// Pass the consumer's AugustCredits credentials through to the upstream (defaults to false)
+    #[serde(default)]
+    pub forward_credentials: Option<bool>,
+    /// Retry non-idempotent methods on upstream failures (defaults to false)
+    #[serde(default)]
+    pub retry_non_idempotent: Option<bool>,
+    /// ws:// or wss:// upstream served at /proxy-ws
+    #[serde(default)]
+    pub upstream_ws_url: Option<String>,
+    /// How traffic is billed (defaults to per request)
+    #[serde(default)]
+    pub pricing_model: Option<PricingModel>,
+    /// Price per started kilobyte of response, required by byte-based pricing models
+    #[serde(default)]
+    pub price_per_kilobyte: Option<String>,
+    /// Volume discounts by month-to-date usage, in ascending order of `up_to`
+    #[serde(default)]
+    pub pricing_tiers: Option<Vec<PricingTier>>,
+    /// Canned response for requests sent with X-AugustCredits-Sandbox: true
+    #[serde(default)]
+    pub sandbox_response: Option<SandboxResponse>,
+    /// Upstream for sandbox requests when no canned response is configured
+    #[serde(default)]
+    pub sandbox_upstream_url: Option<String>,
+    /// Keywords the endpoint can be found by in the public listing
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Category the endpoint is listed under
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Latency bound, in milliseconds, past which billed calls are refunded
+    #[serde(default)]
+    pub sla_max_latency_ms: Option<i32>,
+    /// Whether billed calls failing with a 5xx are refunded
+    #[serde(default)]
+    pub sla_error_refund: Option<bool>,
+    /// Calls a consumer may have in flight at once
+    #[serde(default)]
+    pub max_concurrent_requests: Option<i32>,
+    /// How the rate limit is enforced
+    #[serde(default)]
+    pub rate_limit_algorithm: Option<RateLimitAlgorithm>,
+    /// Requests a token bucket lets through in a burst
+    #[serde(default)]
+    pub rate_limit_burst: Option<i32>,
+    /// Billable units any one consumer may use per month, unless overridden for them
+    #[serde(default)]
+    pub consumer_monthly_quota: Option<i64>,
+    /// Keep redacted request/response samples of calls for debugging
+    #[serde(default)]
+    pub capture_samples: Option<bool>,
+    /// Fraction of calls sampled, between 0 (exclusive) and 1
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    /// Bytes of each request and response body kept in a sample
+    #[serde(default)]
+    pub sample_max_body_bytes: Option<i32>,
+}
+
+/// Result of a single active health probe against an endpoint's upstream
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointHealthCheck {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub healthy: bool,
+    pub status_code: Option<i32>,
+    pub latency_ms: i32,
+    pub error_message: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Outcome of a health probe, before it is recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEndpointHealthCheckRequest {
+    pub endpoint_id: Uuid,
+    pub healthy: bool,
+    pub status_code: Option<i32>,
+    pub latency_ms: i32,
+    pub error_message: Option<String>,
+}
+
+/// Endpoint health flags after a probe has been recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointHealthState {
+    pub was_degraded: bool,
+    pub is_degraded: bool,
+    pub is_active: bool,
+    pub consecutive_failures: i32,
+}
+
+/// Lifecycle of a usage spike alert
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "alert_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Active,
+    Resolved,
+}
+
+/// Alert raised when a user calls an endpoint far more often than usual
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UsageAlert {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub endpoint_name: String,
+    pub status: AlertStatus,
+    pub hourly_requests: i64, // trailing-hour requests when raised
+    pub peak_hourly_requests: i64,
+    pub baseline_hourly: f64, // usual hourly requests the spike was measured against
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// A user's trailing-hour requests to an endpoint next to their usual hourly rate
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointRequestRate {
+    pub user_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub hourly_requests: i64,
+    pub baseline_hourly: f64,
+}
+
+/// Query parameters for listing a user's alerts, newest first
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct AlertQuery {
+    pub status: Option<AlertStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Endpoint details together with the most recent upstream health probe and a
+/// summary of its OpenAPI spec, if the owner uploaded one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointDetails {
+    #[serde(flatten)]
+    pub endpoint: ApiEndpoint,
+    pub latest_health: Option<EndpointHealthCheck>,
+    pub spec: Option<SpecSummary>,
+}
+
+/// Format an endpoint's OpenAPI spec was uploaded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "spec_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SpecFormat {
+    Json,
+    Yaml,
+}
+
+impl SpecFormat {
+    /// Content type the spec is served with
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SpecFormat::Json => "application/json",
+            SpecFormat::Yaml => "application/yaml",
+        }
+    }
+}
+
+/// OpenAPI document attached to an endpoint; the document itself is only served raw
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointSpec {
+    pub endpoint_id: Uuid,
+    pub format: SpecFormat,
+    #[serde(skip_serializing)]
+    pub content: String,
+    pub summary: Json<SpecSummary>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What an endpoint's OpenAPI spec describes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecSummary {
+    pub spec_version: String, // `openapi` or `swagger` version of the document
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub operations: Vec<SpecOperation>,
+}
+
+/// One operation listed in an OpenAPI spec
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecOperation {
+    pub method: String,
+    pub path: String,
+    pub summary: Option<String>,
+}
+
+/// Query parameters for uploading an endpoint's OpenAPI spec
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadSpecParams {
+    /// Replace the endpoint's allowed methods with those the spec uses
+    #[serde(default)]
+    pub derive_methods: bool,
+}
+
+/// Stored outcome of a proxied request sent with an Idempotency-Key
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IdempotencyRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub idempotency_key: String,
+    pub request_hash: String, // SHA-256 of method, path and body
+    pub status_code: Option<i32>, // None while the original request is in flight
+    pub response_headers: Option<Json<Vec<(String, String)>>>,
+    pub response_body: Option<Vec<u8>>,
+    pub cost: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Result of claiming an idempotency key for a proxied request
+#[derive(Debug, Clone)]
+pub enum IdempotencyReservation {
+    /// The key was free and is now held by this request
+    Reserved(Uuid),
+    /// Another request already holds the key
+    Existing(IdempotencyRecord),
 }
 
 // Usage Tracking
 
+/// Exact amount charged or owed, in wei or in units of the configured ERC-20 token
+///
+/// Costs are kept as decimals end to end so that summing many small wei charges, or a
+/// few very large ones, never drifts the way floating point would. Amounts are stored
+/// as TEXT and serialized as strings, both in their shortest exact form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CostAmount(Decimal);
+
+impl CostAmount {
+    pub const ZERO: Self = Self(Decimal::ZERO);
+
+    pub fn new(amount: Decimal) -> Self {
+        Self(amount.normalize())
+    }
+
+    pub fn as_decimal(&self) -> Decimal {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_sign_negative(&self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    /// Whether the amount has at most `decimals` decimal places, so it can be paid
+    /// on-chain in a currency with that many
+    pub fn fits_decimals(&self, decimals: u32) -> bool {
+        self.0.normalize().scale() <= decimals
+    }
+
+    /// Amount an on-chain value of `base_units` is, in a currency with `decimals`
+    /// decimal places; `None` if it is too large to represent
+    pub fn from_base_units(base_units: U256, decimals: u32) -> Option<Self> {
+        let mut amount = Decimal::from_str_exact(&base_units.to_string()).ok()?;
+        amount.set_scale(decimals).ok()?;
+        Some(Self::new(amount))
+    }
+
+    /// On-chain value of the amount in a currency with `decimals` decimal places;
+    /// `None` if it is negative or has more decimal places than the currency
+    pub fn to_base_units(self, decimals: u32) -> Option<U256> {
+        if self.is_sign_negative() || !self.fits_decimals(decimals) {
+            return None;
+        }
+        let amount = self.0.normalize();
+        U256::from(amount.mantissa().unsigned_abs()).checked_mul(U256::exp10((decimals - amount.scale()) as usize))
+    }
+
+    /// Sum of two amounts, or `None` past the largest representable amount
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self::new)
+    }
+
+    /// Product with a unitless factor such as a unit count or a billing share
+    pub fn checked_mul(self, factor: Decimal) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self::new)
+    }
+
+    /// Sum of all amounts, or `None` if it overflows
+    pub fn checked_sum<I: IntoIterator<Item = Self>>(amounts: I) -> Option<Self> {
+        amounts.into_iter().try_fold(Self::ZERO, Self::checked_add)
+    }
+}
+
+impl FromStr for CostAmount {
+    type Err = rust_decimal::Error;
+
+    fn from_str(amount: &str) -> Result<Self, Self::Err> {
+        Decimal::from_str_exact(amount.trim()).map(Self::new)
+    }
+}
+
+impl fmt::Display for CostAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Compares against the amount a string spells out, so `"1.50"` equals 1.5
+impl PartialEq<&str> for CostAmount {
+    fn eq(&self, other: &&str) -> bool {
+        other.parse::<CostAmount>().is_ok_and(|other| other == *self)
+    }
+}
+
+impl Serialize for CostAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CostAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        amount.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Type<Postgres> for CostAmount {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for CostAmount {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(<&str as Decode<Postgres>>::decode(value)?.parse()?)
+    }
+}
+
+impl Encode<'_, Postgres> for CostAmount {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <String as Encode<Postgres>>::encode(self.to_string(), buf)
+    }
+}
+
 /// Individual usage record for billing and analytics
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct UsageRecord {
@@ -119,13 +720,15 @@ pub struct UsageRecord {
     pub user_id: Uuid,
     pub endpoint_id: Uuid,
     pub request_count: i64,
-    pub total_cost: String, // Stored as string to handle large numbers
+    pub total_cost: CostAmount,
     pub timestamp: DateTime<Utc>,
     pub billing_period: String, // e.g., "2024-01"
     pub status: UsageStatus,
     pub transaction_hash: Option<String>,
     pub gas_used: Option<String>,
     pub block_number: Option<i64>,
+    /// Chain the usage was billed on
+    pub chain_id: Option<i64>,
 }
 
 /// Status of usage records in the billing pipeline
@@ -142,7 +745,7 @@ pub enum UsageStatus {
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct RequestLog {
     pub id: Uuid,
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>, // cleared once a deleted user's logs are anonymized
     pub endpoint_id: Uuid,
     pub request_id: String,
     pub method: String,
@@ -154,8 +757,13 @@ pub struct RequestLog {
     pub ip_address_hash: String,
     pub user_agent_hash: Option<String>,
     pub timestamp: DateTime<Utc>,
-    pub cost: String,
+    pub cost: CostAmount,
     pub error_message: Option<String>,
+    pub upstream_target: Option<String>,
+    pub stream_duration_ms: Option<i32>, // set for streamed responses and WebSocket sessions
+    pub cost_breakdown: Option<Json<CostBreakdown>>, // how `cost` splits across request and kilobyte charges
+    pub is_sandbox: bool, // unbilled sandbox request
+    pub api_key_id: Option<Uuid>, // named API key that made the call
 }
 
 /// Request payload for creating request log entries
@@ -172,8 +780,171 @@ pub struct CreateRequestLogRequest {
     pub response_size: Option<i64>,
     pub ip_address_hash: String,
     pub user_agent_hash: Option<String>,
-    pub cost: String,
+    pub cost: CostAmount,
     pub error_message: Option<String>,
+    pub upstream_target: Option<String>,
+    pub stream_duration_ms: Option<i32>,
+    pub cost_breakdown: Option<Json<CostBreakdown>>,
+    pub is_sandbox: bool,
+    pub api_key_id: Option<Uuid>,
+}
+
+/// Redacted request and response of a proxied call, kept for endpoints that capture samples
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RequestSample {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub request_id: String,
+    pub method: String,
+    pub path: String, // as called by the consumer, with credentials in the query redacted
+    pub request_headers: Json<BTreeMap<String, String>>, // sensitive values redacted
+    pub request_body: String, // lossily decoded as UTF-8
+    pub request_truncated: bool, // body was longer than the endpoint's sample_max_body_bytes
+    pub status_code: i32,
+    pub response_headers: Json<BTreeMap<String, String>>,
+    pub response_body: String,
+    pub response_truncated: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request payload for storing a request sample
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRequestSample {
+    pub endpoint_id: Uuid,
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub request_body: String,
+    pub request_truncated: bool,
+    pub status_code: i32,
+    pub response_headers: BTreeMap<String, String>,
+    pub response_body: String,
+    pub response_truncated: bool,
+}
+
+/// Request and kilobyte components of a proxied call's cost
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CostBreakdown {
+    pub pricing_model: PricingModel,
+    pub requests: i64, // charged at price_per_request
+    pub request_cost: CostAmount,
+    pub kilobytes: i64, // charged at price_per_kilobyte
+    pub kilobyte_cost: CostAmount,
+    pub total: CostAmount,
+}
+
+/// Owner-granted exception to an endpoint's consumer monthly quota
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointConsumerOverride {
+    pub endpoint_id: Uuid,
+    pub user_id: Uuid,
+    pub monthly_quota: Option<i64>, // replaces the endpoint's quota; None lifts it
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request payload for setting a consumer's quota on an endpoint; null lifts the cap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetConsumerQuotaRequest {
+    pub monthly_quota: Option<i64>,
+}
+
+/// A consumer's quota standing on one endpoint this billing period
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ConsumerQuotaUsage {
+    pub overridden: bool,
+    pub override_quota: Option<i64>,
+    pub used: i64,
+}
+
+/// Flat-rate monthly plan a consumer can subscribe to instead of paying per call
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointPlan {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub name: String,
+    pub monthly_price: String, // charged up front for each period
+    pub included_requests: i64, // billable units covered by the monthly price
+    pub overage_price: String, // per billable unit past included_requests
+    pub is_active: bool, // retired plans take no new subscribers and are not renewed
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request payload for adding a plan to an endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePlanRequest {
+    pub name: String,
+    pub monthly_price: String,
+    pub included_requests: i64,
+    pub overage_price: String,
+}
+
+/// Request payload for changing a plan; existing subscribers see the change immediately
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdatePlanRequest {
+    pub name: Option<String>,
+    pub monthly_price: Option<String>,
+    pub included_requests: Option<i64>,
+    pub overage_price: Option<String>,
+    pub is_active: Option<bool>,
+}
+
+/// Lifecycle of a consumer's subscription
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "subscription_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionStatus {
+    Active,
+    Cancelled,
+    /// Ended because the consumer's balance could not cover its renewal
+    Lapsed,
+}
+
+/// Consumer's subscription to an endpoint plan
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointSubscription {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub plan_id: Uuid,
+    pub status: SubscriptionStatus,
+    pub current_period_start: DateTime<Utc>,
+    pub current_period_end: DateTime<Utc>,
+    pub requests_used: i64, // billable units used this period
+    pub cancel_at_period_end: bool, // stops renewal; the plan applies until current_period_end
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request payload for subscribing to an endpoint plan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeRequest {
+    pub plan_id: Uuid,
+}
+
+/// A subscriber's allowance after counting a call against it
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct SubscriptionAllowance {
+    pub used_before: i64, // billable units used this period before the call
+    pub included_requests: i64,
+    pub overage_price: String,
+}
+
+/// Prices a call after a number of billable units already used this billing period,
+/// or against the subscription allowance it drew on
+pub type UsagePricer = std::sync::Arc<
+    dyn Fn(i64, Option<&SubscriptionAllowance>) -> anyhow::Result<CostBreakdown> + Send + Sync,
+>;
+
+/// Usage a proxied call adds to its consumer's aggregates when it is logged
+#[derive(Clone)]
+pub struct UsageCharge {
+    pub api_key_id: Option<Uuid>,
+    pub overage: bool, // admitted past the user's monthly limit
+    pub units: i64, // billable units drawn from a subscription allowance
+    pub price: UsagePricer,
 }
 
 // Billing and Payments
@@ -192,8 +963,11 @@ pub struct BillingRecord {
     pub transaction_hash: Option<String>,
     pub gas_used: Option<String>,
     pub block_number: Option<i64>,
+    /// Chain the period was billed on
+    pub chain_id: Option<i64>,
     pub retry_count: i32,
     pub error_message: Option<String>,
+    pub updated_at: DateTime<Utc>,
 }
 
 /// Status of billing records in the payment pipeline
@@ -207,6 +981,111 @@ pub enum BillingStatus {
     Cancelled,
 }
 
+/// Pending usage of a closed billing period, with what the contract needs to bill it
+#[derive(Debug, Clone, FromRow)]
+pub struct BillableUsage {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub endpoint_name: String,
+    pub request_count: i64,
+    pub total_cost: CostAmount,
+    pub billing_period: String,
+}
+
+/// On-chain transaction that billed usage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BillingTransaction {
+    pub hash: String,
+    pub gas_used: Option<String>,
+    pub block_number: Option<i64>,
+    pub chain_id: i64,
+}
+
+/// Usage records one queued transaction settled for a user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettledUsage {
+    pub user_id: Uuid,
+    pub usage_record_ids: Vec<Uuid>,
+}
+
+/// What a user's transaction moved on-chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionEventKind {
+    Billing,
+    Withdrawal,
+}
+
+/// Payload of the `transaction.confirmed` and `transaction.failed` webhook events
+///
+/// `record_ids` are the usage records a billing transaction carried, or the payment
+/// transaction of a withdrawal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionEvent {
+    pub kind: TransactionEventKind,
+    pub transaction_id: Uuid,
+    pub transaction_hash: Option<String>,
+    pub block_number: Option<i64>,
+    pub chain_id: Option<i64>,
+    pub record_ids: Vec<Uuid>,
+    pub error: Option<String>,
+}
+
+/// Status of an operation in the blockchain transaction queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "blockchain_tx_status", rename_all = "lowercase")]
+pub enum BlockchainTxStatus {
+    /// Waiting to be sent
+    Pending,
+    /// Sent and waiting for confirmation
+    Submitted,
+    Confirmed,
+    /// The last attempt failed; sent again once `next_attempt_at` passes
+    Failed,
+    /// Out of attempts
+    Abandoned,
+}
+
+/// An operation queued to be sent to the chain by the transaction queue
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct BlockchainTx {
+    pub id: Uuid,
+    pub operation: String,
+    pub payload: serde_json::Value,
+    pub status: BlockchainTxStatus,
+    /// Transaction of the latest attempt
+    pub tx_hash: Option<String>,
+    pub block_number: Option<i64>,
+    pub gas_used: Option<String>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Outcome of a billing run, returned to the admin who triggered it
+///
+/// Counts the usage queued to be billed on-chain; it is marked billed once the
+/// transaction queue confirms the transaction carrying it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BillingRunSummary {
+    pub users_billed: i64,
+    pub records_billed: i64,
+    pub total_requests: i64,
+    pub total_cost: CostAmount,
+    pub failures: Vec<BillingFailure>,
+}
+
+/// A user's billing period left pending because it could not be queued for billing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillingFailure {
+    pub user_id: Uuid,
+    pub billing_period: String,
+    pub error: String,
+}
+
 // Analytics
 
 /// Platform-wide analytics and metrics
@@ -214,7 +1093,7 @@ pub enum BillingStatus {
 pub struct AnalyticsData {
     pub period: String,
     pub total_requests: i64,
-    pub total_revenue: String,
+    pub total_revenue: CostAmount,
     pub new_users: i64,
     pub active_users: i64,
     pub start_date: DateTime<Utc>,
@@ -230,6 +1109,73 @@ pub struct EndpointStats {
     pub avg_response_time: f64,
     pub error_rate: f64,
     pub revenue: String,
+    pub pricing_model: PricingModel,
+    pub price_per_request: String,
+    pub price_per_kilobyte: Option<String>,
+    pub pricing_tiers: Vec<PricingTier>,
+    /// SLA refunds over the range, reported to the endpoint's owner only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sla_refunds: Option<SlaRefundStats>,
+}
+
+/// How a billed call breached its endpoint's SLA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "sla_breach", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SlaBreach {
+    Latency,
+    ServerError,
+}
+
+/// A billed call flagged for refund after breaching its endpoint's SLA
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SlaRefund {
+    pub id: Uuid,
+    pub usage_record_id: Uuid,
+    pub user_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub request_id: String,
+    pub amount: String,
+    pub breach: SlaBreach,
+    pub created_at: DateTime<Utc>,
+    pub refunded_at: Option<DateTime<Utc>>,
+}
+
+/// A user's aggregated usage of one endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct EndpointUsageSummary {
+    pub endpoint_id: Uuid,
+    pub endpoint_name: String,
+    pub total_requests: i64,
+    pub total_cost: CostAmount,
+}
+
+/// Volume of an endpoint's calls refunded, or awaiting refund, for breaching its SLA
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct SlaRefundStats {
+    pub refunded_requests: i64,
+    pub refunded_amount: String,
+    pub pending_requests: i64,
+    pub pending_amount: String,
+    pub latency_breaches: i64,
+    pub error_breaches: i64,
+}
+
+/// Inclusive UTC date range for traffic statistics; open-ended when a bound is unset
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StatsRange {
+    pub start: Option<chrono::NaiveDate>,
+    pub end: Option<chrono::NaiveDate>,
+}
+
+/// Traffic aggregated over endpoints and days
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct TrafficStats {
+    pub total_requests: i64,
+    pub requests_today: i64,
+    pub avg_response_time: f64, // milliseconds
+    pub error_rate: f64, // share of requests answered with a 4xx or 5xx status
+    pub revenue: String,
 }
 
 // PaymentTransaction struct removed as it was unused
@@ -255,6 +1201,70 @@ pub enum TransactionStatus {
     Cancelled,
 }
 
+// Usage Exports
+
+/// File format of a usage export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "export_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Progress of a usage export generated in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "export_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// Usage export too large to stream, generated in the background and downloaded by id
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UsageExport {
+    pub id: Uuid,
+    pub requested_by: Uuid,
+    pub user_id: Option<Uuid>, // every user's requests when unset
+    pub format: ExportFormat,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: DateTime<Utc>,
+    pub status: ExportStatus,
+    pub row_count: Option<i64>,
+    pub error_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// One request of a usage export
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct UsageExportRow {
+    pub timestamp: DateTime<Utc>,
+    pub user_id: Uuid,
+    pub api_key_id: Option<Uuid>, // named API key that made the call
+    pub endpoint_name: String,
+    pub method: String,
+    pub status_code: i32,
+    pub response_time_ms: i32,
+    pub cost: CostAmount,
+}
+
+/// Query parameters selecting the requests to export; the range is half-open and
+/// ends now when `end` is unset
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageExportQuery {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub format: Option<ExportFormat>,
+    /// Only honoured for admins; users always export their own requests
+    pub user_id: Option<Uuid>,
+}
+
 // Analytics and Reporting
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -269,6 +1279,7 @@ pub struct DailyStats {
     pub avg_response_time: f64,
     pub error_rate: f64,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>, // last time the day was re-aggregated
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -311,6 +1322,7 @@ pub struct RateLimitEntry {
     pub user_id: Uuid,
     pub endpoint_id: Uuid,
     pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
     pub request_count: i32,
     pub limit_exceeded: bool,
     pub created_at: DateTime<Utc>,
@@ -332,6 +1344,7 @@ pub struct ApiKey {
     pub created_at: DateTime<Utc>,
     pub usage_count: i64,
     pub rate_limit_override: Option<i32>,
+    pub allowed_ips: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -340,6 +1353,117 @@ pub struct CreateApiKeyRequest {
     pub permissions: Option<Vec<String>>,
     pub expires_at: Option<DateTime<Utc>>,
     pub rate_limit_override: Option<i32>,
+    /// CIDR blocks the key may be used from; any address if unset
+    #[serde(default)]
+    pub allowed_ips: Option<Vec<String>>,
+}
+
+/// API key metadata returned to its owner; never includes the key itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub is_active: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub usage_count: i64,
+    pub rate_limit_override: Option<i32>,
+    pub allowed_ips: Option<Vec<String>>,
+}
+
+/// Newly created API key; the raw key is only ever returned here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiKeyResponse {
+    pub key: String,
+    pub api_key: ApiKeyResponse,
+}
+
+/// Request to rotate the user's primary API key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RotateApiKeyRequest {
+    /// How long the old key keeps working after rotation; it stops immediately if unset
+    #[serde(default)]
+    pub grace_period_seconds: Option<u64>,
+}
+
+/// Newly rotated primary API key; the raw key is only ever returned here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotateApiKeyResponse {
+    pub api_key: String,
+    pub previous_key_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Justification supplied with administrative actions on a user account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminActionRequest {
+    pub reason: String,
+}
+
+/// Who performed an audited action, and a hash of the address they acted from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditActor {
+    /// `None` for actions performed by the system itself
+    pub id: Option<Uuid>,
+    pub ip_address_hash: Option<String>,
+}
+
+/// Entry in the audit trail of administrative and security-sensitive actions
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<Uuid>,
+    pub metadata: serde_json::Value,
+    pub ip_address_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Query parameters for browsing the audit log, newest first; the date range is
+/// half-open like [`RequestLogQuery`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditLogQuery {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Filters for the admin user list; the registration range is half-open like
+/// [`RequestLogQuery`], and paging and sorting come from [`PaginationParams`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserListQuery {
+    /// Matched against the wallet address, email and username
+    pub q: Option<String>,
+    pub tier: Option<UserTier>,
+    pub is_active: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+/// Replacement primary API key issued by an administrator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegenerateApiKeyResponse {
+    pub api_key: String,
+    /// Number of named API keys that were revoked alongside the primary key
+    pub revoked_api_keys: u64,
+}
+
+/// Stored refresh token; only the SHA-256 hash of the token is persisted
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub revoked: bool,
+    pub created_at: DateTime<Utc>,
 }
 
 // System Configuration
@@ -350,7 +1474,13 @@ pub struct SystemConfig {
     pub value: String,
     pub description: Option<String>,
     pub updated_at: DateTime<Utc>,
-    pub updated_by: Uuid,
+    pub updated_by: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateSystemConfigRequest {
+    pub value: String,
+    pub description: Option<String>,
 }
 
 // Webhook Configuration
@@ -393,6 +1523,70 @@ pub enum WebhookStatus {
     Cancelled,
 }
 
+/// Request to register a webhook endpoint for some of the user's events
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateWebhookEndpointRequest {
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+/// Changes to a webhook endpoint; fields left unset are kept
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateWebhookEndpointRequest {
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub is_active: Option<bool>,
+}
+
+/// Webhook endpoint returned to its owner; never includes the signing secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEndpointResponse {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<String>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_triggered: Option<DateTime<Utc>>,
+    pub failure_count: i32,
+    pub max_retries: i32,
+}
+
+impl From<WebhookEndpoint> for WebhookEndpointResponse {
+    fn from(webhook: WebhookEndpoint) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            is_active: webhook.is_active,
+            created_at: webhook.created_at,
+            last_triggered: webhook.last_triggered,
+            failure_count: webhook.failure_count,
+            max_retries: webhook.max_retries,
+        }
+    }
+}
+
+/// Newly registered webhook endpoint; its signing secret is only ever returned here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateWebhookEndpointResponse {
+    pub secret: String,
+    pub webhook: WebhookEndpointResponse,
+}
+
+/// A queued webhook delivery claimed for sending, with where and how to send it
+#[derive(Debug, Clone, FromRow)]
+pub struct DueWebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub retry_count: i32,
+    pub url: String,
+    pub secret: String,
+    pub max_retries: i32,
+}
+
 // Response DTOs
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -400,12 +1594,14 @@ pub struct UserResponse {
     pub id: Uuid,
     pub wallet_address: String,
     pub email: Option<String>,
+    pub email_verified: bool,
     pub username: Option<String>,
     pub is_active: bool,
     pub tier: UserTier,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub monthly_limit: Option<i64>,
+    pub overage_policy: OveragePolicy,
     pub current_usage: i64,
     pub balance: String,
 }
@@ -436,6 +1632,34 @@ pub struct UsageResponse {
     pub last_request: Option<DateTime<Utc>>,
 }
 
+// Endpoint Search
+
+/// Query parameters for searching the public endpoint listing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointSearchParams {
+    /// Matched against the name, description and tags
+    pub q: Option<String>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    /// Bounds on the price per request, inclusive
+    pub min_price: Option<String>,
+    pub max_price: Option<String>,
+    pub sort: Option<EndpointSort>,
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+/// Order of endpoint search results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointSort {
+    /// Most recently registered first
+    #[default]
+    Newest,
+    /// Most requests over the last 30 days first
+    Popular,
+}
+
 // Pagination
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -446,18 +1670,15 @@ pub struct PaginationParams {
     pub sort_order: Option<SortOrder>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Pagination {
-    pub limit: Option<i64>,
-    pub offset: Option<i64>,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SortOrder {
     Asc,
     Desc,
 }
 
+/// A page of rows with its total and page count, for small tables and listings that
+/// need them. Offset pagination gets slower the deeper it pages, so large append-only
+/// tables like request_logs use [`CursorPage`] instead.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
@@ -478,6 +1699,156 @@ impl<T> PaginatedResponse<T> {
             total_pages,
         }
     }
+
+    /// Converts every row of the page, keeping its position and totals
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> PaginatedResponse<U> {
+        PaginatedResponse {
+            data: self.data.into_iter().map(f).collect(),
+            total: self.total,
+            page: self.page,
+            limit: self.limit,
+            total_pages: self.total_pages,
+        }
+    }
+}
+
+/// A page of rows from a large table, read by keyset pagination so every page costs
+/// the same however deep it is. Pass `next_cursor` back as `cursor` for the next page;
+/// it is unset on the last page. There is no total or page count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CursorPage<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Position of a request log in (timestamp, id) order, passed to clients as an opaque
+/// base64 token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLogCursor {
+    pub timestamp: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl fmt::Display for RequestLogCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let position = format!("{}|{}", self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true), self.id);
+        f.write_str(&URL_SAFE_NO_PAD.encode(position))
+    }
+}
+
+impl FromStr for RequestLogCursor {
+    type Err = String;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let invalid = || "Invalid cursor".to_string();
+        let position = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let position = String::from_utf8(position).map_err(|_| invalid())?;
+        let (timestamp, id) = position.split_once('|').ok_or_else(invalid)?;
+        Ok(Self {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).map_err(|_| invalid())?.with_timezone(&Utc),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl<T> CursorPage<T> {
+    /// Converts each row, keeping the cursor
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> CursorPage<U> {
+        CursorPage {
+            data: self.data.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Range of HTTP status codes, like "4xx"
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StatusClass {
+    #[serde(rename = "1xx")]
+    Informational,
+    #[serde(rename = "2xx")]
+    Success,
+    #[serde(rename = "3xx")]
+    Redirection,
+    #[serde(rename = "4xx")]
+    ClientError,
+    #[serde(rename = "5xx")]
+    ServerError,
+}
+
+impl StatusClass {
+    /// The first digit shared by the class's status codes
+    pub fn digit(self) -> i32 {
+        match self {
+            Self::Informational => 1,
+            Self::Success => 2,
+            Self::Redirection => 3,
+            Self::ClientError => 4,
+            Self::ServerError => 5,
+        }
+    }
+}
+
+/// Query parameters for browsing request logs, newest first; the date range is
+/// half-open like [`UsageExportQuery`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestLogQuery {
+    /// Only honoured for admins; users always browse their own requests
+    pub user_id: Option<Uuid>,
+    pub endpoint_id: Option<Uuid>,
+    pub status_code: Option<i32>,
+    pub status_class: Option<StatusClass>,
+    /// Only failed requests: a 4xx or 5xx response, or no response at all
+    #[serde(default)]
+    pub errors_only: bool,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// A request as shown to the consumer who made it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestLogEntry {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub status_code: i32,
+    pub response_time_ms: i32,
+    pub cost: CostAmount,
+    pub error_message: Option<String>,
+    pub is_sandbox: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<RequestLog> for RequestLogEntry {
+    fn from(log: RequestLog) -> Self {
+        Self {
+            id: log.id,
+            endpoint_id: log.endpoint_id,
+            request_id: log.request_id,
+            method: log.method,
+            path: log.path,
+            status_code: log.status_code,
+            response_time_ms: log.response_time_ms,
+            cost: log.cost,
+            error_message: log.error_message,
+            is_sandbox: log.is_sandbox,
+            timestamp: log.timestamp,
+        }
+    }
+}
+
+/// A request to an endpoint as shown to its owner, who sees an anonymous token in
+/// place of the consumer. The token is stable for a consumer of one endpoint but
+/// differs between endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointRequestLogEntry {
+    pub consumer: Option<String>, // None once a deleted consumer's logs are anonymized
+    #[serde(flatten)]
+    pub request: RequestLogEntry,
 }
 
 // Error types
@@ -556,6 +1927,7 @@ pub struct LoginRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoginResponse {
     pub access_token: String,
+    pub refresh_token: String,
     pub token_type: String,
     pub expires_in: i64,
     pub user: UserResponse,
@@ -577,10 +1949,68 @@ pub struct RegisterRequest {
 pub struct RegisterResponse {
     pub user: UserResponse,
     pub access_token: String,
+    pub refresh_token: String,
     pub token_type: String,
     pub expires_in: i64,
 }
 
+/// Query parameters for requesting a sign-in challenge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonceRequest {
+    pub wallet_address: String,
+    #[serde(default)]
+    pub format: SignMessageFormat,
+}
+
+/// Format of the message a wallet is asked to sign
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignMessageFormat {
+    /// AugustCredits' original plain-text challenge
+    #[default]
+    Legacy,
+    /// Sign-In with Ethereum (EIP-4361)
+    Siwe,
+}
+
+/// Sign-in challenge the wallet must sign to register or log in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonceResponse {
+    pub wallet_address: String,
+    pub nonce: String,
+    pub message: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Server-side record of an issued, not yet consumed nonce
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AuthNonce {
+    pub nonce: String,
+    pub wallet_address: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Rows removed by one cleanup of expired nonces and tokens
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExpiredAuthRows {
+    pub nonces: u64,
+    pub refresh_tokens: u64,
+    pub revoked_tokens: u64,
+}
+
+/// Single-use email verification token; only the SHA-256 hash is persisted
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct VerificationToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub email: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RefreshTokenRequest {
     pub refresh_token: String,
@@ -591,33 +2021,217 @@ pub struct UserProfile {
     pub id: Uuid,
     pub wallet_address: String,
     pub email: Option<String>,
+    pub email_verified: bool,
     pub username: Option<String>,
     pub tier: UserTier,
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub monthly_limit: Option<i64>,
+    pub overage_policy: OveragePolicy,
     pub current_usage: i64,
     pub balance: String,
 }
 
 /// User account balance information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct UserBalance {
     pub user_id: Uuid,
-    pub balance: String,
-    pub pending_charges: String,
+    pub balance: CostAmount, // prepaid funds left after every charge so far
+    pub pending_charges: CostAmount, // usage recorded but not yet billed on-chain
     pub last_updated: DateTime<Utc>,
 }
 
+/// Kind of movement recorded in the balance ledger
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "ledger_entry_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerEntryType {
+    Deposit,
+    Charge,
+    Refund,
+    Withdrawal,
+    /// Credits back a withdrawal, escrow or stream whose on-chain transaction failed
+    Reversal,
+    /// Correction or goodwill credit made by an administrator
+    Adjustment,
+    /// Funds moved into an escrow on the payments contract
+    Escrow,
+    /// Funds moved into a payment stream on the payments contract
+    Stream,
+}
+
+impl LedgerEntryType {
+    /// The platform account on the other side of a user's entry of this type
+    pub fn platform_account(self) -> PlatformAccount {
+        match self {
+            LedgerEntryType::Deposit
+            | LedgerEntryType::Withdrawal
+            | LedgerEntryType::Reversal
+            | LedgerEntryType::Escrow
+            | LedgerEntryType::Stream => PlatformAccount::Treasury,
+            LedgerEntryType::Charge | LedgerEntryType::Refund => PlatformAccount::Revenue,
+            LedgerEntryType::Adjustment => PlatformAccount::Adjustments,
+        }
+    }
+}
+
+/// Platform-side account balancing users' ledger entries
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[sqlx(type_name = "platform_account", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PlatformAccount {
+    /// Funds deposited and paid out on-chain
+    Treasury,
+    /// Charges for calls, less refunds
+    Revenue,
+    /// Administrator corrections and goodwill credits
+    Adjustments,
+}
+
+/// A user's side of a ledger transaction
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LedgerEntry {
+    pub id: Uuid,
+    pub transaction_id: Uuid,
+    pub entry_type: LedgerEntryType,
+    pub amount: CostAmount, // signed; debits are negative
+    pub balance_after: CostAmount,
+    pub reference: Option<String>, // request ID, transaction ID or audit log entry
+    pub created_at: DateTime<Utc>,
+    /// Status of the payout behind a withdrawal or its reversal
+    #[sqlx(default)]
+    pub transaction_status: Option<TransactionStatus>,
+    #[sqlx(default)]
+    pub transaction_hash: Option<String>,
+}
+
+/// Query parameters for listing a user's ledger entries, newest first
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LedgerQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Whether an administrator adjustment adds to or takes from a balance
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustmentDirection {
+    Credit,
+    Debit,
+}
+
+/// Request to correct a user's balance, recorded in the audit log with its reason
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceAdjustmentRequest {
+    pub amount: String,
+    pub direction: AdjustmentDirection,
+    pub reason: String,
+}
+
+/// Outcome of comparing a user's ledger balance with their billing contract balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "reconciliation_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReconciliationStatus {
+    /// Within the auto-correct threshold, and adjusted in the ledger
+    Corrected,
+    /// Awaiting review by an administrator
+    Flagged,
+    /// Reviewed by an administrator
+    Resolved,
+}
+
+/// A user's wallet and ledger position, as compared with the billing contract
+#[derive(Debug, Clone, FromRow)]
+pub struct ReconciliationAccount {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub ledger_balance: CostAmount,
+    /// Usage charged to the ledger balance but not yet billed on-chain
+    pub pending_charges: CostAmount,
+}
+
+/// A drift found between a user's ledger balance and their on-chain balance
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ReconciliationReport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    /// Block the contract balance was read at
+    pub block_number: i64,
+    pub chain_balance: CostAmount,
+    pub pending_charges: CostAmount,
+    pub ledger_balance: CostAmount,
+    /// Contract balance less pending charges, less the ledger balance
+    pub drift: CostAmount,
+    pub status: ReconciliationStatus,
+    pub resolved_by: Option<Uuid>,
+    pub resolution_note: Option<String>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Filters for the admin reconciliation report list, newest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReconciliationQuery {
+    pub status: Option<ReconciliationStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Result of holding funds for a proxied call before it is forwarded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceReservation {
+    /// The funds are held until the call is charged or released
+    Held(Uuid),
+    /// The consumer's available balance does not cover the hold
+    Insufficient { available: String },
+}
+
+/// Result of debiting a user's balance for a withdrawal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WithdrawalOutcome {
+    /// The balance was debited and the payout is waiting to be sent on-chain
+    Pending { transaction_id: Uuid, created_at: DateTime<Utc> },
+    /// The balance left after open holds does not cover the withdrawal
+    Insufficient { available: CostAmount },
+    /// The withdrawal would take the user past their daily cap
+    DailyLimitExceeded { withdrawn_today: CostAmount, limit: CostAmount },
+}
+
+/// What to pay, and where, to fund a balance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepositInstructions {
+    /// Decimal places of the token, which balances and prices are given in token units
+    /// of; 0 when they are given in wei
+    pub decimals: u32,
+    pub chains: Vec<DepositChain>,
+}
+
+/// Where to deposit on one chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepositChain {
+    pub chain_id: u64,
+    /// ERC-20 token to transfer; `None` when deposits are made in the native coin
+    pub token_address: Option<String>,
+    /// Payments contract to transfer the token to, or to deposit the native coin with;
+    /// the paying wallet's balance is credited once the transfer is confirmed
+    pub deposit_address: String,
+}
+
 /// Request to deposit funds via blockchain transaction
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DepositRequest {
     pub amount: String,
     pub transaction_hash: String,
+    /// Chain the deposit was made on; must be one of the configured chains
+    pub chain_id: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A reported deposit, pending until its transfer's event is applied
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct DepositResponse {
     pub transaction_id: Uuid,
     pub amount: String,
@@ -639,4 +2253,167 @@ pub struct WithdrawResponse {
     pub destination_address: String,
     pub status: TransactionStatus,
     pub created_at: DateTime<Utc>,
-}
\ No newline at end of file
+}
+/// Progress of an escrow opened through the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "escrow_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum EscrowStatus {
+    /// Funded from the ledger; the contract call is in flight
+    Pending,
+    /// Open on the payments contract
+    Active,
+    /// Release sent and waiting for confirmation
+    Releasing,
+    Released,
+    /// The contract call failed and the amount was credited back
+    Failed,
+}
+
+/// An escrow funded from a user's ledger balance
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Escrow {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub recipient_address: String,
+    pub amount: CostAmount,
+    pub release_delay_secs: i64,
+    pub description: String,
+    pub status: EscrowStatus,
+    pub chain_id: i64,
+    /// The payments contract's id for the escrow, once its creation is confirmed
+    pub escrow_id: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub release_transaction_hash: Option<String>,
+    pub error_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub released_at: Option<DateTime<Utc>>,
+}
+
+/// Request to open an escrow paying `recipient` from the caller's balance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEscrowRequest {
+    pub recipient: String,
+    pub amount: String,
+    /// Seconds after creation before the escrow can be released
+    pub release_delay: u64,
+    pub description: String,
+}
+
+/// Result of debiting a user's balance for the first period of a subscription
+#[derive(Debug, Clone)]
+pub enum SubscriptionOutcome {
+    /// The balance was debited and the subscription is active
+    Active(Box<EndpointSubscription>),
+    /// The balance left after open holds does not cover the first period
+    Insufficient { available: CostAmount },
+}
+
+/// Result of debiting a user's balance to fund an escrow
+#[derive(Debug, Clone)]
+pub enum EscrowOutcome {
+    /// The balance was debited and the escrow is waiting to be opened on-chain
+    Pending(Box<Escrow>),
+    /// The balance left after open holds does not cover the escrow
+    Insufficient { available: CostAmount },
+}
+
+/// An escrow together with its state on the payments contract
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscrowDetails {
+    pub escrow: Escrow,
+    /// `None` until the escrow is open on-chain
+    pub on_chain: Option<EscrowChainState>,
+}
+
+/// An escrow as the payments contract reports it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscrowChainState {
+    pub release_time: DateTime<Utc>,
+    pub released: bool,
+    pub disputed: bool,
+}
+
+/// Progress of a payment stream opened through the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "payment_stream_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStreamStatus {
+    /// Funded from the ledger; the contract call is in flight
+    Pending,
+    /// Open on the payments contract and paying out
+    Active,
+    /// The contract call failed and the total was credited back
+    Failed,
+}
+
+/// A stream paying a recipient continuously, funded from a user's ledger balance
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PaymentStream {
+    pub id: Uuid,
+    pub payer_id: Uuid,
+    pub recipient_address: String,
+    pub total_amount: CostAmount,
+    /// Claimed through the API so far
+    pub claimed_amount: CostAmount,
+    pub duration_secs: i64,
+    pub description: String,
+    pub status: PaymentStreamStatus,
+    pub chain_id: i64,
+    /// The payments contract's id for the stream, once its creation is confirmed
+    pub stream_id: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub error_message: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub last_claimed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request to stream `total_amount` from the caller's balance to `recipient`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePaymentStreamRequest {
+    pub recipient: String,
+    pub total_amount: String,
+    /// Seconds over which the total is paid out
+    pub duration: u64,
+    pub description: String,
+}
+
+/// Result of debiting a user's balance to fund a payment stream
+#[derive(Debug, Clone)]
+pub enum PaymentStreamOutcome {
+    /// The balance was debited and the stream is waiting to be opened on-chain
+    Pending(Box<PaymentStream>),
+    /// The balance left after open holds does not cover the stream
+    Insufficient { available: CostAmount },
+}
+
+/// Which side of their payment streams a user is listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStreamRole {
+    /// Streams the user pays into
+    #[default]
+    Payer,
+    /// Streams paying the user's wallet
+    Recipient,
+}
+
+/// Query parameters for listing a user's payment streams, newest first
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PaymentStreamQuery {
+    #[serde(default)]
+    pub role: PaymentStreamRole,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// A payment stream together with what its recipient can claim now
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentStreamDetails {
+    pub stream: PaymentStream,
+    /// Read from the contract; `None` until the stream is open on-chain
+    pub claimable_amount: Option<CostAmount>,
+}
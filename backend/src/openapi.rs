@@ -0,0 +1,146 @@
+//! OpenAPI documents attached to endpoints
+//!
+//! Owners upload the spec of the API behind an endpoint so consumers can see what it
+//! offers before paying for calls. Documents are accepted as JSON or YAML, checked to
+//! look like OpenAPI 3 or Swagger 2, and summarised into their operations.
+
+use serde_json::Value;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{SpecFormat, SpecOperation, SpecSummary},
+};
+
+/// Largest spec document accepted, in bytes
+pub const MAX_SPEC_BYTES: usize = 1024 * 1024;
+
+/// Path item keys that name operations, in the order the specification lists them
+const OPERATION_METHODS: [&str; 8] = ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// Parses an uploaded spec, returning the format it was written in and its summary
+pub fn parse_spec(content: &str) -> AppResult<(SpecFormat, SpecSummary)> {
+    if content.len() > MAX_SPEC_BYTES {
+        return Err(AppError::PayloadTooLarge(format!(
+            "OpenAPI spec is {} bytes, the limit is {}",
+            content.len(),
+            MAX_SPEC_BYTES
+        )));
+    }
+
+    let (format, document) = match serde_json::from_str::<Value>(content) {
+        Ok(document) => (SpecFormat::Json, document),
+        Err(_) => {
+            let document = serde_yaml::from_str::<Value>(content)
+                .map_err(|e| AppError::Validation(format!("OpenAPI spec is neither valid JSON nor YAML: {}", e)))?;
+            (SpecFormat::Yaml, document)
+        }
+    };
+
+    Ok((format, summarize(&document)?))
+}
+
+/// Lists the operations of an OpenAPI 3 or Swagger 2 document
+fn summarize(document: &Value) -> AppResult<SpecSummary> {
+    let document = document.as_object()
+        .ok_or_else(|| AppError::Validation("OpenAPI spec must be an object".to_string()))?;
+
+    let spec_version = match (document.get("openapi"), document.get("swagger")) {
+        (Some(Value::String(version)), _) if version.starts_with("3.") => version.clone(),
+        (None, Some(Value::String(version))) if version == "2.0" => version.clone(),
+        _ => return Err(AppError::Validation("Only OpenAPI 3.x and Swagger 2.0 specs are supported".to_string())),
+    };
+
+    let paths = document.get("paths")
+        .and_then(Value::as_object)
+        .ok_or_else(|| AppError::Validation("OpenAPI spec must have a paths object".to_string()))?;
+
+    let mut operations = Vec::new();
+    for (path, item) in paths {
+        if !path.starts_with('/') {
+            return Err(AppError::Validation(format!("OpenAPI path must start with '/': {}", path)));
+        }
+        let Some(item) = item.as_object() else {
+            return Err(AppError::Validation(format!("OpenAPI path item must be an object: {}", path)));
+        };
+        for method in OPERATION_METHODS {
+            if let Some(operation) = item.get(method) {
+                operations.push(SpecOperation {
+                    method: method.to_uppercase(),
+                    path: path.clone(),
+                    summary: operation.get("summary").and_then(Value::as_str).map(str::to_string),
+                });
+            }
+        }
+    }
+
+    let info = document.get("info");
+    let info_field = |name: &str| info.and_then(|info| info.get(name)).and_then(Value::as_str).map(str::to_string);
+
+    Ok(SpecSummary {
+        spec_version,
+        title: info_field("title"),
+        version: info_field("version"),
+        operations,
+    })
+}
+
+/// HTTP methods the spec's operations use, each listed once; TRACE is left out as the
+/// gateway never proxies it
+pub fn spec_methods(summary: &SpecSummary) -> Vec<String> {
+    let mut methods: Vec<String> = Vec::new();
+    for operation in &summary.operations {
+        if operation.method != "TRACE" && !methods.contains(&operation.method) {
+            methods.push(operation.method.clone());
+        }
+    }
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that JSON and YAML specs are summarised into their operations
+    #[test]
+    fn test_parse_spec() {
+        let json = r#"{
+            "openapi": "3.0.3",
+            "info": {"title": "Quotes", "version": "1.2.0"},
+            "paths": {
+                "/quotes/{symbol}": {
+                    "parameters": [],
+                    "get": {"summary": "Latest quote"},
+                    "delete": {}
+                }
+            }
+        }"#;
+        let (format, summary) = parse_spec(json).unwrap();
+        assert_eq!(format, SpecFormat::Json);
+        assert_eq!(summary.title.as_deref(), Some("Quotes"));
+        assert_eq!(summary.version.as_deref(), Some("1.2.0"));
+        assert_eq!(summary.operations, vec![
+            SpecOperation { method: "GET".to_string(), path: "/quotes/{symbol}".to_string(), summary: Some("Latest quote".to_string()) },
+            SpecOperation { method: "DELETE".to_string(), path: "/quotes/{symbol}".to_string(), summary: None },
+        ]);
+
+        let yaml = "swagger: '2.0'\ninfo:\n  title: Weather\npaths:\n  /forecast:\n    get: {}\n    post: {}\n  /alerts:\n    get: {}\n";
+        let (format, summary) = parse_spec(yaml).unwrap();
+        assert_eq!(format, SpecFormat::Yaml);
+        assert_eq!(summary.spec_version, "2.0");
+        assert_eq!(spec_methods(&summary), ["GET", "POST"]);
+    }
+
+    /// Tests that documents which are not OpenAPI specs are rejected
+    #[test]
+    fn test_parse_spec_rejects_invalid_documents() {
+        assert!(matches!(parse_spec("paths: [unclosed"), Err(AppError::Validation(_))));
+        assert!(matches!(parse_spec("- just\n- a list\n"), Err(AppError::Validation(_))));
+        assert!(matches!(parse_spec(r#"{"openapi": "1.0", "paths": {}}"#), Err(AppError::Validation(_))));
+        assert!(matches!(parse_spec(r#"{"openapi": "3.1.0"}"#), Err(AppError::Validation(_))));
+        assert!(matches!(parse_spec(r#"{"openapi": "3.1.0", "paths": {"quotes": {}}}"#), Err(AppError::Validation(_))));
+        assert!(parse_spec(r#"{"openapi": "3.1.0", "paths": {}}"#).is_ok());
+
+        let oversized = format!("{{\"openapi\": \"3.0.0\", \"paths\": {{}}, \"x\": \"{}\"}}", "a".repeat(MAX_SPEC_BYTES));
+        assert!(matches!(parse_spec(&oversized), Err(AppError::PayloadTooLarge(_))));
+    }
+}
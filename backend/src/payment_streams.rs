@@ -0,0 +1,402 @@
+//! Payment streams for AugustCredits
+//!
+//! Users stream part of their balance to a recipient's wallet over a period of time
+//! through the payments contract. The total is debited from the ledger before the
+//! contract is called and credited back if the stream cannot be opened. Streams are
+//! mirrored locally so they can be listed without reading the contract; what the
+//! recipient can claim is read from the contract only when a stream is looked up or
+//! claimed from. Claims pay the recipient's wallet on-chain. Nothing is available
+//! while the streaming payments feature is disabled.
+
+use crate::{
+    address,
+    blockchain::PaymentStreamContract,
+    config::Config,
+    database::Database,
+    error::{AppError, AppResult},
+    escrow::{parse_recipient, settlement, validate_description, Settlement},
+    models::{
+        CostAmount, CreatePaymentStreamRequest, PaymentStream, PaymentStreamDetails, PaymentStreamOutcome,
+        PaymentStreamQuery, PaymentStreamStatus,
+    },
+};
+use ethers::types::{Address, U256};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Longest period a stream may pay out over
+const MAX_DURATION_SECS: u64 = 5 * 365 * 24 * 60 * 60;
+
+/// Opens, lists and claims from users' payment streams
+#[derive(Clone)]
+pub struct PaymentStreamService {
+    database: Arc<Database>,
+    contract: Arc<dyn PaymentStreamContract>,
+    enabled: bool,
+    amount_decimals: u32,
+    /// Streams are opened on the settlement chain
+    chain_id: u64,
+}
+
+impl PaymentStreamService {
+    pub fn new(config: &Config, database: Arc<Database>, contract: Arc<dyn PaymentStreamContract>) -> Self {
+        Self {
+            database,
+            contract,
+            enabled: config.is_feature_enabled("streaming_payments"),
+            amount_decimals: config.blockchain.amount_decimals(),
+            chain_id: config.blockchain.settlement_chain().chain_id,
+        }
+    }
+
+    /// Debits the stream's total from the payer's balance and opens it on-chain in the
+    /// background; the stream is returned pending
+    pub async fn create(&self, payer_id: Uuid, request: CreatePaymentStreamRequest) -> AppResult<PaymentStream> {
+        self.ensure_enabled()?;
+        let recipient = parse_recipient(&request.recipient)?;
+        let total_amount = request.total_amount.parse::<CostAmount>().ok()
+            .filter(|amount| amount.fits_decimals(self.amount_decimals) && !amount.is_zero() && !amount.is_sign_negative())
+            .ok_or_else(|| AppError::Validation(format!(
+                "Stream total must be positive, with at most {} decimal places: {}",
+                self.amount_decimals, request.total_amount
+            )))?;
+        let value = total_amount.to_base_units(self.amount_decimals)
+            .ok_or_else(|| AppError::Validation(format!("Stream total is too large: {}", total_amount)))?;
+        if request.duration == 0 || request.duration > MAX_DURATION_SECS {
+            return Err(AppError::Validation(format!(
+                "Stream duration must be between 1 and {} seconds",
+                MAX_DURATION_SECS
+            )));
+        }
+        validate_description(&request.description)?;
+
+        let recipient_address = address::checksummed(recipient);
+        let outcome = self.database
+            .begin_payment_stream(payer_id, total_amount, &request, &recipient_address, self.chain_id)
+            .await?;
+        let stream = match outcome {
+            PaymentStreamOutcome::Pending(stream) => *stream,
+            PaymentStreamOutcome::Insufficient { available } => {
+                return Err(AppError::Payment(format!(
+                    "Insufficient balance: {} requested, {} available",
+                    total_amount, available
+                )));
+            }
+        };
+        info!(
+            "Payment stream {} of {} over {}s from user {} to {}",
+            stream.id, total_amount, request.duration, payer_id, recipient_address
+        );
+
+        let database = self.database.clone();
+        let contract = self.contract.clone();
+        let id = stream.id;
+        let duration = U256::from(request.duration);
+        tokio::spawn(async move {
+            settle_creation(&database, contract.as_ref(), id, recipient, value, duration, request.description).await;
+        });
+
+        Ok(stream)
+    }
+
+    /// Lists the streams the user pays into, or those paying their wallet, without
+    /// reading the contract
+    pub async fn list(&self, user_id: Uuid, wallet_address: &str, query: PaymentStreamQuery) -> AppResult<Vec<PaymentStream>> {
+        self.ensure_enabled()?;
+        Ok(self.database.list_payment_streams(user_id, wallet_address, query).await?)
+    }
+
+    /// Gets a stream the user pays into or that pays their wallet, with what can be
+    /// claimed from it now
+    pub async fn details(&self, user_id: Uuid, wallet_address: &str, id: Uuid) -> AppResult<PaymentStreamDetails> {
+        self.ensure_enabled()?;
+        let stream = self.database.get_payment_stream(user_id, wallet_address, id).await?
+            .ok_or_else(|| AppError::NotFound("Payment stream not found".to_string()))?;
+
+        let claimable_amount = match contract_stream_id(&stream) {
+            Some(stream_id) => Some(self.claimable(stream_id).await?),
+            None => None,
+        };
+        Ok(PaymentStreamDetails { stream, claimable_amount })
+    }
+
+    /// Claims what has accrued on a stream paying the user's wallet, in the background;
+    /// the stream is returned with the amount being claimed
+    pub async fn claim(&self, user_id: Uuid, wallet_address: &str, id: Uuid) -> AppResult<PaymentStreamDetails> {
+        self.ensure_enabled()?;
+        let stream = self.database.get_payment_stream(user_id, wallet_address, id).await?
+            .ok_or_else(|| AppError::NotFound("Payment stream not found".to_string()))?;
+        if !stream.recipient_address.eq_ignore_ascii_case(wallet_address) {
+            return Err(AppError::Forbidden("Only the stream's recipient can claim from it".to_string()));
+        }
+        let stream_id = match (stream.status, contract_stream_id(&stream)) {
+            (PaymentStreamStatus::Active, Some(stream_id)) => stream_id,
+            _ => return Err(AppError::Conflict("Only streams open on-chain can be claimed from".to_string())),
+        };
+
+        let claimable = self.claimable(stream_id).await?;
+        if claimable.is_zero() {
+            return Err(AppError::Conflict("Nothing has accrued on the stream yet".to_string()));
+        }
+        info!("Claiming {} from payment stream {} for user {}", claimable, stream.id, user_id);
+
+        let database = self.database.clone();
+        let contract = self.contract.clone();
+        let amount_decimals = self.amount_decimals;
+        tokio::spawn(async move {
+            settle_claim(&database, contract.as_ref(), id, stream_id, amount_decimals).await;
+        });
+
+        Ok(PaymentStreamDetails { stream, claimable_amount: Some(claimable) })
+    }
+
+    /// Reads what can be claimed from a stream on the contract
+    async fn claimable(&self, stream_id: U256) -> AppResult<CostAmount> {
+        let claimable = self.contract.claimable(stream_id).await.map_err(AppError::Blockchain)?;
+        CostAmount::from_base_units(claimable, self.amount_decimals)
+            .ok_or_else(|| AppError::Blockchain(anyhow::anyhow!(
+                "Claimable amount {} of stream {} is out of range", claimable, stream_id
+            )))
+    }
+
+    fn ensure_enabled(&self) -> AppResult<()> {
+        if self.enabled {
+            Ok(())
+        } else {
+            Err(AppError::NotFound("Streaming payments are not enabled".to_string()))
+        }
+    }
+}
+
+/// The contract's id for a stream, once its creation is confirmed
+fn contract_stream_id(stream: &PaymentStream) -> Option<U256> {
+    stream.stream_id.as_deref().and_then(|id| U256::from_dec_str(id).ok())
+}
+
+/// Opens a stream on-chain and records how it settled, crediting the total back when
+/// the transaction fails or reverts
+async fn settle_creation(
+    database: &Database,
+    contract: &dyn PaymentStreamContract,
+    id: Uuid,
+    recipient: Address,
+    total_amount: U256,
+    duration: U256,
+    description: String,
+) {
+    let (result, stream_id) = match contract.open_stream(recipient, total_amount, duration, description).await {
+        Ok((result, stream_id)) => (Ok(result), stream_id),
+        Err(e) => (Err(e), None),
+    };
+
+    let failure = match settlement(result) {
+        Settlement::Confirmed(hash) => {
+            if stream_id.is_none() {
+                warn!("Payment stream {} opened as {:?} but the contract's id for it was not found", id, hash);
+            }
+            let stream_id = stream_id.map(|stream_id| stream_id.to_string());
+            if let Err(e) = database.activate_payment_stream(id, stream_id.as_deref(), &format!("{:?}", hash)).await {
+                error!("Failed to activate payment stream {}: {}", id, e);
+            }
+            return;
+        }
+        Settlement::Pending(hash) => {
+            warn!("Payment stream {} sent as {:?} but not yet confirmed", id, hash);
+            return;
+        }
+        Settlement::Failed(failure) => failure,
+    };
+
+    warn!("Payment stream {} could not be opened: {}", id, failure);
+    match database.fail_payment_stream(id, &failure).await {
+        Ok(true) => info!("Payment stream {} credited back", id),
+        Ok(false) => {}
+        Err(e) => error!("Failed to credit back payment stream {}: {}", id, e),
+    }
+}
+
+/// Claims from a stream on-chain and adds the amount paid out to what has been claimed
+async fn settle_claim(
+    database: &Database,
+    contract: &dyn PaymentStreamContract,
+    id: Uuid,
+    stream_id: U256,
+    amount_decimals: u32,
+) {
+    let (result, claimed) = match contract.claim(stream_id).await {
+        Ok((result, claimed)) => (Ok(result), claimed),
+        Err(e) => (Err(e), None),
+    };
+
+    match settlement(result) {
+        Settlement::Confirmed(hash) => {
+            let Some(amount) = claimed.and_then(|claimed| CostAmount::from_base_units(claimed, amount_decimals)) else {
+                warn!("Claim {:?} from payment stream {} confirmed but the amount paid out was not found", hash, id);
+                return;
+            };
+            if let Err(e) = database.record_payment_stream_claim(id, amount).await {
+                error!("Failed to record claim from payment stream {}: {}", id, e);
+            }
+        }
+        Settlement::Pending(hash) => warn!("Claim from payment stream {} sent as {:?} but not yet confirmed", id, hash),
+        Settlement::Failed(failure) => warn!("Claim from payment stream {} failed: {}", id, failure),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::{
+        blockchain::{TransactionResult, TransactionStatus},
+        models::PaymentStreamRole,
+    };
+    use anyhow::Result;
+    use ethers::types::H256;
+    use std::str::FromStr;
+
+    /// Payments contract settling every transaction with a fixed outcome, on which
+    /// `claimable` has accrued on every stream
+    struct StubContract {
+        status: TransactionStatus,
+        claimable: U256,
+    }
+
+    impl StubContract {
+        fn result(&self) -> TransactionResult {
+            TransactionResult {
+                hash: H256::repeat_byte(0xcd),
+                block_number: Some(1),
+                gas_used: None,
+                status: self.status.clone(),
+                confirmations: 1,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PaymentStreamContract for StubContract {
+        async fn open_stream(
+            &self,
+            _recipient: Address,
+            _total_amount: U256,
+            _duration: U256,
+            _description: String,
+        ) -> Result<(TransactionResult, Option<U256>)> {
+            Ok((self.result(), Some(U256::from(Uuid::new_v4().as_u128()))))
+        }
+
+        async fn claim(&self, _stream_id: U256) -> Result<(TransactionResult, Option<U256>)> {
+            Ok((self.result(), Some(self.claimable)))
+        }
+
+        async fn claimable(&self, _stream_id: U256) -> Result<U256> {
+            Ok(self.claimable)
+        }
+    }
+
+    fn request(total_amount: &str, duration: u64) -> CreatePaymentStreamRequest {
+        CreatePaymentStreamRequest {
+            recipient: "0x52908400098527886E0F7030069857D2E4169EE7".to_string(),
+            total_amount: total_amount.to_string(),
+            duration,
+            description: "Monthly retainer".to_string(),
+        }
+    }
+
+    /// Tests that stream operations are not found while the feature is disabled and
+    /// that bad durations are refused before the balance is touched
+    #[tokio::test]
+    async fn test_payment_stream_requests() {
+        let mut config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        let contract = || Arc::new(StubContract { status: TransactionStatus::Confirmed, claimable: U256::zero() });
+
+        config.features.enable_streaming_payments = false;
+        let streams = PaymentStreamService::new(&config, database.clone(), contract());
+        assert!(matches!(streams.create(Uuid::new_v4(), request("10", 60)).await, Err(AppError::NotFound(_))));
+        assert!(matches!(streams.list(Uuid::new_v4(), "0x0", Default::default()).await, Err(AppError::NotFound(_))));
+
+        config.features.enable_streaming_payments = true;
+        let streams = PaymentStreamService::new(&config, database, contract());
+        assert!(matches!(streams.create(Uuid::new_v4(), request("10", 0)).await, Err(AppError::Validation(_))));
+        assert!(matches!(streams.create(Uuid::new_v4(), request("10", MAX_DURATION_SECS + 1)).await, Err(AppError::Validation(_))));
+        assert!(matches!(streams.create(Uuid::new_v4(), request("0", 60)).await, Err(AppError::Validation(_))));
+    }
+
+    /// Tests that a stream is debited from its payer, listed for both sides, claimed
+    /// from only by its recipient and credited back when it cannot be opened
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_payment_stream_lifecycle() {
+        let mut config = test_support::config();
+        config.features.enable_streaming_payments = true;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let mut users = Vec::new();
+        for _ in 0..2 {
+            users.push(database.create_user(test_support::user_request()).await.unwrap());
+        }
+        let (payer, recipient) = (&users[0], &users[1]);
+        database.credit_balance(payer.id, "1000", None).await.unwrap();
+        let contract = StubContract { status: TransactionStatus::Confirmed, claimable: U256::from(40) };
+        let streams = PaymentStreamService::new(
+            &config,
+            database.clone(),
+            Arc::new(StubContract { status: TransactionStatus::Confirmed, claimable: U256::from(40) }),
+        );
+        let request = CreatePaymentStreamRequest { recipient: recipient.wallet_address.clone(), ..request("600", 3600) };
+
+        assert!(matches!(streams.create(payer.id, CreatePaymentStreamRequest { total_amount: "2000".to_string(), ..request.clone() }).await, Err(AppError::Payment(_))));
+
+        let PaymentStreamOutcome::Pending(stream) = database
+            .begin_payment_stream(payer.id, CostAmount::from_str("600").unwrap(), &request, &recipient.wallet_address, 1)
+            .await
+            .unwrap()
+        else {
+            panic!("payment stream was not accepted");
+        };
+        assert_eq!(database.get_balance(payer.id).await.unwrap(), "400");
+        assert!(matches!(streams.claim(recipient.id, &recipient.wallet_address, stream.id).await, Err(AppError::Conflict(_))));
+
+        settle_creation(&database, &contract, stream.id, Address::zero(), U256::from(600), U256::from(3600), String::new()).await;
+        assert!(!database.fail_payment_stream(stream.id, "late failure").await.unwrap());
+
+        // Both sides see the stream, each in their own listing
+        let as_payer = PaymentStreamQuery { role: PaymentStreamRole::Payer, ..Default::default() };
+        let as_recipient = PaymentStreamQuery { role: PaymentStreamRole::Recipient, ..Default::default() };
+        assert_eq!(streams.list(payer.id, &payer.wallet_address, as_payer).await.unwrap().len(), 1);
+        assert!(streams.list(payer.id, &payer.wallet_address, as_recipient).await.unwrap().is_empty());
+        assert_eq!(streams.list(recipient.id, &recipient.wallet_address, as_recipient).await.unwrap().len(), 1);
+
+        let details = streams.details(recipient.id, &recipient.wallet_address, stream.id).await.unwrap();
+        assert_eq!(details.stream.status, PaymentStreamStatus::Active);
+        assert_eq!(details.claimable_amount, Some(CostAmount::from_str("40").unwrap()));
+
+        // Only the recipient claims, and confirmed claims add up
+        assert!(matches!(streams.claim(payer.id, &payer.wallet_address, stream.id).await, Err(AppError::Forbidden(_))));
+        let stream_id = contract_stream_id(&details.stream).unwrap();
+        settle_claim(&database, &contract, stream.id, stream_id, 0).await;
+        settle_claim(&database, &contract, stream.id, stream_id, 0).await;
+        let claimed = database.get_payment_stream(payer.id, &payer.wallet_address, stream.id).await.unwrap().unwrap();
+        assert_eq!(claimed.claimed_amount, CostAmount::from_str("80").unwrap());
+        assert!(claimed.last_claimed_at.is_some());
+
+        // A stream that cannot be opened is credited back exactly once
+        let PaymentStreamOutcome::Pending(stream) = database
+            .begin_payment_stream(payer.id, CostAmount::from_str("100").unwrap(), &request, &recipient.wallet_address, 1)
+            .await
+            .unwrap()
+        else {
+            panic!("payment stream was not accepted");
+        };
+        let failing = StubContract { status: TransactionStatus::Reverted("paused".to_string()), claimable: U256::zero() };
+        settle_creation(&database, &failing, stream.id, Address::zero(), U256::from(100), U256::from(3600), String::new()).await;
+        assert_eq!(database.get_balance(payer.id).await.unwrap(), "400");
+        assert!(!database.fail_payment_stream(stream.id, "again").await.unwrap());
+
+        // Strangers cannot see either stream
+        assert!(matches!(streams.details(Uuid::new_v4(), "0x0", stream.id).await, Err(AppError::NotFound(_))));
+    }
+}
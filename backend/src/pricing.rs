@@ -0,0 +1,363 @@
+//! Off-chain pricing for proxied traffic
+//!
+//! Mirrors the billing contract's `estimateCost` so the gateway can price a response
+//! once its size is known, and consumers can estimate what a workload will cost
+//! without a chain call.
+
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{ApiEndpoint, CostAmount, CostBreakdown, PricingModel, PricingTier, SubscriptionAllowance},
+};
+
+/// Number of started kilobytes in a byte count
+pub fn kilobytes(bytes: u64) -> u64 {
+    bytes.div_ceil(1024)
+}
+
+/// Total cost of `units` billable units at `unit_price`
+pub fn charge(unit_price: &str, units: i64) -> AppResult<CostAmount> {
+    multiply(parse_price(unit_price)?, Decimal::from(units))
+}
+
+/// Total cost of the units numbered `prior_units + 1 ..= prior_units + units` in a
+/// billing period, charging each at the tier it falls in
+///
+/// Units past the last bounded tier are charged `list_price`, so a call that crosses
+/// a tier boundary is split exactly between the tiers on either side.
+pub fn tiered_charge(tiers: &[PricingTier], list_price: &str, prior_units: i64, units: i64) -> AppResult<CostAmount> {
+    let end = prior_units + units;
+    let mut priced = prior_units;
+    let mut total = CostAmount::ZERO;
+    for tier in tiers {
+        if priced >= end {
+            break;
+        }
+        let tier_end = tier.up_to.map_or(end, |up_to| up_to.min(end));
+        if tier_end > priced {
+            total = add(total, multiply(parse_price(&tier.price)?, Decimal::from(tier_end - priced))?)?;
+            priced = tier_end;
+        }
+    }
+    if priced < end {
+        total = add(total, multiply(parse_price(list_price)?, Decimal::from(end - priced))?)?;
+    }
+    Ok(total)
+}
+
+/// Estimates the cost of `requests` HTTP calls that each return `response_bytes`,
+/// after `prior_units` billable units already used this billing period
+///
+/// Kilobytes are counted per response, exactly as they are billed. Connection-minute
+/// and per-message endpoints charge plain HTTP calls per request. Volume tiers
+/// apply to the billable unit only, so per-request-plus-bytes endpoints discount
+/// the request component and charge kilobytes at the list price.
+pub fn estimate_cost(endpoint: &ApiEndpoint, prior_units: i64, requests: i64, response_bytes: u64) -> AppResult<CostBreakdown> {
+    let response_kilobytes = requests * kilobytes(response_bytes) as i64;
+    let (requests, kilobytes) = match endpoint.pricing_model {
+        PricingModel::PerKilobyte => (0, response_kilobytes),
+        PricingModel::PerRequestPlusBytes => (requests, response_kilobytes),
+        PricingModel::PerRequest | PricingModel::PerConnectionMinute | PricingModel::PerMessage => (requests, 0),
+    };
+
+    let tiers = endpoint.pricing_tiers.as_slice();
+    let (request_tiers, kilobyte_tiers) = match endpoint.pricing_model {
+        PricingModel::PerKilobyte => (&[][..], tiers),
+        _ => (tiers, &[][..]),
+    };
+
+    let request_cost = tiered_charge(request_tiers, &endpoint.price_per_request, prior_units, requests)?;
+    let kilobyte_cost = if kilobytes > 0 {
+        let price = endpoint.price_per_kilobyte.as_deref()
+            .ok_or_else(|| AppError::Internal("Invalid pricing configuration".to_string()))?;
+        tiered_charge(kilobyte_tiers, price, prior_units, kilobytes)?
+    } else {
+        CostAmount::ZERO
+    };
+
+    breakdown(endpoint.pricing_model, requests, request_cost, kilobytes, kilobyte_cost)
+}
+
+/// Cost of `units` connection minutes or messages charged at the endpoint's
+/// per-request price, after `prior_units` already used this billing period
+pub fn session_units_cost(endpoint: &ApiEndpoint, prior_units: i64, units: i64) -> AppResult<CostBreakdown> {
+    let cost = tiered_charge(&endpoint.pricing_tiers, &endpoint.price_per_request, prior_units, units)?;
+    breakdown(endpoint.pricing_model, units, cost, 0, CostAmount::ZERO)
+}
+
+/// Reprices a call for a subscriber: units still within the plan's allowance are
+/// covered by its monthly price and the rest charged at its overage price
+pub fn subscription_cost(list: CostBreakdown, allowance: &SubscriptionAllowance) -> AppResult<CostBreakdown> {
+    let units = list.billable_units();
+    let included = (allowance.included_requests - allowance.used_before).clamp(0, units);
+    let overage = charge(&allowance.overage_price, units - included)?;
+    let (request_cost, kilobyte_cost) = match list.pricing_model {
+        PricingModel::PerKilobyte => (CostAmount::ZERO, overage),
+        _ => (overage, CostAmount::ZERO),
+    };
+    breakdown(list.pricing_model, list.requests, request_cost, list.kilobytes, kilobyte_cost)
+}
+
+fn breakdown(
+    pricing_model: PricingModel,
+    requests: i64,
+    request_cost: CostAmount,
+    kilobytes: i64,
+    kilobyte_cost: CostAmount,
+) -> AppResult<CostBreakdown> {
+    let total = add(request_cost, kilobyte_cost)?;
+    Ok(CostBreakdown {
+        pricing_model,
+        requests,
+        request_cost,
+        kilobytes,
+        kilobyte_cost,
+        total,
+    })
+}
+
+impl PricingModel {
+    /// Whether the cost of a call depends on the size of its response
+    pub fn is_byte_based(&self) -> bool {
+        matches!(self, PricingModel::PerKilobyte | PricingModel::PerRequestPlusBytes)
+    }
+}
+
+impl CostBreakdown {
+    /// Units added to the consumer's usage: kilobytes under per-kilobyte pricing,
+    /// requests (or session minutes and messages) otherwise
+    pub fn billable_units(&self) -> i64 {
+        match self.pricing_model {
+            PricingModel::PerKilobyte => self.kilobytes,
+            _ => self.requests,
+        }
+    }
+
+    /// Charges `share` of every component, e.g. nothing for an unbilled failure or
+    /// a discount while the endpoint is degraded
+    pub fn scaled(self, share: Decimal) -> AppResult<Self> {
+        if share == Decimal::ONE {
+            return Ok(self);
+        }
+        Ok(Self {
+            request_cost: scale(self.request_cost, share)?,
+            kilobyte_cost: scale(self.kilobyte_cost, share)?,
+            total: scale(self.total, share)?,
+            ..self
+        })
+    }
+}
+
+/// Multiplies an amount by a share of the list price
+pub fn scale(amount: CostAmount, share: Decimal) -> AppResult<CostAmount> {
+    amount.checked_mul(share).ok_or_else(overflow)
+}
+
+/// Whether a price is a valid, non-negative decimal
+pub fn is_valid_price(price: &str) -> bool {
+    CostAmount::from_str(price).is_ok_and(|price| !price.is_sign_negative())
+}
+
+fn parse_price(price: &str) -> AppResult<CostAmount> {
+    CostAmount::from_str(price).map_err(|_| AppError::Internal("Invalid pricing configuration".to_string()))
+}
+
+fn multiply(price: CostAmount, units: Decimal) -> AppResult<CostAmount> {
+    price.checked_mul(units).ok_or_else(overflow)
+}
+
+fn add(a: CostAmount, b: CostAmount) -> AppResult<CostAmount> {
+    a.checked_add(b).ok_or_else(overflow)
+}
+
+fn overflow() -> AppError {
+    AppError::Internal("Cost exceeds the largest representable amount".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn endpoint(pricing_model: PricingModel, price_per_kilobyte: Option<&str>) -> ApiEndpoint {
+        ApiEndpoint {
+            price_per_request: "100".to_string(),
+            pricing_model,
+            price_per_kilobyte: price_per_kilobyte.map(str::to_string),
+            ..test_support::endpoint("reports", "https://api.example.com")
+        }
+    }
+
+    /// Tests the request and kilobyte components under each pricing model
+    #[test]
+    fn test_estimate_cost() {
+        let per_request = estimate_cost(&endpoint(PricingModel::PerRequest, Some("5")), 0, 3, 4096).unwrap();
+        assert_eq!((per_request.requests, per_request.kilobytes), (3, 0));
+        assert_eq!(per_request.total, "300");
+        assert_eq!(per_request.billable_units(), 3);
+
+        // Kilobytes are started per response: 3 x 1025 bytes is 6 kilobytes, not 4
+        let per_kilobyte = estimate_cost(&endpoint(PricingModel::PerKilobyte, Some("0.5")), 0, 3, 1025).unwrap();
+        assert_eq!((per_kilobyte.requests, per_kilobyte.kilobytes), (0, 6));
+        assert_eq!([per_kilobyte.request_cost, per_kilobyte.kilobyte_cost], ["0", "3"]);
+        assert_eq!(per_kilobyte.billable_units(), 6);
+
+        let combined = estimate_cost(&endpoint(PricingModel::PerRequestPlusBytes, Some("2")), 0, 2, 2048).unwrap();
+        assert_eq!([combined.request_cost, combined.kilobyte_cost], ["200", "8"]);
+        assert_eq!(combined.total, "208");
+        assert_eq!(combined.billable_units(), 2);
+
+        // Empty responses cost nothing per kilobyte, even without a kilobyte price
+        let empty = estimate_cost(&endpoint(PricingModel::PerRequestPlusBytes, None), 0, 1, 0).unwrap();
+        assert_eq!(empty.total, "100");
+        assert!(estimate_cost(&endpoint(PricingModel::PerKilobyte, None), 0, 1, 1).is_err());
+    }
+
+    /// Tests pricing a range of units across tier boundaries
+    #[test]
+    fn test_tiered_charge() {
+        let tier = |up_to, price: &str| PricingTier { up_to, price: price.to_string() };
+        let tiers = [tier(Some(100), "10"), tier(Some(1000), "5")];
+
+        assert_eq!(tiered_charge(&[], "7", 500, 3).unwrap(), "21");
+        assert_eq!(tiered_charge(&tiers, "7", 0, 1).unwrap(), "10");
+        assert_eq!(tiered_charge(&tiers, "7", 99, 1).unwrap(), "10");
+        assert_eq!(tiered_charge(&tiers, "7", 100, 1).unwrap(), "5");
+
+        // Units straddling a boundary are split exactly: 2 at 10, then 3 at 5
+        assert_eq!(tiered_charge(&tiers, "7", 98, 5).unwrap(), "35");
+        // Past the last bounded tier the list price applies
+        assert_eq!(tiered_charge(&tiers, "7", 999, 2).unwrap(), "12");
+        assert_eq!(tiered_charge(&tiers, "7", 0, 1002).unwrap(), "5514");
+
+        // An unbounded last tier covers everything after the others
+        let open = [tier(Some(10), "0"), tier(None, "0.5")];
+        assert_eq!(tiered_charge(&open, "7", 8, 4).unwrap(), "1");
+        assert_eq!(tiered_charge(&open, "7", 0, 0).unwrap(), "0");
+
+        assert!(tiered_charge(&[tier(None, "cheap")], "7", 0, 1).is_err());
+    }
+
+    /// Tests that tiers discount the billable unit of each pricing model
+    #[test]
+    fn test_estimate_tiered_cost() {
+        let tiers = vec![PricingTier { up_to: Some(10), price: "50".to_string() }];
+        let tiered = |pricing_model| ApiEndpoint {
+            pricing_tiers: sqlx::types::Json(tiers.clone()),
+            ..endpoint(pricing_model, Some("2"))
+        };
+
+        let per_request = estimate_cost(&tiered(PricingModel::PerRequest), 9, 2, 0).unwrap();
+        assert_eq!(per_request.total, "150");
+
+        // Kilobytes stay at the list price when requests are the billable unit
+        let combined = estimate_cost(&tiered(PricingModel::PerRequestPlusBytes), 0, 1, 2048).unwrap();
+        assert_eq!([combined.request_cost, combined.kilobyte_cost], ["50", "4"]);
+
+        // Per-kilobyte endpoints count their tiers in kilobytes
+        let per_kilobyte = estimate_cost(&tiered(PricingModel::PerKilobyte), 8, 1, 4096).unwrap();
+        assert_eq!((per_kilobyte.kilobytes, per_kilobyte.kilobyte_cost.to_string()), (4, "104".to_string()));
+
+        let minutes = session_units_cost(&tiered(PricingModel::PerConnectionMinute), 9, 3).unwrap();
+        assert_eq!((minutes.requests, minutes.total.to_string()), (3, "250".to_string()));
+    }
+
+    /// Tests that subscribers pay only for units past their plan's allowance
+    #[test]
+    fn test_subscription_cost() {
+        let allowance = |used_before| SubscriptionAllowance {
+            used_before,
+            included_requests: 100,
+            overage_price: "3".to_string(),
+        };
+        let list = |pricing_model, bytes| estimate_cost(&endpoint(pricing_model, Some("2")), 0, 1, bytes).unwrap();
+
+        let covered = subscription_cost(list(PricingModel::PerRequest, 0), &allowance(99)).unwrap();
+        assert_eq!((covered.requests, covered.total.to_string()), (1, "0".to_string()));
+        let overage = subscription_cost(list(PricingModel::PerRequest, 0), &allowance(100)).unwrap();
+        assert_eq!(overage.total, "3");
+
+        // The plan replaces the kilobyte charge too, and a call can straddle the allowance
+        let combined = subscription_cost(list(PricingModel::PerRequestPlusBytes, 4096), &allowance(150)).unwrap();
+        assert_eq!([combined.request_cost, combined.kilobyte_cost], ["3", "0"]);
+        let straddling = subscription_cost(list(PricingModel::PerKilobyte, 4096), &allowance(98)).unwrap();
+        assert_eq!((straddling.kilobytes, straddling.kilobyte_cost.to_string()), (4, "6".to_string()));
+    }
+
+    /// Tests that scaling a breakdown discounts every component
+    #[test]
+    fn test_scaled_breakdown() {
+        let breakdown = estimate_cost(&endpoint(PricingModel::PerRequestPlusBytes, Some("3")), 0, 1, 1024).unwrap();
+
+        let half = breakdown.clone().scaled(Decimal::new(5, 1)).unwrap();
+        assert_eq!([half.request_cost, half.kilobyte_cost, half.total], ["50", "1.5", "51.5"]);
+        assert_eq!(half.billable_units(), 1);
+
+        let free = breakdown.scaled(Decimal::ZERO).unwrap();
+        assert_eq!(free.total, "0");
+    }
+
+    /// Tests charging whole units and counting started kilobytes
+    #[test]
+    fn test_charge() {
+        assert_eq!(charge("0.5", 3).unwrap(), "1.5");
+        assert_eq!(charge("1000", 0).unwrap(), "0");
+        assert!(charge("free", 1).is_err());
+
+        assert_eq!(kilobytes(0), 0);
+        assert_eq!(kilobytes(1024), 1);
+        assert_eq!(kilobytes(1025), 2);
+    }
+
+    /// Tests that costs add up exactly however many wei are summed
+    #[test]
+    fn test_cost_amount_sums_exactly() {
+        let wei = CostAmount::from_str("1").unwrap();
+        let total = CostAmount::checked_sum(std::iter::repeat_n(wei, 5_000_000)).unwrap();
+        assert_eq!(total, "5000000");
+
+        // A million ether plus one wei, where f64 would drop the wei
+        let ether = CostAmount::from_str("1000000000000000000000000").unwrap();
+        assert_eq!(ether.checked_add(wei).unwrap().to_string(), "1000000000000000000000001");
+        assert_eq!(charge("1000000000000000000", 3).unwrap(), "3000000000000000000");
+
+        // Amounts are written in their shortest exact form, as strings
+        let amount = CostAmount::from_str("1.50").unwrap();
+        assert_eq!(amount.to_string(), "1.5");
+        assert_eq!(serde_json::to_string(&amount).unwrap(), r#""1.5""#);
+        assert_eq!(serde_json::from_str::<CostAmount>(r#""0.0005""#).unwrap(), "0.0005");
+
+        // Anything that cannot be held exactly is refused rather than rounded
+        assert!(CostAmount::from_str(&format!("1{}", "0".repeat(30))).is_err());
+        assert!(CostAmount::checked_sum([CostAmount::new(Decimal::MAX), wei]).is_none());
+    }
+
+    /// Tests converting amounts to and from on-chain values in wei and token units
+    #[test]
+    fn test_cost_amount_base_units() {
+        use ethers::types::U256;
+
+        let usdc = CostAmount::from_base_units(U256::from(1_500_000), 6).unwrap();
+        assert_eq!(usdc, "1.5");
+        assert_eq!(usdc.to_base_units(6), Some(U256::from(1_500_000)));
+        assert!(usdc.fits_decimals(6) && !usdc.fits_decimals(0));
+        assert_eq!(CostAmount::from_str("1000").unwrap().to_base_units(0), Some(U256::from(1000)));
+        assert_eq!(CostAmount::from_base_units(U256::from(1000), 0).unwrap(), "1000");
+
+        // Finer than the currency, negative, or beyond what a decimal holds is refused
+        assert_eq!(CostAmount::from_str("0.0000001").unwrap().to_base_units(6), None);
+        assert_eq!(CostAmount::from_str("-1").unwrap().to_base_units(6), None);
+        assert!(CostAmount::from_base_units(U256::MAX, 0).is_none());
+    }
+
+    /// Tests which prices are accepted
+    #[test]
+    fn test_is_valid_price() {
+        assert!(is_valid_price("0"));
+        assert!(is_valid_price("0.0005"));
+        assert!(!is_valid_price("-1"));
+        assert!(!is_valid_price("free"));
+    }
+}
@@ -0,0 +1,259 @@
+//! On-chain balance reconciliation for AugustCredits
+//!
+//! Periodically compares each active user's ledger balance with their balance on the
+//! billing contract, summed across every configured chain. Each chain's contract is
+//! read at the block its events have been applied up to, so a deposit not yet
+//! credited is not mistaken for drift, and usage
+//! charged to the ledger but not yet billed on-chain is allowed for. A drift within
+//! the configured threshold is corrected with a ledger adjustment; anything larger is
+//! flagged for an administrator to review.
+
+use crate::{
+    blockchain::BalanceReader,
+    chain_events,
+    config::Config,
+    database::Database,
+    models::{CostAmount, ReconciliationAccount, ReconciliationStatus},
+};
+use anyhow::{Context, Result};
+use ethers::types::{Address, U256};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Number of accounts whose balances are read and compared per page
+const ACCOUNT_BATCH_SIZE: i64 = 500;
+
+/// What one reconciliation pass found
+#[derive(Debug, Default)]
+pub struct ReconciliationRun {
+    pub checked: u64,
+    pub corrected: u64,
+    pub flagged: u64,
+}
+
+/// Compares ledger balances with the billing contract and records any drift
+#[derive(Clone)]
+pub struct ReconciliationJob {
+    database: Arc<Database>,
+    reader: Arc<dyn BalanceReader>,
+    cursors: Vec<(u64, String)>,
+    interval: Duration,
+    auto_correct_threshold: Option<CostAmount>,
+    amount_decimals: u32,
+}
+
+impl ReconciliationJob {
+    pub fn new(config: &Config, database: Arc<Database>, reader: Arc<dyn BalanceReader>) -> Self {
+        Self {
+            database,
+            cursors: config.blockchain.chains.iter()
+                .map(|chain| (chain.chain_id, chain_events::cursor(&config.blockchain, chain.chain_id)))
+                .collect(),
+            reader,
+            interval: Duration::from_secs(config.reconciliation.interval_secs),
+            auto_correct_threshold: config.reconciliation.auto_correct_threshold,
+            amount_decimals: config.blockchain.amount_decimals(),
+        }
+    }
+
+    /// Reconciles every account in the background every `interval_secs`
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.run().await {
+                    Ok(run) => info!(
+                        "Reconciliation: {} accounts checked, {} corrected, {} flagged",
+                        run.checked, run.corrected, run.flagged
+                    ),
+                    Err(e) => error!("Balance reconciliation failed: {:#}", e),
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Compares every account's ledger balance with its contract balance once
+    pub async fn run(&self) -> Result<ReconciliationRun> {
+        let mut blocks = Vec::with_capacity(self.cursors.len());
+        for (chain_id, cursor) in &self.cursors {
+            let confirmed = self.reader.confirmed_block(*chain_id).await?;
+            let block = match self.database.get_chain_event_cursor(cursor).await? {
+                Some(applied) => applied.min(confirmed),
+                None => confirmed,
+            };
+            blocks.push((*chain_id, block));
+        }
+        // Reports are recorded against the settlement chain's block
+        let block = blocks.first().map(|(_, block)| *block).context("No chains configured")?;
+
+        let mut run = ReconciliationRun::default();
+        let mut after = None;
+        loop {
+            let accounts = self.database.get_reconciliation_accounts(after, ACCOUNT_BATCH_SIZE).await?;
+            let Some(last) = accounts.last() else {
+                break;
+            };
+            after = Some(last.user_id);
+
+            let (accounts, wallets): (Vec<_>, Vec<_>) = accounts.into_iter()
+                .filter_map(|account| match account.wallet_address.parse::<Address>() {
+                    Ok(wallet) => Some((account, wallet)),
+                    Err(_) => {
+                        warn!("User {} has an invalid wallet address, not reconciled", account.user_id);
+                        None
+                    }
+                })
+                .unzip();
+            let mut balances = vec![Some(U256::zero()); wallets.len()];
+            for (chain_id, chain_block) in &blocks {
+                let chain_balances = self.reader.balances_at(*chain_id, &wallets, *chain_block).await?;
+                for (total, balance) in balances.iter_mut().zip(chain_balances) {
+                    *total = total.and_then(|total| total.checked_add(balance));
+                }
+            }
+
+            for (account, balance) in accounts.iter().zip(balances) {
+                run.checked += 1;
+                let Some(chain_balance) = balance.and_then(|balance| CostAmount::from_base_units(balance, self.amount_decimals)) else {
+                    warn!("Contract balance of user {} is out of range, not reconciled", account.user_id);
+                    continue;
+                };
+                let Some((drift, status)) = assess(account, chain_balance, self.auto_correct_threshold) else {
+                    continue;
+                };
+
+                self.database
+                    .create_reconciliation_report(account, block, chain_balance, drift, status)
+                    .await
+                    .with_context(|| format!("Failed to record drift of user {}", account.user_id))?;
+                match status {
+                    ReconciliationStatus::Corrected => run.corrected += 1,
+                    _ => {
+                        warn!("User {} ledger balance is {} off the contract at block {}", account.user_id, drift, block);
+                        run.flagged += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(run)
+    }
+}
+
+/// How far an account's ledger balance is off its contract balance, and whether the
+/// drift is small enough to correct; `None` when the two agree
+fn assess(
+    account: &ReconciliationAccount,
+    chain_balance: CostAmount,
+    auto_correct_threshold: Option<CostAmount>,
+) -> Option<(CostAmount, ReconciliationStatus)> {
+    let drift = chain_balance.as_decimal()
+        .checked_sub(account.pending_charges.as_decimal())
+        .and_then(|expected| expected.checked_sub(account.ledger_balance.as_decimal()))
+        .map(CostAmount::new)?;
+    if drift.is_zero() {
+        return None;
+    }
+
+    let correctable = auto_correct_threshold
+        .is_some_and(|threshold| drift.as_decimal().abs() <= threshold.as_decimal());
+    let status = if correctable { ReconciliationStatus::Corrected } else { ReconciliationStatus::Flagged };
+    Some((drift, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::models::CreateUserRequest;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    /// Contract holding fixed balances; wallets it does not know have none
+    struct StubContract(HashMap<Address, U256>);
+
+    #[async_trait::async_trait]
+    impl BalanceReader for StubContract {
+        async fn balances_at(&self, _chain_id: u64, users: &[Address], _block: u64) -> Result<Vec<U256>> {
+            Ok(users.iter().map(|user| self.0.get(user).copied().unwrap_or_default()).collect())
+        }
+
+        async fn confirmed_block(&self, _chain_id: u64) -> Result<u64> {
+            Ok(100)
+        }
+    }
+
+    #[test]
+    fn test_assess() {
+        let amount = |amount: &str| amount.parse::<CostAmount>().unwrap();
+        let account = ReconciliationAccount {
+            user_id: Uuid::new_v4(),
+            wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
+            ledger_balance: amount("900"),
+            pending_charges: amount("100"),
+        };
+        let threshold = Some(amount("10"));
+
+        // Usage not yet billed on-chain is still in the contract balance
+        assert!(assess(&account, amount("1000"), threshold).is_none());
+        assert_eq!(assess(&account, amount("1005"), threshold), Some((amount("5"), ReconciliationStatus::Corrected)));
+        assert_eq!(assess(&account, amount("990"), threshold), Some((amount("-10"), ReconciliationStatus::Corrected)));
+        assert_eq!(assess(&account, amount("1500"), threshold), Some((amount("500"), ReconciliationStatus::Flagged)));
+        assert_eq!(assess(&account, amount("1005"), None), Some((amount("5"), ReconciliationStatus::Flagged)));
+    }
+
+    /// Tests that a small drift is corrected in the ledger and a large one flagged once,
+    /// however many passes find it
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_reconciliation_corrects_and_flags() {
+        let mut config = test_support::config();
+        config.reconciliation.auto_correct_threshold = Some("10".parse().unwrap());
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+
+        let mut balances = HashMap::new();
+        let mut users = Vec::new();
+        for chain_balance in [1005u64, 5000] {
+            let wallet = Address::from_low_u64_be(Uuid::new_v4().as_u128() as u64);
+            let user = database.create_user(CreateUserRequest {
+                wallet_address: format!("{:?}", wallet),
+                email: None,
+                username: None,
+                tier: None,
+            }).await.unwrap();
+            let mut tx = database.begin_transaction().await.unwrap();
+            database.credit_balance_in(&mut tx, user.id, "1000", None).await.unwrap();
+            tx.commit().await.unwrap();
+            balances.insert(wallet, U256::from(chain_balance));
+            users.push(user);
+        }
+
+        let job = ReconciliationJob::new(&config, database.clone(), Arc::new(StubContract(balances)));
+        job.run().await.unwrap();
+        job.run().await.unwrap();
+
+        assert_eq!(database.get_balance(users[0].id).await.unwrap(), "1005");
+        assert_eq!(database.get_balance(users[1].id).await.unwrap(), "1000");
+
+        let reports = database.list_reconciliation_reports(Default::default()).await.unwrap();
+        let ours = |user_id| reports.iter().filter(|report| report.user_id == user_id).collect::<Vec<_>>();
+        let corrected = ours(users[0].id);
+        assert_eq!(corrected.len(), 1);
+        assert_eq!((corrected[0].status, corrected[0].drift), (ReconciliationStatus::Corrected, "5".parse().unwrap()));
+        let flagged = ours(users[1].id);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!((flagged[0].status, flagged[0].drift), (ReconciliationStatus::Flagged, "4000".parse().unwrap()));
+
+        let admin = database.create_user(CreateUserRequest {
+            wallet_address: format!("{:?}", Address::random()),
+            email: None,
+            username: None,
+            tier: None,
+        }).await.unwrap();
+        let resolved = database.resolve_reconciliation_report(flagged[0].id, admin.id, "Deposit credited by hand").await.unwrap().unwrap();
+        assert_eq!(resolved.status, ReconciliationStatus::Resolved);
+        assert!(database.resolve_reconciliation_report(flagged[0].id, admin.id, "again").await.unwrap().is_none());
+    }
+}
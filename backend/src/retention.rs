@@ -0,0 +1,270 @@
+//! Data retention for AugustCredits
+//!
+//! Background task that keeps `request_logs` bounded. Logs are partitioned by month:
+//! the task creates upcoming partitions ahead of time and drops those whose logs are
+//! all past the configured retention, falling back to batched deletes on databases
+//! where the table is not partitioned. It also anonymizes the request logs of deleted
+//! accounts once they have been deleted for the configured number of days; anonymized
+//! logs lose their user, API key and client fingerprints but keep endpoint, status and
+//! cost data, so endpoint analytics and daily stats are unaffected. Request samples
+//! are only kept for a few days, and idempotent responses until their TTL ends.
+
+use crate::{
+    config::{Config, RetentionConfig},
+    database::Database,
+};
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// What one retention run changed
+#[derive(Debug, Default)]
+pub struct RetentionRun {
+    pub created_partitions: Vec<String>,
+    pub dropped_partitions: Vec<String>,
+    /// Logs deleted row by row when the table is not partitioned
+    pub deleted_logs: u64,
+    pub anonymized_users: u64,
+    pub deleted_samples: u64,
+    pub deleted_idempotency_keys: u64,
+}
+
+/// Maintains request log partitions and anonymizes deleted users' logs
+#[derive(Clone)]
+pub struct RetentionJob {
+    database: Arc<Database>,
+    config: RetentionConfig,
+}
+
+impl RetentionJob {
+    /// Creates a job applying the configured retention windows
+    pub fn new(config: &Config, database: Arc<Database>) -> Self {
+        Self {
+            database,
+            config: config.retention.clone(),
+        }
+    }
+
+    /// Runs at startup and then on the configured interval until the process exits
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.run(Utc::now()).await {
+                    error!("Request log retention failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Brings partitions up to date, removes logs, samples and idempotency keys past
+    /// their retention windows and anonymizes the logs of users deleted more than the anonymization
+    /// window before `now`
+    pub async fn run(&self, now: DateTime<Utc>) -> Result<RetentionRun> {
+        let mut run = RetentionRun::default();
+
+        let partitioned = self.database.is_request_log_partitioned().await?;
+        if partitioned {
+            run.created_partitions = self.database
+                .create_request_log_partitions(now.date_naive(), self.config.partition_months_ahead)
+                .await?;
+            if !run.created_partitions.is_empty() {
+                info!("Created request log partitions {}", run.created_partitions.join(", "));
+            }
+        }
+
+        if self.config.request_log_days > 0 {
+            if partitioned {
+                let cutoff = now.date_naive() - ChronoDuration::days(self.config.request_log_days as i64);
+                run.dropped_partitions = self.database.drop_request_log_partitions(cutoff).await?;
+                if !run.dropped_partitions.is_empty() {
+                    info!("Dropped request log partitions {}", run.dropped_partitions.join(", "));
+                }
+            } else {
+                run.deleted_logs = self.database.cleanup_old_logs(self.config.request_log_days as i32).await?;
+            }
+        }
+
+        let deleted_before = now - ChronoDuration::days(self.config.deleted_user_log_days as i64);
+        run.anonymized_users = self.database.anonymize_deleted_user_logs(deleted_before).await?;
+        if run.anonymized_users > 0 {
+            info!("Anonymized request logs of {} deleted user(s)", run.anonymized_users);
+        }
+
+        let sampled_before = now - ChronoDuration::days(self.config.request_sample_days as i64);
+        run.deleted_samples = self.database.delete_request_samples_before(sampled_before).await?;
+        if run.deleted_samples > 0 {
+            info!("Deleted {} expired request sample(s)", run.deleted_samples);
+        }
+
+        run.deleted_idempotency_keys = self.database.cleanup_expired_idempotency_keys(now).await?;
+        if run.deleted_idempotency_keys > 0 {
+            info!("Deleted {} expired idempotency key(s)", run.deleted_idempotency_keys);
+        }
+        Ok(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::models::{
+        CreateEndpointRequest, CreateRequestLogRequest, CreateRequestSample, CreateUserRequest, IdempotencyReservation,
+        RequestLogQuery,
+    };
+    use uuid::Uuid;
+
+    /// Tests that a deleted user disappears from lookups and takes their endpoints
+    /// offline at once, and that their logs are only anonymized after the retention window
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_deleted_user_log_anonymization() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let job = RetentionJob::new(&config, database.clone());
+
+        let wallet_address = format!("0x{:0>40}", Uuid::new_v4().simple());
+        let user = database.create_user(CreateUserRequest {
+            wallet_address: wallet_address.clone(),
+            email: None,
+            username: None,
+            tier: None,
+        }).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("retention-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "1000".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+        database.create_request_log(CreateRequestLogRequest {
+            user_id: user.id,
+            endpoint_id: endpoint.id,
+            request_id: Uuid::new_v4().to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status_code: 200,
+            response_time_ms: 10,
+            request_size: None,
+            response_size: None,
+            ip_address_hash: "test".to_string(),
+            user_agent_hash: Some("agent".to_string()),
+            cost: "1000".parse().unwrap(),
+            error_message: None,
+            upstream_target: None,
+            stream_duration_ms: None,
+            cost_breakdown: None,
+            is_sandbox: false,
+            api_key_id: None,
+        }).await.unwrap();
+
+        let now = Utc::now();
+        assert!(database.delete_user(user.id, now).await.unwrap());
+        assert!(!database.delete_user(user.id, now).await.unwrap());
+        assert!(database.get_user_by_id(user.id).await.unwrap().is_none());
+        assert!(database.get_user_by_wallet(&wallet_address).await.unwrap().is_none());
+        assert!(database.get_user_by_api_key(&user.api_key).await.unwrap().is_none());
+        assert!(!database.get_endpoint_by_id(endpoint.id).await.unwrap().unwrap().is_active);
+
+        let logs_of = |user_id| RequestLogQuery { user_id: Some(user_id), ..Default::default() };
+        job.run(now).await.unwrap();
+        assert_eq!(database.get_request_logs(&logs_of(user.id), None, 10).await.unwrap().len(), 1);
+
+        let retention = ChronoDuration::days(config.retention.deleted_user_log_days as i64);
+        assert!(job.run(now + retention + ChronoDuration::seconds(1)).await.unwrap().anonymized_users >= 1);
+        assert!(database.get_request_logs(&logs_of(user.id), None, 10).await.unwrap().is_empty());
+
+        // The wallet is free to register a new account
+        let again = database.create_user(CreateUserRequest {
+            wallet_address,
+            email: None,
+            username: None,
+            tier: None,
+        }).await.unwrap();
+        assert_ne!(again.id, user.id);
+    }
+
+    /// Tests that request samples are stored once per call and deleted after their
+    /// retention window
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_request_sample_retention() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let job = RetentionJob::new(&config, database.clone());
+
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, serde_json::from_value::<CreateEndpointRequest>(serde_json::json!({
+            "name": format!("samples-{}", Uuid::new_v4().simple()),
+            "upstream_url": "https://api.example.com",
+            "price_per_request": "1000",
+            "capture_samples": true,
+            "sample_rate": 0.5,
+        })).unwrap()).await.unwrap();
+        assert!(endpoint.capture_samples);
+        assert_eq!(endpoint.sample_rate, 0.5);
+        assert_eq!(endpoint.sample_max_body_bytes, 4096);
+
+        let request_id = Uuid::new_v4().to_string();
+        let sample = |request_body: &str| CreateRequestSample {
+            endpoint_id: endpoint.id,
+            request_id: request_id.clone(),
+            method: "POST".to_string(),
+            path: "/search?api_key=[REDACTED]".to_string(),
+            request_headers: [("authorization".to_string(), "[REDACTED]".to_string())].into(),
+            request_body: request_body.to_string(),
+            request_truncated: false,
+            status_code: 200,
+            response_headers: Default::default(),
+            response_body: "{}".to_string(),
+            response_truncated: false,
+        };
+        database.create_request_sample(&sample("first")).await.unwrap();
+        database.create_request_sample(&sample("second")).await.unwrap();
+
+        let stored = database.get_request_sample(endpoint.id, &request_id).await.unwrap().unwrap();
+        assert_eq!(stored.request_body, "first");
+        assert_eq!(stored.request_headers.0["authorization"], "[REDACTED]");
+
+        let now = Utc::now();
+        job.run(now).await.unwrap();
+        assert!(database.get_request_sample(endpoint.id, &request_id).await.unwrap().is_some());
+
+        let retention = ChronoDuration::days(config.retention.request_sample_days as i64);
+        assert!(job.run(now + retention + ChronoDuration::seconds(1)).await.unwrap().deleted_samples >= 1);
+        assert!(database.get_request_sample(endpoint.id, &request_id).await.unwrap().is_none());
+    }
+
+    /// Tests that idempotency keys are kept until their TTL ends and deleted afterwards
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_idempotency_key_retention() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let job = RetentionJob::new(&config, database.clone());
+
+        let user = database.create_user(test_support::user_request()).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("idempotent-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "1000".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+        let ttl = Duration::from_secs(60);
+        let reserve = || database.reserve_idempotency_key(user.id, endpoint.id, "order-1", "hash", ttl);
+        assert!(matches!(reserve().await.unwrap(), IdempotencyReservation::Reserved(_)));
+
+        let now = Utc::now();
+        job.run(now).await.unwrap();
+        assert!(matches!(reserve().await.unwrap(), IdempotencyReservation::Existing(_)));
+
+        assert!(job.run(now + ChronoDuration::minutes(2)).await.unwrap().deleted_idempotency_keys >= 1);
+        assert!(matches!(reserve().await.unwrap(), IdempotencyReservation::Reserved(_)));
+    }
+}
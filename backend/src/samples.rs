@@ -0,0 +1,225 @@
+//! Request samples for AugustCredits
+//!
+//! Endpoints that opt in keep a copy of some of their proxied calls so their owners
+//! can see what consumers sent and what the upstream answered. Credentials are never
+//! stored: headers and query parameters that look sensitive are redacted, and each
+//! body is cut off at the endpoint's `sample_max_body_bytes`. Samples are short-lived
+//! and deleted by the retention job.
+
+use crate::models::{ApiEndpoint, CreateRequestSample};
+use axum::http::{HeaderMap, Method, Uri};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// Fraction of calls sampled when an endpoint does not set one
+pub const DEFAULT_SAMPLE_RATE: f64 = 1.0;
+
+/// Bytes of each body kept when an endpoint does not set a limit
+pub const DEFAULT_SAMPLE_BODY_BYTES: i32 = 4096;
+
+/// Most bytes of each body an endpoint may keep
+pub const MAX_SAMPLE_BODY_BYTES: i32 = 64 * 1024;
+
+/// Stored in place of a redacted value
+const REDACTED: &str = "[REDACTED]";
+
+/// Header and query parameter names containing any of these are redacted
+const SENSITIVE_NAME_PARTS: [&str; 6] = ["auth", "cookie", "key", "password", "secret", "token"];
+
+/// A call being sampled, completed once its response body has been streamed
+pub struct SampleCapture {
+    endpoint_id: Uuid,
+    method: String,
+    path: String,
+    request_headers: BTreeMap<String, String>,
+    response_headers: BTreeMap<String, String>,
+    request_body: Arc<SampleBuffer>,
+    response_body: Arc<SampleBuffer>,
+}
+
+impl SampleCapture {
+    /// Starts sampling a call when its endpoint captures samples and the call is picked
+    pub fn start(endpoint: &ApiEndpoint, method: &Method, uri: &Uri, headers: &HeaderMap) -> Option<Self> {
+        if !endpoint.capture_samples || !sampled(endpoint.sample_rate) {
+            return None;
+        }
+
+        let limit = endpoint.sample_max_body_bytes.clamp(0, MAX_SAMPLE_BODY_BYTES) as usize;
+        let path = match uri.query() {
+            Some(query) => format!("{}?{}", uri.path(), redact_query(query)),
+            None => uri.path().to_string(),
+        };
+        Some(Self {
+            endpoint_id: endpoint.id,
+            method: method.to_string(),
+            path,
+            request_headers: redact_headers(headers),
+            response_headers: BTreeMap::new(),
+            request_body: Arc::new(SampleBuffer::new(limit)),
+            response_body: Arc::new(SampleBuffer::new(limit)),
+        })
+    }
+
+    /// Buffer the request body is copied into as it is forwarded
+    pub fn request_body(&self) -> Arc<SampleBuffer> {
+        self.request_body.clone()
+    }
+
+    /// Buffer the response body is copied into as it is streamed to the consumer
+    pub fn response_body(&self) -> Arc<SampleBuffer> {
+        self.response_body.clone()
+    }
+
+    pub fn set_response_headers(&mut self, headers: &HeaderMap) {
+        self.response_headers = redact_headers(headers);
+    }
+
+    /// The sample to store once the call has finished
+    pub fn finish(self, request_id: &str, status_code: i32) -> CreateRequestSample {
+        let (request_body, request_truncated) = self.request_body.contents();
+        let (response_body, response_truncated) = self.response_body.contents();
+        CreateRequestSample {
+            endpoint_id: self.endpoint_id,
+            request_id: request_id.to_string(),
+            method: self.method,
+            path: self.path,
+            request_headers: self.request_headers,
+            request_body,
+            request_truncated,
+            status_code,
+            response_headers: self.response_headers,
+            response_body,
+            response_truncated,
+        }
+    }
+}
+
+/// Keeps the first bytes of a body as it streams past
+pub struct SampleBuffer {
+    limit: usize,
+    /// Bytes kept, and whether any were dropped
+    state: Mutex<(Vec<u8>, bool)>,
+}
+
+impl SampleBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            state: Mutex::new((Vec::new(), false)),
+        }
+    }
+
+    pub fn record(&self, chunk: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let (kept, truncated) = &mut *state;
+        let room = self.limit.saturating_sub(kept.len());
+        if chunk.len() > room {
+            *truncated = true;
+        }
+        kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    /// Bytes kept, decoded lossily as UTF-8, and whether the body was cut off
+    fn contents(&self) -> (String, bool) {
+        let state = self.state.lock().unwrap();
+        (String::from_utf8_lossy(&state.0).into_owned(), state.1)
+    }
+}
+
+/// Picks a call for sampling with probability `rate`
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    // The top 48 bits of a v4 UUID are all random
+    let roll = (Uuid::new_v4().as_u128() >> 80) as f64 / (1u64 << 48) as f64;
+    roll < rate
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_NAME_PARTS.iter().any(|part| name.contains(part))
+}
+
+/// Headers by name, repeated ones joined with commas, with sensitive values redacted
+fn redact_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut redacted: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if is_sensitive(name.as_str()) {
+            REDACTED.into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes())
+        };
+        redacted.entry(name.to_string())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    redacted
+}
+
+/// Replaces the values of sensitive query parameters
+fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if is_sensitive(name) => format!("{}={}", name, REDACTED),
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_redact_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer ak_secret"));
+        headers.insert("x-api-key", HeaderValue::from_static("ak_secret"));
+        headers.insert("cookie", HeaderValue::from_static("session=abc"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.append("accept", HeaderValue::from_static("text/plain"));
+        headers.append("accept", HeaderValue::from_static("application/json"));
+
+        let redacted = redact_headers(&headers);
+        assert_eq!(redacted["authorization"], REDACTED);
+        assert_eq!(redacted["x-api-key"], REDACTED);
+        assert_eq!(redacted["cookie"], REDACTED);
+        assert_eq!(redacted["content-type"], "application/json");
+        assert_eq!(redacted["accept"], "text/plain, application/json");
+    }
+
+    #[test]
+    fn test_redact_query() {
+        assert_eq!(
+            redact_query("q=rust&api_key=ak_secret&access_token=t&page=2"),
+            "q=rust&api_key=[REDACTED]&access_token=[REDACTED]&page=2"
+        );
+        assert_eq!(redact_query("flag&q=1"), "flag&q=1");
+    }
+
+    #[test]
+    fn test_sample_buffer_truncates() {
+        let buffer = SampleBuffer::new(5);
+        buffer.record(b"abc");
+        assert_eq!(buffer.contents(), ("abc".to_string(), false));
+        buffer.record(b"defg");
+        buffer.record(b"h");
+        assert_eq!(buffer.contents(), ("abcde".to_string(), true));
+    }
+
+    #[test]
+    fn test_sampled_rate() {
+        assert!(sampled(1.0));
+        assert!(!(0..100).any(|_| sampled(0.0)));
+    }
+}
@@ -0,0 +1,93 @@
+//! Encryption of stored secrets for AugustCredits
+//!
+//! Seals values such as endpoint owners' upstream credentials with AES-256-GCM
+//! before they are written to the database, so a database dump alone does not
+//! reveal them.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts and decrypts secrets with a single configured key
+#[derive(Clone)]
+pub struct SecretCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    /// Creates a cipher from a hex-encoded 256-bit key
+    pub fn from_hex(key: &str) -> Result<Self> {
+        let bytes = hex::decode(key).context("Secret key is not valid hex")?;
+        if bytes.len() != 32 {
+            anyhow::bail!("Secret key must be 32 bytes");
+        }
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)),
+        })
+    }
+
+    /// Encrypts `plaintext` under a fresh nonce, returning base64 of nonce || ciphertext
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(STANDARD.encode(sealed))
+    }
+
+    /// Decrypts a value produced by [`SecretCipher::encrypt`]
+    pub fn decrypt(&self, sealed: &str) -> Result<String> {
+        let sealed = STANDARD.decode(sealed).context("Sealed secret is not valid base64")?;
+        if sealed.len() < NONCE_LEN {
+            anyhow::bail!("Sealed secret is too short");
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret"))?;
+        String::from_utf8(plaintext).context("Decrypted secret is not valid UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that secrets round-trip and are not stored in the clear
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let cipher = SecretCipher::from_hex(&"42".repeat(32)).unwrap();
+
+        let sealed = cipher.encrypt("sk_live_upstream").unwrap();
+        assert!(!sealed.contains("sk_live_upstream"));
+        assert_ne!(sealed, cipher.encrypt("sk_live_upstream").unwrap());
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), "sk_live_upstream");
+    }
+
+    /// Tests that tampered values and the wrong key are rejected
+    #[test]
+    fn test_decrypt_rejects_tampering() {
+        let cipher = SecretCipher::from_hex(&"42".repeat(32)).unwrap();
+        let sealed = cipher.encrypt("secret").unwrap();
+
+        let mut bytes = STANDARD.decode(&sealed).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(cipher.decrypt(&STANDARD.encode(bytes)).is_err());
+
+        let other = SecretCipher::from_hex(&"24".repeat(32)).unwrap();
+        assert!(other.decrypt(&sealed).is_err());
+
+        assert!(SecretCipher::from_hex("abcd").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Runtime settings for AugustCredits
+//!
+//! Operational knobs stored in the `system_config` table, changed by admins through
+//! the API and picked up without a redeploy. Each gateway process caches the whole
+//! table for a few seconds, so a change made on one instance reaches the others
+//! within [`CACHE_TTL`].
+
+use crate::{
+    database::Database,
+    error::{AppError, AppResult},
+    models::SystemConfig,
+    AppState,
+};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// How long settings are served from memory before being read again
+pub const CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Rejects proxied requests with 503 while `true`
+pub const MAINTENANCE_MODE: &str = "maintenance_mode";
+
+/// Requests allowed per rate limit window on endpoints without their own limit
+pub const DEFAULT_RATE_LIMIT: &str = "default_rate_limit";
+
+/// Settings as last read from the database
+struct Snapshot {
+    values: HashMap<String, String>,
+    loaded_at: Instant,
+}
+
+/// Reads and changes runtime settings
+#[derive(Clone)]
+pub struct SystemConfigService {
+    database: Arc<Database>,
+    cache: Arc<RwLock<Option<Snapshot>>>,
+}
+
+impl SystemConfigService {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self {
+            database,
+            cache: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Lists every setting straight from the database
+    pub async fn list(&self) -> AppResult<Vec<SystemConfig>> {
+        Ok(self.database.list_system_config().await?)
+    }
+
+    /// Gets a setting straight from the database
+    pub async fn get(&self, key: &str) -> AppResult<SystemConfig> {
+        self.database
+            .get_system_config(key)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No setting named '{}'", key)))
+    }
+
+    /// Changes a setting after checking that settings the gateway reads can be parsed
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        description: Option<&str>,
+        updated_by: Uuid,
+    ) -> AppResult<SystemConfig> {
+        validate_setting(key, value)?;
+        let setting = self.database.set_system_config(key, value, description, updated_by).await?;
+        // Other instances see the change once their own cache expires
+        *self.cache.write().await = None;
+        Ok(setting)
+    }
+
+    /// Whether proxied requests are currently being turned away
+    pub async fn maintenance_mode(&self) -> AppResult<bool> {
+        Ok(self.parsed(MAINTENANCE_MODE).await?.unwrap_or(false))
+    }
+
+    /// Rate limit for endpoints without their own, if one has been set
+    pub async fn default_rate_limit(&self) -> AppResult<Option<u32>> {
+        self.parsed(DEFAULT_RATE_LIMIT).await
+    }
+
+    /// Reads a setting through the cache, ignoring values that do not parse
+    async fn parsed<T: std::str::FromStr>(&self, key: &str) -> AppResult<Option<T>> {
+        let Some(value) = self.cached(key).await? else {
+            return Ok(None);
+        };
+        match value.parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => {
+                warn!("Ignoring unparseable value '{}' of setting {}", value, key);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn cached(&self, key: &str) -> AppResult<Option<String>> {
+        if let Some(snapshot) = self.cache.read().await.as_ref() {
+            if snapshot.loaded_at.elapsed() < CACHE_TTL {
+                return Ok(snapshot.values.get(key).cloned());
+            }
+        }
+
+        let values: HashMap<String, String> = self.database
+            .list_system_config()
+            .await?
+            .into_iter()
+            .map(|setting| (setting.key, setting.value))
+            .collect();
+        let value = values.get(key).cloned();
+        *self.cache.write().await = Some(Snapshot { values, loaded_at: Instant::now() });
+        Ok(value)
+    }
+}
+
+/// Rejects values the gateway would be unable to use for the settings it reads
+fn validate_setting(key: &str, value: &str) -> AppResult<()> {
+    let valid = match key {
+        MAINTENANCE_MODE => value.parse::<bool>().is_ok(),
+        DEFAULT_RATE_LIMIT => value.parse::<u32>().is_ok_and(|limit| limit > 0),
+        _ => true,
+    };
+    if !valid {
+        return Err(AppError::Validation(format!("Invalid value '{}' for setting {}", value, key)));
+    }
+    Ok(())
+}
+
+/// Turns proxied requests away with 503 while maintenance mode is on
+pub async fn maintenance_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match state.system_config.maintenance_mode().await {
+        Ok(false) => next.run(request).await,
+        Ok(true) => AppError::Maintenance(
+            "The gateway is down for maintenance, please retry later".to_string(),
+        ).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// Tests that only values the gateway can use are accepted for the settings it reads
+    #[test]
+    fn test_validate_setting() {
+        assert!(validate_setting(MAINTENANCE_MODE, "true").is_ok());
+        assert!(validate_setting(MAINTENANCE_MODE, "yes").is_err());
+        assert!(validate_setting(DEFAULT_RATE_LIMIT, "500").is_ok());
+        assert!(validate_setting(DEFAULT_RATE_LIMIT, "0").is_err());
+        assert!(validate_setting(DEFAULT_RATE_LIMIT, "-1").is_err());
+        assert!(validate_setting("max_request_size", "anything").is_ok());
+    }
+
+    /// Tests that a changed setting is read back at once on the instance that changed it
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_set_and_read_settings() {
+        let config = test_support::config();
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+        let settings = SystemConfigService::new(database.clone());
+
+        let admin = database.create_user(test_support::user_request()).await.unwrap();
+        let original = settings.get(DEFAULT_RATE_LIMIT).await.unwrap();
+        assert!(settings.default_rate_limit().await.unwrap().is_some());
+
+        let changed = settings.set(DEFAULT_RATE_LIMIT, "250", None, admin.id).await.unwrap();
+        assert_eq!(changed.updated_by, Some(admin.id));
+        assert_eq!(changed.description, original.description);
+        assert_eq!(settings.default_rate_limit().await.unwrap(), Some(250));
+        assert!(matches!(
+            settings.set(DEFAULT_RATE_LIMIT, "many", None, admin.id).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(settings.get("no_such_setting").await, Err(AppError::NotFound(_))));
+
+        settings.set(DEFAULT_RATE_LIMIT, &original.value, None, admin.id).await.unwrap();
+    }
+}
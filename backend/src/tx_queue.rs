@@ -0,0 +1,544 @@
+//! Blockchain transaction queue for AugustCredits
+//!
+//! Operations bound for the chain, such as billing submissions and withdrawal
+//! payouts, are written to the database by their callers and sent by this background
+//! worker, so a restart never loses one. An entry's transaction hash is saved before
+//! the worker waits for it to confirm, letting a restarted worker follow the
+//! transaction instead of sending it again. Entries whose transaction fails, or is
+//! dropped by the node, are resent with exponential backoff until they run out of
+//! attempts; the usage an abandoned entry carried is left pending for the next billing
+//! run, and an abandoned payout is credited back to its user. With webhooks enabled,
+//! users are told when the transaction carrying their usage or withdrawal confirms or
+//! is given up on.
+
+use crate::{
+    blockchain::{TransactionResult, TransactionStatus, TxOperation, TxSubmitter},
+    config::Config,
+    database::Database,
+    models::{BillingTransaction, BlockchainTx, SettledUsage, TransactionEvent, TransactionEventKind},
+};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use ethers::types::H256;
+use sqlx::{Postgres, Transaction};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Number of sent transactions followed per pass
+const SUBMITTED_BATCH_SIZE: i64 = 100;
+
+/// Longest wait before a failed transaction is resent
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Webhook event sent when a user's billing or withdrawal transaction confirms
+pub const TRANSACTION_CONFIRMED_EVENT: &str = "transaction.confirmed";
+/// Webhook event sent when a user's billing or withdrawal transaction is given up on
+pub const TRANSACTION_FAILED_EVENT: &str = "transaction.failed";
+
+/// Queues an operation in `tx`, linking the pending usage it bills
+pub async fn enqueue(
+    db: &Database,
+    tx: &mut Transaction<'_, Postgres>,
+    operation: &TxOperation,
+    usage_ids: &[Uuid],
+) -> Result<Uuid> {
+    let payload = serde_json::to_value(operation).context("Failed to serialize queued operation")?;
+    db.enqueue_blockchain_tx_in(tx, operation.name(), &payload, usage_ids).await
+}
+
+/// What one pass over the queue did
+#[derive(Debug, Default)]
+pub struct QueueRun {
+    pub sent: u64,
+    pub confirmed: u64,
+    pub failed: u64,
+}
+
+/// Sends queued transactions and records how they settle
+#[derive(Clone)]
+pub struct TxQueueJob {
+    database: Arc<Database>,
+    submitter: Arc<dyn TxSubmitter>,
+    chain_id: u64,
+    poll_interval: Duration,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    webhooks: bool,
+}
+
+impl TxQueueJob {
+    pub fn new(config: &Config, database: Arc<Database>, submitter: Arc<dyn TxSubmitter>) -> Self {
+        Self {
+            database,
+            submitter,
+            chain_id: config.blockchain.settlement_chain().chain_id,
+            poll_interval: Duration::from_secs(config.blockchain.tx_queue_poll_interval_secs),
+            max_attempts: config.blockchain.tx_max_attempts,
+            retry_backoff: Duration::from_secs(config.blockchain.tx_retry_backoff_secs),
+            webhooks: config.is_feature_enabled("webhooks"),
+        }
+    }
+
+    /// Drains the queue in the background every `tx_queue_poll_interval_secs`
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.drain().await {
+                    Ok(run) if run.sent + run.confirmed + run.failed > 0 => info!(
+                        "Transaction queue: {} sent, {} confirmed, {} failed",
+                        run.sent, run.confirmed, run.failed
+                    ),
+                    Ok(_) => {}
+                    Err(e) => error!("Transaction queue processing failed: {:#}", e),
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        })
+    }
+
+    /// Follows transactions sent earlier, such as before a restart, then sends and
+    /// follows every entry that is due
+    pub async fn drain(&self) -> Result<QueueRun> {
+        let mut run = QueueRun::default();
+
+        for entry in self.database.get_submitted_blockchain_txs(SUBMITTED_BATCH_SIZE).await? {
+            match entry.tx_hash.as_deref().map(H256::from_str) {
+                Some(Ok(tx_hash)) => self.follow(&entry, tx_hash, &mut run).await?,
+                _ => warn!("Queued transaction {} was submitted without a valid hash", entry.id),
+            }
+        }
+
+        loop {
+            // The entry stays locked while it is sent, so no other worker sends it too
+            let mut tx = self.database.begin_transaction().await?;
+            let Some(entry) = self.database.claim_due_blockchain_tx_in(&mut tx).await? else {
+                break;
+            };
+
+            match self.send(&entry).await {
+                Ok(tx_hash) => {
+                    self.database.mark_blockchain_tx_submitted_in(&mut tx, entry.id, &format!("{:?}", tx_hash)).await?;
+                    tx.commit().await.context("Failed to record sent transaction")?;
+                    run.sent += 1;
+                    self.follow(&entry, tx_hash, &mut run).await?;
+                }
+                Err(e) => {
+                    let error = format!("Transaction could not be sent: {:#}", e);
+                    let released = self.failed_in(&mut tx, &entry, &error).await?;
+                    tx.commit().await.context("Failed to record failed send")?;
+                    run.failed += 1;
+                    self.notify_failed(&entry, None, &error, released).await;
+                }
+            }
+        }
+
+        Ok(run)
+    }
+
+    async fn send(&self, entry: &BlockchainTx) -> Result<H256> {
+        let operation: TxOperation = serde_json::from_value(entry.payload.clone())
+            .with_context(|| format!("Invalid {} payload", entry.operation))?;
+        self.submitter.send(&operation).await
+    }
+
+    /// Waits for an entry's transaction to settle and records the outcome; one still
+    /// pending is left to the next pass
+    async fn follow(&self, entry: &BlockchainTx, tx_hash: H256, run: &mut QueueRun) -> Result<()> {
+        let result = match self.submitter.confirm(tx_hash).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Failed to check queued transaction {:?}: {:#}", tx_hash, e);
+                return Ok(());
+            }
+        };
+
+        let failure = match settlement(result, self.chain_id) {
+            Settlement::Confirmed(transaction) => {
+                let mut tx = self.database.begin_transaction().await?;
+                let mut billed = self.database.confirm_blockchain_tx_in(&mut tx, entry.id, &transaction).await?;
+                if let (Some(billed), Some(withdrawal_id)) = (billed.as_mut(), payout_withdrawal(entry)) {
+                    if let Some(user_id) = self.database.confirm_withdrawal_in(&mut tx, withdrawal_id, &transaction).await? {
+                        billed.push(SettledUsage { user_id, usage_record_ids: vec![withdrawal_id] });
+                    }
+                }
+                tx.commit().await.context("Failed to record confirmed transaction")?;
+                if let Some(billed) = billed {
+                    let count: usize = billed.iter().map(|usage| usage.usage_record_ids.len()).sum();
+                    debug!("Queued transaction {} confirmed as {}, billing {} usage records", entry.id, transaction.hash, count);
+                    run.confirmed += 1;
+                    self.notify_confirmed(entry, &transaction, billed).await;
+                }
+                return Ok(());
+            }
+            Settlement::Failed(failure) => failure,
+            Settlement::Pending => match self.submitter.is_dropped(tx_hash).await {
+                Ok(true) => format!("Transaction {:?} was dropped before it was mined", tx_hash),
+                Ok(false) => {
+                    debug!("Queued transaction {} still waiting on {:?}", entry.id, tx_hash);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Failed to check queued transaction {:?}: {:#}", tx_hash, e);
+                    return Ok(());
+                }
+            },
+        };
+
+        let mut tx = self.database.begin_transaction().await?;
+        let released = self.failed_in(&mut tx, entry, &failure).await?;
+        tx.commit().await.context("Failed to record failed transaction")?;
+        run.failed += 1;
+        self.notify_failed(entry, Some(tx_hash), &failure, released).await;
+        Ok(())
+    }
+
+    /// Schedules a failed entry to be sent again, or gives up on it once it has used
+    /// all of its attempts, returning the usage released, or the withdrawal credited
+    /// back, when it is given up on
+    async fn failed_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        entry: &BlockchainTx,
+        error: &str,
+    ) -> Result<Vec<SettledUsage>> {
+        let retry_at = if entry.attempts < self.max_attempts as i32 {
+            let retry_at = Utc::now() + chrono::Duration::from_std(retry_delay(self.retry_backoff, entry.attempts))?;
+            warn!("Queued transaction {} failed on attempt {}, retrying at {}: {}", entry.id, entry.attempts, retry_at, error);
+            Some(retry_at)
+        } else {
+            error!("Queued transaction {} abandoned after {} attempts: {}", entry.id, entry.attempts, error);
+            None
+        };
+        let mut released = self.database.fail_blockchain_tx_in(tx, entry.id, error, retry_at).await?;
+        if let (None, Some(withdrawal_id)) = (retry_at, payout_withdrawal(entry)) {
+            if let Some(user_id) = self.database.fail_withdrawal_in(tx, withdrawal_id, error).await? {
+                info!("Withdrawal {} credited back", withdrawal_id);
+                released.push(SettledUsage { user_id, usage_record_ids: vec![withdrawal_id] });
+            }
+        }
+        Ok(released)
+    }
+
+    /// Tells each user whose usage a confirmed entry billed, or whose withdrawal it paid out
+    async fn notify_confirmed(&self, entry: &BlockchainTx, transaction: &BillingTransaction, billed: Vec<SettledUsage>) {
+        if !self.webhooks {
+            return;
+        }
+        for usage in billed {
+            let event = TransactionEvent {
+                kind: event_kind(entry),
+                transaction_id: entry.id,
+                transaction_hash: Some(transaction.hash.clone()),
+                block_number: transaction.block_number,
+                chain_id: Some(transaction.chain_id),
+                record_ids: usage.usage_record_ids,
+                error: None,
+            };
+            notify(&self.database, usage.user_id, TRANSACTION_CONFIRMED_EVENT, &event).await;
+        }
+    }
+
+    /// Tells each user whose usage or withdrawal an abandoned entry carried; nothing is
+    /// sent for an entry that will be retried
+    async fn notify_failed(&self, entry: &BlockchainTx, tx_hash: Option<H256>, error: &str, released: Vec<SettledUsage>) {
+        if !self.webhooks {
+            return;
+        }
+        for usage in released {
+            let event = TransactionEvent {
+                kind: event_kind(entry),
+                transaction_id: entry.id,
+                transaction_hash: tx_hash.map(|hash| format!("{:?}", hash)),
+                block_number: None,
+                chain_id: Some(self.chain_id as i64),
+                record_ids: usage.usage_record_ids,
+                error: Some(error.to_string()),
+            };
+            notify(&self.database, usage.user_id, TRANSACTION_FAILED_EVENT, &event).await;
+        }
+    }
+}
+
+/// The withdrawal an entry pays out, if it is a payout
+fn payout_withdrawal(entry: &BlockchainTx) -> Option<Uuid> {
+    match serde_json::from_value(entry.payload.clone()) {
+        Ok(TxOperation::Payout { withdrawal_id, .. }) => Some(withdrawal_id),
+        _ => None,
+    }
+}
+
+/// What an entry's transaction moves on-chain, as told to webhooks
+fn event_kind(entry: &BlockchainTx) -> TransactionEventKind {
+    match payout_withdrawal(entry) {
+        Some(_) => TransactionEventKind::Withdrawal,
+        None => TransactionEventKind::Billing,
+    }
+}
+
+/// Queues a transaction event for the user's webhooks; a failure is logged, since
+/// the transaction has already settled
+async fn notify(database: &Database, user_id: Uuid, event_type: &str, event: &TransactionEvent) {
+    let payload = match serde_json::to_value(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Failed to encode transaction event for {}: {}", event.transaction_id, e);
+            return;
+        }
+    };
+    if let Err(e) = database.enqueue_webhook_event(user_id, event_type, &payload).await {
+        error!("Failed to queue {} webhook for user {}: {}", event_type, user_id, e);
+    }
+}
+
+/// How a sent transaction settled
+#[derive(Debug, PartialEq)]
+enum Settlement {
+    Confirmed(BillingTransaction),
+    /// Not yet confirmed; it may still be mined
+    Pending,
+    Failed(String),
+}
+
+/// Transactions are sent on the settlement chain, `chain_id`
+fn settlement(result: TransactionResult, chain_id: u64) -> Settlement {
+    match result.status {
+        TransactionStatus::Confirmed => Settlement::Confirmed(BillingTransaction {
+            hash: format!("{:?}", result.hash),
+            gas_used: result.gas_used.map(|gas| gas.to_string()),
+            block_number: result.block_number.map(|block| block as i64),
+            chain_id: chain_id as i64,
+        }),
+        TransactionStatus::Pending => Settlement::Pending,
+        TransactionStatus::Failed => Settlement::Failed(format!("Transaction {:?} failed", result.hash)),
+        TransactionStatus::Reverted(reason) => {
+            Settlement::Failed(format!("Transaction {:?} reverted: {}", result.hash, reason))
+        }
+    }
+}
+
+/// Wait before the next send after `attempts` failed ones, doubling from `backoff`
+pub(crate) fn retry_delay(backoff: Duration, attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    backoff.saturating_mul(1 << doublings).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::models::{CreateEndpointRequest, CreateUserRequest, UsageStatus};
+    use ethers::types::{Address, U256};
+    use std::sync::Mutex;
+
+    /// Chain whose transactions stay unmined until `mined` is set, and which drops
+    /// them when `dropped` is set
+    #[derive(Default)]
+    struct StubChain {
+        sent: Mutex<Vec<H256>>,
+        mined: Mutex<bool>,
+        dropped: Mutex<bool>,
+    }
+
+    #[async_trait::async_trait]
+    impl TxSubmitter for StubChain {
+        async fn send(&self, _operation: &TxOperation) -> Result<H256> {
+            let tx_hash = H256::random();
+            self.sent.lock().unwrap().push(tx_hash);
+            Ok(tx_hash)
+        }
+
+        async fn confirm(&self, tx_hash: H256) -> Result<TransactionResult> {
+            let mined = *self.mined.lock().unwrap();
+            Ok(TransactionResult {
+                hash: tx_hash,
+                block_number: mined.then_some(9),
+                gas_used: mined.then(|| U256::from(21000)),
+                status: if mined { TransactionStatus::Confirmed } else { TransactionStatus::Pending },
+                confirmations: 0,
+            })
+        }
+
+        async fn is_dropped(&self, _tx_hash: H256) -> Result<bool> {
+            Ok(*self.dropped.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let backoff = Duration::from_secs(30);
+        assert_eq!(retry_delay(backoff, 1), Duration::from_secs(30));
+        assert_eq!(retry_delay(backoff, 2), Duration::from_secs(60));
+        assert_eq!(retry_delay(backoff, 4), Duration::from_secs(240));
+        assert_eq!(retry_delay(backoff, 40), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(backoff, 0), Duration::from_secs(30));
+    }
+
+    /// Tests which outcomes settle a transaction and which leave it to be followed
+    #[test]
+    fn test_settlement() {
+        let result = |status| TransactionResult {
+            hash: H256::repeat_byte(0xab),
+            block_number: Some(7),
+            gas_used: Some(U256::from(21000)),
+            status,
+            confirmations: 1,
+        };
+
+        let Settlement::Confirmed(confirmed) = settlement(result(TransactionStatus::Confirmed), 1) else {
+            panic!("confirmed transaction not settled");
+        };
+        assert_eq!(confirmed.hash, format!("0x{}", "ab".repeat(32)));
+        assert_eq!((confirmed.gas_used.as_deref(), confirmed.block_number), (Some("21000"), Some(7)));
+        assert_eq!(confirmed.chain_id, 1);
+
+        // Sent but unconfirmed is followed again rather than resent, so it is never billed twice
+        assert_eq!(settlement(result(TransactionStatus::Pending), 1), Settlement::Pending);
+        assert!(matches!(settlement(result(TransactionStatus::Failed), 1), Settlement::Failed(_)));
+        assert!(matches!(
+            settlement(result(TransactionStatus::Reverted("limit".to_string())), 1),
+            Settlement::Failed(e) if e.contains("limit")
+        ));
+    }
+
+    /// Tests that a transaction sent before a restart is followed rather than sent
+    /// again, and that a dropped one is resent once its backoff passes
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_queued_transaction_survives_restart() {
+        let mut config = test_support::config();
+        config.blockchain.tx_retry_backoff_secs = 1;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+
+        let wallet = Address::from_low_u64_be(Uuid::new_v4().as_u128() as u64);
+        let user = database.create_user(CreateUserRequest {
+            wallet_address: format!("{:?}", wallet),
+            email: None,
+            username: None,
+            tier: None,
+        }).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, serde_json::from_value::<CreateEndpointRequest>(serde_json::json!({
+            "name": format!("queue-{}", Uuid::new_v4().simple()),
+            "upstream_url": "https://api.example.com",
+            "price_per_request": "10",
+        })).unwrap()).await.unwrap();
+        let record = database.create_usage_record(user.id, endpoint.id, 3, "30", "2024-01").await.unwrap();
+
+        let mut tx = database.begin_transaction().await.unwrap();
+        let operation = TxOperation::RecordUsage { user: wallet, endpoint: endpoint.name.clone(), request_count: U256::from(3) };
+        let id = enqueue(&database, &mut tx, &operation, &[record.id]).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let ours = |entries: Vec<BlockchainTx>| entries.into_iter().find(|entry| entry.id == id);
+        let chain = Arc::new(StubChain::default());
+        let job = TxQueueJob::new(&config, database.clone(), chain.clone());
+        job.drain().await.unwrap();
+        assert_eq!(chain.sent.lock().unwrap().len(), 1);
+        let submitted = ours(database.get_submitted_blockchain_txs(1000).await.unwrap()).unwrap();
+        assert_eq!(submitted.attempts, 1);
+
+        // A new worker follows the sent transaction instead of sending it again
+        let restarted = TxQueueJob::new(&config, database.clone(), chain.clone());
+        restarted.drain().await.unwrap();
+        assert_eq!(chain.sent.lock().unwrap().len(), 1);
+
+        // Dropped by the node, it is resent once the backoff passes
+        *chain.dropped.lock().unwrap() = true;
+        restarted.drain().await.unwrap();
+        assert!(ours(database.get_submitted_blockchain_txs(1000).await.unwrap()).is_none());
+        *chain.dropped.lock().unwrap() = false;
+        *chain.mined.lock().unwrap() = true;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        restarted.drain().await.unwrap();
+        assert_eq!(chain.sent.lock().unwrap().len(), 2);
+
+        let billed = database.get_usage_record(user.id, endpoint.id, "2024-01").await.unwrap().unwrap();
+        assert!(matches!(billed.status, UsageStatus::Billed));
+        assert_eq!(billed.transaction_hash, Some(format!("{:?}", chain.sent.lock().unwrap()[1])));
+        let billing_record = database.get_billing_record(user.id, "2024-01").await.unwrap().unwrap();
+        assert_eq!((billing_record.total_requests, billing_record.block_number), (3, Some(9)));
+    }
+
+    /// Tests that a confirmed and an abandoned billing transaction each queue one
+    /// webhook delivery carrying the transaction hash, block and usage records
+    #[tokio::test]
+    #[ignore] // Requires database connection
+    async fn test_settled_transactions_notify_webhooks() {
+        use crate::models::CreateWebhookEndpointRequest;
+        use crate::webhooks::{WebhookDispatcher, WebhookService};
+        use httpmock::prelude::*;
+
+        let mut config = test_support::config();
+        config.features.enable_webhooks = true;
+        config.blockchain.tx_max_attempts = 1;
+        let database = Arc::new(Database::new_lazy(&config.database_url).unwrap());
+        database.migrate().await.unwrap();
+
+        let wallet = Address::from_low_u64_be(Uuid::new_v4().as_u128() as u64);
+        let user = database.create_user(CreateUserRequest {
+            wallet_address: format!("{:?}", wallet),
+            ..test_support::user_request()
+        }).await.unwrap();
+        let endpoint = database.create_endpoint(user.id, CreateEndpointRequest {
+            name: format!("notify-{}", Uuid::new_v4().simple()),
+            upstream_url: "https://api.example.com".to_string(),
+            price_per_request: "10".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        let server = MockServer::start_async().await;
+        WebhookService::new(&config, database.clone()).create(user.id, CreateWebhookEndpointRequest {
+            url: server.url("/hooks"),
+            events: vec![TRANSACTION_CONFIRMED_EVENT.to_string(), TRANSACTION_FAILED_EVENT.to_string()],
+        }).await.unwrap();
+
+        let chain = Arc::new(StubChain::default());
+        let job = TxQueueJob::new(&config, database.clone(), chain.clone());
+        let queue = |billing_period: &'static str| {
+            let database = database.clone();
+            let operation = TxOperation::RecordUsage { user: wallet, endpoint: endpoint.name.clone(), request_count: U256::from(3) };
+            let endpoint_id = endpoint.id;
+            async move {
+                let record = database.create_usage_record(user.id, endpoint_id, 3, "30", billing_period).await.unwrap();
+                let mut tx = database.begin_transaction().await.unwrap();
+                enqueue(&database, &mut tx, &operation, &[record.id]).await.unwrap();
+                tx.commit().await.unwrap();
+                record.id
+            }
+        };
+
+        *chain.mined.lock().unwrap() = true;
+        let billed = queue("2024-01").await;
+        job.drain().await.unwrap();
+
+        // Dropped on its only attempt, the second transaction is given up on
+        *chain.mined.lock().unwrap() = false;
+        *chain.dropped.lock().unwrap() = true;
+        let released = queue("2024-02").await;
+        job.drain().await.unwrap();
+
+        let sent = chain.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        let confirmed = server.mock_async(|when, then| {
+            when.method(POST).path("/hooks").json_body_partial(serde_json::json!({
+                "event": TRANSACTION_CONFIRMED_EVENT,
+                "data": { "transaction_hash": format!("{:?}", sent[0]), "block_number": 9, "record_ids": [billed] },
+            }).to_string());
+            then.status(200);
+        }).await;
+        let failed = server.mock_async(|when, then| {
+            when.method(POST).path("/hooks").json_body_partial(serde_json::json!({
+                "event": TRANSACTION_FAILED_EVENT,
+                "data": { "transaction_hash": format!("{:?}", sent[1]), "block_number": null, "record_ids": [released] },
+            }).to_string());
+            then.status(200);
+        }).await;
+
+        let run = WebhookDispatcher::new(&config, database.clone()).drain().await.unwrap();
+        assert!(run.delivered >= 2);
+        confirmed.assert_async().await;
+        failed.assert_async().await;
+    }
+}
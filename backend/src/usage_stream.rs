@@ -0,0 +1,258 @@
+//! Live usage streams for AugustCredits
+//!
+//! Fans out an event for every proxied request to the dashboards of the user who
+//! made it, over server-sent events. Each user with an open stream gets a bounded
+//! broadcast channel; publishing never waits on a subscriber, so a client that
+//! falls behind misses the oldest events instead of slowing the proxy down.
+
+use crate::{
+    error::AppResult,
+    models::{CostAmount, CreateRequestLogRequest},
+};
+use axum::response::sse::Event;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::Stream;
+use serde::Serialize;
+use std::{convert::Infallible, future::Future, sync::Arc, time::Duration};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+/// A proxied request as reported on its user's live usage stream
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageEvent {
+    pub request_id: String,
+    pub endpoint_id: Uuid,
+    pub endpoint_name: String,
+    pub method: String,
+    pub status_code: i32,
+    pub cost: CostAmount,
+    pub response_time_ms: i32,
+    pub is_sandbox: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl UsageEvent {
+    /// Describes a request from its finished log entry
+    pub fn new(endpoint_name: &str, log_request: &CreateRequestLogRequest) -> Self {
+        Self {
+            request_id: log_request.request_id.clone(),
+            endpoint_id: log_request.endpoint_id,
+            endpoint_name: endpoint_name.to_string(),
+            method: log_request.method.clone(),
+            status_code: log_request.status_code,
+            cost: log_request.cost,
+            response_time_ms: log_request.response_time_ms,
+            is_sandbox: log_request.is_sandbox,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Per-user broadcast channels feeding live usage streams
+#[derive(Clone)]
+pub struct UsageStream {
+    channels: Arc<DashMap<Uuid, broadcast::Sender<UsageEvent>>>,
+    buffer: usize,
+    snapshot_interval: Duration,
+}
+
+impl UsageStream {
+    /// Creates usage streams keeping up to `buffer` unread events per user and
+    /// sending aggregate snapshots every `snapshot_interval`
+    pub fn new(buffer: usize, snapshot_interval: Duration) -> Self {
+        Self {
+            channels: Arc::new(DashMap::new()),
+            buffer,
+            snapshot_interval,
+        }
+    }
+
+    /// How often open streams send an aggregate usage snapshot
+    pub fn snapshot_interval(&self) -> Duration {
+        self.snapshot_interval
+    }
+
+    /// Sends an event to the user's open streams, if any; never waits on a slow client
+    pub fn publish(&self, user_id: Uuid, event: UsageEvent) {
+        if let Some(sender) = self.channels.get(&user_id) {
+            // Only fails when the last subscriber is just going away
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Subscribes to the events published for a user from now on
+    pub fn subscribe(&self, user_id: Uuid) -> UsageSubscription {
+        let receiver = self.channels
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(self.buffer).0)
+            .subscribe();
+        UsageSubscription {
+            receiver,
+            channels: self.channels.clone(),
+            user_id,
+        }
+    }
+}
+
+/// One client's subscription to a user's usage events; the user's channel is
+/// dropped along with their last subscription
+pub struct UsageSubscription {
+    receiver: broadcast::Receiver<UsageEvent>,
+    channels: Arc<DashMap<Uuid, broadcast::Sender<UsageEvent>>>,
+    user_id: Uuid,
+}
+
+impl UsageSubscription {
+    async fn recv(&mut self) -> Result<UsageEvent, RecvError> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for UsageSubscription {
+    fn drop(&mut self) {
+        // This subscription's receiver is still counted until the struct is gone
+        self.channels.remove_if(&self.user_id, |_, sender| sender.receiver_count() <= 1);
+    }
+}
+
+/// Turns a subscription into server-sent events
+///
+/// Sends a `request` event per call, a `snapshot` event with the result of
+/// `snapshot` straight away and then every `interval`, and a `lagged` event
+/// counting the calls a client missed by falling too far behind. The stream ends
+/// at `expires_at`, when the credentials it was opened with run out.
+pub fn sse_events<F, Fut, T>(
+    subscription: UsageSubscription,
+    snapshot: F,
+    interval: Duration,
+    expires_at: Option<Instant>,
+) -> impl Stream<Item = Result<Event, Infallible>>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = AppResult<T>> + Send,
+    T: Serialize,
+{
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    futures::stream::unfold((subscription, ticks, snapshot), move |(mut subscription, mut ticks, snapshot)| async move {
+        let expired = async {
+            match expires_at {
+                Some(expires_at) => tokio::time::sleep_until(expires_at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(expired);
+
+        loop {
+            let event = tokio::select! {
+                biased;
+                _ = &mut expired => return None,
+                received = subscription.recv() => match received {
+                    Ok(event) => Event::default().event("request").json_data(event),
+                    Err(RecvError::Lagged(missed)) => {
+                        Event::default().event("lagged").json_data(serde_json::json!({ "missed": missed }))
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = ticks.tick() => match snapshot().await {
+                    Ok(stats) => Event::default().event("snapshot").json_data(stats),
+                    Err(e) => {
+                        warn!("Failed to load usage snapshot: {}", e);
+                        continue;
+                    }
+                },
+            };
+            match event {
+                Ok(event) => return Some((Ok(event), (subscription, ticks, snapshot))),
+                Err(e) => warn!("Failed to encode usage stream event: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn test_event(request_id: &str) -> UsageEvent {
+        UsageEvent {
+            request_id: request_id.to_string(),
+            endpoint_id: Uuid::new_v4(),
+            endpoint_name: "quotes".to_string(),
+            method: "GET".to_string(),
+            status_code: 200,
+            cost: "1000".parse().unwrap(),
+            response_time_ms: 12,
+            is_sandbox: false,
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Tests that events only reach their own user's subscribers and that a user's
+    /// channel goes away with their last subscriber
+    #[tokio::test]
+    async fn test_publish_and_subscribe() {
+        let stream = UsageStream::new(8, Duration::from_secs(30));
+        let user_id = Uuid::new_v4();
+
+        // Nobody is listening, so nothing is kept
+        stream.publish(user_id, test_event("unheard"));
+        assert!(stream.channels.is_empty());
+
+        let mut first = stream.subscribe(user_id);
+        let second = stream.subscribe(user_id);
+        stream.publish(Uuid::new_v4(), test_event("other"));
+        stream.publish(user_id, test_event("mine"));
+        assert_eq!(first.recv().await.unwrap().request_id, "mine");
+        assert!(first.receiver.is_empty());
+
+        drop(second);
+        assert_eq!(stream.channels.len(), 1);
+        drop(first);
+        assert!(stream.channels.is_empty());
+    }
+
+    /// Tests that a client falling behind is told how many events it missed
+    /// instead of holding up the publisher
+    #[tokio::test]
+    async fn test_slow_subscriber_misses_events() {
+        let stream = UsageStream::new(2, Duration::from_secs(30));
+        let user_id = Uuid::new_v4();
+        let mut subscription = stream.subscribe(user_id);
+
+        for request_id in ["1", "2", "3", "4", "5"] {
+            stream.publish(user_id, test_event(request_id));
+        }
+        assert!(matches!(subscription.recv().await, Err(RecvError::Lagged(3))));
+        assert_eq!(subscription.recv().await.unwrap().request_id, "4");
+        assert_eq!(subscription.recv().await.unwrap().request_id, "5");
+    }
+
+    /// Tests that the event stream opens with a snapshot, relays requests and ends
+    /// once its credentials expire
+    #[tokio::test(start_paused = true)]
+    async fn test_sse_events() {
+        let stream = UsageStream::new(8, Duration::from_secs(30));
+        let user_id = Uuid::new_v4();
+        let snapshot = || async { Ok(serde_json::json!({ "total_requests": 1 })) };
+        let events = sse_events(stream.subscribe(user_id), snapshot, Duration::from_secs(30), Some(Instant::now() + Duration::from_secs(45)));
+        tokio::pin!(events);
+
+        // Snapshots are sent on connect and then on every interval
+        assert!(events.next().await.is_some());
+        stream.publish(user_id, test_event("live"));
+        assert!(events.next().await.is_some());
+        assert!(events.next().await.is_some());
+
+        // The stream ends at expiry and releases the user's channel
+        assert!(events.next().await.is_none());
+        assert!(stream.channels.is_empty());
+    }
+}
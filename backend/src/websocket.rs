@@ -0,0 +1,429 @@
+//! WebSocket proxying for streaming upstream APIs
+//!
+//! `/proxy-ws/:endpoint` upgrades the client connection, dials the endpoint's
+//! `upstream_ws_url` and relays frames in both directions. Sessions are billed by
+//! the endpoint's pricing model and recorded once either side disconnects.
+
+use axum::{
+    extract::{
+        ws::{self, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use futures::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, client::IntoClientRequest, protocol::CloseFrame},
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use crate::{
+    auth::enforce_ip_allowlist,
+    error::{AppError, AppResult},
+    gateway::{forwarded_query, FORWARDING_HEADERS},
+    models::{ApiEndpoint, CostAmount, CostBreakdown, CreateRequestLogRequest, PricingModel},
+    pricing,
+    AppState,
+};
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Query parameters read from the upgrade request
+#[derive(Deserialize)]
+struct WsProxyQuery {
+    api_key: Option<String>,
+}
+
+/// Who a relayed session is billed to and how it is logged
+struct Session {
+    request_id: String,
+    user_id: Uuid,
+    api_key_id: Option<Uuid>, // named API key that opened the session
+    overage_rate: Option<Decimal>, // price multiplier when opened past the monthly limit
+    hold: Option<Uuid>, // balance held until the session is billed
+    endpoint: ApiEndpoint,
+    upstream_url: String,
+    ip_address_hash: String,
+    user_agent_hash: Option<String>,
+}
+
+/// Data frames and payload bytes relayed in one direction
+#[derive(Debug, Default, Clone, Copy)]
+struct Traffic {
+    messages: u64,
+    bytes: u64,
+}
+
+impl Traffic {
+    fn record(&mut self, len: usize) {
+        self.messages += 1;
+        self.bytes += len as u64;
+    }
+}
+
+/// Upgrades `/proxy-ws/:endpoint` and relays the session to the endpoint's upstream socket
+///
+/// Browsers cannot set headers on a WebSocket handshake, so the API key may also be
+/// passed as the `api_key` query parameter; it is never forwarded upstream.
+pub async fn handle_ws_proxy(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    uri: Uri,
+    mut headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Response> {
+    let api_key = match state.gateway.extract_api_key(&headers) {
+        Ok(api_key) => api_key,
+        Err(e) => query_api_key(uri.query())
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| e.into_response())?,
+    };
+    let user = state.gateway.authenticate_consumer(&api_key).await
+        .map_err(IntoResponse::into_response)?;
+
+    // Restricted keys are only honoured from their allowlisted networks
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    enforce_ip_allowlist(&state, &user, peer, &headers)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let gateway = &state.gateway;
+    gateway.set_forwarding_headers(peer, &mut headers);
+    let endpoint = gateway.load_endpoint(&slug).await
+        .map_err(IntoResponse::into_response)?;
+    let Some(upstream_ws_url) = endpoint.upstream_ws_url.clone() else {
+        return Err(AppError::Validation(format!(
+            "Endpoint '{}' does not accept WebSocket connections",
+            endpoint.name
+        )).into_response());
+    };
+    let limits = gateway.check_usage_limits(&user, &endpoint).await
+        .map_err(IntoResponse::into_response)?;
+
+    // Hold the price of the shortest possible session until it is billed
+    let request_id = Uuid::new_v4().to_string();
+    let hold = state.metering
+        .hold_funds(user.id, endpoint.id, &request_id, |prior_units| {
+            session_charge(&endpoint, prior_units, Duration::ZERO, Traffic::default())
+        })
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // Dial the upstream before upgrading so a dead upstream is reported as a 502
+    let (upstream, protocol) = match connect_upstream(&state, &endpoint, &upstream_ws_url, uri.query(), &headers).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            state.metering.release_funds(hold).await;
+            return Err(e.into_response());
+        }
+    };
+
+    let session = Session {
+        request_id,
+        user_id: user.id,
+        api_key_id: user.api_key_id,
+        overage_rate: limits.overage_rate(),
+        hold,
+        upstream_url: upstream_ws_url,
+        ip_address_hash: gateway.hash_ip_address(&headers),
+        user_agent_hash: gateway.hash_user_agent(&headers),
+        endpoint,
+    };
+    let ws = match protocol {
+        Some(protocol) => ws.protocols([protocol]),
+        None => ws,
+    };
+    let metering = state.metering.clone();
+    Ok(ws
+        .on_failed_upgrade(move |e| {
+            error!("WebSocket upgrade failed: {}", e);
+            tokio::spawn(async move { metering.release_funds(hold).await });
+        })
+        .on_upgrade(move |socket| relay(state, socket, upstream, session)))
+}
+
+/// Reads the API key from the upgrade request's query string, if present
+fn query_api_key(query: Option<&str>) -> AppResult<Option<String>> {
+    let Some(query) = query else {
+        return Ok(None);
+    };
+    let params: WsProxyQuery = serde_urlencoded::from_str(query)
+        .map_err(|e| AppError::Validation(format!("Malformed query string: {}", e)))?;
+    Ok(params.api_key.filter(|key| !key.is_empty()))
+}
+
+/// Opens the upstream socket, returning it with the subprotocol the upstream accepted
+async fn connect_upstream(
+    state: &AppState,
+    endpoint: &ApiEndpoint,
+    upstream_ws_url: &str,
+    query: Option<&str>,
+    headers: &HeaderMap,
+) -> AppResult<(UpstreamSocket, Option<String>)> {
+    let url = match query.and_then(|query| forwarded_query(query, endpoint.forward_credentials, false)) {
+        Some(query) => format!("{}?{}", upstream_ws_url, query),
+        None => upstream_ws_url.to_string(),
+    };
+    let mut request = url.as_str().into_client_request().map_err(|e| {
+        error!("Endpoint {} has an invalid upstream WebSocket URL: {}", endpoint.name, e);
+        AppError::Internal("Invalid upstream WebSocket URL".to_string())
+    })?;
+
+    if let Some(protocols) = headers.get(header::SEC_WEBSOCKET_PROTOCOL) {
+        request.headers_mut().insert(header::SEC_WEBSOCKET_PROTOCOL, protocols.clone());
+    }
+    for name in FORWARDING_HEADERS {
+        if let Some(value) = headers.get(name) {
+            request.headers_mut().insert(name, value.clone());
+        }
+    }
+    if let Some((name, value)) = state.gateway.upstream_auth_header(endpoint)? {
+        request.headers_mut().insert(name, value);
+    }
+
+    let timeout = Duration::from_secs(endpoint.request_timeout.unwrap_or(30) as u64);
+    let (socket, response) = tokio::time::timeout(timeout, connect_async(request))
+        .await
+        .map_err(|_| AppError::ExternalService("Timed out connecting to upstream WebSocket".to_string()))?
+        .map_err(|e| AppError::ExternalService(format!("Failed to connect to upstream WebSocket: {}", e)))?;
+
+    let protocol = response.headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    Ok((socket, protocol))
+}
+
+/// Pipes frames between client and upstream until either side disconnects, then
+/// closes the other side and bills the session
+///
+/// Pings and pongs are not relayed; each hop answers its own keepalives.
+async fn relay(state: AppState, client: WebSocket, upstream: UpstreamSocket, session: Session) {
+    let started = Instant::now();
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+    let mut sent = Traffic::default();
+    let mut received = Traffic::default();
+
+    {
+        let client_to_upstream = async {
+            while let Some(Ok(message)) = client_rx.next().await {
+                let message = match message {
+                    ws::Message::Text(text) => {
+                        sent.record(text.len());
+                        tungstenite::Message::Text(text)
+                    }
+                    ws::Message::Binary(data) => {
+                        sent.record(data.len());
+                        tungstenite::Message::Binary(data)
+                    }
+                    ws::Message::Close(frame) => {
+                        let frame = frame.map(|frame| CloseFrame { code: frame.code.into(), reason: frame.reason });
+                        let _ = upstream_tx.send(tungstenite::Message::Close(frame)).await;
+                        break;
+                    }
+                    ws::Message::Ping(_) | ws::Message::Pong(_) => continue,
+                };
+                if upstream_tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let upstream_to_client = async {
+            while let Some(Ok(message)) = upstream_rx.next().await {
+                let message = match message {
+                    tungstenite::Message::Text(text) => {
+                        received.record(text.len());
+                        ws::Message::Text(text)
+                    }
+                    tungstenite::Message::Binary(data) => {
+                        received.record(data.len());
+                        ws::Message::Binary(data)
+                    }
+                    tungstenite::Message::Close(frame) => {
+                        let frame = frame.map(|frame| ws::CloseFrame { code: frame.code.into(), reason: frame.reason });
+                        let _ = client_tx.send(ws::Message::Close(frame)).await;
+                        break;
+                    }
+                    tungstenite::Message::Ping(_)
+                    | tungstenite::Message::Pong(_)
+                    | tungstenite::Message::Frame(_) => continue,
+                };
+                if client_tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = client_to_upstream => debug!("Client closed WebSocket session {}", session.request_id),
+            _ = upstream_to_client => debug!("Upstream closed WebSocket session {}", session.request_id),
+        }
+    }
+
+    let _ = client_tx.close().await;
+    let _ = upstream_tx.close().await;
+
+    finish_session(&state, session, started.elapsed(), sent, received).await;
+}
+
+/// Logs a finished session and flushes its usage
+async fn finish_session(state: &AppState, session: Session, duration: Duration, sent: Traffic, received: Traffic) {
+    let endpoint = &session.endpoint;
+    let traffic = Traffic {
+        messages: sent.messages + received.messages,
+        bytes: sent.bytes + received.bytes,
+    };
+    let response_time = duration.as_millis() as i32;
+
+    // Unbilled sessions are priced without recording usage, like unbilled HTTP calls
+    let share = state.gateway.billing_share(endpoint, StatusCode::SWITCHING_PROTOCOLS);
+    let charged = if share.is_zero() {
+        session_charge(endpoint, 0, duration, traffic).and_then(|breakdown| breakdown.scaled(share))
+    } else {
+        state.metering
+            .record_usage(session.user_id, endpoint.id, session.api_key_id, share, session.overage_rate, |prior_units| {
+                session_charge(endpoint, prior_units, duration, traffic)
+            })
+            .await
+    };
+    let breakdown = match charged {
+        Ok(breakdown) => {
+            state.metering.settle_funds(session.user_id, session.hold, breakdown.total, &session.request_id).await;
+            Some(breakdown)
+        }
+        Err(e) => {
+            error!("Failed to record usage for WebSocket session {}: {}", session.request_id, e);
+            state.metering.release_funds(session.hold).await;
+            None
+        }
+    };
+    let cost = breakdown.as_ref().map_or(CostAmount::ZERO, |breakdown| breakdown.total);
+
+    info!(
+        "WebSocket session closed: {} {} ({}ms, {} messages in, {} out, {} units, cost {})",
+        endpoint.name, session.request_id, response_time, sent.messages, received.messages,
+        breakdown.as_ref().map_or(0, CostBreakdown::billable_units), cost
+    );
+
+    let log_request = CreateRequestLogRequest {
+        user_id: session.user_id,
+        endpoint_id: endpoint.id,
+        request_id: session.request_id.clone(),
+        method: "GET".to_string(),
+        path: format!("/proxy-ws/{}", endpoint.slug),
+        status_code: StatusCode::SWITCHING_PROTOCOLS.as_u16() as i32,
+        response_time_ms: response_time,
+        request_size: Some(sent.bytes as i64),
+        response_size: Some(received.bytes as i64),
+        ip_address_hash: session.ip_address_hash,
+        user_agent_hash: session.user_agent_hash,
+        cost,
+        error_message: None,
+        upstream_target: Some(session.upstream_url),
+        stream_duration_ms: Some(response_time),
+        cost_breakdown: breakdown.map(sqlx::types::Json),
+        is_sandbox: false,
+        api_key_id: session.api_key_id,
+    };
+    state.gateway.write_request_log(&endpoint.name, log_request).await;
+
+    if let Err(e) = state.metering
+        .record_request(session.user_id, endpoint.id, StatusCode::SWITCHING_PROTOCOLS.as_u16() as i32, response_time)
+        .await
+    {
+        error!("Failed to update metering: {}", e);
+    }
+}
+
+/// List price of a finished session after `prior_units` billable units already used
+/// this billing period
+///
+/// Per-minute pricing charges each started minute (at least one) and per-message
+/// pricing each data frame in either direction. Other models price the session as
+/// a single request whose response is all the payload relayed both ways.
+fn session_charge(endpoint: &ApiEndpoint, prior_units: i64, duration: Duration, traffic: Traffic) -> AppResult<CostBreakdown> {
+    match endpoint.pricing_model {
+        PricingModel::PerConnectionMinute => {
+            let minutes = duration.as_secs().div_ceil(60).max(1) as i64;
+            pricing::session_units_cost(endpoint, prior_units, minutes)
+        }
+        PricingModel::PerMessage => pricing::session_units_cost(endpoint, prior_units, traffic.messages as i64),
+        PricingModel::PerRequest | PricingModel::PerKilobyte | PricingModel::PerRequestPlusBytes => {
+            pricing::estimate_cost(endpoint, prior_units, 1, traffic.bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PricingTier;
+    use crate::test_support;
+
+    fn endpoint(pricing_model: PricingModel, price_per_request: &str, price_per_kilobyte: Option<&str>) -> ApiEndpoint {
+        ApiEndpoint {
+            price_per_request: price_per_request.to_string(),
+            upstream_ws_url: Some("wss://stream.example.com/feed".to_string()),
+            pricing_model,
+            price_per_kilobyte: price_per_kilobyte.map(str::to_string),
+            ..test_support::endpoint("ticker", "https://stream.example.com")
+        }
+    }
+
+    /// Tests the units and list price billed under each pricing model
+    #[test]
+    fn test_session_charge() {
+        let minute = Duration::from_secs(60);
+        let traffic = |messages, bytes| Traffic { messages, bytes };
+        let idle = traffic(0, 0);
+        let charge = |pricing_model, price, duration, traffic| {
+            let breakdown = session_charge(&endpoint(pricing_model, price, Some("10")), 0, duration, traffic).unwrap();
+            (breakdown.billable_units(), breakdown.total.to_string())
+        };
+
+        assert_eq!(charge(PricingModel::PerRequest, "1000", minute * 5, traffic(40, 900)), (1, "1000".to_string()));
+        assert_eq!(charge(PricingModel::PerMessage, "2.5", minute, traffic(4, 90)), (4, "10".to_string()));
+        assert_eq!(charge(PricingModel::PerMessage, "1000", minute, idle), (0, "0".to_string()));
+        assert_eq!(charge(PricingModel::PerKilobyte, "1000", minute, traffic(3, 2049)), (3, "30".to_string()));
+        assert_eq!(charge(PricingModel::PerRequestPlusBytes, "1000", minute, traffic(3, 2049)), (1, "1030".to_string()));
+
+        // Every started minute counts, and even a brief session is billed one
+        let per_minute = PricingModel::PerConnectionMinute;
+        assert_eq!(charge(per_minute, "100", Duration::from_secs(3), idle), (1, "100".to_string()));
+        assert_eq!(charge(per_minute, "100", minute * 2 + Duration::from_secs(1), idle), (3, "300".to_string()));
+
+        // Volume tiers count minutes already used this month
+        let tiered = ApiEndpoint {
+            pricing_tiers: sqlx::types::Json(vec![PricingTier { up_to: Some(60), price: "40".to_string() }]),
+            ..endpoint(per_minute, "100", None)
+        };
+        let session = session_charge(&tiered, 59, minute * 3, idle).unwrap();
+        assert_eq!(session.total, "240");
+
+        assert!(session_charge(&endpoint(PricingModel::PerRequest, "free", None), 0, minute, idle).is_err());
+    }
+
+    /// Tests reading the API key from the upgrade request's query string
+    #[test]
+    fn test_query_api_key() {
+        assert_eq!(query_api_key(None).unwrap(), None);
+        assert_eq!(query_api_key(Some("symbol=ETH")).unwrap(), None);
+        assert_eq!(query_api_key(Some("api_key=")).unwrap(), None);
+        assert_eq!(query_api_key(Some("symbol=ETH&api_key=ak_123")).unwrap().as_deref(), Some("ak_123"));
+        assert!(query_api_key(Some("api_key=a&api_key=b")).is_err());
+    }
+}